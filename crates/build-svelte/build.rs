@@ -1,14 +1,32 @@
-use std::{io, process::Command};
+use std::{
+    io::{self, ErrorKind},
+    process::Command,
+};
+
+const SKIP_ENV: &str = "SKIP_FRONTEND_BUILD";
 
 fn main() -> Result<(), io::Error> {
     println!("cargo:rerun-if-changed=../../frontend/src");
     println!("cargo:rerun-if-changed=../../frontend/static");
     println!("cargo:rerun-if-changed=../../frontend/package.json");
+    println!("cargo:rerun-if-env-changed={SKIP_ENV}");
+
+    if std::env::var_os(SKIP_ENV).is_some_and(|skip| skip != "0" && skip != "false") {
+        println!("cargo:warning={SKIP_ENV} is set, skipping the frontend build");
+        return Ok(());
+    }
 
-    Command::new("npm")
+    match Command::new("npm")
         .arg("install")
         .current_dir("../../frontend")
-        .output()?;
+        .output()
+    {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            panic!("Could not find `npm` to build the frontend. Install Node or set {SKIP_ENV}=1 to only build the backend");
+        }
+        Err(err) => return Err(err),
+    }
     let output = Command::new("npm")
         .arg("run")
         .arg("build")
@@ -17,8 +17,8 @@ type YoutubeChannel = {
 };
 
 type TwitchAccount = {
-    display_name: string;
-    follower_count: number;
-    subscriber_count: number;
-    profile_image_url: string;
+    displayName: string;
+    followerCount: number;
+    subscriberCount: number;
+    profileImageUrl: string;
 };
\ No newline at end of file
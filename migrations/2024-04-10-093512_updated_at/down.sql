@@ -0,0 +1,8 @@
+DROP TRIGGER set_updated_at ON Company;
+ALTER TABLE Company DROP COLUMN updated_at;
+
+DROP TRIGGER set_updated_at ON CompanyUserProfile;
+ALTER TABLE CompanyUserProfile DROP COLUMN updated_at;
+
+DROP TRIGGER set_updated_at ON CreatorProfile;
+ALTER TABLE CreatorProfile DROP COLUMN updated_at;
@@ -0,0 +1,8 @@
+ALTER TABLE CreatorProfile ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+SELECT diesel_manage_updated_at('CreatorProfile');
+
+ALTER TABLE CompanyUserProfile ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+SELECT diesel_manage_updated_at('CompanyUserProfile');
+
+ALTER TABLE Company ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+SELECT diesel_manage_updated_at('Company');
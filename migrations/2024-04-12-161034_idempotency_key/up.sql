@@ -0,0 +1,8 @@
+CREATE TABLE IdempotencyKey (
+    user_id UUID NOT NULL,
+    key TEXT NOT NULL,
+    response TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES InnerUser(id) ON DELETE CASCADE,
+    CONSTRAINT pk_user_key PRIMARY KEY (user_id, key)
+);
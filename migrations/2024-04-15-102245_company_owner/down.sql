@@ -0,0 +1,3 @@
+DROP INDEX company_single_owner;
+
+ALTER TABLE CompanyUser DROP COLUMN is_owner;
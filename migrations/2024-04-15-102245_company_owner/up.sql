@@ -0,0 +1,13 @@
+ALTER TABLE CompanyUser ADD COLUMN is_owner BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Every existing company gets one of its admins as the owner
+UPDATE CompanyUser SET is_owner = TRUE
+FROM (
+    SELECT DISTINCT ON (company_id) company_id, user_id
+    FROM CompanyUser
+    WHERE is_admin
+    ORDER BY company_id, user_id
+) AS owner
+WHERE CompanyUser.company_id = owner.company_id AND CompanyUser.user_id = owner.user_id;
+
+CREATE UNIQUE INDEX company_single_owner ON CompanyUser (company_id) WHERE is_owner;
@@ -0,0 +1,3 @@
+DROP INDEX stored_file_user;
+
+DROP TABLE StoredFile;
@@ -0,0 +1,11 @@
+CREATE TABLE StoredFile (
+    folder TEXT NOT NULL,
+    object_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    path TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES InnerUser(id) ON DELETE CASCADE,
+    CONSTRAINT pk_folder_object PRIMARY KEY (folder, object_id)
+);
+
+CREATE INDEX stored_file_user ON StoredFile (user_id);
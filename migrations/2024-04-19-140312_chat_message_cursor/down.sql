@@ -0,0 +1 @@
+DROP INDEX chat_message_room_id;
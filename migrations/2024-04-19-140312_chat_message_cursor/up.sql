@@ -0,0 +1,2 @@
+-- Backs paging through a room's messages with the message id as the cursor
+CREATE INDEX chat_message_room_id ON ChatMessage (room_id, id);
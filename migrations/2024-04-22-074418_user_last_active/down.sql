@@ -0,0 +1 @@
+ALTER TABLE InnerUser DROP COLUMN last_active_at;
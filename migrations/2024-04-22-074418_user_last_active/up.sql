@@ -0,0 +1 @@
+ALTER TABLE InnerUser ADD COLUMN last_active_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
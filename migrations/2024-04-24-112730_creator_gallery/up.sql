@@ -0,0 +1,10 @@
+CREATE TABLE CreatorGallery (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL,
+    position INT NOT NULL,
+    image_path TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES InnerUser(id) ON DELETE CASCADE
+);
+
+CREATE INDEX creator_gallery_user ON CreatorGallery (user_id, position);
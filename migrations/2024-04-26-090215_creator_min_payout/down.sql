@@ -0,0 +1,2 @@
+ALTER TABLE CreatorProfile DROP COLUMN min_payout_strict;
+ALTER TABLE CreatorProfile DROP COLUMN min_payout;
@@ -0,0 +1,2 @@
+ALTER TABLE CreatorProfile ADD COLUMN min_payout MONEY;
+ALTER TABLE CreatorProfile ADD COLUMN min_payout_strict BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1 @@
+ALTER TABLE ChatContractOffer DROP COLUMN currency;
@@ -0,0 +1,2 @@
+ALTER TABLE ChatContractOffer ADD COLUMN currency CHAR(3) NOT NULL DEFAULT 'USD';
+ALTER TABLE ChatContractOffer ALTER COLUMN currency DROP DEFAULT;
@@ -0,0 +1,5 @@
+DROP TRIGGER contract_offer_update_immutable ON ChatContractOfferUpdate;
+
+DROP TRIGGER contract_offer_immutable ON ChatContractOffer;
+
+DROP FUNCTION reject_contract_offer_change();
@@ -0,0 +1,10 @@
+CREATE FUNCTION reject_contract_offer_change() RETURNS trigger LANGUAGE plpgsql AS
+$$
+BEGIN
+    RAISE EXCEPTION 'Contract offers and their updates cannot be changed';
+END
+$$;
+
+CREATE TRIGGER contract_offer_immutable BEFORE UPDATE ON ChatContractOffer FOR EACH ROW EXECUTE PROCEDURE reject_contract_offer_change();
+
+CREATE TRIGGER contract_offer_update_immutable BEFORE UPDATE ON ChatContractOfferUpdate FOR EACH ROW EXECUTE PROCEDURE reject_contract_offer_change();
@@ -0,0 +1,3 @@
+ALTER TABLE TwitchAccount DROP COLUMN refreshed_at;
+
+ALTER TABLE GoogleAccount DROP COLUMN refreshed_at;
@@ -0,0 +1,3 @@
+ALTER TABLE GoogleAccount ADD COLUMN refreshed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+ALTER TABLE TwitchAccount ADD COLUMN refreshed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
@@ -0,0 +1,3 @@
+ALTER TABLE TwitchAccount DROP COLUMN reauth_required;
+
+ALTER TABLE GoogleAccount DROP COLUMN reauth_required;
@@ -0,0 +1,3 @@
+ALTER TABLE GoogleAccount ADD COLUMN reauth_required BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE TwitchAccount ADD COLUMN reauth_required BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,12 @@
+CREATE TABLE CreatorPlatformStats (
+    platform TEXT NOT NULL,
+    platform_id TEXT NOT NULL,
+    user_id UUID NOT NULL,
+    followers BIGINT,
+    subscribers BIGINT NOT NULL,
+    refreshed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (platform, platform_id),
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES InnerUser(id) ON DELETE CASCADE
+);
+
+CREATE INDEX creator_platform_stats_user ON CreatorPlatformStats (user_id);
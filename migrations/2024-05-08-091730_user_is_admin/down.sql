@@ -0,0 +1 @@
+ALTER TABLE InnerUser DROP COLUMN is_admin;
@@ -0,0 +1 @@
+ALTER TABLE InnerUser ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
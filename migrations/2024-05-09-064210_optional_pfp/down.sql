@@ -0,0 +1,4 @@
+UPDATE CreatorProfile SET pfp_path = '' WHERE pfp_path IS NULL;
+UPDATE CompanyUserProfile SET pfp_path = '' WHERE pfp_path IS NULL;
+ALTER TABLE CreatorProfile ALTER COLUMN pfp_path SET NOT NULL;
+ALTER TABLE CompanyUserProfile ALTER COLUMN pfp_path SET NOT NULL;
@@ -0,0 +1,2 @@
+ALTER TABLE CreatorProfile ALTER COLUMN pfp_path DROP NOT NULL;
+ALTER TABLE CompanyUserProfile ALTER COLUMN pfp_path DROP NOT NULL;
@@ -0,0 +1,3 @@
+DROP INDEX inner_user_deleted_at;
+
+ALTER TABLE InnerUser DROP COLUMN deleted_at;
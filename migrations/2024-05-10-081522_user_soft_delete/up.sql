@@ -0,0 +1,3 @@
+ALTER TABLE InnerUser ADD COLUMN deleted_at TIMESTAMP;
+
+CREATE INDEX inner_user_deleted_at ON InnerUser (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1 @@
+ALTER TABLE Company DROP COLUMN logo_source_url;
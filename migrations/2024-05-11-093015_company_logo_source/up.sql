@@ -0,0 +1 @@
+ALTER TABLE Company ADD COLUMN logo_source_url TEXT;
@@ -0,0 +1,9 @@
+CREATE TABLE ChatReaction (
+    message_id BIGINT NOT NULL,
+    user_id UUID NOT NULL,
+    emoji TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, user_id, emoji),
+    CONSTRAINT fk_message FOREIGN KEY (message_id) REFERENCES ChatMessage(id) ON DELETE CASCADE,
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES InnerUser(id) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+ALTER TABLE ChatRoom DROP CONSTRAINT chat_room_company_user;
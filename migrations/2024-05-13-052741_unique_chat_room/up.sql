@@ -0,0 +1,21 @@
+-- Rooms that were created twice by racing requests are merged into one before the constraint is
+-- added. Message ids are global so the merged messages keep their order.
+CREATE TEMPORARY TABLE KeptChatRoom AS
+    SELECT company_id, user_id, MIN(id::TEXT)::UUID AS id
+    FROM ChatRoom
+    GROUP BY company_id, user_id
+    HAVING COUNT(*) > 1;
+
+UPDATE ChatMessage m
+SET room_id = k.id
+FROM ChatRoom r
+JOIN KeptChatRoom k ON k.company_id = r.company_id AND k.user_id = r.user_id
+WHERE m.room_id = r.id AND r.id <> k.id;
+
+DELETE FROM ChatRoom r
+USING KeptChatRoom k
+WHERE k.company_id = r.company_id AND k.user_id = r.user_id AND r.id <> k.id;
+
+DROP TABLE KeptChatRoom;
+
+ALTER TABLE ChatRoom ADD CONSTRAINT chat_room_company_user UNIQUE (company_id, user_id);
@@ -0,0 +1,11 @@
+CREATE TABLE SuggestedMatch (
+    creator_id UUID NOT NULL,
+    company_id UUID NOT NULL,
+    score REAL NOT NULL,
+    computed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (creator_id, company_id),
+    CONSTRAINT fk_creator FOREIGN KEY (creator_id) REFERENCES CreatorProfile(user_id) ON DELETE CASCADE,
+    CONSTRAINT fk_company FOREIGN KEY (company_id) REFERENCES Company(id) ON DELETE CASCADE
+);
+
+CREATE INDEX suggested_match_company ON SuggestedMatch (company_id);
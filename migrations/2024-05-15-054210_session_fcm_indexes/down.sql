@@ -0,0 +1,2 @@
+DROP INDEX session_fcm_token_session;
+DROP INDEX inner_user_session_user;
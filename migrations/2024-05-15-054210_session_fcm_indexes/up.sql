@@ -0,0 +1,3 @@
+-- Backs finding the sessions of a user and their fcm tokens when notifying them
+CREATE INDEX inner_user_session_user ON InnerUserSession (user_id);
+CREATE INDEX session_fcm_token_session ON SessionFcmToken (session_token);
@@ -0,0 +1,10 @@
+-- Keys of services that call the API without a browser session, they sign their requests
+CREATE TABLE ApiKey (
+    id TEXT PRIMARY KEY,
+    -- Signatures are checked with the secret so it cannot be hashed
+    secret TEXT NOT NULL,
+    name TEXT NOT NULL,
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    revoked_at TIMESTAMP
+);
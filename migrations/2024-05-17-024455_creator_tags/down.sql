@@ -0,0 +1,2 @@
+DROP INDEX creator_profile_tags;
+ALTER TABLE CreatorProfile DROP COLUMN tags;
@@ -0,0 +1,3 @@
+-- Keywords a creator picks for themselves, companies can require them when browsing creators
+ALTER TABLE CreatorProfile ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+CREATE INDEX creator_profile_tags ON CreatorProfile USING GIN (tags);
@@ -0,0 +1,2 @@
+DROP TABLE WebhookDeadLetter;
+DROP TABLE Webhook;
@@ -0,0 +1,27 @@
+-- Urls of companies that are notified of events with signed POST requests
+CREATE TABLE Webhook (
+    id UUID PRIMARY KEY,
+    company_id UUID NOT NULL,
+    url TEXT NOT NULL,
+    -- Payloads are signed with the secret so it cannot be hashed
+    secret TEXT NOT NULL,
+    events TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_company FOREIGN KEY (company_id) REFERENCES Company(id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_company ON Webhook (company_id);
+
+-- Deliveries that still failed after every retry
+CREATE TABLE WebhookDeadLetter (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id UUID NOT NULL,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INT NOT NULL,
+    last_error TEXT NOT NULL,
+    failed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_webhook FOREIGN KEY (webhook_id) REFERENCES Webhook(id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_dead_letter_webhook ON WebhookDeadLetter (webhook_id);
@@ -0,0 +1,2 @@
+DROP INDEX company_user_invitation_company;
+ALTER TABLE CompanyUserInvitation DROP COLUMN invited_at;
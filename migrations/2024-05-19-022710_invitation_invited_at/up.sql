@@ -0,0 +1,4 @@
+-- Invites that exist already count as sent now
+ALTER TABLE CompanyUserInvitation ADD COLUMN invited_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+-- Invites of a company are paged through by email
+CREATE INDEX company_user_invitation_company ON CompanyUserInvitation (company_id, invited_google_email);
@@ -0,0 +1 @@
+ALTER TABLE CompanyUser DROP COLUMN joined_at;
@@ -0,0 +1,2 @@
+-- Members that exist already count as joined now
+ALTER TABLE CompanyUser ADD COLUMN joined_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
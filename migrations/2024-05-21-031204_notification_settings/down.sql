@@ -0,0 +1,2 @@
+ALTER TABLE CompanyUser DROP COLUMN push_notifications;
+ALTER TABLE InnerUser DROP COLUMN push_notifications;
@@ -0,0 +1,4 @@
+-- Whether the user gets push notifications at all
+ALTER TABLE InnerUser ADD COLUMN push_notifications BOOLEAN NOT NULL DEFAULT TRUE;
+-- Overrides the setting of the user for events of the company, NULL follows it
+ALTER TABLE CompanyUser ADD COLUMN push_notifications BOOLEAN;
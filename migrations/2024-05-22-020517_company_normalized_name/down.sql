@@ -0,0 +1 @@
+DROP INDEX company_normalized_name_idx;
@@ -0,0 +1,2 @@
+-- Not unique as unique names are optional, see `UNIQUE_COMPANY_NAMES`
+CREATE INDEX company_normalized_name_idx ON Company (lower(btrim(full_name)));
@@ -0,0 +1,7 @@
+DELETE FROM IdempotencyKey WHERE response IS NULL;
+DELETE FROM IdempotencyKey a USING IdempotencyKey b
+    WHERE a.user_id = b.user_id AND a.key = b.key AND a.endpoint > b.endpoint;
+ALTER TABLE IdempotencyKey DROP CONSTRAINT pk_user_endpoint_key;
+ALTER TABLE IdempotencyKey ADD CONSTRAINT pk_user_key PRIMARY KEY (user_id, key);
+ALTER TABLE IdempotencyKey ALTER COLUMN response SET NOT NULL;
+ALTER TABLE IdempotencyKey DROP COLUMN endpoint;
@@ -0,0 +1,5 @@
+-- Keys are scoped per endpoint, a NULL response marks a request that is still being processed
+ALTER TABLE IdempotencyKey ADD COLUMN endpoint TEXT NOT NULL DEFAULT '';
+ALTER TABLE IdempotencyKey ALTER COLUMN response DROP NOT NULL;
+ALTER TABLE IdempotencyKey DROP CONSTRAINT pk_user_key;
+ALTER TABLE IdempotencyKey ADD CONSTRAINT pk_user_endpoint_key PRIMARY KEY (user_id, endpoint, key);
@@ -0,0 +1 @@
+ALTER TABLE ChatRoom DROP COLUMN opened_by;
@@ -0,0 +1,3 @@
+-- The user who opened the room, NULL for rooms opened before it was recorded
+ALTER TABLE ChatRoom ADD COLUMN opened_by UUID;
+ALTER TABLE ChatRoom ADD CONSTRAINT fk_opened_by FOREIGN KEY (opened_by) REFERENCES InnerUser(id) ON DELETE SET NULL;
@@ -0,0 +1,31 @@
+-- `old` is NULL in an insert trigger so the previous status was never found
+CREATE OR REPLACE FUNCTION check_contract_offer_update() RETURNS trigger LANGUAGE plpgsql STABLE AS
+$$
+DECLARE
+    correct_transition BOOLEAN;
+BEGIN
+    SELECT CASE (SELECT update_kind FROM ChatContractOfferUpdate WHERE offer_id = new.offer_id ORDER BY id DESC LIMIT 1)
+        WHEN 'AcceptedByCreator' THEN
+            CASE new.update_kind
+                WHEN 'CancelledByCreator' THEN 'true'::BOOLEAN
+                WHEN 'FinishedByCreator' THEN 'true'::BOOLEAN
+                ELSE 'false'::BOOLEAN
+            END
+        WHEN 'WithdrawnByCompany' THEN 'false'::BOOLEAN
+        WHEN 'CancelledByCreator' THEN 'false'::BOOLEAN
+        WHEN 'FinishedByCreator' THEN
+            CASE new.update_kind
+                WHEN 'ApprovedByCompany' THEN 'true'::BOOLEAN
+                ELSE 'false'::BOOLEAN
+            END
+        WHEN 'ApprovedByCompany' THEN 'false'::BOOLEAN
+        ELSE 'true'::BOOLEAN
+    END AS correct_transition;
+
+    IF correct_transition THEN
+        RETURN new;
+    ELSE
+        RAISE EXCEPTION 'Cannot do this state transition';
+    END IF;
+END
+$$;
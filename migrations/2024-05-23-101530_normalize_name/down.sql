@@ -0,0 +1,2 @@
+DROP INDEX creator_profile_normalized_name_idx;
+DROP FUNCTION normalize_name(TEXT);
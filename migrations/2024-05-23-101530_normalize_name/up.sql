@@ -0,0 +1,9 @@
+-- Names are compared ignoring case and with their whitespace collapsed. Used by both the
+-- lookups and the indexes so they always agree.
+CREATE FUNCTION normalize_name(name TEXT) RETURNS TEXT LANGUAGE sql IMMUTABLE STRICT AS
+$$
+    SELECT lower(btrim(regexp_replace(name, '\s+', ' ', 'g')))
+$$;
+
+CREATE INDEX creator_profile_normalized_name_idx
+    ON CreatorProfile (normalize_name(given_name || ' ' || family_name));
@@ -0,0 +1,2 @@
+DROP INDEX company_normalized_name_idx;
+CREATE INDEX company_normalized_name_idx ON Company (lower(btrim(full_name)));
@@ -0,0 +1,6 @@
+DROP INDEX company_normalized_name_idx;
+-- Not unique. Unique names are only required when `UNIQUE_COMPANY_NAMES` is set, and a database
+-- that ran without it can already hold companies sharing a name, which a unique index would
+-- reject. `CompanyInsertUpdate::claim_name` enforces the uniqueness instead. It takes an advisory
+-- lock on the normalized name, so two transactions cannot both pass the check.
+CREATE INDEX company_normalized_name_idx ON Company (normalize_name(full_name));
@@ -0,0 +1,3 @@
+DROP TRIGGER contract_offer_update_undeletable ON ChatContractOfferUpdate;
+
+DROP TRIGGER contract_offer_undeletable ON ChatContractOffer;
@@ -0,0 +1,3 @@
+CREATE TRIGGER contract_offer_undeletable BEFORE DELETE ON ChatContractOffer FOR EACH ROW EXECUTE PROCEDURE reject_contract_offer_change();
+
+CREATE TRIGGER contract_offer_update_undeletable BEFORE DELETE ON ChatContractOfferUpdate FOR EACH ROW EXECUTE PROCEDURE reject_contract_offer_change();
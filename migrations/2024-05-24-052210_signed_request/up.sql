@@ -0,0 +1,9 @@
+-- Signatures of recent signed requests, a signature seen twice is a replayed request
+CREATE TABLE SignedRequest (
+    signature BYTEA PRIMARY KEY,
+    key_id TEXT NOT NULL,
+    recorded_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT fk_key FOREIGN KEY (key_id) REFERENCES ApiKey(id) ON DELETE CASCADE
+);
+
+CREATE INDEX signed_request_recorded_at ON SignedRequest (recorded_at);
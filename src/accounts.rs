@@ -0,0 +1,19 @@
+use axum::{routing, Json, Router};
+
+use crate::{
+    db::{LinkedAccount, User},
+    state::{AppState, DbConn},
+    Error,
+};
+
+/// Every account the user linked, across all providers
+async fn list_accounts(
+    user: User,
+    DbConn { mut conn }: DbConn,
+) -> Result<Json<Vec<LinkedAccount>>, Error> {
+    Ok(Json(LinkedAccount::list(user, &mut conn).await?))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/", routing::get(list_accounts))
+}
@@ -0,0 +1,103 @@
+use std::{sync::Mutex, time::Instant};
+
+use axum::{extract::Path, http::StatusCode, routing, Json, Router};
+use diesel::pg::Pg;
+use diesel_async::AsyncConnection;
+use uuid::Uuid;
+
+use crate::{
+    db::{Admin, AdminUserView, CreatorProfileInsert, Encoder, PlatformStats, User},
+    state::{AppState, DbConn},
+    Error,
+};
+
+static STATS_CACHE: Mutex<Option<(Instant, PlatformStats)>> = Mutex::new(None);
+
+/// Counts across the platform, recomputed at most once every [`crate::ADMIN_STATS_CACHE_DURATION`]
+pub(crate) async fn cached_stats(
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<PlatformStats, Error> {
+    if let Some((counted_at, stats)) = *STATS_CACHE.lock().unwrap()
+        && counted_at.elapsed() < crate::ADMIN_STATS_CACHE_DURATION
+    {
+        return Ok(stats);
+    }
+
+    let stats = PlatformStats::count(conn).await?;
+    *STATS_CACHE.lock().unwrap() = Some((Instant::now(), stats));
+
+    Ok(stats)
+}
+
+async fn stats(_: Admin, DbConn { mut conn }: DbConn) -> Result<Json<PlatformStats>, Error> {
+    Ok(Json(cached_stats(&mut conn).await?))
+}
+
+async fn get_user(
+    _: Admin,
+    DbConn { mut conn }: DbConn,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<AdminUserView>, Error> {
+    let Some(user) = AdminUserView::get(user_id, &mut conn).await? else {
+        return Err(Error::Custom {
+            status_code: StatusCode::NOT_FOUND,
+            error: "There is no user of this id".into(),
+        });
+    };
+
+    Ok(Json(user))
+}
+
+/// Restores a user deleted within the grace period
+async fn restore_user(
+    _: Admin,
+    DbConn { mut conn }: DbConn,
+    Path(user_id): Path<Uuid>,
+) -> Result<(), Error> {
+    if !User::restore(user_id, &mut conn).await? {
+        return Err(Error::Custom {
+            status_code: StatusCode::NOT_FOUND,
+            error: "There is no restorable deleted user of this id".into(),
+        });
+    }
+
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Reembedded {
+    dimension: usize,
+    /// Euclidean norm of the new embedding, close to 1 for the normalized embeddings in use
+    norm: f32,
+}
+
+/// Recomputes the embedding of a single creator, like after the embedding model changed
+async fn reembed_creator(
+    _: Admin,
+    DbConn { mut conn }: DbConn,
+    encoder: Encoder,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Reembedded>, Error> {
+    let Some(embedding) =
+        CreatorProfileInsert::reembed(User { id: user_id }, &mut conn, encoder).await?
+    else {
+        return Err(Error::Custom {
+            status_code: StatusCode::NOT_FOUND,
+            error: "There is no creator profile for this user".into(),
+        });
+    };
+
+    Ok(Json(Reembedded {
+        dimension: embedding.len(),
+        norm: embedding.iter().map(|x| x * x).sum::<f32>().sqrt(),
+    }))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/stats", routing::get(stats))
+        .route("/user/:user-id", routing::get(get_user))
+        .route("/user/:user-id/restore", routing::post(restore_user))
+        .route("/creator/:user-id/reembed", routing::post(reembed_creator))
+}
@@ -2,20 +2,89 @@ use std::collections::HashMap;
 
 use axum::{http::StatusCode, Json};
 use diesel::{data_types::Cents, pg::Pg, ExpressionMethods};
-use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
 use time::PrimitiveDateTime;
 use uuid::Uuid;
 
 use crate::{
-    db::{company, User, UserInfo},
+    db::{company, CreatorProfileQuery, User, UserInfo, UserSession, WebhookEvent},
     models,
-    state::{DbConn, MsgEmitter},
+    state::{AppState, Config, DbConn, MsgEmitter, SessionWithPage, Typing},
+    storage::StoredImages,
+    utils::currency,
     ws::{WsError, WsFunctions},
     Error,
 };
 
 type Result<T> = std::result::Result<T, WsError>;
 
+/// Largest payout in cents a contract can be offered for. Keeps amounts far from the limits of
+/// `Money` so they never overflow when summed.
+const MAX_CONTRACT_PAYOUT: i64 = 100_000_000_000;
+
+/// Emojis a message can be reacted with
+const ALLOWED_REACTIONS: &[&str] = &["👍", "❤️", "😂", "😮", "😢", "🎉", "🙏", "👀"];
+
+/// How long a typing indicator lasts without the client refreshing it
+const TYPING_EXPIRY: std::time::Duration = std::time::Duration::from_secs(6);
+
+/// Returned both when a room does not exist and when the user is not in it, so the rooms of
+/// others cannot be probed for
+fn room_not_found() -> WsError {
+    WsError::Custom {
+        reason: "Room of this id was not found".into(),
+    }
+}
+
+/// Like [`room_not_found`] for companies the user is not a member of
+fn company_not_found() -> WsError {
+    WsError::Custom {
+        reason: "Company of this id was not found".into(),
+    }
+}
+
+/// Rejects payouts that are not positive or are above [`MAX_CONTRACT_PAYOUT`]
+fn check_payout(payout: i64) -> Result<()> {
+    if !(1..=MAX_CONTRACT_PAYOUT).contains(&payout) {
+        return Err(WsError::Custom {
+            reason: format!("The payout must be between 1 and {MAX_CONTRACT_PAYOUT} cents"),
+        });
+    }
+
+    Ok(())
+}
+
+/// The room if the user is its creator or a member of its company, `None` if it does not
+/// exist or the user is not in it
+async fn participant_room(
+    room_id: Uuid,
+    user: &User,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> std::result::Result<Option<models::ChatRoom>, Error> {
+    match models::ChatRoom::from_id(room_id, conn).await? {
+        Some(room)
+            if room.user_id == user.id
+                || company::users_in(room.company_id, conn)
+                    .await?
+                    .contains(&user.id) =>
+        {
+            Ok(Some(room))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Every chat function taking a room checks membership through this, see [`room_not_found`]
+async fn assert_participant(
+    room_id: Uuid,
+    user: &User,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<models::ChatRoom> {
+    participant_room(room_id, user, conn)
+        .await?
+        .ok_or_else(room_not_found)
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct NewMessage {
     content: String,
@@ -29,6 +98,8 @@ enum MessageContractChange {
     ProposedByCompany {
         campaign_id: Option<Uuid>,
         payout: i64,
+        /// ISO 4217 code
+        currency: String,
     },
     AcceptedByCreator,
     WithdrawnByCompany,
@@ -37,6 +108,34 @@ enum MessageContractChange {
     ApprovedByCompany,
 }
 
+impl MessageContractChange {
+    /// If the change is made by the creator of the room rather than a member of the company
+    fn by_creator(&self) -> bool {
+        match self {
+            MessageContractChange::AcceptedByCreator
+            | MessageContractChange::CancelledByCreator
+            | MessageContractChange::FinishedByCreator => true,
+            MessageContractChange::ProposedByCompany { .. }
+            | MessageContractChange::WithdrawnByCompany
+            | MessageContractChange::ApprovedByCompany => false,
+        }
+    }
+
+    /// The new status of the offer if this changes an existing offer
+    fn new_status(&self) -> Option<models::ContractOfferStatus> {
+        use models::ContractOfferStatus as Status;
+
+        match self {
+            MessageContractChange::ProposedByCompany { .. } => None,
+            MessageContractChange::AcceptedByCreator => Some(Status::AcceptedByCreator),
+            MessageContractChange::WithdrawnByCompany => Some(Status::WithdrawnByCompany),
+            MessageContractChange::CancelledByCreator => Some(Status::CancelledByCreator),
+            MessageContractChange::FinishedByCreator => Some(Status::FinishedByCreator),
+            MessageContractChange::ApprovedByCompany => Some(Status::ApprovedByCompany),
+        }
+    }
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct NewMessageFile {
     name: String,
@@ -59,6 +158,7 @@ enum CreateChatRoomDirection {
 }
 
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 struct ChatRoom {
     users: HashMap<Uuid, UserInfo>,
     messages: Vec<models::Message>,
@@ -75,6 +175,7 @@ enum CreateParam {
 }
 
 #[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 struct Room {
     room_id: Uuid,
 }
@@ -88,12 +189,220 @@ async fn list_rooms(
         .map(|rooms| Json(rooms))?)
 }
 
+#[derive(serde::Deserialize)]
+struct CompanyRoomsParam {
+    company_id: Uuid,
+}
+
+/// Inbox of a company, only visible to its users
+async fn list_company_rooms(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Json(param): Json<CompanyRoomsParam>,
+) -> Result<Json<Vec<models::RoomSummary>>> {
+    if !company::users_in(param.company_id, &mut conn)
+        .await?
+        .contains(&user.id)
+    {
+        return Err(company_not_found());
+    }
+
+    Ok(Json(
+        models::ChatRoom::list_for_company(param.company_id, user.id, &mut conn).await?,
+    ))
+}
+
+/// Unread messages of every room the user is in, much cheaper than listing the rooms so badges
+/// can be refreshed often
+async fn unread_counts(
+    user: User,
+    DbConn { mut conn }: DbConn,
+) -> Result<Json<models::UnreadCounts>> {
+    Ok(Json(
+        models::UnreadCounts::for_user(user.id, &mut conn).await?,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+struct CreatorActivityParam {
+    company_id: Uuid,
+    creator_id: Uuid,
+}
+
+/// Only users of a company that has a room with the creator can see how active they are
+async fn creator_activity(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Json(param): Json<CreatorActivityParam>,
+) -> Result<Json<models::CreatorActivity>> {
+    if !company::users_in(param.company_id, &mut conn)
+        .await?
+        .contains(&user.id)
+    {
+        return Err(company_not_found());
+    }
+    if models::ChatRoom::find(param.company_id, param.creator_id, &mut conn)
+        .await?
+        .is_none()
+    {
+        return Err(room_not_found());
+    }
+
+    Ok(Json(
+        models::CreatorActivity::for_company(param.company_id, param.creator_id, &mut conn).await?,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+struct MarkCompanySeenParam {
+    company_id: Uuid,
+    /// Id of the last message to mark seen, every message when not set
+    up_to_message_id: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MarkedSeen {
+    rooms_marked: usize,
+}
+
+/// Clears the inbox of a company for the user up to a message in one go
+async fn mark_company_seen(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    emitter: MsgEmitter,
+    Json(param): Json<MarkCompanySeenParam>,
+) -> Result<Json<MarkedSeen>> {
+    let members = company::users_in(param.company_id, &mut conn).await?;
+    if !members.contains(&user.id) {
+        return Err(company_not_found());
+    }
+
+    let seen_rooms = models::ChatLastSeen::mark_company_seen(
+        param.company_id,
+        user.id,
+        param.up_to_message_id,
+        &mut conn,
+    )
+    .await?;
+
+    for room in &seen_rooms {
+        for id in members.iter().copied().chain([room.creator_id]) {
+            emitter
+                .send_for_company(
+                    param.company_id,
+                    id,
+                    Some(serde_json::json!({
+                        "kind": "chat.seen",
+                        "data": {
+                            "room_id": room.room_id,
+                            "user_id": user.id,
+                            "message_id": room.last_message_seen_id,
+                        },
+                    })),
+                    None,
+                    &mut conn,
+                )
+                .await?;
+        }
+    }
+
+    Ok(Json(MarkedSeen {
+        rooms_marked: seen_rooms.len(),
+    }))
+}
+
+const DEFAULT_MESSAGE_PAGE_SIZE: i64 = 50;
+const MAX_MESSAGE_PAGE_SIZE: i64 = 200;
+
+#[derive(serde::Deserialize)]
+struct MessagesParam {
+    room_id: Uuid,
+    /// Id of the oldest message the client already has
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+
+async fn messages(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Json(param): Json<MessagesParam>,
+) -> Result<Json<Vec<models::Message>>> {
+    let room = assert_participant(param.room_id, &user, &mut conn).await?;
+
+    let limit = param
+        .limit
+        .unwrap_or(DEFAULT_MESSAGE_PAGE_SIZE)
+        .clamp(1, MAX_MESSAGE_PAGE_SIZE);
+
+    Ok(Json(
+        models::Message::list(room.id, param.before, limit, &mut conn).await?,
+    ))
+}
+
+const DEFAULT_MESSAGE_CONTEXT: i64 = 5;
+const MAX_MESSAGE_CONTEXT: i64 = 50;
+
+#[derive(serde::Deserialize)]
+struct GetMessageParam {
+    message_id: i64,
+    /// How many messages to return on each side of the message
+    context: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageWithContext {
+    room_id: Uuid,
+    message: models::Message,
+    /// Messages posted before this one, newest first
+    before: Vec<models::Message>,
+    /// Messages posted after this one, newest first
+    after: Vec<models::Message>,
+}
+
+/// Used to jump to a message, like when a notification is opened
+async fn get_message(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Json(param): Json<GetMessageParam>,
+) -> Result<Json<MessageWithContext>> {
+    let room = message_room(param.message_id, &user, &mut conn).await?;
+    let message = models::Message::get(param.message_id, &mut conn)
+        .await?
+        .ok_or_else(|| WsError::Custom {
+            reason: "Message of this id was not found".into(),
+        })?;
+
+    let context = param
+        .context
+        .unwrap_or(DEFAULT_MESSAGE_CONTEXT)
+        .clamp(0, MAX_MESSAGE_CONTEXT);
+    let (before, after) = if context == 0 {
+        (Vec::new(), Vec::new())
+    } else {
+        (
+            models::Message::list(room.id, Some(message.id), context, &mut conn).await?,
+            models::Message::list_after(room.id, message.id, context, &mut conn).await?,
+        )
+    };
+
+    Ok(Json(MessageWithContext {
+        room_id: room.id,
+        message,
+        before,
+        after,
+    }))
+}
+
 async fn create(
     user: User,
     DbConn { mut conn }: DbConn,
     emitter: MsgEmitter,
+    state: AppState,
     Json(param): Json<CreateParam>,
 ) -> Result<Json<Uuid>> {
+    let is_creator = matches!(param, CreateParam::WithCompany(_));
     let (company_id, user_id) = match param {
         CreateParam::WithCompany(company_id) => (company_id, user.id),
         CreateParam::WithUser {
@@ -110,9 +419,7 @@ async fn create(
             {
                 (current_user_company_id, other_user_id)
             } else {
-                return Err(WsError::Custom {
-                    reason: "You are not in that company".into(),
-                });
+                return Err(company_not_found());
             }
         }
     };
@@ -124,12 +431,41 @@ async fn create(
         });
     }
 
-    let room_id = models::ChatRoom::create(company_id, user_id, &mut conn).await?;
+    // Everyone already knows about a room that existed so it is returned as is
+    if let Some(room_id) = models::ChatRoom::find(company_id, user_id, &mut conn).await? {
+        return Ok(Json(room_id));
+    }
+
+    // The side opening the room is the one limited, so one account cannot spam every company
+    let empty_rooms = if is_creator {
+        models::ChatRoom::count_empty(Some(user_id), None, &mut conn).await?
+    } else {
+        models::ChatRoom::count_empty(None, Some(company_id), &mut conn).await?
+    };
+    if empty_rooms >= crate::MAX_EMPTY_ROOMS {
+        return Err(WsError::Custom {
+            reason: format!(
+                "There can be at most {} rooms without messages, send a message in one first",
+                crate::MAX_EMPTY_ROOMS
+            ),
+        });
+    }
+    if let Err(retry_after) = state.room_creation_limiter().check(user.id) {
+        return Err(WsError::InnerError(Error::TooManyRequests { retry_after }));
+    }
+
+    let (room_id, created) =
+        models::ChatRoom::create(company_id, user_id, user.id, &mut conn).await?;
+    if !created {
+        return Ok(Json(room_id));
+    }
+
     let user_ids = users_in_company.into_iter().chain([user_id]);
 
     for id in user_ids {
         emitter
-            .send(
+            .send_for_company(
+                company_id,
                 id,
                 Some(serde_json::json!({
                     "kind": "chat.new_room",
@@ -146,6 +482,569 @@ async fn create(
     Ok(Json(room_id))
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct PostParam {
+    room_id: Uuid,
+    content: String,
+    contract_change: Option<MessageContractChange>,
+    /// The offer a status change in `contract_change` applies to
+    offer_id: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Posted {
+    message: models::Message,
+    /// Set to the minimum payout of the creator when the offer is below it
+    below_min_payout: Option<i64>,
+}
+
+async fn post(
+    user: User,
+    mut db: DbConn,
+    emitter: MsgEmitter,
+    state: AppState,
+    typing: Typing,
+    Json(param): Json<PostParam>,
+) -> Result<Json<Posted>> {
+    let room = assert_participant(param.room_id, &user, &mut db.conn).await?;
+
+    // Contract changes have a limiter of their own so a busy room never holds up a deal
+    let limiter = match param.contract_change {
+        None => state.message_limiter(),
+        Some(_) => state.contract_change_limiter(),
+    };
+    if let Err(retry_after) = limiter.check((room.id, user.id)) {
+        return Err(WsError::InnerError(Error::TooManyRequests { retry_after }));
+    }
+
+    let company_users = company::users_in(room.company_id, &mut db.conn).await?;
+    let is_creator = room.user_id == user.id;
+
+    let mut below_min_payout = None;
+    if let Some(change) = &param.contract_change {
+        if change.by_creator() != is_creator {
+            return Err(WsError::Custom {
+                reason: "You cannot make this change to the contract".into(),
+            });
+        }
+
+        if let MessageContractChange::ProposedByCompany { currency, .. } = change
+            && currency::normalize(currency).is_none()
+        {
+            return Err(WsError::Custom {
+                reason: format!("{currency} is not an ISO 4217 currency code"),
+            });
+        }
+
+        if let MessageContractChange::ProposedByCompany { payout, .. } = change {
+            check_payout(*payout)?;
+        }
+
+        // The minimum payout of creators is in the default currency so offers in other
+        // currencies cannot be compared against it
+        if let MessageContractChange::ProposedByCompany {
+            payout, currency, ..
+        } = change
+            && currency::normalize(currency).as_deref() == Some(currency::DEFAULT_CURRENCY)
+            && let Some(min_payout) =
+                CreatorProfileQuery::min_payout(room.user_id, &mut db.conn).await?
+            && *payout < min_payout.cents
+        {
+            if min_payout.strict {
+                return Err(WsError::Custom {
+                    reason: format!(
+                        "The creator does not accept offers below {} cents of {}",
+                        min_payout.cents,
+                        currency::DEFAULT_CURRENCY
+                    ),
+                });
+            }
+            below_min_payout = Some(min_payout.cents);
+        }
+
+        if change.new_status().is_some() {
+            let Some(offer_id) = param.offer_id else {
+                return Err(WsError::Custom {
+                    reason: "The offer to change is missing".into(),
+                });
+            };
+            if models::ChatContractOffer::room_id(offer_id, &mut db.conn).await? != Some(room.id) {
+                return Err(WsError::Custom {
+                    reason: "There is no contract offer of this id in the room".into(),
+                });
+            }
+        }
+    }
+
+    let from_user = user.id;
+    let message = db
+        .transaction(|conn| {
+            async move {
+                let (id, created_at) =
+                    models::Message::insert(room.id, from_user, &param.content, conn).await?;
+                models::ChatDraft::clear(room.id, from_user, conn).await?;
+
+                let extra = match param.contract_change {
+                    None => None,
+                    Some(MessageContractChange::ProposedByCompany {
+                        payout, currency, ..
+                    }) => {
+                        let currency =
+                            currency::normalize(&currency).expect("Currency was validated above");
+                        let offer_id =
+                            models::ChatContractOffer::create(id, payout, &currency, conn).await?;
+                        Some(models::MessageExtra::ContractOfferCreated {
+                            offer_id,
+                            payout,
+                            currency,
+                        })
+                    }
+                    Some(change) => {
+                        let new_status = change
+                            .new_status()
+                            .expect("Every other change updates an existing offer");
+                        let offer_id = param.offer_id.expect("The offer was checked above");
+                        models::ChatContractOffer::update(id, offer_id, new_status, conn).await?;
+                        Some(models::MessageExtra::ContractOfferStatusChange {
+                            offer_id,
+                            new_status,
+                        })
+                    }
+                };
+
+                Ok(models::Message {
+                    id,
+                    from_user,
+                    content: param.content,
+                    created_at,
+                    extra,
+                    reactions: Vec::new(),
+                })
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    // Clients clear the indicator of a user when their message arrives
+    typing.stop(room.id, from_user);
+
+    for id in company_users.into_iter().chain([room.user_id]) {
+        emitter
+            .send_for_company(
+                room.company_id,
+                id,
+                Some(serde_json::json!({
+                    "kind": "chat.message",
+                    "data": {
+                        "room_id": room.id,
+                        "message": message,
+                    },
+                })),
+                None,
+                &mut db.conn,
+            )
+            .await?;
+    }
+
+    let data = serde_json::json!({
+        "room_id": room.id,
+        "creator_id": room.user_id,
+        "message": message,
+    });
+    if let Some(models::MessageExtra::ContractOfferStatusChange {
+        new_status: models::ContractOfferStatus::AcceptedByCreator,
+        ..
+    }) = &message.extra
+    {
+        crate::webhook::dispatch(
+            state,
+            room.company_id,
+            WebhookEvent::ContractAccepted,
+            data.clone(),
+        );
+    }
+    crate::webhook::dispatch(state, room.company_id, WebhookEvent::MessageCreated, data);
+
+    Ok(Json(Posted {
+        message,
+        below_min_payout,
+    }))
+}
+
+#[derive(serde::Deserialize)]
+struct ContractHistoryParam {
+    offer_id: i64,
+}
+
+/// Returns the room the offer was made in if the user is a member of it
+async fn offer_room(
+    offer_id: i64,
+    user: &User,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<models::ChatRoom> {
+    let not_found = || WsError::Custom {
+        reason: "Contract offer of this id was not found".into(),
+    };
+
+    let room_id = models::ChatContractOffer::room_id(offer_id, conn)
+        .await?
+        .ok_or_else(not_found)?;
+    participant_room(room_id, user, conn)
+        .await?
+        .ok_or_else(not_found)
+}
+
+async fn contract_history(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Json(param): Json<ContractHistoryParam>,
+) -> Result<Json<Vec<models::ContractHistoryEntry>>> {
+    offer_room(param.offer_id, &user, &mut conn).await?;
+
+    Ok(Json(
+        models::ChatContractOffer::history(param.offer_id, &mut conn).await?,
+    ))
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OfferDetails {
+    offer_id: i64,
+    room_id: Uuid,
+    /// In the minor unit of `currency`
+    payout: i64,
+    /// ISO 4217 code
+    currency: String,
+    offered_by: Uuid,
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    offered_at: PrimitiveDateTime,
+    /// `None` while the offer has not been acted on
+    status: Option<models::ContractOfferStatus>,
+    history: Vec<models::ContractHistoryEntry>,
+}
+
+/// The offer with its current status and every change made to it. Offers are not tied to
+/// campaigns yet so there is no campaign to include.
+async fn get_offer(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Json(param): Json<ContractHistoryParam>,
+) -> Result<Json<OfferDetails>> {
+    let room = offer_room(param.offer_id, &user, &mut conn).await?;
+    let history = models::ChatContractOffer::history(param.offer_id, &mut conn).await?;
+
+    let Some(models::ContractHistoryEntry {
+        by_user: offered_by,
+        at: offered_at,
+        change:
+            models::MessageExtra::ContractOfferCreated {
+                payout, currency, ..
+            },
+        ..
+    }) = history.first()
+    else {
+        return Err(WsError::Custom {
+            reason: "Contract offer of this id was not found".into(),
+        });
+    };
+    let status = history.iter().rev().find_map(|entry| match entry.change {
+        models::MessageExtra::ContractOfferStatusChange { new_status, .. } => Some(new_status),
+        _ => None,
+    });
+
+    Ok(Json(OfferDetails {
+        offer_id: param.offer_id,
+        room_id: room.id,
+        payout: *payout,
+        currency: currency.clone(),
+        offered_by: *offered_by,
+        offered_at: *offered_at,
+        status,
+        history,
+    }))
+}
+
+const MAX_DRAFT_LEN: usize = 10_000;
+
+#[derive(serde::Deserialize)]
+struct RoomUsersParam {
+    room_id: Uuid,
+}
+
+/// Info of the creator and every company user of the room, keyed by user id
+async fn room_users(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    config: Config,
+    Json(param): Json<RoomUsersParam>,
+) -> Result<Json<HashMap<Uuid, UserInfo>>> {
+    let room = assert_participant(param.room_id, &user, &mut conn).await?;
+
+    let mut user_ids = company::users_in(room.company_id, &mut conn).await?;
+    user_ids.push(room.user_id);
+
+    let mut infos = UserInfo::for_users(&user_ids, &mut conn).await?;
+    for info in infos.values_mut() {
+        info.resolve_urls(&config);
+    }
+
+    Ok(Json(infos))
+}
+
+#[derive(serde::Deserialize)]
+struct SaveDraftParam {
+    room_id: Uuid,
+    content: String,
+}
+
+async fn save_draft(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Json(param): Json<SaveDraftParam>,
+) -> Result<Json<models::ChatDraft>> {
+    if param.content.chars().count() > MAX_DRAFT_LEN {
+        return Err(WsError::Custom {
+            reason: format!("Drafts cannot be longer than {MAX_DRAFT_LEN} characters"),
+        });
+    }
+
+    let room = assert_participant(param.room_id, &user, &mut conn).await?;
+
+    Ok(Json(
+        models::ChatDraft::save(room.id, user.id, &param.content, &mut conn).await?,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+struct DraftParam {
+    room_id: Uuid,
+}
+
+async fn get_draft(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Json(param): Json<DraftParam>,
+) -> Result<Json<Option<models::ChatDraft>>> {
+    let room = assert_participant(param.room_id, &user, &mut conn).await?;
+
+    Ok(Json(
+        models::ChatDraft::get(room.id, user.id, &mut conn).await?,
+    ))
+}
+
+async fn clear_draft(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Json(param): Json<DraftParam>,
+) -> Result<Json<()>> {
+    let room = assert_participant(param.room_id, &user, &mut conn).await?;
+    models::ChatDraft::clear(room.id, user.id, &mut conn).await?;
+
+    Ok(Json(()))
+}
+
+#[derive(serde::Deserialize)]
+struct ReactionParam {
+    message_id: i64,
+    emoji: String,
+}
+
+async fn react(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    emitter: MsgEmitter,
+    Json(param): Json<ReactionParam>,
+) -> Result<Json<()>> {
+    if !ALLOWED_REACTIONS.contains(&param.emoji.as_str()) {
+        return Err(WsError::Custom {
+            reason: format!("Messages can only be reacted with one of {ALLOWED_REACTIONS:?}"),
+        });
+    }
+
+    let room = message_room(param.message_id, &user, &mut conn).await?;
+    if models::ChatReaction::add(param.message_id, user.id, &param.emoji, &mut conn).await? {
+        send_reaction(&room, user.id, &param, true, emitter, &mut conn).await?;
+    }
+
+    Ok(Json(()))
+}
+
+async fn unreact(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    emitter: MsgEmitter,
+    Json(param): Json<ReactionParam>,
+) -> Result<Json<()>> {
+    let room = message_room(param.message_id, &user, &mut conn).await?;
+    if models::ChatReaction::remove(param.message_id, user.id, &param.emoji, &mut conn).await? {
+        send_reaction(&room, user.id, &param, false, emitter, &mut conn).await?;
+    }
+
+    Ok(Json(()))
+}
+
+/// Returns the room of the message if the user is a member of it
+async fn message_room(
+    message_id: i64,
+    user: &User,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<models::ChatRoom> {
+    let not_found = || WsError::Custom {
+        reason: "Message of this id was not found".into(),
+    };
+
+    let room_id = models::Message::room_id(message_id, conn)
+        .await?
+        .ok_or_else(not_found)?;
+    participant_room(room_id, user, conn)
+        .await?
+        .ok_or_else(not_found)
+}
+
+async fn send_reaction(
+    room: &models::ChatRoom,
+    user_id: Uuid,
+    param: &ReactionParam,
+    added: bool,
+    emitter: MsgEmitter,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> std::result::Result<(), Error> {
+    let members = company::users_in(room.company_id, conn).await?;
+    for id in members.into_iter().chain([room.user_id]) {
+        emitter
+            .send_for_company(
+                room.company_id,
+                id,
+                Some(serde_json::json!({
+                    "kind": "chat.reaction",
+                    "data": {
+                        "room_id": room.id,
+                        "message_id": param.message_id,
+                        "user_id": user_id,
+                        "emoji": param.emoji,
+                        "added": added,
+                    },
+                })),
+                None,
+                conn,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct SetViewingParam {
+    room_id: Option<Uuid>,
+}
+
+/// Clients call this when the room in focus on the page changes, `None` when no room is. Push
+/// notifications of the room in focus are not sent while it stays in focus.
+async fn set_viewing(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    page: SessionWithPage,
+    Json(param): Json<SetViewingParam>,
+) -> Result<Json<()>> {
+    if let Some(room_id) = param.room_id {
+        assert_participant(room_id, &user, &mut conn).await?;
+    }
+
+    page.set_viewing(param.room_id).await?;
+
+    Ok(Json(()))
+}
+
+#[derive(serde::Deserialize)]
+struct TypingParam {
+    room_id: Uuid,
+    is_typing: bool,
+}
+
+/// Clients call this with `is_typing: true` every few seconds while the user is typing. Only
+/// changes are sent to the room, a refresh just pushes back the expiry.
+async fn typing(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    emitter: MsgEmitter,
+    typing: Typing,
+    Json(param): Json<TypingParam>,
+) -> Result<Json<()>> {
+    let room = assert_participant(param.room_id, &user, &mut conn).await?;
+
+    let changed = if param.is_typing {
+        typing.refresh(room.id, user.id)
+    } else {
+        typing.stop(room.id, user.id)
+    };
+    if changed {
+        send_typing(&room, user.id, param.is_typing, emitter, &mut conn).await?;
+    }
+
+    Ok(Json(()))
+}
+
+async fn send_typing(
+    room: &models::ChatRoom,
+    user_id: Uuid,
+    is_typing: bool,
+    emitter: MsgEmitter,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> std::result::Result<(), Error> {
+    let members = company::users_in(room.company_id, conn).await?;
+    for id in members.into_iter().chain([room.user_id]) {
+        if id == user_id {
+            continue;
+        }
+
+        emitter
+            .send_ephemeral(
+                id,
+                serde_json::json!({
+                    "kind": "chat.typing",
+                    "data": {
+                        "room_id": room.id,
+                        "user_id": user_id,
+                        "is_typing": is_typing,
+                    },
+                }),
+                conn,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Clears the typing indicators of clients that stopped refreshing them, like the ones that
+/// disconnected mid-typing, and tells the rooms they stopped
+pub async fn expire_typing_job(state: AppState) {
+    loop {
+        tokio::time::sleep(TYPING_EXPIRY / 4).await;
+
+        for (room_id, user_id) in state.typing().take_expired(TYPING_EXPIRY) {
+            if let Err(err) = expire_typing(state, room_id, user_id).await {
+                tracing::warn!("Failed to clear the typing indicator of {user_id}: {err:?}");
+            }
+        }
+    }
+}
+
+async fn expire_typing(
+    state: AppState,
+    room_id: Uuid,
+    user_id: Uuid,
+) -> std::result::Result<(), Error> {
+    let mut conn = state.get_conn().await?;
+    let Some(room) = models::ChatRoom::from_id(room_id, &mut conn).await? else {
+        return Ok(());
+    };
+
+    send_typing(&room, user_id, false, state.msg_emitter(), &mut conn).await
+}
+
 #[derive(serde::Deserialize)]
 struct SubscribeParam {
     room_id: Uuid,
@@ -201,133 +1100,6 @@ struct SubscribeParam {
 //     }
 // }
 
-// async fn post(
-//     user: models::User,
-//     Json(message): Json<Message>,
-//     DbConn { mut conn }: DbConn,
-//     emitter: MsgEmitter,
-// ) -> Result<()> {
-//     if let Some(room) = models::ChatRoom::from_id(message.room_id, &mut conn).await? {
-//         let mut users = models::CompanyUser::users_in_company(room.company_id, &mut conn).await?;
-//         users.push(room.user_id);
-
-//         if !users.iter().any(|id| *id == user.id) {
-//             return Err(Error::Custom {
-//                 status_code: StatusCode::NOT_FOUND,
-//                 error: "Room of this id was not found".into(),
-//             });
-//         }
-
-//         let message = message.insert(user.id, &mut conn).await?;
-
-//         for id in users {
-//             emitter
-//                 .send(
-//                     id,
-//                     Some(serde_json::json!({
-//                         "kind": "chat.message",
-//                         "data": {
-//                             "room_id": room.id,
-//                             "message": message,
-//                         },
-//                     })),
-//                     None,
-//                     &mut conn,
-//                 )
-//                 .await?;
-//         }
-
-//         todo!("Send every user in the room a message on their websockets")
-//     } else {
-//         Err(Error::Custom {
-//             status_code: StatusCode::NOT_FOUND,
-//             error: "Room of this id was not found".into(),
-//         })
-//     }
-// }
-
-// #[derive(Debug, serde::Deserialize)]
-// struct Message {
-//     room_id: Uuid,
-//     message: String,
-//     extra: Option<MessageExtra>,
-// }
-
-// impl Message {
-//     async fn insert(
-//         self,
-//         user_id: Uuid,
-//         conn: &mut impl AsyncConnection<Backend = Pg>,
-//     ) -> Result<models::Message> {
-//         use crate::schema::chatmessage::dsl as dsl_cm;
-
-//         let message_data = diesel::insert_into(dsl_cm::chatmessage)
-//             .values((
-//                 dsl_cm::room_id.eq(self.room_id),
-//                 dsl_cm::from_user_id.eq(user_id),
-//                 dsl_cm::content.eq(&self.message),
-//             ))
-//             .returning((dsl_cm::id, dsl_cm::created_at))
-//             .load::<(i64, PrimitiveDateTime)>(conn)
-//             .await?;
-//         let (id, created_at) = message_data[0];
-
-//         let extra = if let Some(extra) = self.extra {
-//             let extra = match extra {
-//                 MessageExtra::ContractOfferCreated { payout } => {
-//                     use crate::schema::chatcontractoffer::dsl as dsl_cco;
-
-//                     let offer_ids = diesel::insert_into(dsl_cco::chatcontractoffer)
-//                         .values((
-//                             dsl_cco::message_id.eq(id),
-//                             dsl_cco::offered_payout.eq(Cents(payout)),
-//                         ))
-//                         .returning(dsl_cco::id)
-//                         .load::<i64>(conn)
-//                         .await?;
-
-//                     models::MessageExtra::ContractOfferCreated {
-//                         offer_id: offer_ids[0],
-//                         payout,
-//                     }
-//                 }
-//                 MessageExtra::ContractOfferStatusChange {
-//                     offer_id,
-//                     new_status,
-//                 } => {
-//                     use crate::schema::chatcontractofferupdate::dsl as dsl_ccou;
-
-//                     diesel::insert_into(dsl_ccou::chatcontractofferupdate)
-//                         .values((
-//                             dsl_ccou::message_id.eq(id),
-//                             dsl_ccou::offer_id.eq(offer_id),
-//                             dsl_ccou::update_kind.eq(new_status),
-//                         ))
-//                         .execute(conn)
-//                         .await?;
-
-//                     models::MessageExtra::ContractOfferStatusChange {
-//                         offer_id,
-//                         new_status,
-//                     }
-//                 }
-//             };
-
-//             Some(extra)
-//         } else {
-//             None
-//         };
-
-//         Ok(models::Message {
-//             id,
-//             from_user: user_id,
-//             content: self.message,
-//             created_at,
-//             extra,
-//         })
-//     }
-// }
-
 #[derive(Debug, serde::Deserialize)]
 enum MessageExtra {
     ContractOfferCreated {
@@ -339,6 +1111,173 @@ enum MessageExtra {
     },
 }
 
+/// The room a chat event is about, if it is about one
+pub fn event_room_id(data: Option<&serde_json::Value>) -> Option<Uuid> {
+    data.and_then(|data| data.get("data"))
+        .and_then(|data| data.get("room_id"))
+        .and_then(|room_id| room_id.as_str())
+        .and_then(|room_id| Uuid::parse_str(room_id).ok())
+}
+
+/// Whether an event can still be delivered to the session. Events can sit in a queue for a while
+/// so events about a room are dropped if the user of the session has left it since.
+pub async fn may_deliver(
+    session_token: &str,
+    data: Option<&serde_json::Value>,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> std::result::Result<bool, Error> {
+    let Some(room_id) = event_room_id(data) else {
+        return Ok(true);
+    };
+
+    let Some(user) = UserSession::get_user_by_token(session_token, conn).await? else {
+        return Ok(false);
+    };
+    let Some(room) = models::ChatRoom::from_id(room_id, conn).await? else {
+        return Ok(false);
+    };
+
+    Ok(room.user_id == user.id
+        || company::users_in(room.company_id, conn)
+            .await?
+            .contains(&user.id))
+}
+
 pub fn functions() -> WsFunctions {
-    WsFunctions::default().add(list_rooms).add(create)
+    WsFunctions::default()
+        .add(list_rooms)
+        .add(list_company_rooms)
+        .add(unread_counts)
+        .add(creator_activity)
+        .add(mark_company_seen)
+        .add(create)
+        .add(messages)
+        .add(get_message)
+        .add(post)
+        .add(contract_history)
+        .add(get_offer)
+        .add(room_users)
+        .add(save_draft)
+        .add(get_draft)
+        .add(clear_draft)
+        .add(typing)
+        .add(set_viewing)
+        .add(react)
+        .add(unreact)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn payouts_have_to_be_positive_and_capped() {
+        assert!(check_payout(1).is_ok());
+        assert!(check_payout(MAX_CONTRACT_PAYOUT).is_ok());
+
+        assert!(check_payout(0).is_err());
+        assert!(check_payout(-100).is_err());
+        assert!(check_payout(MAX_CONTRACT_PAYOUT + 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn room_events_are_only_delivered_to_participants() {
+        let mut conn = testing::conn().await;
+        let creator = testing::create_user(&mut conn).await;
+        let owner = testing::create_user(&mut conn).await;
+        let outsider = testing::create_user(&mut conn).await;
+        let company_id = testing::create_company("Deliver Inc", owner, &mut conn).await;
+        let (room_id, _) = models::ChatRoom::create(company_id, creator.id, creator.id, &mut conn)
+            .await
+            .unwrap();
+
+        let event = serde_json::json!({
+            "kind": "chat.new_room",
+            "data": { "room_id": room_id },
+        });
+        let other_event = serde_json::json!({ "kind": "other" });
+
+        for (user, expected) in [(creator, true), (owner, true), (outsider, false)] {
+            let session = testing::create_session(user, &mut conn).await;
+            assert_eq!(
+                may_deliver(&session.token, Some(&event), &mut conn)
+                    .await
+                    .unwrap(),
+                expected
+            );
+            // Events that are not about a room always go through
+            assert!(may_deliver(&session.token, Some(&other_event), &mut conn)
+                .await
+                .unwrap());
+        }
+
+        assert!(!may_deliver("not-a-session", Some(&event), &mut conn)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn room_events_are_dropped_once_the_member_left() {
+        use crate::schema::companyuser::dsl as dsl_cu;
+        use diesel::QueryDsl;
+
+        let mut conn = testing::conn().await;
+        let creator = testing::create_user(&mut conn).await;
+        let owner = testing::create_user(&mut conn).await;
+        let member = testing::create_user(&mut conn).await;
+        let company_id = testing::create_company("Leaving Inc", owner, &mut conn).await;
+        company::add_user(company_id, member, false, &mut conn)
+            .await
+            .unwrap();
+        let (room_id, _) = models::ChatRoom::create(company_id, creator.id, creator.id, &mut conn)
+            .await
+            .unwrap();
+        let session = testing::create_session(member, &mut conn).await;
+
+        // The event is queued while the member is still in the company
+        let event = serde_json::json!({
+            "kind": "chat.new_room",
+            "data": { "room_id": room_id },
+        });
+        assert!(may_deliver(&session.token, Some(&event), &mut conn)
+            .await
+            .unwrap());
+
+        diesel::delete(dsl_cu::companyuser)
+            .filter(dsl_cu::company_id.eq(company_id))
+            .filter(dsl_cu::user_id.eq(member.id))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        assert!(!may_deliver(&session.token, Some(&event), &mut conn)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn rooms_of_others_look_like_missing_rooms() {
+        let mut conn = testing::conn().await;
+        let creator = testing::create_user(&mut conn).await;
+        let owner = testing::create_user(&mut conn).await;
+        let outsider = testing::create_user(&mut conn).await;
+        let company_id = testing::create_company("Hidden Inc", owner, &mut conn).await;
+        let (room_id, _) = models::ChatRoom::create(company_id, creator.id, creator.id, &mut conn)
+            .await
+            .unwrap();
+
+        for user in [creator, owner] {
+            let room = assert_participant(room_id, &user, &mut conn).await.unwrap();
+            assert_eq!(room.id, room_id);
+        }
+
+        let reason = |res: Result<models::ChatRoom>| match res {
+            Err(WsError::Custom { reason }) => reason,
+            Err(err) => panic!("Unexpected error: {err:?}"),
+            Ok(_) => panic!("The room was returned"),
+        };
+        let not_in_room = reason(assert_participant(room_id, &outsider, &mut conn).await);
+        let missing = reason(assert_participant(Uuid::new_v4(), &outsider, &mut conn).await);
+        assert_eq!(not_in_room, missing);
+    }
 }
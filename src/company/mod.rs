@@ -1,79 +1,170 @@
 use axum::{
-    extract::{Multipart, Path},
+    extract::{Multipart, Path, Query, State},
     http::StatusCode,
     routing, Json, Router,
 };
-use fxhash::FxHashMap;
+use diesel_async::scoped_futures::ScopedFutureExt;
 use uuid::Uuid;
 
 use crate::{
-    db::{company, Encoder, User},
-    state::DbConn,
-    storage::Storage,
-    utils::formdata::ImageFileBuilder,
+    db::{
+        company, CompanyNotificationSettings, CreatorProfileQuery, Encoder, IdempotencyKey, User,
+        MAX_DESCRIPTION_CHARS,
+    },
+    state::{AppState, Config, DbConn, MsgEmitter},
+    storage::{public_url, Storage, StoredImages},
+    utils::{
+        etag::{Conditional, ETag, IfNoneMatch},
+        formdata::ImageFileBuilder,
+        validation::ValidationError,
+    },
     Error,
 };
 
 const PROFILE_FIELDS: &'static [&'static str] = &["given_name", "family_name", "pronouns"];
 const COMPANY_FIELDS: &'static [&'static str] = &["full_name", "banner_desc"];
 
+const DEFAULT_MEMBER_PAGE_SIZE: i64 = 50;
+const MAX_MEMBER_PAGE_SIZE: i64 = 200;
+
+#[derive(serde::Deserialize)]
+struct MembersQuery {
+    role: Option<company::MemberRole>,
+    /// Part of the full name of the member, matched case insensitively
+    name: Option<String>,
+    #[serde(default)]
+    order: company::MemberOrder,
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MembersPage {
+    members: Vec<company::CompanyUser>,
+    /// `None` once there are no more members
+    next_offset: Option<i64>,
+    /// Every member matching the role and the name, across all pages
+    total: i64,
+}
+
 async fn list_users(
     user: User,
     DbConn { mut conn }: DbConn,
+    config: Config,
     Path(company_id): Path<Uuid>,
-) -> Result<Json<FxHashMap<Uuid, company::CompanyUser>>, Error> {
+    Query(query): Query<MembersQuery>,
+) -> Result<Json<MembersPage>, Error> {
     if !company::is_admin(company_id, user, &mut conn)
         .await?
         .unwrap_or(false)
     {
-        return Err(Error::Custom {
-            status_code: StatusCode::UNAUTHORIZED,
-            error: "You are not an admin of this company".into(),
+        return Err(Error::Forbidden {
+            reason: "You are not an admin of this company".into(),
         });
     }
 
-    let users = company::CompanyUser::list(company_id, &mut conn).await?;
-
-    Ok(Json(users.collect()))
+    let offset = query.offset.unwrap_or(0).max(0);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_MEMBER_PAGE_SIZE)
+        .clamp(1, MAX_MEMBER_PAGE_SIZE);
+    let name = query
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+
+    let mut members = company::CompanyUser::search(
+        company_id,
+        query.role,
+        name,
+        query.order,
+        offset,
+        limit,
+        &mut conn,
+    )
+    .await?;
+    members.resolve_urls(&config);
+    let next_offset = (members.len() as i64 == limit).then_some(offset + limit);
+
+    Ok(Json(MembersPage {
+        members,
+        next_offset,
+        total: company::CompanyUser::count(company_id, query.role, name, &mut conn).await?,
+    }))
 }
 
 async fn insert_update_user_profile(
     user: User,
-    DbConn { mut conn }: DbConn,
+    mut db: DbConn,
+    idempotency_key: IdempotencyKey,
     storage: Storage,
     multipart: Multipart,
 ) -> Result<(), Error> {
-    let builder = ImageFileBuilder::build(multipart).await?;
+    if let Some(()) = idempotency_key.reserve(user, &mut db.conn).await? {
+        return Ok(());
+    }
 
-    let missing_fields = builder.missing_fields(&PROFILE_FIELDS);
-    if missing_fields.is_empty() {
-        company::UserProfile::insert_update(
-            user,
-            &builder.fields[PROFILE_FIELDS[0]],
-            &builder.fields[PROFILE_FIELDS[1]],
-            &builder.fields[PROFILE_FIELDS[1]],
-            builder.fields.get("pfp_hidden").map(|s| s.as_str()),
-            builder.image,
-            &mut conn,
-            storage,
-        )
-        .await?;
+    let result = async {
+        let builder = ImageFileBuilder::build(multipart, "pfp").await?;
 
-        Ok(())
-    } else {
-        Err(Error::Custom {
-            status_code: StatusCode::BAD_REQUEST,
-            error: format!("Missing fields: {missing_fields:?}"),
-        })
+        let mut errors = builder.required_fields(&PROFILE_FIELDS);
+
+        let remove_pfp = builder
+            .fields
+            .get("remove_pfp")
+            .is_some_and(|remove| remove == "true");
+        if remove_pfp && builder.image.is_some() {
+            errors.add("remove_pfp", "A pfp cannot be uploaded and removed at once");
+        }
+
+        if errors.is_empty() {
+            let idempotency_key = &idempotency_key;
+            db.transaction(|conn| {
+                async move {
+                    company::UserProfile::insert_update(
+                        user,
+                        &builder.fields[PROFILE_FIELDS[0]],
+                        &builder.fields[PROFILE_FIELDS[1]],
+                        &builder.fields[PROFILE_FIELDS[1]],
+                        builder.fields.get("pfp_hidden").map(|s| s.as_str()),
+                        builder.image,
+                        remove_pfp,
+                        conn,
+                        storage,
+                    )
+                    .await?;
+
+                    idempotency_key.record(user, &(), conn).await
+                }
+                .scope_boxed()
+            })
+            .await
+        } else {
+            Err(errors.into())
+        }
     }
+    .await;
+
+    if result.is_err() {
+        idempotency_key.release(user, &mut db.conn).await?;
+    }
+    result
 }
 
 async fn get_user_profile(
     user: User,
     DbConn { mut conn }: DbConn,
-) -> Result<Json<company::UserProfile>, Error> {
+    config: Config,
+    if_none_match: IfNoneMatch,
+) -> Result<Conditional<Json<company::UserProfile>>, Error> {
     match company::UserProfile::get(user, &mut conn).await? {
-        Some(profile) => Ok(Json(profile)),
+        Some(mut profile) => {
+            profile.resolve_urls(&config);
+            let etag = ETag::from_updated_at(user.id, profile.updated_at);
+            Ok(if_none_match.respond(etag, Json(profile)))
+        }
         None => Err(Error::Custom {
             status_code: StatusCode::NOT_FOUND,
             error: "No company user profile found for this user".into(),
@@ -81,98 +172,259 @@ async fn get_user_profile(
     }
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct InsertResponse {
     company_id: Uuid,
 }
 
 async fn insert_company(
     user: User,
-    DbConn { mut conn }: DbConn,
+    mut db: DbConn,
+    idempotency_key: IdempotencyKey,
     encoder: Encoder,
     storage: Storage,
+    config: Config,
     multipart: Multipart,
 ) -> Result<Json<InsertResponse>, Error> {
-    let builder = ImageFileBuilder::build(multipart).await?;
-
-    let missing_fields = builder.missing_fields(&COMPANY_FIELDS);
-    if missing_fields.is_empty() {
-        let company_id = company::CompanyInsertUpdate::insert(
-            &builder.fields[COMPANY_FIELDS[0]],
-            &builder.fields[COMPANY_FIELDS[1]],
-            builder.fields.get("logo_hidden").map(|s| s.as_str()),
-            builder.image,
-            &mut conn,
-            encoder,
-            storage,
-        )
-        .await?;
-
-        if let Err(err) = company::add_user(company_id, user, true, &mut conn).await {
-            company::delete(company_id, &mut conn).await?;
+    if let Some(resp) = idempotency_key.reserve(user, &mut db.conn).await? {
+        return Ok(Json(resp));
+    }
 
-            return Err(err);
+    let result = async {
+        let builder = ImageFileBuilder::build(multipart, "logo").await?;
+
+        let mut errors = builder.required_fields(&COMPANY_FIELDS);
+        builder.check_max_chars(&COMPANY_FIELDS[1..], MAX_DESCRIPTION_CHARS, &mut errors);
+        if errors.is_empty() {
+            // The encoder and a remote logo are waited on before the transaction is opened
+            let full_name = &builder.fields[COMPANY_FIELDS[0]];
+            let banner_desc = &builder.fields[COMPANY_FIELDS[1]];
+            let embedding =
+                company::CompanyInsertUpdate::embed(user, None, banner_desc, &mut db.conn, encoder)
+                    .await?;
+            let logo = storage
+                .prepare_public_image(
+                    builder.fields.get("logo_hidden").map(|s| s.as_str()),
+                    builder.image,
+                )
+                .await?;
+
+            let idempotency_key = &idempotency_key;
+            db.transaction(|conn| {
+                async move {
+                    let company_id = company::CompanyInsertUpdate::insert(
+                        user,
+                        full_name,
+                        banner_desc,
+                        embedding,
+                        logo,
+                        config.unique_company_names,
+                        conn,
+                        storage,
+                    )
+                    .await?;
+
+                    company::add_owner(company_id, user, conn).await?;
+
+                    let resp = InsertResponse { company_id };
+                    idempotency_key.record(user, &resp, conn).await?;
+                    Ok(resp)
+                }
+                .scope_boxed()
+            })
+            .await
+        } else {
+            Err(errors.into())
         }
+    }
+    .await;
 
-        Ok(Json(InsertResponse { company_id }))
-    } else {
-        Err(Error::Custom {
-            status_code: StatusCode::BAD_REQUEST,
-            error: format!("Missing fields: {missing_fields:?}"),
-        })
+    if result.is_err() {
+        idempotency_key.release(user, &mut db.conn).await?;
     }
+    result.map(Json)
 }
 
 async fn update_company(
     user: User,
-    DbConn { mut conn }: DbConn,
+    mut db: DbConn,
     Path(company_id): Path<Uuid>,
     encoder: Encoder,
     storage: Storage,
+    config: Config,
     multipart: Multipart,
 ) -> Result<(), Error> {
-    if !company::is_admin(company_id, user, &mut conn)
+    if !company::is_admin(company_id, user, &mut db.conn)
         .await?
         .unwrap_or(false)
     {
-        return Err(Error::Custom {
-            status_code: StatusCode::UNAUTHORIZED,
-            error: "You are not an admin of this company".into(),
+        return Err(Error::Forbidden {
+            reason: "You are not an admin of this company".into(),
         });
     }
 
-    let builder = ImageFileBuilder::build(multipart).await?;
-
-    let missing_fields = builder.missing_fields(&COMPANY_FIELDS);
-    if missing_fields.is_empty() {
-        company::CompanyInsertUpdate::update(
-            company_id,
-            &builder.fields[COMPANY_FIELDS[0]],
-            &builder.fields[COMPANY_FIELDS[1]],
-            builder.fields.get("logo_hidden").map(|s| s.as_str()),
-            builder.image,
-            &mut conn,
+    let builder = ImageFileBuilder::build(multipart, "logo").await?;
+
+    let mut errors = builder.required_fields(&COMPANY_FIELDS);
+    builder.check_max_chars(&COMPANY_FIELDS[1..], MAX_DESCRIPTION_CHARS, &mut errors);
+    if errors.is_empty() {
+        let full_name = &builder.fields[COMPANY_FIELDS[0]];
+        let banner_desc = &builder.fields[COMPANY_FIELDS[1]];
+        let embedding = company::CompanyInsertUpdate::embed(
+            user,
+            Some(company_id),
+            banner_desc,
+            &mut db.conn,
             encoder,
-            storage,
         )
         .await?;
-
-        Ok(())
+        let logo = storage
+            .prepare_public_image(
+                builder.fields.get("logo_hidden").map(|s| s.as_str()),
+                builder.image,
+            )
+            .await?;
+
+        // In a transaction so the name stays claimed until the company has it
+        db.transaction(|conn| {
+            async move {
+                company::CompanyInsertUpdate::update(
+                    user,
+                    company_id,
+                    full_name,
+                    banner_desc,
+                    embedding,
+                    logo,
+                    config.unique_company_names,
+                    conn,
+                    storage,
+                )
+                .await
+            }
+            .scope_boxed()
+        })
+        .await
     } else {
-        Err(Error::Custom {
+        Err(errors.into())
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RefreshedLogo {
+    logo_url: String,
+}
+
+/// Fetches the logo again from the url it was originally fetched from
+async fn refresh_logo(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    State(state): State<AppState>,
+    Path(company_id): Path<Uuid>,
+    storage: Storage,
+) -> Result<Json<RefreshedLogo>, Error> {
+    if !company::is_admin(company_id, user, &mut conn)
+        .await?
+        .unwrap_or(false)
+    {
+        return Err(Error::Forbidden {
+            reason: "You are not an admin of this company".into(),
+        });
+    }
+
+    state
+        .logo_refresh_limiter()
+        .check(company_id)
+        .map_err(|retry_after| Error::TooManyRequests { retry_after })?;
+
+    match company::refresh_logo(user, company_id, &mut conn, storage).await? {
+        Some(logo_url) => Ok(Json(RefreshedLogo {
+            logo_url: public_url(&state.config(), &logo_url),
+        })),
+        None => Err(Error::Custom {
             status_code: StatusCode::BAD_REQUEST,
-            error: format!("Missing fields: {missing_fields:?}"),
-        })
+            error: "The logo was uploaded so there is no url to refresh it from".into(),
+        }),
     }
 }
 
+const DEFAULT_BROWSE_PAGE_SIZE: i64 = 20;
+const MAX_BROWSE_PAGE_SIZE: i64 = 50;
+
+#[derive(serde::Deserialize)]
+struct BrowseQuery {
+    /// Free text the companies are matched against, the profile of the creator is used without it
+    query: Option<String>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BrowseResults {
+    results: Vec<company::CompanyMinimal>,
+    /// `None` once there are no more results
+    next_offset: Option<i64>,
+}
+
+/// Lets creators look for companies they have not contacted yet
+async fn browse_companies(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    encoder: Encoder,
+    config: Config,
+    Query(query): Query<BrowseQuery>,
+) -> Result<Json<BrowseResults>, Error> {
+    let offset = query.offset.unwrap_or(0).max(0);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_BROWSE_PAGE_SIZE)
+        .clamp(1, MAX_BROWSE_PAGE_SIZE);
+
+    let near = match query.query.as_deref().map(str::trim) {
+        Some(text) if !text.is_empty() => {
+            if text.chars().count() > MAX_DESCRIPTION_CHARS {
+                let mut errors = ValidationError::default();
+                errors.add(
+                    "query",
+                    format!("Cannot be longer than {MAX_DESCRIPTION_CHARS} characters"),
+                );
+                return Err(errors.into());
+            }
+
+            // Companies are embedded through the company template so the query has to be too.
+            // Searching must not use up the budget for saving profiles, so only the request
+            // rate limit applies.
+            let text = encoder.templates().company(text);
+            Some(encoder.encode(text).await?.into())
+        }
+        _ => CreatorProfileQuery::embedding(user, &mut conn).await?,
+    };
+
+    let mut results = company::CompanyMinimal::browse(user, near, offset, limit, &mut conn).await?;
+    results.resolve_urls(&config);
+    let next_offset = (results.len() as i64 == limit).then_some(offset + limit);
+
+    Ok(Json(BrowseResults {
+        results,
+        next_offset,
+    }))
+}
+
 async fn get_companies(
     user: User,
     DbConn { mut conn }: DbConn,
-) -> Result<Json<Vec<company::Company>>, Error> {
-    company::Company::list_for_user(user, &mut conn)
-        .await
-        .map(Json)
+    config: Config,
+    if_none_match: IfNoneMatch,
+) -> Result<Conditional<Json<Vec<company::Company>>>, Error> {
+    let mut companies = company::Company::list_for_user(user, &mut conn).await?;
+    companies.resolve_urls(&config);
+    // The response is aggregated from companies, their users and invites so there is no single
+    // `updated_at` to rely on
+    let etag = ETag::from_json(&companies)?;
+
+    Ok(if_none_match.respond(etag, Json(companies)))
 }
 
 #[derive(serde::Deserialize)]
@@ -182,31 +434,118 @@ struct InviteRequest {
     is_admin: bool,
 }
 
+/// Lets the users the email belongs to know about the invite
+async fn notify_invited(
+    company_id: Uuid,
+    google_email: &str,
+    is_admin: bool,
+    from_user: User,
+    emitter: &MsgEmitter,
+    conn: &mut DbConn,
+) -> Result<(), Error> {
+    for id in company::users_with_email(google_email, &mut conn.conn).await? {
+        emitter
+            .send(
+                id,
+                Some(serde_json::json!({
+                    "kind": "company.invited",
+                    "data": {
+                        "company_id": company_id,
+                        "from_user_id": from_user.id,
+                        "is_admin": is_admin,
+                    },
+                })),
+                None,
+                &mut conn.conn,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
 async fn invite_user_to_company(
     user: User,
-    DbConn { mut conn }: DbConn,
+    mut db: DbConn,
+    emitter: MsgEmitter,
     Json(req): Json<InviteRequest>,
 ) -> Result<(), Error> {
-    if !company::is_admin(req.company_id, user, &mut conn)
+    if !company::is_admin(req.company_id, user, &mut db.conn)
         .await?
         .unwrap_or(false)
     {
-        return Err(Error::Custom {
-            status_code: StatusCode::UNAUTHORIZED,
-            error: "You are not an admin of this company".into(),
+        return Err(Error::Forbidden {
+            reason: "You are not an admin of this company".into(),
         });
     }
 
     company::invite_by_email(
         req.company_id,
-        req.google_email,
+        req.google_email.clone(),
         req.is_admin,
         user,
-        &mut conn,
+        &mut db.conn,
     )
     .await?;
 
-    Ok(())
+    notify_invited(
+        req.company_id,
+        &req.google_email,
+        req.is_admin,
+        user,
+        &emitter,
+        &mut db,
+    )
+    .await
+}
+
+#[derive(serde::Deserialize)]
+struct ResendInviteRequest {
+    google_email: String,
+}
+
+/// Notifies the invitee of a pending invite again
+async fn resend_invite(
+    user: User,
+    mut db: DbConn,
+    emitter: MsgEmitter,
+    State(state): State<AppState>,
+    Path(company_id): Path<Uuid>,
+    Json(req): Json<ResendInviteRequest>,
+) -> Result<(), Error> {
+    if !company::is_admin(company_id, user, &mut db.conn)
+        .await?
+        .unwrap_or(false)
+    {
+        return Err(Error::Forbidden {
+            reason: "You are not an admin of this company".into(),
+        });
+    }
+
+    let Some(is_admin) =
+        company::pending_invite(company_id, &req.google_email, &mut db.conn).await?
+    else {
+        return Err(Error::Custom {
+            status_code: StatusCode::NOT_FOUND,
+            error: format!("There is no pending invite for {}", req.google_email),
+        });
+    };
+
+    state
+        .invite_resend_limiter()
+        .check((company_id, req.google_email.clone()))
+        .map_err(|retry_after| Error::TooManyRequests { retry_after })?;
+
+    company::renew_invite(company_id, &req.google_email, &mut db.conn).await?;
+    notify_invited(
+        company_id,
+        &req.google_email,
+        is_admin,
+        user,
+        &emitter,
+        &mut db,
+    )
+    .await
 }
 
 #[derive(serde::Deserialize)]
@@ -224,9 +563,8 @@ async fn uninvite_user_to_company(
         .await?
         .unwrap_or(false)
     {
-        return Err(Error::Custom {
-            status_code: StatusCode::UNAUTHORIZED,
-            error: "You are not an admin of this company".into(),
+        return Err(Error::Forbidden {
+            reason: "You are not an admin of this company".into(),
         });
     }
 
@@ -235,21 +573,150 @@ async fn uninvite_user_to_company(
     Ok(())
 }
 
+#[derive(serde::Deserialize)]
+struct TransferOwnershipRequest {
+    user_id: Uuid,
+}
+
+async fn transfer_ownership(
+    user: User,
+    mut db: DbConn,
+    emitter: MsgEmitter,
+    Path(company_id): Path<Uuid>,
+    Json(req): Json<TransferOwnershipRequest>,
+) -> Result<(), Error> {
+    if !company::is_owner(company_id, user, &mut db.conn).await? {
+        return Err(Error::Forbidden {
+            reason: "You are not the owner of this company".into(),
+        });
+    }
+    if req.user_id == user.id {
+        return Err(Error::Custom {
+            status_code: StatusCode::BAD_REQUEST,
+            error: "You already own this company".into(),
+        });
+    }
+
+    db.transaction(|conn| {
+        company::transfer_ownership(company_id, user, req.user_id, conn).scope_boxed()
+    })
+    .await?;
+
+    for id in company::users_in(company_id, &mut db.conn).await? {
+        emitter
+            .send_for_company(
+                company_id,
+                id,
+                Some(serde_json::json!({
+                    "kind": "company.ownership_transferred",
+                    "data": {
+                        "company_id": company_id,
+                        "from_user_id": user.id,
+                        "to_user_id": req.user_id,
+                    },
+                })),
+                None,
+                &mut db.conn,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct InvitesQuery<C> {
+    status: Option<company::InviteStatus>,
+    /// `next_cursor` of the previous page
+    after: Option<C>,
+    limit: Option<i64>,
+}
+
+impl<C> InvitesQuery<C> {
+    fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(company::INVITE_PAGE_SIZE)
+            .clamp(1, company::INVITE_PAGE_SIZE)
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InvitesPage<T, C> {
+    invites: Vec<T>,
+    /// `None` once there are no more invites
+    next_cursor: Option<C>,
+    /// Every pending invite, regardless of the page and the status filter
+    pending_count: i64,
+}
+
+/// Invites sent by the company, which every member can see
+async fn list_company_invites(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Path(company_id): Path<Uuid>,
+    Query(query): Query<InvitesQuery<String>>,
+) -> Result<Json<InvitesPage<company::CompanyInvitationMinimal, String>>, Error> {
+    if company::is_admin(company_id, user, &mut conn)
+        .await?
+        .is_none()
+    {
+        return Err(Error::Forbidden {
+            reason: "You are not a member of this company".into(),
+        });
+    }
+
+    let limit = query.limit();
+    let invites = company::CompanyInvitationMinimal::list(
+        company_id,
+        query.status,
+        query.after.as_deref(),
+        limit,
+        &mut conn,
+    )
+    .await?;
+    let next_cursor = (invites.len() as i64 == limit)
+        .then(|| invites.last().map(|invite| invite.google_email.clone()))
+        .flatten();
+
+    Ok(Json(InvitesPage {
+        invites,
+        next_cursor,
+        pending_count: company::CompanyInvitationMinimal::pending_count(company_id, &mut conn)
+            .await?,
+    }))
+}
+
+/// Invites the user received
 async fn get_invites(
     user: User,
     DbConn { mut conn }: DbConn,
-) -> Result<Json<Vec<company::CompanyInvitationDetailed>>, Error> {
-    company::CompanyInvitationDetailed::list(user, &mut conn)
-        .await
-        .map(Json)
+    config: Config,
+    Query(query): Query<InvitesQuery<company::InviteCursor>>,
+) -> Result<Json<InvitesPage<company::CompanyInvitationDetailed, company::InviteCursor>>, Error> {
+    let limit = query.limit();
+    let mut invites =
+        company::CompanyInvitationDetailed::list(user, query.status, query.after, limit, &mut conn)
+            .await?;
+    invites.resolve_urls(&config);
+    let next_cursor = (invites.len() as i64 == limit)
+        .then(|| invites.last().map(|invite| invite.cursor()))
+        .flatten();
+
+    Ok(Json(InvitesPage {
+        invites,
+        next_cursor,
+        pending_count: company::CompanyInvitationDetailed::pending_count(user, &mut conn).await?,
+    }))
 }
 
 async fn accept_invitation(
     user: User,
-    DbConn { mut conn }: DbConn,
+    mut db: DbConn,
     Path(company_id): Path<Uuid>,
 ) -> Result<(), Error> {
-    company::accept_invitation(user, company_id, &mut conn).await
+    db.transaction(|conn| company::accept_invitation(user, company_id, conn).scope_boxed())
+        .await
 }
 
 async fn reject_invitation(
@@ -260,14 +727,108 @@ async fn reject_invitation(
     company::reject_invitation(user, company_id, &mut conn).await
 }
 
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NameAvailableQuery {
+    name: String,
+    /// The company being renamed, its own name is not counted as taken
+    company_id: Option<Uuid>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NameAvailable {
+    available: bool,
+}
+
+/// Only exists when company names have to be unique, see [`Config::unique_company_names`]
+async fn name_available(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    State(state): State<AppState>,
+    Query(query): Query<NameAvailableQuery>,
+) -> Result<Json<NameAvailable>, Error> {
+    if !state.config().unique_company_names {
+        return Err(Error::Custom {
+            status_code: StatusCode::NOT_FOUND,
+            error: "Company names do not have to be unique".into(),
+        });
+    }
+
+    state
+        .name_availability_limiter()
+        .check(user.id)
+        .map_err(|retry_after| Error::TooManyRequests { retry_after })?;
+
+    if query.name.trim().is_empty() {
+        return Err(Error::Custom {
+            status_code: StatusCode::BAD_REQUEST,
+            error: "The name cannot be empty".into(),
+        });
+    }
+
+    let taken =
+        company::CompanyInsertUpdate::is_name_taken(&query.name, query.company_id, &mut conn)
+            .await?;
+
+    Ok(Json(NameAvailable { available: !taken }))
+}
+
+fn company_not_found() -> Error {
+    Error::Custom {
+        status_code: StatusCode::NOT_FOUND,
+        error: "Company of this id was not found".into(),
+    }
+}
+
+/// Notification settings of the user for the company, only members have them
+async fn get_notification_settings(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Path(company_id): Path<Uuid>,
+) -> Result<Json<CompanyNotificationSettings>, Error> {
+    CompanyNotificationSettings::get(company_id, user, &mut conn)
+        .await?
+        .map(Json)
+        .ok_or_else(company_not_found)
+}
+
+async fn set_notification_settings(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Path(company_id): Path<Uuid>,
+    Json(settings): Json<CompanyNotificationSettings>,
+) -> Result<Json<CompanyNotificationSettings>, Error> {
+    if !settings.set(company_id, user, &mut conn).await? {
+        return Err(company_not_found());
+    }
+
+    Ok(Json(settings))
+}
+
 pub fn router() -> Router<crate::state::AppState> {
     Router::new()
         .route("/", routing::get(get_companies).post(insert_company))
+        .route("/browse", routing::get(browse_companies))
+        .route("/name-available", routing::get(name_available))
         .route("/:company-id", routing::patch(update_company))
+        .route("/:company-id/logo/refresh", routing::post(refresh_logo))
         .route("/:company-id/user", routing::get(list_users))
+        .route(
+            "/:company-id/transfer-ownership",
+            routing::post(transfer_ownership),
+        )
         .route(
             "/:company-id/invite",
-            routing::post(invite_user_to_company).delete(uninvite_user_to_company),
+            routing::get(list_company_invites)
+                .post(invite_user_to_company)
+                .delete(uninvite_user_to_company),
+        )
+        .route("/:company-id/invite/resend", routing::post(resend_invite))
+        .nest("/:company-id/webhook", crate::webhook::router())
+        .route(
+            "/:company-id/notifications",
+            routing::get(get_notification_settings).put(set_notification_settings),
         )
         .route(
             "/:company-id/invite/accept",
@@ -1,10 +1,27 @@
-use axum::{extract::Multipart, http::StatusCode, routing, Json, Router};
+pub mod stats;
+
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    http::StatusCode,
+    routing, Json, Router,
+};
+use diesel::data_types::Cents;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use uuid::Uuid;
 
 use crate::{
-    db::{CreatorProfileInsert, CreatorProfileQuery, Encoder, User},
-    state::DbConn,
-    storage::Storage,
-    utils::formdata::ImageFileBuilder,
+    db::{
+        normalize_tags, CreatorGalleryImage, CreatorPlatformStats, CreatorProfileInsert,
+        CreatorProfileQuery, CreatorPublicProfile, Encoder, IdempotencyKey, User, MAX_CREATOR_TAGS,
+        MAX_DESCRIPTION_CHARS, MAX_TAG_CHARS,
+    },
+    models,
+    state::{AppState, Config, DbConn},
+    storage::{public_url, Folder, Storage, StoredImages},
+    utils::{
+        etag::{Conditional, ETag, IfNoneMatch},
+        formdata::ImageFileBuilder,
+    },
     Error,
 };
 
@@ -17,48 +34,178 @@ const PROFILE_FIELDS: &'static [&'static str] = &[
     "audience_desc",
 ];
 
+const MAX_GALLERY_IMAGES: usize = 12;
+
 async fn insert_update_profile(
     user: User,
-    DbConn { mut conn }: DbConn,
+    mut db: DbConn,
+    idempotency_key: IdempotencyKey,
     encoder: Encoder,
     storage: Storage,
     multipart: Multipart,
 ) -> Result<(StatusCode, String), Error> {
-    let builder = ImageFileBuilder::build(multipart).await?;
-
-    let missing_fields = builder.missing_fields(&PROFILE_FIELDS);
-    if missing_fields.is_empty() {
-        CreatorProfileInsert::insert_update(
-            user,
-            &builder.fields[PROFILE_FIELDS[0]],
-            &builder.fields[PROFILE_FIELDS[1]],
-            &builder.fields[PROFILE_FIELDS[2]],
-            &builder.fields[PROFILE_FIELDS[3]],
-            &builder.fields[PROFILE_FIELDS[4]],
-            &builder.fields[PROFILE_FIELDS[5]],
-            builder.fields.get("pfp_hidden").map(|s| s.as_str()),
-            builder.image,
-            &mut conn,
-            encoder,
-            storage,
-        )
+    if let Some(resp) = idempotency_key.reserve(user, &mut db.conn).await? {
+        return Ok((StatusCode::OK, resp));
+    }
+
+    let result = async {
+        let builder = ImageFileBuilder::build(multipart, "pfp").await?;
+
+        let mut errors = builder.required_fields(&PROFILE_FIELDS);
+        builder.check_max_chars(&PROFILE_FIELDS[3..], MAX_DESCRIPTION_CHARS, &mut errors);
+
+        let min_payout = match builder.fields.get("min_payout").map(|cents| cents.trim()) {
+            None | Some("") => None,
+            Some(cents) => match cents.parse::<i64>() {
+                Ok(cents) if cents >= 0 => Some(Cents(cents)),
+                _ => {
+                    errors.add("min_payout", "Must be a non negative amount of cents");
+                    None
+                }
+            },
+        };
+        let min_payout_strict = builder
+            .fields
+            .get("min_payout_strict")
+            .is_some_and(|strict| strict == "true");
+
+        // Comma separated, left unchanged when the field is not sent
+        let tags = builder.fields.get("tags").map(|tags| normalize_tags(tags));
+        if let Some(tags) = &tags {
+            if tags.len() > MAX_CREATOR_TAGS {
+                errors.add(
+                    "tags",
+                    format!("Cannot have more than {MAX_CREATOR_TAGS} tags"),
+                );
+            } else if tags.iter().any(|tag| tag.chars().count() > MAX_TAG_CHARS) {
+                errors.add(
+                    "tags",
+                    format!("A tag cannot be longer than {MAX_TAG_CHARS} characters"),
+                );
+            }
+        }
+
+        let remove_pfp = builder
+            .fields
+            .get("remove_pfp")
+            .is_some_and(|remove| remove == "true");
+        if remove_pfp && builder.image.is_some() {
+            errors.add("remove_pfp", "A pfp cannot be uploaded and removed at once");
+        }
+
+        if errors.is_empty() {
+            let idempotency_key = &idempotency_key;
+            return db
+                .transaction(|conn| {
+                    async move {
+                        CreatorProfileInsert::insert_update(
+                            user,
+                            &builder.fields[PROFILE_FIELDS[0]],
+                            &builder.fields[PROFILE_FIELDS[1]],
+                            &builder.fields[PROFILE_FIELDS[2]],
+                            &builder.fields[PROFILE_FIELDS[3]],
+                            &builder.fields[PROFILE_FIELDS[4]],
+                            &builder.fields[PROFILE_FIELDS[5]],
+                            min_payout,
+                            min_payout_strict,
+                            tags.as_deref(),
+                            builder.fields.get("pfp_hidden").map(|s| s.as_str()),
+                            builder.image,
+                            remove_pfp,
+                            conn,
+                            encoder,
+                            storage,
+                        )
+                        .await?;
+
+                        let resp = String::from("OK");
+                        idempotency_key.record(user, &resp, conn).await?;
+                        Ok(resp)
+                    }
+                    .scope_boxed()
+                })
+                .await;
+        }
+
+        Err(errors.into())
+    }
+    .await;
+
+    if result.is_err() {
+        idempotency_key.release(user, &mut db.conn).await?;
+    }
+    result.map(|resp| (StatusCode::OK, resp))
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdatedPfp {
+    pfp_path: String,
+}
+
+async fn update_pfp(
+    user: User,
+    mut db: DbConn,
+    storage: Storage,
+    config: Config,
+    multipart: Multipart,
+) -> Result<Json<UpdatedPfp>, Error> {
+    let builder = ImageFileBuilder::build(multipart, "pfp").await?;
+    let Some(pfp) = builder.image else {
+        return Err(Error::Custom {
+            status_code: StatusCode::BAD_REQUEST,
+            error: "Missing the pfp".into(),
+        });
+    };
+
+    let pfp = storage
+        .prepare_public_image(None, Some(pfp))
+        .await?
+        .expect("The image was provided so it should have been prepared");
+    let pfp_path = db
+        .transaction(|conn| {
+            CreatorProfileInsert::update_pfp(user, pfp, conn, storage).scope_boxed()
+        })
         .await?;
 
-        return Ok((StatusCode::OK, "OK".into()));
+    match pfp_path {
+        Some(pfp_path) => Ok(Json(UpdatedPfp {
+            pfp_path: public_url(&config, &pfp_path),
+        })),
+        None => Err(Error::Custom {
+            status_code: StatusCode::NOT_FOUND,
+            error: "There is no creator profile informatio for you".into(),
+        }),
     }
+}
 
-    Err(Error::Custom {
-        status_code: StatusCode::BAD_REQUEST,
-        error: format!("Missing fields: {missing_fields:?}"),
-    })
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreatorProfile {
+    #[serde(flatten)]
+    profile: CreatorProfileQuery,
+    gallery: Vec<CreatorGalleryImage>,
+    /// An estimate, see [`CreatorPlatformStats::estimated_reach`]
+    estimated_reach: i64,
 }
 
 async fn get_profile(
     user: User,
     DbConn { mut conn }: DbConn,
-) -> Result<Json<CreatorProfileQuery>, Error> {
+    config: Config,
+    if_none_match: IfNoneMatch,
+) -> Result<Conditional<Json<CreatorProfile>>, Error> {
     if let Some(profile) = CreatorProfileQuery::get(user, &mut conn).await? {
-        return Ok(Json(profile));
+        let mut profile = CreatorProfile {
+            profile,
+            gallery: CreatorGalleryImage::list(user, &mut conn).await?,
+            estimated_reach: CreatorPlatformStats::estimated_reach(user, &mut conn).await?,
+        };
+        profile.profile.resolve_urls(&config);
+        profile.gallery.resolve_urls(&config);
+        // The gallery is not covered by `updated_at` so the tag has to come from the body
+        let etag = ETag::from_json(&profile)?;
+        return Ok(if_none_match.respond(etag, Json(profile)));
     } else {
         Err(Error::Custom {
             status_code: StatusCode::NOT_FOUND,
@@ -67,9 +214,175 @@ async fn get_profile(
     }
 }
 
-pub fn router() -> Router<crate::state::AppState> {
-    Router::new().route(
-        "/profile",
-        routing::get(get_profile).post(insert_update_profile),
-    )
+async fn add_gallery_image(
+    user: User,
+    mut db: DbConn,
+    storage: Storage,
+    config: Config,
+    multipart: Multipart,
+) -> Result<Json<CreatorGalleryImage>, Error> {
+    let builder = ImageFileBuilder::build(multipart, "image").await?;
+    let Some(image) = builder.image else {
+        return Err(Error::Custom {
+            status_code: StatusCode::BAD_REQUEST,
+            error: "Missing the image".into(),
+        });
+    };
+
+    let image = storage.prepare_public_image(None, Some(image)).await?;
+
+    db.transaction(|conn| {
+        async move {
+            // Two uploads could both see room for one more image without the lock
+            CreatorGalleryImage::lock(user, conn).await?;
+            if CreatorGalleryImage::list(user, conn).await?.len() >= MAX_GALLERY_IMAGES {
+                return Err(Error::Custom {
+                    status_code: StatusCode::BAD_REQUEST,
+                    error: format!("A gallery can have at most {MAX_GALLERY_IMAGES} images"),
+                });
+            }
+
+            let id = Uuid::new_v4();
+            let image_path = storage
+                .store_public_image(Folder::Gallery, id, user, image, conn)
+                .await?
+                .expect("The image was provided so it should have been stored");
+
+            CreatorGalleryImage::insert(user, id, &image_path, conn).await
+        }
+        .scope_boxed()
+    })
+    .await
+    .map(|mut image| {
+        image.resolve_urls(&config);
+        Json(image)
+    })
+}
+
+async fn reorder_gallery(
+    user: User,
+    mut db: DbConn,
+    Json(order): Json<Vec<Uuid>>,
+) -> Result<(), Error> {
+    db.transaction(|conn| CreatorGalleryImage::reorder(user, &order, conn).scope_boxed())
+        .await
+}
+
+async fn delete_gallery_image(
+    user: User,
+    mut db: DbConn,
+    storage: Storage,
+    Path(image_id): Path<Uuid>,
+) -> Result<(), Error> {
+    db.transaction(|conn| {
+        async move {
+            if !CreatorGalleryImage::delete(user, image_id, conn).await? {
+                return Err(Error::Custom {
+                    status_code: StatusCode::NOT_FOUND,
+                    error: "There is no gallery image of this id".into(),
+                });
+            }
+
+            storage
+                .delete_public_image(Folder::Gallery, image_id, conn)
+                .await
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Companies the creator already has a room with, so they can be marked or left out of search
+async fn contacted_companies(
+    user: User,
+    DbConn { mut conn }: DbConn,
+) -> Result<Json<Vec<Uuid>>, Error> {
+    Ok(Json(
+        models::ChatRoom::company_ids_for_creator(user.id, &mut conn).await?,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+struct NameAvailableQuery {
+    name: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NameAvailable {
+    available: bool,
+}
+
+async fn name_available(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    State(state): State<AppState>,
+    Query(query): Query<NameAvailableQuery>,
+) -> Result<Json<NameAvailable>, Error> {
+    state
+        .name_availability_limiter()
+        .check(user.id)
+        .map_err(|retry_after| Error::TooManyRequests { retry_after })?;
+
+    if query.name.trim().is_empty() {
+        return Err(Error::Custom {
+            status_code: StatusCode::BAD_REQUEST,
+            error: "The name cannot be empty".into(),
+        });
+    }
+
+    let taken = CreatorProfileQuery::is_name_taken(&query.name, user, &mut conn).await?;
+
+    Ok(Json(NameAvailable { available: !taken }))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfilesParam {
+    user_ids: Vec<Uuid>,
+}
+
+/// Public profiles of several creators at once, e.g. for a page of search results. Ids that are
+/// not creators are left out of the response.
+async fn get_profiles(
+    _user: User,
+    DbConn { mut conn }: DbConn,
+    config: Config,
+    Json(mut param): Json<ProfilesParam>,
+) -> Result<Json<Vec<CreatorPublicProfile>>, Error> {
+    // Keeps the first occurrence so the order of the request is kept
+    let mut seen = std::collections::HashSet::new();
+    param.user_ids.retain(|id| seen.insert(*id));
+
+    if param.user_ids.len() > crate::MAX_BULK_CREATOR_PROFILES {
+        return Err(Error::Custom {
+            status_code: StatusCode::BAD_REQUEST,
+            error: format!(
+                "At most {} profiles can be fetched at once",
+                crate::MAX_BULK_CREATOR_PROFILES
+            ),
+        });
+    }
+
+    let mut profiles = CreatorPublicProfile::list(&param.user_ids, &mut conn).await?;
+    profiles.resolve_urls(&config);
+
+    Ok(Json(profiles))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/profile",
+            routing::get(get_profile).post(insert_update_profile),
+        )
+        .route("/profiles", routing::post(get_profiles))
+        .route("/profile/pfp", routing::patch(update_pfp))
+        .route("/contacted-companies", routing::get(contacted_companies))
+        .route("/name-available", routing::get(name_available))
+        .route(
+            "/gallery",
+            routing::post(add_gallery_image).put(reorder_gallery),
+        )
+        .route("/gallery/:image-id", routing::delete(delete_gallery_image))
 }
@@ -0,0 +1,103 @@
+use diesel::pg::Pg;
+use diesel_async::AsyncConnection;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+use crate::{
+    db::{CreatorPlatformStats, CreatorProfileQuery, GoogleAccount, TwitchAccount, User},
+    google::youtube::Channel,
+    state::AppState,
+    twitch,
+    utils::{AuthenticationHeader, GetDetail},
+    Error,
+};
+
+/// Creators who have not been active for this long are not refreshed
+const ACTIVE_CREATOR_WINDOW: Duration = Duration::days(30);
+/// Pause between the requests for two accounts so a batch stays within the provider quotas
+const ACCOUNT_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Refreshes the stats of active creators one batch per interval, forever
+pub async fn refresh_job(state: AppState) {
+    let stats_refresh = state.config().stats_refresh;
+    let mut after = None;
+
+    loop {
+        tokio::time::sleep(stats_refresh.interval).await;
+
+        match refresh_batch(state, after, stats_refresh.batch_size).await {
+            Ok(last) => after = last,
+            Err(err) => tracing::warn!("Failed to refresh creator stats: {err:?}"),
+        }
+    }
+}
+
+/// Refreshes the creators after `after` and returns where the next batch should start
+async fn refresh_batch(
+    state: AppState,
+    after: Option<Uuid>,
+    batch_size: i64,
+) -> Result<Option<Uuid>, Error> {
+    let mut conn = state.get_conn().await?;
+
+    let since = OffsetDateTime::now_utc() - ACTIVE_CREATOR_WINDOW;
+    let since = PrimitiveDateTime::new(since.date(), since.time());
+    let creators =
+        CreatorProfileQuery::active_creators(after, since, batch_size, &mut conn).await?;
+
+    for &creator in &creators {
+        if let Err(err) = refresh_creator(state, creator, &mut conn).await {
+            tracing::warn!("Failed to refresh the stats of {}: {err:?}", creator.id);
+        }
+    }
+
+    // Start over from the first creator once the end is reached
+    Ok(match creators.last() {
+        Some(last) if creators.len() as i64 == batch_size => Some(last.id),
+        _ => None,
+    })
+}
+
+async fn refresh_creator(
+    state: AppState,
+    creator: User,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<(), Error> {
+    let client = state.http_client();
+    let emitter = state.msg_emitter();
+    let config = state.config();
+
+    // Accounts that need to be linked again are skipped, their tokens cannot be refreshed
+    for mut account in GoogleAccount::list_usable(creator, conn).await? {
+        let headers = account.headers(client, &emitter, &config, conn).await?;
+        for channel in Vec::<Channel>::get(&mut account, client, headers).await? {
+            CreatorPlatformStats {
+                platform: "youtube",
+                platform_id: &channel.id,
+                followers: None,
+                subscribers: channel.statistics.subscriber_count as i64,
+            }
+            .upsert(creator, conn)
+            .await?;
+        }
+
+        tokio::time::sleep(ACCOUNT_DELAY).await;
+    }
+
+    for mut account in TwitchAccount::list_usable(creator, conn).await? {
+        let headers = account.headers(client, &emitter, &config, conn).await?;
+        let details = twitch::Account::get(&mut account, client, headers).await?;
+        CreatorPlatformStats {
+            platform: "twitch",
+            platform_id: &details.id,
+            followers: Some(details.follower_count as i64),
+            subscribers: details.subscriber_count as i64,
+        }
+        .upsert(creator, conn)
+        .await?;
+
+        tokio::time::sleep(ACCOUNT_DELAY).await;
+    }
+
+    Ok(())
+}
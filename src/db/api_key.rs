@@ -0,0 +1,149 @@
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use diesel::{pg::Pg, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+use crate::{state::AppState, Error};
+
+use super::schema;
+
+/// A key a service signs its requests with, see [`crate::service`]
+pub struct ApiKey {
+    pub id: String,
+    pub secret: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+impl ApiKey {
+    /// The key of this id unless it was revoked
+    pub async fn active(
+        id: &str,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Self>, Error> {
+        use schema::apikey::dsl as ak_dsl;
+
+        let key = ak_dsl::apikey
+            .filter(ak_dsl::id.eq(id))
+            .filter(ak_dsl::revoked_at.is_null())
+            .select((ak_dsl::id, ak_dsl::secret, ak_dsl::name, ak_dsl::scopes))
+            .first::<(String, String, String, Vec<String>)>(conn)
+            .await
+            .optional()?;
+
+        Ok(key.map(|(id, secret, name, scopes)| ApiKey {
+            id,
+            secret,
+            name,
+            scopes,
+        }))
+    }
+}
+
+/// Signatures of the signed requests that could still pass the timestamp check. A request
+/// signed at `t` is accepted from `t - SIGNED_REQUEST_MAX_AGE` until `t + SIGNED_REQUEST_MAX_AGE`,
+/// so signatures are kept for twice [`crate::SIGNED_REQUEST_MAX_AGE`].
+pub struct SignedRequest;
+
+impl SignedRequest {
+    /// Records the signature of a verified request, returns false if it was recorded before and
+    /// the request is a replay
+    pub async fn record(
+        key_id: &str,
+        signature: &[u8],
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<bool, Error> {
+        use schema::signedrequest::dsl as sr_dsl;
+
+        let inserted = diesel::insert_into(sr_dsl::signedrequest)
+            .values((sr_dsl::signature.eq(signature), sr_dsl::key_id.eq(key_id)))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        Ok(inserted > 0)
+    }
+
+    pub async fn prune_expired(conn: &mut impl AsyncConnection<Backend = Pg>) -> Result<(), Error> {
+        use schema::signedrequest::dsl as sr_dsl;
+
+        let oldest = OffsetDateTime::now_utc() - crate::SIGNED_REQUEST_MAX_AGE * 2;
+        diesel::delete(sr_dsl::signedrequest)
+            .filter(sr_dsl::recorded_at.lt(PrimitiveDateTime::new(oldest.date(), oldest.time())))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// A service that made a request signed with one of the [`ApiKey`]s. It is not a [`super::User`]
+/// and can only do what its scopes allow.
+#[derive(Debug, Clone)]
+pub struct ServicePrincipal {
+    pub key_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+impl ServicePrincipal {
+    pub fn require_scope(&self, scope: &str) -> Result<(), Error> {
+        if self.scopes.iter().any(|granted| granted == scope) {
+            Ok(())
+        } else {
+            Err(Error::Forbidden {
+                reason: format!("The api key is missing the scope {scope}"),
+            })
+        }
+    }
+}
+
+/// Only set by [`crate::service::verify_signature`] once the signature checked out
+#[async_trait]
+impl FromRequestParts<AppState> for ServicePrincipal {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ServicePrincipal>()
+            .cloned()
+            .ok_or(Error::Unauthenticated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn signatures_are_only_accepted_once() {
+        use schema::apikey::dsl as ak_dsl;
+
+        let mut conn = testing::conn().await;
+        diesel::insert_into(ak_dsl::apikey)
+            .values((
+                ak_dsl::id.eq("replay-key"),
+                ak_dsl::secret.eq("secret"),
+                ak_dsl::name.eq("Replay"),
+            ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let signature = [1, 2, 3];
+        assert!(SignedRequest::record("replay-key", &signature, &mut conn)
+            .await
+            .unwrap());
+        assert!(!SignedRequest::record("replay-key", &signature, &mut conn)
+            .await
+            .unwrap());
+        assert!(SignedRequest::record("replay-key", &[4, 5, 6], &mut conn)
+            .await
+            .unwrap());
+    }
+}
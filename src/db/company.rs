@@ -1,21 +1,22 @@
 use diesel::{
-    deserialize::Queryable, pg::Pg, upsert::excluded, AsChangeset, BoolExpressionMethods,
-    ExpressionMethods, Insertable, JoinOnDsl, OptionalExtension, QueryDsl, Selectable,
-    SelectableHelper,
+    deserialize::Queryable, pg::Pg, sql_types, upsert::excluded, AsChangeset,
+    BoolExpressionMethods, ExpressionMethods, Insertable, JoinOnDsl, OptionalExtension, QueryDsl,
+    QueryableByName, Selectable, SelectableHelper,
 };
 use diesel_async::{AsyncConnection, RunQueryDsl};
-use fxhash::FxHashMap;
 use image::{DynamicImage, ImageFormat};
 use pgvector::Vector;
 use reqwest::StatusCode;
+use time::{OffsetDateTime, PrimitiveDateTime};
 use uuid::Uuid;
 
 use crate::{
-    storage::{Folder, Storage},
+    state::Config,
+    storage::{fill_default_avatar, Folder, PreparedImage, Storage, StoredImages},
     Error,
 };
 
-use super::{schema, Encoder, User};
+use super::{normalize_name, schema, Encoder, User};
 
 #[derive(Clone, Insertable, AsChangeset)]
 #[diesel(table_name = schema::company)]
@@ -28,21 +29,97 @@ pub struct CompanyInsertUpdate<'c> {
 }
 
 impl<'c> CompanyInsertUpdate<'c> {
-    fn format_creator_descriptions(banner_desc: &str) -> String {
-        format!("Question: Who are we?\nAnswer: {banner_desc}")
+    /// Checks if a company other than `except` already has this name, ignoring case and
+    /// whitespace, see the `normalize_name` SQL function
+    pub async fn is_name_taken(
+        full_name: &str,
+        except: Option<Uuid>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<bool, Error> {
+        use schema::company::dsl as c_dsl;
+
+        let mut query = c_dsl::company
+            .filter(normalize_name(c_dsl::full_name).eq(normalize_name(full_name)))
+            .into_boxed();
+        if let Some(except) = except {
+            query = query.filter(c_dsl::id.ne(except));
+        }
+
+        Ok(diesel::select(diesel::dsl::exists(query))
+            .get_result(conn)
+            .await?)
     }
 
-    pub async fn insert(
+    /// Fails with a conflict if the name is taken. Has to run in a transaction, the name stays
+    /// locked until it ends so two companies cannot take it at once.
+    async fn claim_name(
         full_name: &str,
+        except: Option<Uuid>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext(normalize_name($1)))")
+            .bind::<sql_types::Text, _>(full_name)
+            .execute(conn)
+            .await?;
+
+        if Self::is_name_taken(full_name, except, conn).await? {
+            return Err(Error::Custom {
+                status_code: StatusCode::CONFLICT,
+                error: "A company with this name already exists".into(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The embedding of the banner of a new company, or of `company_id` which keeps its
+    /// embedding if the banner has not changed. Called before the transaction of
+    /// [`CompanyInsertUpdate::insert`] or [`CompanyInsertUpdate::update`] as encoding can take
+    /// a while.
+    pub async fn embed(
+        user: User,
+        company_id: Option<Uuid>,
         banner_desc: &str,
-        logo_hidden: Option<&str>,
-        logo: Option<(DynamicImage, ImageFormat)>,
         conn: &mut impl AsyncConnection<Backend = Pg>,
         encoder: Encoder,
+    ) -> Result<Vector, Error> {
+        use schema::company::dsl as c_dsl;
+
+        if let Some(company_id) = company_id {
+            let existing = c_dsl::company
+                .filter(c_dsl::id.eq(company_id))
+                .select((c_dsl::banner_desc, c_dsl::embedding))
+                .first::<(String, Vector)>(conn)
+                .await
+                .optional()?;
+
+            // Only re-embed when the text that goes into the embedding has changed
+            if let Some((old_banner_desc, embedding)) = existing
+                && old_banner_desc == banner_desc
+            {
+                return Ok(embedding);
+            }
+        }
+
+        let embedding_desc = encoder.templates().company(banner_desc);
+        Ok(encoder.encode_for(user, embedding_desc).await?.into())
+    }
+
+    /// Rejects names other companies already have if `unique_name` is set, see
+    /// [`CompanyInsertUpdate::claim_name`]
+    pub async fn insert(
+        user: User,
+        full_name: &str,
+        banner_desc: &str,
+        embedding: Vector,
+        logo: Option<PreparedImage>,
+        unique_name: bool,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
         storage: Storage,
     ) -> Result<Uuid, Error> {
-        let embedding_desc = CompanyInsertUpdate::format_creator_descriptions(banner_desc);
-        let embedding = encoder.encode(embedding_desc).await?;
+        if unique_name {
+            Self::claim_name(full_name, None, conn).await?;
+        }
 
         use schema::company::dsl as c_dsl;
 
@@ -51,7 +128,7 @@ impl<'c> CompanyInsertUpdate<'c> {
                 full_name,
                 banner_desc,
                 logo_url: None,
-                embedding: embedding.into(),
+                embedding,
             })
             .returning(c_dsl::id)
             .load(conn)
@@ -59,13 +136,17 @@ impl<'c> CompanyInsertUpdate<'c> {
             .pop()
             .expect("No company id was returned");
 
+        let logo_source = logo.as_ref().and_then(|logo| logo.source_url.clone());
         let logo_path = storage
-            .store_public_image(Folder::Logo, company_id, logo_hidden, logo)
+            .store_public_image(Folder::Logo, company_id, user, logo, conn)
             .await?;
 
         if let Some(logo_path) = logo_path {
             diesel::update(c_dsl::company)
-                .set(c_dsl::logo_url.eq(logo_path))
+                .set((
+                    c_dsl::logo_url.eq(logo_path),
+                    c_dsl::logo_source_url.eq(logo_source),
+                ))
                 .filter(c_dsl::id.eq(company_id))
                 .execute(conn)
                 .await?;
@@ -74,23 +155,27 @@ impl<'c> CompanyInsertUpdate<'c> {
         Ok(company_id)
     }
 
+    /// Like [`CompanyInsertUpdate::insert`] the name is only checked if `unique_name` is set
     pub async fn update(
+        user: User,
         company_id: Uuid,
         full_name: &str,
         banner_desc: &str,
-        logo_hidden: Option<&str>,
-        logo: Option<(DynamicImage, ImageFormat)>,
+        embedding: Vector,
+        logo: Option<PreparedImage>,
+        unique_name: bool,
         conn: &mut impl AsyncConnection<Backend = Pg>,
-        encoder: Encoder,
         storage: Storage,
     ) -> Result<(), Error> {
-        let embedding_desc = CompanyInsertUpdate::format_creator_descriptions(banner_desc);
-        let embedding = encoder.encode(embedding_desc).await?;
-
         use schema::company::dsl as c_dsl;
 
+        if unique_name {
+            Self::claim_name(full_name, Some(company_id), conn).await?;
+        }
+
+        let logo_source = logo.as_ref().and_then(|logo| logo.source_url.clone());
         let logo_path = storage
-            .store_public_image(Folder::Logo, company_id, logo_hidden, logo)
+            .store_public_image(Folder::Logo, company_id, user, logo, conn)
             .await?;
 
         diesel::update(c_dsl::company)
@@ -98,24 +183,81 @@ impl<'c> CompanyInsertUpdate<'c> {
                 full_name,
                 banner_desc,
                 logo_url: logo_path.as_deref(),
-                embedding: embedding.into(),
+                embedding,
             })
             .filter(c_dsl::id.eq(company_id))
             .execute(conn)
             .await?;
 
+        if logo_path.is_some() {
+            diesel::update(c_dsl::company)
+                .set(c_dsl::logo_source_url.eq(logo_source))
+                .filter(c_dsl::id.eq(company_id))
+                .execute(conn)
+                .await?;
+        }
+
         Ok(())
     }
 }
 
+/// Fetches the logo again from the url it was last fetched from. Returns `None` if the logo of
+/// the company was uploaded rather than fetched.
+pub async fn refresh_logo(
+    user: User,
+    company_id: Uuid,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+    storage: Storage,
+) -> Result<Option<String>, Error> {
+    use schema::company::dsl as c_dsl;
+
+    let Some(source_url) = c_dsl::company
+        .filter(c_dsl::id.eq(company_id))
+        .select(c_dsl::logo_source_url)
+        .first::<Option<String>>(conn)
+        .await
+        .optional()?
+        .flatten()
+    else {
+        return Ok(None);
+    };
+
+    let logo = storage
+        .prepare_public_image(Some(&source_url), None)
+        .await?;
+    let logo_path = storage
+        .store_public_image(Folder::Logo, company_id, user, logo, conn)
+        .await?
+        .expect("The source url is never empty so an image is always stored");
+
+    diesel::update(c_dsl::company)
+        .set(c_dsl::logo_url.eq(&logo_path))
+        .filter(c_dsl::id.eq(company_id))
+        .execute(conn)
+        .await?;
+
+    Ok(Some(logo_path))
+}
+
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Company {
     pub id: Uuid,
     pub full_name: String,
     pub banner_desc: String,
     pub logo_url: String,
-    pub users: FxHashMap<Uuid, CompanyUser>,
+    /// Ordered by [`CompanyUser::list`]
+    pub users: Vec<CompanyUser>,
+    /// The first page of invites ordered by email, see [`CompanyInvitationMinimal::list`]
     pub invites: Vec<CompanyInvitationMinimal>,
+    pub pending_invites: i64,
+}
+
+impl StoredImages for Company {
+    fn resolve_urls(&mut self, config: &Config) {
+        self.logo_url.resolve_urls(config);
+        self.users.resolve_urls(config);
+    }
 }
 
 impl Company {
@@ -146,8 +288,10 @@ impl Company {
                 full_name,
                 banner_desc,
                 logo_url,
-                users: CompanyUser::list(id, conn).await?.into_iter().collect(),
-                invites: CompanyInvitationMinimal::list(id, conn).await?,
+                users: CompanyUser::list(id, conn).await?,
+                invites: CompanyInvitationMinimal::list(id, None, None, INVITE_PAGE_SIZE, conn)
+                    .await?,
+                pending_invites: CompanyInvitationMinimal::pending_count(id, conn).await?,
             });
         }
 
@@ -156,6 +300,7 @@ impl Company {
 }
 
 #[derive(Queryable, Selectable, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 #[diesel(table_name = schema::company)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 
@@ -166,6 +311,54 @@ pub struct CompanyMinimal {
     pub logo_url: String,
 }
 
+impl StoredImages for CompanyMinimal {
+    fn resolve_urls(&mut self, config: &Config) {
+        self.logo_url.resolve_urls(config);
+    }
+}
+
+impl CompanyMinimal {
+    /// Companies the user is not a member of and has no room with, closest to `near` first or
+    /// newest first without it
+    pub async fn browse(
+        user: User,
+        near: Option<Vector>,
+        offset: i64,
+        limit: i64,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        use pgvector::VectorExpressionMethods;
+        use schema::chatroom::dsl as cr_dsl;
+        use schema::company::dsl as c_dsl;
+        use schema::companyuser::dsl as cu_dsl;
+
+        let member_of = cu_dsl::companyuser
+            .filter(cu_dsl::user_id.eq(user.id))
+            .select(cu_dsl::company_id);
+        let chatting_with = cr_dsl::chatroom
+            .filter(cr_dsl::user_id.eq(user.id))
+            .select(cr_dsl::company_id);
+
+        let query = c_dsl::company
+            .filter(diesel::dsl::not(c_dsl::id.eq_any(member_of)))
+            .filter(diesel::dsl::not(c_dsl::id.eq_any(chatting_with)))
+            .select(CompanyMinimal::as_select())
+            .into_boxed();
+        let query = match near {
+            // Matches the `vector_ip_ops` index of the embeddings
+            Some(near) => query.order_by(c_dsl::embedding.max_inner_product(near)),
+            None => query.order_by(c_dsl::created_at.desc()),
+        };
+
+        Ok(query
+            .then_order_by(c_dsl::id)
+            .offset(offset)
+            .limit(limit)
+            .load(conn)
+            .await?)
+    }
+}
+
 pub async fn users_in(
     company_id: Uuid,
     conn: &mut impl AsyncConnection<Backend = Pg>,
@@ -179,6 +372,31 @@ pub async fn users_in(
         .await?)
 }
 
+#[derive(Queryable, Selectable, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[diesel(table_name = schema::companyuser)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CompanyMembership {
+    pub company_id: Uuid,
+    pub is_admin: bool,
+    pub is_owner: bool,
+}
+
+impl CompanyMembership {
+    pub async fn list_for_user(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        use schema::companyuser::dsl as cu_dsl;
+
+        Ok(cu_dsl::companyuser
+            .filter(cu_dsl::user_id.eq(user.id))
+            .select(CompanyMembership::as_select())
+            .load(conn)
+            .await?)
+    }
+}
+
 pub async fn delete(
     company_id: Uuid,
     conn: &mut impl AsyncConnection<Backend = Pg>,
@@ -213,16 +431,155 @@ pub async fn add_user(
     Ok(())
 }
 
+/// Adds the user who created the company as its owner
+pub async fn add_owner(
+    company_id: Uuid,
+    user: User,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<(), Error> {
+    use schema::companyuser::dsl as cu_dsl;
+
+    diesel::insert_into(cu_dsl::companyuser)
+        .values((
+            cu_dsl::company_id.eq(company_id),
+            cu_dsl::user_id.eq(user.id),
+            cu_dsl::is_admin.eq(true),
+            cu_dsl::is_owner.eq(true),
+        ))
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Makes `to_user_id` the owner of the company and demotes `from` to an admin.
+///
+/// Must be run inside a transaction so a failed promotion does not leave the company ownerless.
+pub async fn transfer_ownership(
+    company_id: Uuid,
+    from: User,
+    to_user_id: Uuid,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<(), Error> {
+    use schema::companyuser::dsl as cu_dsl;
+
+    // Demoting first since `company_single_owner` allows only a single owner at a time
+    diesel::update(cu_dsl::companyuser)
+        .filter(
+            cu_dsl::company_id
+                .eq(company_id)
+                .and(cu_dsl::user_id.eq(from.id)),
+        )
+        .set((cu_dsl::is_owner.eq(false), cu_dsl::is_admin.eq(true)))
+        .execute(conn)
+        .await?;
+
+    let promoted = diesel::update(cu_dsl::companyuser)
+        .filter(
+            cu_dsl::company_id
+                .eq(company_id)
+                .and(cu_dsl::user_id.eq(to_user_id)),
+        )
+        .set((cu_dsl::is_owner.eq(true), cu_dsl::is_admin.eq(true)))
+        .execute(conn)
+        .await?;
+
+    if promoted == 0 {
+        return Err(Error::Custom {
+            status_code: StatusCode::NOT_FOUND,
+            error: "The new owner must already be a member of this company".into(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Invites listed at once unless asked for fewer
+pub const INVITE_PAGE_SIZE: i64 = 50;
+
+/// Invites are pending until [`crate::INVITE_EXPIRY`] has passed since they were sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InviteStatus {
+    Pending,
+    Expired,
+}
+
+impl InviteStatus {
+    /// Invites sent before this have expired
+    fn expiry_cutoff() -> PrimitiveDateTime {
+        let cutoff = OffsetDateTime::now_utc() - crate::INVITE_EXPIRY;
+        PrimitiveDateTime::new(cutoff.date(), cutoff.time())
+    }
+
+    fn of(invited_at: PrimitiveDateTime) -> Self {
+        if invited_at < Self::expiry_cutoff() {
+            InviteStatus::Expired
+        } else {
+            InviteStatus::Pending
+        }
+    }
+}
+
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CompanyInvitationDetailed {
     from: UserProfile,
     company: CompanyMinimal,
     is_admin: bool,
+    status: InviteStatus,
+    #[serde(skip)]
+    invited_email: String,
+}
+
+/// Where a page of [`CompanyInvitationDetailed::list`] ends. A user with several emails can be
+/// invited to the same company more than once so the email is part of it. Sent to clients as
+/// `{company_id}:{email}`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct InviteCursor {
+    company_id: Uuid,
+    invited_email: String,
+}
+
+impl TryFrom<String> for InviteCursor {
+    type Error = String;
+
+    fn try_from(cursor: String) -> Result<Self, Self::Error> {
+        let (company_id, invited_email) = cursor
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid invite cursor: {cursor}"))?;
+
+        Ok(InviteCursor {
+            company_id: company_id
+                .parse()
+                .map_err(|err| format!("Invalid company id in the invite cursor: {err}"))?,
+            invited_email: invited_email.into(),
+        })
+    }
+}
+
+impl From<InviteCursor> for String {
+    fn from(cursor: InviteCursor) -> Self {
+        format!("{}:{}", cursor.company_id, cursor.invited_email)
+    }
+}
+
+impl StoredImages for CompanyInvitationDetailed {
+    fn resolve_urls(&mut self, config: &Config) {
+        self.from.resolve_urls(config);
+        self.company.resolve_urls(config);
+    }
 }
 
 impl CompanyInvitationDetailed {
+    /// Invites to the emails of the user ordered by company and email, starting after the
+    /// invite `after`
     pub async fn list(
         user: User,
+        status: Option<InviteStatus>,
+        after: Option<InviteCursor>,
+        limit: i64,
         conn: &mut impl AsyncConnection<Backend = Pg>,
     ) -> Result<Vec<Self>, Error> {
         use schema::company::dsl as c_dsl;
@@ -230,40 +587,114 @@ impl CompanyInvitationDetailed {
         use schema::companyuserprofile::dsl as cup_dsl;
         use schema::googleaccount::dsl as ga_dsl;
 
-        let accounts = ga_dsl::googleaccount
+        let mut query = ga_dsl::googleaccount
             .filter(ga_dsl::user_id.eq(user.id))
             .inner_join(
                 cui_dsl::companyuserinvitation.on(ga_dsl::email.eq(cui_dsl::invited_google_email)),
             )
             .inner_join(c_dsl::company.on(c_dsl::id.eq(cui_dsl::company_id)))
             .inner_join(cup_dsl::companyuserprofile.on(cup_dsl::user_id.eq(cui_dsl::from_user_id)))
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(
+                cui_dsl::company_id
+                    .gt(after.company_id)
+                    .or(cui_dsl::company_id
+                        .eq(after.company_id)
+                        .and(cui_dsl::invited_google_email.gt(after.invited_email))),
+            );
+        }
+        match status {
+            Some(InviteStatus::Pending) => {
+                query = query.filter(cui_dsl::invited_at.ge(InviteStatus::expiry_cutoff()))
+            }
+            Some(InviteStatus::Expired) => {
+                query = query.filter(cui_dsl::invited_at.lt(InviteStatus::expiry_cutoff()))
+            }
+            None => {}
+        }
+
+        let accounts = query
+            .order_by((
+                cui_dsl::company_id.asc(),
+                cui_dsl::invited_google_email.asc(),
+            ))
+            .limit(limit)
             .select((
                 UserProfile::as_select(),
                 CompanyMinimal::as_select(),
                 cui_dsl::will_be_given_admin,
+                cui_dsl::invited_at,
+                cui_dsl::invited_google_email,
             ))
-            .load::<(UserProfile, CompanyMinimal, bool)>(conn)
+            .load::<(UserProfile, CompanyMinimal, bool, PrimitiveDateTime, String)>(conn)
             .await?
             .into_iter()
-            .map(|(from, company, is_admin)| CompanyInvitationDetailed {
-                from,
-                company,
-                is_admin,
-            })
+            .map(
+                |(from, company, is_admin, invited_at, invited_email)| CompanyInvitationDetailed {
+                    from,
+                    company,
+                    is_admin,
+                    status: InviteStatus::of(invited_at),
+                    invited_email,
+                },
+            )
             .collect();
 
         Ok(accounts)
     }
+
+    /// The invite to continue listing after
+    pub fn cursor(&self) -> InviteCursor {
+        InviteCursor {
+            company_id: self.company.id,
+            invited_email: self.invited_email.clone(),
+        }
+    }
+
+    /// How many invites to the emails of the user have not expired
+    pub async fn pending_count(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<i64, Error> {
+        use schema::companyuserinvitation::dsl as cui_dsl;
+        use schema::googleaccount::dsl as ga_dsl;
+
+        Ok(ga_dsl::googleaccount
+            .filter(ga_dsl::user_id.eq(user.id))
+            .inner_join(
+                cui_dsl::companyuserinvitation.on(ga_dsl::email.eq(cui_dsl::invited_google_email)),
+            )
+            .filter(cui_dsl::invited_at.ge(InviteStatus::expiry_cutoff()))
+            .count()
+            .get_result(conn)
+            .await?)
+    }
 }
 
 #[derive(Debug, Queryable, Selectable, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 #[diesel(table_name = schema::companyuserprofile)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct UserProfile {
     pub given_name: String,
     pub family_name: String,
     pub pronouns: String,
-    pub pfp_path: String,
+    pub pfp_path: Option<String>,
+    #[serde(skip)]
+    pub updated_at: PrimitiveDateTime,
+}
+
+impl StoredImages for UserProfile {
+    fn resolve_urls(&mut self, config: &Config) {
+        fill_default_avatar(
+            config.default_avatar,
+            &mut self.pfp_path,
+            &self.given_name,
+            &self.family_name,
+        );
+        self.pfp_path.resolve_urls(config);
+    }
 }
 
 impl UserProfile {
@@ -274,19 +705,27 @@ impl UserProfile {
         pronouns: &str,
         pfp_hidden: Option<&str>,
         pfp: Option<(DynamicImage, ImageFormat)>,
+        remove_pfp: bool,
         conn: &mut impl AsyncConnection<Backend = Pg>,
         storage: Storage,
     ) -> Result<(), Error> {
         use schema::companyuserprofile::dsl as cup_dsl;
 
-        let Some(pfp_path) = storage
-            .store_public_image(Folder::ProfilePicture, user.id, pfp_hidden, pfp)
-            .await?
-        else {
-            return Err(Error::Custom {
-                status_code: StatusCode::BAD_REQUEST,
-                error: "Missing pfp picture".into(),
-            });
+        let pfp_path = if remove_pfp {
+            super::remove_profile_picture(user, storage, conn).await?;
+            None
+        } else {
+            let pfp = storage.prepare_public_image(pfp_hidden, pfp).await?;
+            let Some(pfp_path) = storage
+                .store_public_image(Folder::ProfilePicture, user.id, user, pfp, conn)
+                .await?
+            else {
+                return Err(Error::Custom {
+                    status_code: StatusCode::BAD_REQUEST,
+                    error: "Missing pfp picture".into(),
+                });
+            };
+            Some(pfp_path)
         };
 
         diesel::insert_into(cup_dsl::companyuserprofile)
@@ -329,41 +768,83 @@ impl UserProfile {
 }
 
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CompanyInvitationMinimal {
     pub google_email: String,
     pub is_admin: bool,
     pub from_user: Uuid,
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub invited_at: PrimitiveDateTime,
+    pub status: InviteStatus,
 }
 
 impl CompanyInvitationMinimal {
+    /// Up to `limit` invites of the company ordered by email, starting after the email `after`
     pub async fn list(
         company_id: Uuid,
+        status: Option<InviteStatus>,
+        after: Option<&str>,
+        limit: i64,
         conn: &mut impl AsyncConnection<Backend = Pg>,
     ) -> Result<Vec<Self>, Error> {
         use schema::companyuserinvitation::dsl as cui_dsl;
 
-        let mut invites = Vec::default();
-
-        let recorded_invites = cui_dsl::companyuserinvitation
+        let mut query = cui_dsl::companyuserinvitation
             .filter(cui_dsl::company_id.eq(company_id))
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(cui_dsl::invited_google_email.gt(after));
+        }
+        match status {
+            Some(InviteStatus::Pending) => {
+                query = query.filter(cui_dsl::invited_at.ge(InviteStatus::expiry_cutoff()))
+            }
+            Some(InviteStatus::Expired) => {
+                query = query.filter(cui_dsl::invited_at.lt(InviteStatus::expiry_cutoff()))
+            }
+            None => {}
+        }
+
+        let invites = query
+            .order_by(cui_dsl::invited_google_email.asc())
+            .limit(limit)
             .select((
                 cui_dsl::invited_google_email,
                 cui_dsl::will_be_given_admin,
                 cui_dsl::from_user_id,
+                cui_dsl::invited_at,
             ))
-            .load::<(String, bool, Uuid)>(conn)
-            .await?;
-
-        for (google_email, is_admin, from_user) in recorded_invites {
-            invites.push(CompanyInvitationMinimal {
-                google_email,
-                is_admin,
-                from_user,
-            });
-        }
+            .load::<(String, bool, Uuid, PrimitiveDateTime)>(conn)
+            .await?
+            .into_iter()
+            .map(
+                |(google_email, is_admin, from_user, invited_at)| CompanyInvitationMinimal {
+                    google_email,
+                    is_admin,
+                    from_user,
+                    invited_at,
+                    status: InviteStatus::of(invited_at),
+                },
+            )
+            .collect();
 
         Ok(invites)
     }
+
+    /// How many invites of the company have not expired
+    pub async fn pending_count(
+        company_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<i64, Error> {
+        use schema::companyuserinvitation::dsl as cui_dsl;
+
+        Ok(cui_dsl::companyuserinvitation
+            .filter(cui_dsl::company_id.eq(company_id))
+            .filter(cui_dsl::invited_at.ge(InviteStatus::expiry_cutoff()))
+            .count()
+            .get_result(conn)
+            .await?)
+    }
 }
 
 pub async fn invite_by_email(
@@ -388,6 +869,57 @@ pub async fn invite_by_email(
     Ok(())
 }
 
+/// Whether the pending invite for the email gives admin rights, or `None` if there is no invite
+pub async fn pending_invite(
+    company_id: Uuid,
+    google_email: &str,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<Option<bool>, Error> {
+    use schema::companyuserinvitation::dsl as cui_dsl;
+
+    Ok(cui_dsl::companyuserinvitation
+        .filter(cui_dsl::company_id.eq(company_id))
+        .filter(cui_dsl::invited_google_email.eq(google_email))
+        .select(cui_dsl::will_be_given_admin)
+        .first(conn)
+        .await
+        .optional()?)
+}
+
+/// Sends the invite again from now so it is pending for another [`crate::INVITE_EXPIRY`]
+pub async fn renew_invite(
+    company_id: Uuid,
+    google_email: &str,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<(), Error> {
+    use schema::companyuserinvitation::dsl as cui_dsl;
+
+    let now = OffsetDateTime::now_utc();
+    diesel::update(cui_dsl::companyuserinvitation)
+        .filter(cui_dsl::company_id.eq(company_id))
+        .filter(cui_dsl::invited_google_email.eq(google_email))
+        .set(cui_dsl::invited_at.eq(PrimitiveDateTime::new(now.date(), now.time())))
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Users who linked a google account with this email
+pub async fn users_with_email(
+    google_email: &str,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<Vec<Uuid>, Error> {
+    use schema::googleaccount::dsl as ga_dsl;
+
+    Ok(ga_dsl::googleaccount
+        .filter(ga_dsl::email.eq(google_email))
+        .select(ga_dsl::user_id)
+        .distinct()
+        .load(conn)
+        .await?)
+}
+
 pub async fn uninvite_by_email(
     company_id: Uuid,
     google_email: String,
@@ -450,7 +982,8 @@ pub async fn accept_invitation(
         });
     }
 
-    // TODO: A error in this query will erase the invitations without adding the user to the company
+    // Callers run this in a transaction so an error here won't erase the invitations without
+    // adding the user to the company
     use schema::companyuser::dsl as cu_dsl;
     diesel::insert_into(cu_dsl::companyuser)
         .values((
@@ -506,6 +1039,27 @@ pub async fn reject_invitation(
     Ok(())
 }
 
+pub async fn is_owner(
+    company_id: Uuid,
+    user: User,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<bool, Error> {
+    use schema::companyuser::dsl as cu_dsl;
+
+    let is_owner = cu_dsl::companyuser
+        .filter(
+            cu_dsl::company_id
+                .eq(company_id)
+                .and(cu_dsl::user_id.eq(user.id)),
+        )
+        .select(cu_dsl::is_owner)
+        .first(conn)
+        .await
+        .optional()?;
+
+    Ok(is_owner.unwrap_or(false))
+}
+
 pub async fn is_admin(
     company_id: Uuid,
     user: User,
@@ -527,26 +1081,177 @@ pub async fn is_admin(
     Ok(is_admin)
 }
 
-#[derive(serde::Serialize)]
+#[derive(QueryableByName, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CompanyUser {
+    #[diesel(sql_type = sql_types::Uuid)]
+    pub user_id: Uuid,
+    #[diesel(sql_type = sql_types::Text)]
     pub given_name: String,
+    #[diesel(sql_type = sql_types::Text)]
     pub family_name: String,
+    #[diesel(sql_type = sql_types::Text)]
     pub pronouns: String,
-    pub pfp_path: String,
+    #[diesel(sql_type = sql_types::Nullable<sql_types::Text>)]
+    pub pfp_path: Option<String>,
+    #[diesel(sql_type = sql_types::Bool)]
     pub is_admin: bool,
+    #[diesel(sql_type = sql_types::Bool)]
+    pub is_owner: bool,
+    #[diesel(sql_type = sql_types::Timestamp)]
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub joined_at: PrimitiveDateTime,
+}
+
+impl StoredImages for CompanyUser {
+    fn resolve_urls(&mut self, config: &Config) {
+        fill_default_avatar(
+            config.default_avatar,
+            &mut self.pfp_path,
+            &self.given_name,
+            &self.family_name,
+        );
+        self.pfp_path.resolve_urls(config);
+    }
+}
+
+/// The highest role of a member, owners are admins too
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MemberRole {
+    Owner,
+    /// Admins that are not the owner
+    Admin,
+    Member,
+}
+
+impl MemberRole {
+    fn as_str(self) -> &'static str {
+        match self {
+            MemberRole::Owner => "owner",
+            MemberRole::Admin => "admin",
+            MemberRole::Member => "member",
+        }
+    }
+}
+
+/// How [`CompanyUser::search`] orders the members, ties are broken by user id
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MemberOrder {
+    /// The owner first, then admins, then everyone else by name
+    #[default]
+    Role,
+    Name,
+    /// Longest members first
+    Joined,
+}
+
+impl MemberOrder {
+    fn order_by(self) -> &'static str {
+        match self {
+            MemberOrder::Role => {
+                "cu.is_owner DESC, cu.is_admin DESC, cup.given_name, cup.family_name, cu.user_id"
+            }
+            MemberOrder::Name => "cup.given_name, cup.family_name, cu.user_id",
+            MemberOrder::Joined => "cu.joined_at, cu.user_id",
+        }
+    }
+}
+
+/// Members of the company `$1` with the role `$2` whose name contains `$3`, either can be null
+const MEMBERS_SQL: &str = "FROM CompanyUser cu \
+    JOIN CompanyUserProfile cup ON cup.user_id = cu.user_id \
+    JOIN InnerUser u ON u.id = cu.user_id AND u.deleted_at IS NULL \
+    WHERE cu.company_id = $1 \
+    AND ($2::TEXT IS NULL \
+        OR ($2 = 'owner' AND cu.is_owner) \
+        OR ($2 = 'admin' AND cu.is_admin AND NOT cu.is_owner) \
+        OR ($2 = 'member' AND NOT cu.is_admin)) \
+    AND ($3::TEXT IS NULL OR cup.given_name || ' ' || cup.family_name ILIKE '%' || $3 || '%')";
+
+/// Escapes the wildcards of `ILIKE` so the name is matched literally
+fn escape_like(name: &str) -> String {
+    name.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
 }
 
 impl CompanyUser {
+    /// A page of the members of the company that have the role and whose name contains `name`
+    pub async fn search(
+        company_id: Uuid,
+        role: Option<MemberRole>,
+        name: Option<&str>,
+        order: MemberOrder,
+        offset: i64,
+        limit: i64,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        let members = diesel::sql_query(format!(
+            "SELECT cu.user_id, cup.given_name, cup.family_name, cup.pronouns, cup.pfp_path, \
+                cu.is_admin, cu.is_owner, cu.joined_at \
+            {MEMBERS_SQL} \
+            ORDER BY {} \
+            OFFSET $4 LIMIT $5",
+            order.order_by(),
+        ))
+        .bind::<sql_types::Uuid, _>(company_id)
+        .bind::<sql_types::Nullable<sql_types::Text>, _>(role.map(MemberRole::as_str))
+        .bind::<sql_types::Nullable<sql_types::Text>, _>(name.map(escape_like))
+        .bind::<sql_types::BigInt, _>(offset)
+        .bind::<sql_types::BigInt, _>(limit)
+        .load::<Self>(conn)
+        .await?;
+
+        Ok(members)
+    }
+
+    /// How many members [`CompanyUser::search`] pages through
+    pub async fn count(
+        company_id: Uuid,
+        role: Option<MemberRole>,
+        name: Option<&str>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<i64, Error> {
+        #[derive(QueryableByName)]
+        struct Count {
+            #[diesel(sql_type = sql_types::BigInt)]
+            count: i64,
+        }
+
+        Ok(
+            diesel::sql_query(format!("SELECT COUNT(*) AS count {MEMBERS_SQL}"))
+                .bind::<sql_types::Uuid, _>(company_id)
+                .bind::<sql_types::Nullable<sql_types::Text>, _>(role.map(MemberRole::as_str))
+                .bind::<sql_types::Nullable<sql_types::Text>, _>(name.map(escape_like))
+                .get_result::<Count>(conn)
+                .await?
+                .count,
+        )
+    }
+
+    /// Users of the company with the owner first, then admins, then everyone else by name
     pub async fn list(
         company_id: Uuid,
         conn: &mut impl AsyncConnection<Backend = Pg>,
-    ) -> Result<impl Iterator<Item = (Uuid, Self)>, Error> {
+    ) -> Result<Vec<Self>, Error> {
         use schema::companyuser::dsl as cu_dsl;
         use schema::companyuserprofile::dsl as cup_dsl;
+        use schema::inneruser::dsl as iu_dsl;
 
         Ok(cu_dsl::companyuser
             .filter(cu_dsl::company_id.eq(company_id))
             .inner_join(cup_dsl::companyuserprofile.on(cu_dsl::user_id.eq(cup_dsl::user_id)))
+            .inner_join(iu_dsl::inneruser.on(iu_dsl::id.eq(cu_dsl::user_id)))
+            .filter(iu_dsl::deleted_at.is_null())
+            .order_by((
+                cu_dsl::is_owner.desc(),
+                cu_dsl::is_admin.desc(),
+                cup_dsl::given_name.asc(),
+                cup_dsl::family_name.asc(),
+                cup_dsl::user_id.asc(),
+            ))
             .select((
                 cup_dsl::user_id,
                 cup_dsl::given_name,
@@ -554,23 +1259,332 @@ impl CompanyUser {
                 cup_dsl::pronouns,
                 cup_dsl::pfp_path,
                 cu_dsl::is_admin,
+                cu_dsl::is_owner,
+                cu_dsl::joined_at,
             ))
-            .load::<(Uuid, String, String, String, String, bool)>(conn)
+            .load::<(
+                Uuid,
+                String,
+                String,
+                String,
+                Option<String>,
+                bool,
+                bool,
+                PrimitiveDateTime,
+            )>(conn)
             .await?
             .into_iter()
             .map(
-                |(id, given_name, family_name, pronouns, pfp_path, is_admin)| {
-                    (
-                        id,
-                        CompanyUser {
-                            given_name,
-                            family_name,
-                            pronouns,
-                            pfp_path,
-                            is_admin,
-                        },
-                    )
+                |(
+                    user_id,
+                    given_name,
+                    family_name,
+                    pronouns,
+                    pfp_path,
+                    is_admin,
+                    is_owner,
+                    joined_at,
+                )| {
+                    CompanyUser {
+                        user_id,
+                        given_name,
+                        family_name,
+                        pronouns,
+                        pfp_path,
+                        is_admin,
+                        is_owner,
+                        joined_at,
+                    }
                 },
+            )
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{StoredFile, EMBEDDING_DIMENSION},
+        testing,
+    };
+
+    fn embedding() -> Vector {
+        Vector::from(vec![0.0; EMBEDDING_DIMENSION])
+    }
+
+    fn is_conflict<T>(res: &Result<T, Error>) -> bool {
+        matches!(
+            res,
+            Err(Error::Custom {
+                status_code: StatusCode::CONFLICT,
+                ..
+            })
+        )
+    }
+
+    #[tokio::test]
+    async fn insert_rejects_taken_names_only_when_unique() {
+        let mut conn = testing::conn().await;
+        let owner = testing::create_user(&mut conn).await;
+        testing::create_company("Acme  Corp", owner, &mut conn).await;
+
+        let res = CompanyInsertUpdate::insert(
+            owner,
+            " acme corp",
+            "",
+            embedding(),
+            None,
+            true,
+            &mut conn,
+            testing::storage(),
+        )
+        .await;
+        assert!(is_conflict(&res));
+
+        CompanyInsertUpdate::insert(
+            owner,
+            " acme corp",
+            "",
+            embedding(),
+            None,
+            false,
+            &mut conn,
+            testing::storage(),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn update_rejects_names_of_other_companies_only_when_unique() {
+        let mut conn = testing::conn().await;
+        let owner = testing::create_user(&mut conn).await;
+        testing::create_company("Acme Corp", owner, &mut conn).await;
+        let company_id = testing::create_company("Other Corp", owner, &mut conn).await;
+
+        // Keeping its own name is not a conflict
+        CompanyInsertUpdate::update(
+            owner,
+            company_id,
+            "OTHER corp",
+            "",
+            embedding(),
+            None,
+            true,
+            &mut conn,
+            testing::storage(),
+        )
+        .await
+        .unwrap();
+
+        let res = CompanyInsertUpdate::update(
+            owner,
+            company_id,
+            "acme corp",
+            "",
+            embedding(),
+            None,
+            true,
+            &mut conn,
+            testing::storage(),
+        )
+        .await;
+        assert!(is_conflict(&res));
+
+        CompanyInsertUpdate::update(
+            owner,
+            company_id,
+            "acme corp",
+            "",
+            embedding(),
+            None,
+            false,
+            &mut conn,
+            testing::storage(),
+        )
+        .await
+        .unwrap();
+        assert!(
+            CompanyInsertUpdate::is_name_taken("Acme Corp", Some(company_id), &mut conn)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn companies_serialize_as_camel_case() {
+        let mut conn = testing::conn().await;
+        let owner = testing::create_user(&mut conn).await;
+        testing::create_company("Camel Corp", owner, &mut conn).await;
+
+        let companies = Company::list_for_user(owner, &mut conn).await.unwrap();
+        let json = serde_json::to_value(&companies[0]).unwrap();
+        let json = json.as_object().unwrap();
+
+        for key in ["fullName", "bannerDesc", "logoUrl", "pendingInvites"] {
+            assert!(json.contains_key(key), "{key} is missing");
+        }
+        assert!(!json.keys().any(|key| key.contains('_')));
+    }
+
+    #[tokio::test]
+    async fn remove_pfp_clears_the_stored_picture() {
+        let mut conn = testing::conn().await;
+        let user = testing::create_user(&mut conn).await;
+        let pfp = (DynamicImage::new_rgb8(1, 1), ImageFormat::Png);
+
+        UserProfile::insert_update(
+            user,
+            "Given",
+            "Family",
+            "",
+            None,
+            Some(pfp),
+            false,
+            &mut conn,
+            testing::storage(),
+        )
+        .await
+        .unwrap();
+        let profile = UserProfile::get(user, &mut conn).await.unwrap().unwrap();
+        assert!(profile.pfp_path.is_some());
+        let stored = StoredFile::get(Folder::ProfilePicture.name(), user.id, &mut conn)
+            .await
+            .unwrap()
+            .unwrap();
+
+        UserProfile::insert_update(
+            user,
+            "Given",
+            "Family",
+            "",
+            None,
+            None,
+            true,
+            &mut conn,
+            testing::storage(),
+        )
+        .await
+        .unwrap();
+        let profile = UserProfile::get(user, &mut conn).await.unwrap().unwrap();
+        assert_eq!(profile.pfp_path, None);
+        assert!(
+            StoredFile::get(Folder::ProfilePicture.name(), user.id, &mut conn)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(!std::path::Path::new(&stored.path).exists());
+    }
+
+    #[test]
+    fn invite_cursors_round_trip() {
+        let company_id = Uuid::new_v4();
+        // Only the first colon separates the company from the email
+        let cursor = format!("\"{company_id}:odd:name@example.com\"");
+
+        let parsed: InviteCursor = serde_json::from_str(&cursor).unwrap();
+        assert_eq!(parsed.company_id, company_id);
+        assert_eq!(parsed.invited_email, "odd:name@example.com");
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), cursor);
+
+        assert!(serde_json::from_str::<InviteCursor>("\"not-a-cursor\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn invites_are_paged_by_company_and_email() {
+        use schema::companyuserinvitation::dsl as cui_dsl;
+        use schema::companyuserprofile::dsl as cup_dsl;
+        use schema::googleaccount::dsl as ga_dsl;
+
+        let mut conn = testing::conn().await;
+        let invited = testing::create_user(&mut conn).await;
+        let inviter = testing::create_user(&mut conn).await;
+        diesel::insert_into(cup_dsl::companyuserprofile)
+            .values((
+                cup_dsl::user_id.eq(inviter.id),
+                cup_dsl::given_name.eq("Given"),
+                cup_dsl::family_name.eq("Family"),
+                cup_dsl::pronouns.eq(""),
             ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let now = OffsetDateTime::now_utc();
+        let now = PrimitiveDateTime::new(now.date(), now.time());
+        let emails = [
+            format!("{}-a@example.com", invited.id),
+            format!("{}-b@example.com", invited.id),
+        ];
+        for email in &emails {
+            diesel::insert_into(ga_dsl::googleaccount)
+                .values((
+                    ga_dsl::sub.eq(email),
+                    ga_dsl::email.eq(email),
+                    ga_dsl::access_token.eq(""),
+                    ga_dsl::expires_at.eq(now),
+                    ga_dsl::refresh_token.eq(""),
+                    ga_dsl::user_id.eq(invited.id),
+                    ga_dsl::refreshed_at.eq(now),
+                    ga_dsl::reauth_required.eq(false),
+                ))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+
+        let first = testing::create_company("First Invites", inviter, &mut conn).await;
+        let second = testing::create_company("Second Invites", inviter, &mut conn).await;
+        let mut expected = Vec::new();
+        for company_id in [first, second] {
+            for email in &emails {
+                // The last invite has expired
+                let invited_at = if company_id == second && email == &emails[1] {
+                    now - crate::INVITE_EXPIRY - time::Duration::days(1)
+                } else {
+                    now
+                };
+                diesel::insert_into(cui_dsl::companyuserinvitation)
+                    .values((
+                        cui_dsl::invited_google_email.eq(email),
+                        cui_dsl::company_id.eq(company_id),
+                        cui_dsl::will_be_given_admin.eq(false),
+                        cui_dsl::from_user_id.eq(inviter.id),
+                        cui_dsl::invited_at.eq(invited_at),
+                    ))
+                    .execute(&mut conn)
+                    .await
+                    .unwrap();
+                expected.push((company_id, email.clone(), invited_at == now));
+            }
+        }
+        expected.sort();
+
+        for status in [None, Some(InviteStatus::Pending)] {
+            let mut listed = Vec::new();
+            let mut after = None;
+            loop {
+                let page = CompanyInvitationDetailed::list(invited, status, after, 3, &mut conn)
+                    .await
+                    .unwrap();
+                let Some(last) = page.last() else {
+                    break;
+                };
+                after = Some(last.cursor());
+                listed.extend(
+                    page.iter()
+                        .map(|invite| (invite.company.id, invite.invited_email.clone())),
+                );
+            }
+
+            let expected = expected
+                .iter()
+                .filter(|(_, _, pending)| status.is_none() || *pending)
+                .map(|(company_id, email, _)| (*company_id, email.clone()))
+                .collect::<Vec<_>>();
+            assert_eq!(listed, expected);
+        }
     }
 }
@@ -5,7 +5,133 @@ use candle_transformers::models::bert::{BertModel, Config, HiddenAct, DTYPE};
 use hf_hub::{api::tokio::Api, Repo};
 use tokenizers::Tokenizer;
 
-use crate::Error;
+use crate::{state::HttpTimeouts, Error};
+
+/// Dimension of the `embedding` columns, every encoder has to produce vectors of this length
+pub const EMBEDDING_DIMENSION: usize = 1536;
+
+/// Longest description that is accepted. Inputs that still go over the token limit of the encoder
+/// are truncated by it, see [`truncate_tokens`].
+pub const MAX_DESCRIPTION_CHARS: usize = 2_500;
+
+/// Cuts tokens that end with the separator added by the tokenizer down to `max_tokens`, keeping
+/// the separator as the last token. Returns if anything was cut off.
+fn truncate_tokens(tokens: &mut Vec<u32>, max_tokens: usize) -> bool {
+    if tokens.len() <= max_tokens {
+        return false;
+    }
+
+    let sep = tokens[tokens.len() - 1];
+    tokens.truncate(max_tokens - 1);
+    tokens.push(sep);
+
+    true
+}
+
+/// Panics if an `embedding` column does not have [`EMBEDDING_DIMENSION`] dimensions
+pub fn assert_column_dimensions(conn: &mut diesel::PgConnection) {
+    use diesel::{
+        sql_types::{Integer, Text},
+        QueryableByName, RunQueryDsl,
+    };
+
+    #[derive(QueryableByName)]
+    struct Column {
+        #[diesel(sql_type = Text)]
+        table_name: String,
+        #[diesel(sql_type = Integer)]
+        dimension: i32,
+    }
+
+    // The type modifier of a pgvector column is its dimension
+    let columns = diesel::sql_query(
+        "SELECT attrelid::regclass::text AS table_name, atttypmod AS dimension FROM pg_attribute \
+        WHERE attname = 'embedding' AND attrelid IN ('CreatorProfile'::regclass, 'Company'::regclass)",
+    )
+    .load::<Column>(conn)
+    .expect("Failed to get the dimensions of the embedding columns");
+
+    for column in columns {
+        assert_eq!(
+            column.dimension as usize, EMBEDDING_DIMENSION,
+            "The embedding column of {} has {} dimensions but the encoders produce {EMBEDDING_DIMENSION}",
+            column.table_name, column.dimension,
+        );
+    }
+}
+
+/// Text that is embedded for creators and companies. Every embedding that is compared against
+/// another has to be made from text in the same format, so this is the only place it is built.
+///
+/// Changing a template does not re-embed existing rows, they are only re-embedded when their
+/// descriptions change.
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddingTemplates {
+    /// Has to contain `{profile}`, `{content}` and `{audience}`
+    pub creator: &'static str,
+    /// Has to contain `{banner}`
+    pub company: &'static str,
+}
+
+impl EmbeddingTemplates {
+    pub const DEFAULT_CREATOR: &'static str = "Question: Who am I?\nAnswer: {profile}\n\nQuestion: What do I make?\nAnswer: {content}\n\nQuestion: Who watches my content?\nAnswer: {audience}";
+    pub const DEFAULT_COMPANY: &'static str = "Question: Who are we?\nAnswer: {banner}";
+
+    /// Returns the placeholders missing from the templates
+    pub fn missing_placeholders(&self) -> Vec<&'static str> {
+        let creator = ["{profile}", "{content}", "{audience}"]
+            .into_iter()
+            .filter(|placeholder| !self.creator.contains(placeholder));
+        let company = ["{banner}"]
+            .into_iter()
+            .filter(|placeholder| !self.company.contains(placeholder));
+
+        creator.chain(company).collect()
+    }
+
+    /// Fills in the placeholders in a single pass so that text which looks like a placeholder
+    /// inside a description is left alone
+    fn fill(template: &str, values: &[(&str, &str)]) -> String {
+        let mut filled = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            filled.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            match values
+                .iter()
+                .find(|(placeholder, _)| rest.starts_with(placeholder))
+            {
+                Some((placeholder, value)) => {
+                    filled.push_str(value);
+                    rest = &rest[placeholder.len()..];
+                }
+                None => {
+                    filled.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        filled.push_str(rest);
+
+        filled
+    }
+
+    pub fn creator(&self, profile: &str, content: &str, audience: &str) -> String {
+        Self::fill(
+            self.creator,
+            &[
+                ("{profile}", profile),
+                ("{content}", content),
+                ("{audience}", audience),
+            ],
+        )
+    }
+
+    pub fn company(&self, banner: &str) -> String {
+        Self::fill(self.company, &[("{banner}", banner)])
+    }
+}
 
 pub enum EmbeddingEncoder {
     Voyage {
@@ -15,11 +141,13 @@ pub enum EmbeddingEncoder {
         device: Device,
         model: BertModel,
         tokenizer: Tokenizer,
+        /// Longest sequence of tokens the model has position embeddings for
+        max_tokens: usize,
     },
 }
 
 impl EmbeddingEncoder {
-    pub async fn new_voyage() -> Result<Self, Error> {
+    pub async fn new_voyage(timeouts: HttpTimeouts) -> Result<Self, Error> {
         let key = dotenvy::var("VOYAGE_API_KEY").map_err(|_| Error::Custom {
             status_code: StatusCode::INTERNAL_SERVER_ERROR,
             error: "Missing enviorment variable VOYAGE_API_KEY".into(),
@@ -32,9 +160,7 @@ impl EmbeddingEncoder {
                 .expect("Failed to create the bearer header"),
         );
 
-        let client = reqwest::ClientBuilder::new()
-            .default_headers(headers)
-            .build()?;
+        let client = timeouts.client_builder().default_headers(headers).build()?;
 
         Ok(EmbeddingEncoder::Voyage { client })
     }
@@ -72,6 +198,7 @@ impl EmbeddingEncoder {
             device,
             model,
             tokenizer,
+            max_tokens: config.max_position_embeddings,
         })
     }
 
@@ -85,6 +212,8 @@ impl EmbeddingEncoder {
                             text,
                         ],
                         "model": "voyage-large-2",
+                        // Inputs over the context length are cut off instead of failing
+                        "truncation": true,
                     }))
                     .build()?;
 
@@ -123,13 +252,22 @@ impl EmbeddingEncoder {
                 device,
                 model,
                 tokenizer,
+                max_tokens,
             } => {
                 let encoded = tokio::task::spawn_blocking::<_, Result<_, Error>>(move || {
-                    let tokens = tokenizer
+                    let mut tokens = tokenizer
                         .encode(text, true)
                         .map_err(Error::TokenizerError)?
                         .get_ids()
                         .to_vec();
+                    let len = tokens.len();
+                    if truncate_tokens(&mut tokens, *max_tokens) {
+                        tracing::warn!(
+                            tokens = len,
+                            max_tokens,
+                            "Truncated text that is too long for the embedding model"
+                        );
+                    }
                     let token_ids = Tensor::new(&tokens[..], &device)?.unsqueeze(0)?;
                     let token_type_ids = token_ids.zeros_like()?;
 
@@ -146,3 +284,23 @@ impl EmbeddingEncoder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncation_keeps_the_separator() {
+        const CLS: u32 = 0;
+        const SEP: u32 = 2;
+
+        let mut tokens = vec![CLS, 10, 11, 12, 13, SEP];
+        assert!(truncate_tokens(&mut tokens, 4));
+        assert_eq!(tokens, vec![CLS, 10, 11, SEP]);
+
+        // Inputs within the limit are left alone
+        let mut tokens = vec![CLS, 10, SEP];
+        assert!(!truncate_tokens(&mut tokens, 3));
+        assert_eq!(tokens, vec![CLS, 10, SEP]);
+    }
+}
@@ -0,0 +1,313 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, OriginalUri},
+    http::{request::Parts, HeaderName, StatusCode},
+};
+use diesel::{pg::Pg, BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+use crate::{state::AppState, Error};
+
+use super::{schema, User};
+
+const IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+const MAX_KEY_LEN: usize = 255;
+
+/// The `Idempotency-Key` header of a request. Responses of requests made with a key are
+/// recorded per user and endpoint for [`crate::IDEMPOTENCY_KEY_DURATION`] so retries replay the
+/// original response instead of repeating the write.
+pub struct IdempotencyKey(Option<Key>);
+
+struct Key {
+    endpoint: String,
+    key: String,
+}
+
+impl IdempotencyKey {
+    fn ago(duration: time::Duration) -> PrimitiveDateTime {
+        let ago = OffsetDateTime::now_utc() - duration;
+        PrimitiveDateTime::new(ago.date(), ago.time())
+    }
+
+    fn oldest_valid() -> PrimitiveDateTime {
+        Self::ago(crate::IDEMPOTENCY_KEY_DURATION)
+    }
+
+    /// Reservations made before this were abandoned, see [`IdempotencyKey::reserve`]
+    fn oldest_in_progress() -> PrimitiveDateTime {
+        Self::ago(crate::IDEMPOTENCY_KEY_PROCESSING_TIMEOUT)
+    }
+
+    /// Reserves the key before the request is processed. Returns the recorded response if a
+    /// request with this key was already processed and errors while it is still in progress.
+    ///
+    /// A successful request records its response with [`IdempotencyKey::record`] and a failed
+    /// one calls [`IdempotencyKey::release`]. Reservations that get neither, because the
+    /// request was dropped or panicked, expire after
+    /// [`crate::IDEMPOTENCY_KEY_PROCESSING_TIMEOUT`].
+    pub async fn reserve<T: serde::de::DeserializeOwned>(
+        &self,
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<T>, Error> {
+        let Some(Key { endpoint, key }) = &self.0 else {
+            return Ok(None);
+        };
+
+        use schema::idempotencykey::dsl as ik_dsl;
+
+        // An expired key or abandoned reservation can be reused before it is pruned
+        diesel::delete(ik_dsl::idempotencykey)
+            .filter(ik_dsl::user_id.eq(user.id))
+            .filter(ik_dsl::endpoint.eq(endpoint))
+            .filter(ik_dsl::key.eq(key))
+            .filter(
+                ik_dsl::created_at
+                    .le(Self::oldest_valid())
+                    .or(ik_dsl::response
+                        .is_null()
+                        .and(ik_dsl::created_at.le(Self::oldest_in_progress()))),
+            )
+            .execute(conn)
+            .await?;
+
+        let reserved = diesel::insert_into(ik_dsl::idempotencykey)
+            .values((
+                ik_dsl::user_id.eq(user.id),
+                ik_dsl::endpoint.eq(endpoint),
+                ik_dsl::key.eq(key),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+        if reserved == 1 {
+            return Ok(None);
+        }
+
+        let response = ik_dsl::idempotencykey
+            .filter(ik_dsl::user_id.eq(user.id))
+            .filter(ik_dsl::endpoint.eq(endpoint))
+            .filter(ik_dsl::key.eq(key))
+            .select(ik_dsl::response)
+            .first::<Option<String>>(conn)
+            .await
+            .optional()?;
+
+        match response {
+            Some(Some(response)) => Ok(Some(serde_json::from_str(&response)?)),
+            Some(None) => Err(Error::Custom {
+                status_code: StatusCode::CONFLICT,
+                error: "A request with this Idempotency-Key is still being processed".into(),
+            }),
+            // The reservation was released between the insert and the select
+            None => Err(Error::Custom {
+                status_code: StatusCode::CONFLICT,
+                error: "A request with this Idempotency-Key was just retried".into(),
+            }),
+        }
+    }
+
+    /// Records the response of a request reserved with [`IdempotencyKey::reserve`]. Has to run in
+    /// the transaction of the write, so the response is kept exactly when the write is. Fails if
+    /// the reservation has expired in the meantime, which rolls the write back.
+    pub async fn record<T: serde::Serialize>(
+        &self,
+        user: User,
+        response: &T,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        let Some(Key { endpoint, key }) = &self.0 else {
+            return Ok(());
+        };
+
+        use schema::idempotencykey::dsl as ik_dsl;
+
+        let recorded = diesel::update(ik_dsl::idempotencykey)
+            .filter(ik_dsl::user_id.eq(user.id))
+            .filter(ik_dsl::endpoint.eq(endpoint))
+            .filter(ik_dsl::key.eq(key))
+            .filter(ik_dsl::response.is_null())
+            .set(ik_dsl::response.eq(serde_json::to_string(response)?))
+            .execute(conn)
+            .await?;
+        if recorded == 0 {
+            return Err(Error::Custom {
+                status_code: StatusCode::CONFLICT,
+                error: "The Idempotency-Key expired while the request was being processed".into(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Releases the key of a failed request so it can be retried with it
+    pub async fn release(
+        &self,
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        let Some(Key { endpoint, key }) = &self.0 else {
+            return Ok(());
+        };
+
+        use schema::idempotencykey::dsl as ik_dsl;
+
+        diesel::delete(ik_dsl::idempotencykey)
+            .filter(ik_dsl::user_id.eq(user.id))
+            .filter(ik_dsl::endpoint.eq(endpoint))
+            .filter(ik_dsl::key.eq(key))
+            .filter(ik_dsl::response.is_null())
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn prune_expired(conn: &mut impl AsyncConnection<Backend = Pg>) -> Result<(), Error> {
+        use schema::idempotencykey::dsl as ik_dsl;
+
+        diesel::delete(ik_dsl::idempotencykey)
+            .filter(
+                ik_dsl::created_at
+                    .lt(Self::oldest_valid())
+                    .or(ik_dsl::response
+                        .is_null()
+                        .and(ik_dsl::created_at.lt(Self::oldest_in_progress()))),
+            )
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// The method and full path of the request. Nested routers only see the rest of the path, which
+/// other endpoints can share, so the original uri is used.
+fn endpoint(parts: &Parts) -> String {
+    let path = match parts.extensions.get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path(),
+        None => parts.uri.path(),
+    };
+
+    format!("{} {path}", parts.method)
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for IdempotencyKey {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let endpoint = endpoint(parts);
+
+        let Some(key) = parts.headers.get(IDEMPOTENCY_KEY_HEADER) else {
+            return Ok(IdempotencyKey(None));
+        };
+
+        match key.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => {
+                Ok(IdempotencyKey(Some(Key {
+                    endpoint,
+                    key: key.to_string(),
+                })))
+            }
+            _ => Err(Error::Custom {
+                status_code: StatusCode::BAD_REQUEST,
+                error: format!(
+                    "Idempotency-Key must be a visible ASCII string of 1 to {MAX_KEY_LEN} characters"
+                ),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn idempotency_key(key: &str) -> IdempotencyKey {
+        IdempotencyKey(Some(Key {
+            endpoint: "POST /company".into(),
+            key: key.into(),
+        }))
+    }
+
+    fn is_conflict<T>(res: &Result<T, Error>) -> bool {
+        matches!(
+            res,
+            Err(Error::Custom {
+                status_code: StatusCode::CONFLICT,
+                ..
+            })
+        )
+    }
+
+    #[test]
+    fn endpoint_uses_the_path_before_nesting() {
+        let (mut parts, _) = axum::http::Request::post("/")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(endpoint(&parts), "POST /");
+
+        parts
+            .extensions
+            .insert(OriginalUri("/company/".parse().unwrap()));
+        assert_eq!(endpoint(&parts), "POST /company/");
+    }
+
+    #[tokio::test]
+    async fn recorded_responses_are_replayed() {
+        let mut conn = testing::conn().await;
+        let user = testing::create_user(&mut conn).await;
+        let key = idempotency_key("recorded");
+
+        assert_eq!(key.reserve::<String>(user, &mut conn).await.unwrap(), None);
+        assert!(is_conflict(&key.reserve::<String>(user, &mut conn).await));
+
+        key.record(user, &"response", &mut conn).await.unwrap();
+        assert_eq!(
+            key.reserve::<String>(user, &mut conn).await.unwrap(),
+            Some("response".into())
+        );
+        // Only a reservation can be recorded
+        assert!(is_conflict(&key.record(user, &"again", &mut conn).await));
+    }
+
+    #[tokio::test]
+    async fn released_and_abandoned_reservations_can_be_reused() {
+        use schema::idempotencykey::dsl as ik_dsl;
+
+        let mut conn = testing::conn().await;
+        let user = testing::create_user(&mut conn).await;
+
+        let released = idempotency_key("released");
+        released.reserve::<String>(user, &mut conn).await.unwrap();
+        released.release(user, &mut conn).await.unwrap();
+        assert_eq!(
+            released.reserve::<String>(user, &mut conn).await.unwrap(),
+            None
+        );
+
+        // A request that never finished, e.g. because the client disconnected
+        let abandoned = idempotency_key("abandoned");
+        abandoned.reserve::<String>(user, &mut conn).await.unwrap();
+        diesel::update(ik_dsl::idempotencykey)
+            .filter(ik_dsl::user_id.eq(user.id))
+            .filter(ik_dsl::key.eq("abandoned"))
+            .set(ik_dsl::created_at.eq(IdempotencyKey::ago(
+                crate::IDEMPOTENCY_KEY_PROCESSING_TIMEOUT + time::Duration::minutes(1),
+            )))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            abandoned.reserve::<String>(user, &mut conn).await.unwrap(),
+            None
+        );
+    }
+}
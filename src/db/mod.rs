@@ -1,13 +1,14 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashMap};
 
 use axum::{
     async_trait,
     extract::FromRequestParts,
-    http::{request::Parts, HeaderValue},
+    http::{request::Parts, HeaderValue, StatusCode},
 };
 use diesel::{
-    deserialize::Queryable, pg::Pg, prelude::Insertable, upsert::excluded, AsChangeset,
-    ExpressionMethods, OptionalExtension, QueryDsl, Selectable, SelectableHelper,
+    data_types::Cents, deserialize::Queryable, pg::Pg, prelude::Insertable, sql_types::Text,
+    upsert::excluded, AsChangeset, ExpressionMethods, OptionalExtension, QueryDsl, QueryableByName,
+    Selectable, SelectableHelper, TextExpressionMethods,
 };
 use diesel_async::{AsyncConnection, RunQueryDsl};
 use image::{DynamicImage, ImageFormat};
@@ -17,39 +18,147 @@ use uuid::Uuid;
 
 use crate::{
     google::GoogleSession,
-    state::AppState,
-    storage::{Folder, Storage},
+    state::{AppState, Config, HttpTimeouts},
+    storage::{fill_default_avatar, Folder, PreparedImage, Storage, StoredImages},
     twitch::TwitchSession,
-    utils::{oauth::OAuthAccountHelper, AuthenticationHeader},
+    utils::{rate_limit::UserRateLimiter, AuthenticationHeader},
     Error,
 };
 
+mod api_key;
 pub mod company;
 mod embedding;
-mod schema;
+mod idempotency;
+mod notification;
+pub(crate) mod schema;
 mod sql_types;
+mod stats;
+mod suggestion;
+mod webhook;
+
+pub use api_key::{ApiKey, ServicePrincipal, SignedRequest};
+pub use embedding::{
+    assert_column_dimensions, EmbeddingTemplates, EMBEDDING_DIMENSION, MAX_DESCRIPTION_CHARS,
+};
+pub use idempotency::IdempotencyKey;
+pub use notification::{CompanyNotificationSettings, NotificationSettings};
+pub use stats::PlatformStats;
+pub use suggestion::{refresh_suggestions, CompanySuggestion, CreatorSort, CreatorSuggestion};
+pub use webhook::{Webhook, WebhookDeadLetter, WebhookEvent};
+
+diesel::sql_function!(fn normalize_name(x: Text) -> Text);
 
 #[derive(Clone, Copy)]
-pub struct Encoder(&'static embedding::EmbeddingEncoder);
+pub struct Encoder {
+    /// `None` when no backend could be set up, embedding then fails with a `503` while the
+    /// rest of the API keeps working
+    encoder: Option<&'static embedding::EmbeddingEncoder>,
+    limiter: &'static UserRateLimiter,
+    templates: EmbeddingTemplates,
+}
 
 impl Encoder {
-    pub async fn new() -> Self {
-        match embedding::EmbeddingEncoder::new_voyage().await {
-            Ok(encoder) => Encoder(Box::leak(Box::new(encoder))),
+    pub async fn new(
+        limiter: &'static UserRateLimiter,
+        timeouts: HttpTimeouts,
+        templates: EmbeddingTemplates,
+    ) -> Self {
+        let encoder = match embedding::EmbeddingEncoder::new_voyage(timeouts).await {
+            Ok(encoder) => {
+                tracing::info!("Using voyage for embeddings");
+                Some(encoder)
+            }
             Err(err) => {
                 tracing::warn!("Failed to create voyage embeddings due to: {err:?}\nTrying with the custom model");
 
-                let encoder = embedding::EmbeddingEncoder::new_model()
-                    .await
-                    .expect("Failed to build the embedding encoder");
+                match Self::load_model().await {
+                    Ok(encoder) => {
+                        tracing::info!("Using the local model for embeddings");
+                        Some(encoder)
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            "Failed to load the embedding model due to: {err:?}\nStarting without \
+                            embeddings, everything that needs one will fail"
+                        );
+                        None
+                    }
+                }
+            }
+        };
+
+        Encoder {
+            encoder: encoder.map(|encoder| &*Box::leak(Box::new(encoder))),
+            limiter,
+            templates,
+        }
+    }
 
-                Encoder(Box::leak(Box::new(encoder)))
+    /// Loads the local model, retrying with a growing delay since the download can fail
+    async fn load_model() -> Result<embedding::EmbeddingEncoder, Error> {
+        const ATTEMPTS: u32 = 4;
+        let mut delay = std::time::Duration::from_secs(2);
+
+        let mut attempt = 1;
+        loop {
+            match embedding::EmbeddingEncoder::new_model().await {
+                Ok(encoder) => return Ok(encoder),
+                Err(err) if attempt < ATTEMPTS => {
+                    tracing::warn!(
+                        "Failed to load the embedding model on attempt {attempt} of {ATTEMPTS} due \
+                        to: {err:?}\nRetrying in {delay:?}"
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
             }
         }
     }
 
+    /// Whether embeddings can be made, they cannot if no backend could be set up at startup
+    pub fn is_available(&self) -> bool {
+        self.encoder.is_some()
+    }
+
+    pub fn limiter(&self) -> &'static UserRateLimiter {
+        self.limiter
+    }
+
+    pub fn templates(&self) -> EmbeddingTemplates {
+        self.templates
+    }
+
     pub async fn encode(&self, text: String) -> Result<Vec<f32>, Error> {
-        self.0.encode(text).await
+        let Some(encoder) = self.encoder else {
+            return Err(Error::Custom {
+                status_code: StatusCode::SERVICE_UNAVAILABLE,
+                error: "Embeddings are unavailable at the moment".into(),
+            });
+        };
+
+        let embedding = encoder.encode(text).await?;
+        if embedding.len() != EMBEDDING_DIMENSION {
+            return Err(Error::Custom {
+                status_code: StatusCode::INTERNAL_SERVER_ERROR,
+                error: format!(
+                    "The encoder produced an embedding with {} dimensions but {EMBEDDING_DIMENSION} are stored",
+                    embedding.len()
+                ),
+            });
+        }
+
+        Ok(embedding)
+    }
+
+    /// Encodes text on behalf of a user, subject to the per user embedding rate limit
+    pub async fn encode_for(&self, user: User, text: String) -> Result<Vec<f32>, Error> {
+        self.limiter
+            .check(user.id)
+            .map_err(|retry_after| Error::TooManyRequests { retry_after })?;
+
+        self.encode(text).await
     }
 }
 
@@ -73,6 +182,105 @@ impl User {
 
         Ok(user)
     }
+
+    /// Marks the user as active. The row is only written if the previous mark is older than
+    /// [`crate::LAST_ACTIVE_UPDATE_INTERVAL`] so this is cheap to call on every request.
+    pub async fn touch(&self, conn: &mut impl AsyncConnection<Backend = Pg>) -> Result<(), Error> {
+        use schema::inneruser::dsl as dsl_iu;
+
+        let now = OffsetDateTime::now_utc();
+        let stale_before = now - crate::LAST_ACTIVE_UPDATE_INTERVAL;
+
+        diesel::update(dsl_iu::inneruser)
+            .filter(dsl_iu::id.eq(self.id))
+            .filter(dsl_iu::last_active_at.lt(PrimitiveDateTime::new(
+                stale_before.date(),
+                stale_before.time(),
+            )))
+            .set(dsl_iu::last_active_at.eq(PrimitiveDateTime::new(now.date(), now.time())))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    fn deletion_cutoff() -> PrimitiveDateTime {
+        let cutoff = OffsetDateTime::now_utc() - crate::USER_DELETION_GRACE_PERIOD;
+        PrimitiveDateTime::new(cutoff.date(), cutoff.time())
+    }
+
+    /// Marks the user as deleted and ends all of their sessions. Until
+    /// [`crate::USER_DELETION_GRACE_PERIOD`] passes an admin can still [`User::restore`] them.
+    pub async fn soft_delete(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use schema::inneruser::dsl as dsl_iu;
+        use schema::innerusersession::dsl as dsl_ius;
+
+        diesel::update(dsl_iu::inneruser)
+            .filter(dsl_iu::id.eq(self.id))
+            .filter(dsl_iu::deleted_at.is_null())
+            .set(dsl_iu::deleted_at.eq(diesel::dsl::now))
+            .execute(conn)
+            .await?;
+        diesel::delete(dsl_ius::innerusersession)
+            .filter(dsl_ius::user_id.eq(self.id))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Undoes [`User::soft_delete`], returns false if there is no user of this id deleted
+    /// within the grace period
+    pub async fn restore(
+        user_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<bool, Error> {
+        use schema::inneruser::dsl as dsl_iu;
+
+        let restored = diesel::update(dsl_iu::inneruser)
+            .filter(dsl_iu::id.eq(user_id))
+            .filter(dsl_iu::deleted_at.gt(Self::deletion_cutoff()))
+            .set(dsl_iu::deleted_at.eq(None::<PrimitiveDateTime>))
+            .execute(conn)
+            .await?;
+
+        Ok(restored > 0)
+    }
+
+    /// Removes users deleted longer than the grace period ago along with their stored files.
+    ///
+    /// Removing a user cascades to their rooms and messages, and contract offers can never be
+    /// deleted, so users that took part in an offer stay soft deleted to keep its history.
+    pub async fn purge_deleted(conn: &mut impl AsyncConnection<Backend = Pg>) -> Result<(), Error> {
+        use schema::inneruser::dsl as dsl_iu;
+
+        let user_ids = dsl_iu::inneruser
+            .filter(dsl_iu::deleted_at.lt(Self::deletion_cutoff()))
+            .filter(diesel::dsl::sql::<diesel::sql_types::Bool>(
+                "NOT EXISTS (SELECT 1 FROM ChatMessage m JOIN ChatRoom r ON r.id = m.room_id \
+                    WHERE (m.from_user_id = InnerUser.id OR r.user_id = InnerUser.id) \
+                    AND (EXISTS (SELECT 1 FROM ChatContractOffer o WHERE o.message_id = m.id) \
+                        OR EXISTS (SELECT 1 FROM ChatContractOfferUpdate ou \
+                            WHERE ou.message_id = m.id)))",
+            ))
+            .select(dsl_iu::id)
+            .load::<Uuid>(conn)
+            .await?;
+
+        for user_id in user_ids {
+            Storage::purge_user(User { id: user_id }, conn).await?;
+
+            diesel::delete(dsl_iu::inneruser)
+                .filter(dsl_iu::id.eq(user_id))
+                .execute(conn)
+                .await?;
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -83,29 +291,81 @@ impl FromRequestParts<AppState> for User {
         parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        if let Some(cookies) = parts.headers.get(axum::http::header::COOKIE) {
-            let parts = cookies.as_bytes().split(|c| *c == b';');
-            for part in parts {
-                if let Ok(part) = std::str::from_utf8(part) {
-                    let part = part.trim();
-
-                    if let Some((name, value)) = part.split_once('=') {
-                        if name == crate::SESSION_COOKIE_NAME {
-                            let mut conn = state.get_conn().await?;
-
-                            // We ignore the session cookie if we cannot find a session associated with it
-                            if let Some(user) =
-                                UserSession::get_user_by_token(value, &mut conn).await?
-                            {
-                                return Ok(user);
-                            }
-                        }
-                    }
-                }
+        if let Some(token) = state.config().cookies.session_token(&parts.headers) {
+            let mut conn = state.get_conn().await?;
+
+            // We ignore the session cookie if we cannot find a session associated with it
+            if let Some(user) = UserSession::get_user_by_token(token, &mut conn).await? {
+                user.touch(&mut conn).await?;
+                return Ok(user);
             }
         }
 
-        Err(Error::Unauthorized)
+        Err(Error::Unauthenticated)
+    }
+}
+
+/// A user that operates the platform
+pub struct Admin(pub User);
+
+#[async_trait]
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = User::from_request_parts(parts, state).await?;
+        let mut conn = state.get_conn().await?;
+
+        use schema::inneruser::dsl as dsl_iu;
+
+        let is_admin = dsl_iu::inneruser
+            .find(user.id)
+            .select(dsl_iu::is_admin)
+            .first::<bool>(&mut conn)
+            .await?;
+        if !is_admin {
+            return Err(Error::Forbidden {
+                reason: "You are not an admin".into(),
+            });
+        }
+
+        Ok(Admin(user))
+    }
+}
+
+/// A user as seen by admins
+#[derive(Queryable, Selectable, serde::Serialize)]
+#[diesel(table_name = schema::inneruser)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct AdminUserView {
+    pub id: Uuid,
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub created_at: PrimitiveDateTime,
+    /// Updated at most once every [`crate::LAST_ACTIVE_UPDATE_INTERVAL`]
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub last_active_at: PrimitiveDateTime,
+    pub is_admin: bool,
+    #[serde(serialize_with = "crate::utils::serialize_optional_timestamp")]
+    pub deleted_at: Option<PrimitiveDateTime>,
+}
+
+impl AdminUserView {
+    pub async fn get(
+        user_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Self>, Error> {
+        use schema::inneruser::dsl as dsl_iu;
+
+        Ok(dsl_iu::inneruser
+            .find(user_id)
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()?)
     }
 }
 
@@ -149,15 +409,18 @@ impl UserSession {
         token: &str,
         conn: &mut impl AsyncConnection<Backend = Pg>,
     ) -> Result<Option<User>, Error> {
+        use schema::inneruser::dsl as dsl_iu;
         use schema::innerusersession::dsl as dsl_ius;
 
         let now = OffsetDateTime::now_utc();
         let now = PrimitiveDateTime::new(now.date(), now.time());
 
         let user = dsl_ius::innerusersession
+            .inner_join(dsl_iu::inneruser)
             .select((dsl_ius::user_id,))
             .filter(dsl_ius::token.eq(token))
             .filter(dsl_ius::expires_at.gt(now))
+            .filter(dsl_iu::deleted_at.is_null())
             .first(conn)
             .await
             .optional()?;
@@ -165,6 +428,25 @@ impl UserSession {
         Ok(user)
     }
 
+    /// Ends every session of the user except `keep`, returning the tokens of the ones ended.
+    /// Their fcm tokens go with them.
+    pub async fn delete_for_user(
+        user: User,
+        keep: Option<&str>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<String>, Error> {
+        use schema::innerusersession::dsl as dsl_ius;
+
+        let mut query = diesel::delete(dsl_ius::innerusersession)
+            .filter(dsl_ius::user_id.eq(user.id))
+            .into_boxed();
+        if let Some(keep) = keep {
+            query = query.filter(dsl_ius::token.ne(keep));
+        }
+
+        Ok(query.returning(dsl_ius::token).load(conn).await?)
+    }
+
     pub async fn prune_expired(conn: &mut impl AsyncConnection<Backend = Pg>) -> Result<(), Error> {
         let now = OffsetDateTime::now_utc();
         let now = PrimitiveDateTime::new(now.date(), now.time());
@@ -180,6 +462,29 @@ impl UserSession {
     }
 }
 
+/// Most tags a creator can have
+pub const MAX_CREATOR_TAGS: usize = 20;
+/// Longest a single tag can be
+pub const MAX_TAG_CHARS: usize = 32;
+
+/// Splits comma separated tags, trimming, lowercasing and collapsing the whitespace of each so
+/// the same tag is always stored and searched the same way. Empty and repeated tags are dropped.
+pub fn normalize_tags(tags: &str) -> Vec<String> {
+    let mut normalized = Vec::<String>::new();
+    for tag in tags.split(',') {
+        let tag = tag
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+
+    normalized
+}
+
 #[derive(Clone, Insertable)]
 #[diesel(table_name = schema::creatorprofile)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -193,13 +498,11 @@ pub struct CreatorProfileInsert<'d> {
     pub audience_desc: &'d str,
     pfp_path: Option<&'d str>,
     embedding: Vector,
+    min_payout: Option<Cents>,
+    min_payout_strict: bool,
 }
 
 impl<'d> CreatorProfileInsert<'d> {
-    fn format_creator_descriptions(profile: &str, content: &str, audience: &str) -> String {
-        format!("Question: Who am I?\nAnswer: {profile}\n\nQuestion: What do I make?\nAnswer: {content}\n\nQuestion: Who watches my content?\nAnswer: {audience}")
-    }
-
     pub async fn insert_update(
         user: User,
         given_name: &str,
@@ -208,21 +511,60 @@ impl<'d> CreatorProfileInsert<'d> {
         profile_desc: &str,
         content_desc: &str,
         audience_desc: &str,
+        min_payout: Option<Cents>,
+        min_payout_strict: bool,
+        tags: Option<&[String]>,
         pfp_hidden: Option<&str>,
         pfp: Option<(DynamicImage, ImageFormat)>,
+        remove_pfp: bool,
         conn: &mut impl AsyncConnection<Backend = Pg>,
         encoder: Encoder,
         storage: Storage,
     ) -> Result<(), Error> {
-        let user_embedding_desc =
-            Self::format_creator_descriptions(profile_desc, content_desc, audience_desc);
-        let embedding = encoder.encode(user_embedding_desc).await?;
+        use schema::creatorprofile::dsl as cp_dsl;
 
-        let pfp_path = storage
-            .store_public_image(Folder::ProfilePicture, user.id, pfp_hidden, pfp)
-            .await?;
+        let given_name = given_name.trim();
+        let family_name = family_name.trim();
 
-        use schema::creatorprofile::dsl as cp_dsl;
+        let existing = cp_dsl::creatorprofile
+            .filter(cp_dsl::user_id.eq(user.id))
+            .select((
+                cp_dsl::profile_desc,
+                cp_dsl::content_desc,
+                cp_dsl::audience_desc,
+                cp_dsl::embedding,
+            ))
+            .first::<(String, String, String, Vector)>(conn)
+            .await
+            .optional()?;
+
+        // Only re-embed when the text that goes into the embedding has changed
+        let embedding = match existing {
+            Some((old_profile, old_content, old_audience, embedding))
+                if old_profile == profile_desc
+                    && old_content == content_desc
+                    && old_audience == audience_desc =>
+            {
+                embedding
+            }
+            _ => {
+                let user_embedding_desc =
+                    encoder
+                        .templates()
+                        .creator(profile_desc, content_desc, audience_desc);
+                encoder.encode_for(user, user_embedding_desc).await?.into()
+            }
+        };
+
+        let pfp_path = if remove_pfp {
+            remove_profile_picture(user, storage, conn).await?;
+            None
+        } else {
+            let pfp = storage.prepare_public_image(pfp_hidden, pfp).await?;
+            storage
+                .store_public_image(Folder::ProfilePicture, user.id, user, pfp, conn)
+                .await?
+        };
 
         diesel::insert_into(cp_dsl::creatorprofile)
             .values(&CreatorProfileInsert {
@@ -234,7 +576,9 @@ impl<'d> CreatorProfileInsert<'d> {
                 content_desc,
                 audience_desc,
                 pfp_path: pfp_path.as_deref(),
-                embedding: embedding.into(),
+                embedding,
+                min_payout,
+                min_payout_strict,
             })
             .on_conflict(cp_dsl::user_id)
             .do_update()
@@ -245,17 +589,137 @@ impl<'d> CreatorProfileInsert<'d> {
                 cp_dsl::profile_desc.eq(excluded(cp_dsl::profile_desc)),
                 cp_dsl::content_desc.eq(excluded(cp_dsl::content_desc)),
                 cp_dsl::audience_desc.eq(excluded(cp_dsl::audience_desc)),
-                cp_dsl::pfp_path.eq(excluded(cp_dsl::pfp_path)),
                 cp_dsl::embedding.eq(excluded(cp_dsl::embedding)),
+                cp_dsl::min_payout.eq(excluded(cp_dsl::min_payout)),
+                cp_dsl::min_payout_strict.eq(excluded(cp_dsl::min_payout_strict)),
             ))
             .execute(conn)
             .await?;
 
+        // Without a new picture the existing one is kept, unless it was removed
+        if pfp_path.is_some() || remove_pfp {
+            diesel::update(cp_dsl::creatorprofile)
+                .filter(cp_dsl::user_id.eq(user.id))
+                .set(cp_dsl::pfp_path.eq(pfp_path))
+                .execute(conn)
+                .await?;
+        }
+
+        // Without tags the existing ones are kept, like the picture
+        if let Some(tags) = tags {
+            diesel::update(cp_dsl::creatorprofile)
+                .filter(cp_dsl::user_id.eq(user.id))
+                .set(cp_dsl::tags.eq(tags))
+                .execute(conn)
+                .await?;
+        }
+
         Ok(())
     }
+
+    /// Recomputes the embedding from the stored descriptions, even if they have not changed,
+    /// and returns it. Returns `None` if the user has no creator profile.
+    pub async fn reembed(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        encoder: Encoder,
+    ) -> Result<Option<Vec<f32>>, Error> {
+        use schema::creatorprofile::dsl as cp_dsl;
+
+        let Some((profile_desc, content_desc, audience_desc)) = cp_dsl::creatorprofile
+            .filter(cp_dsl::user_id.eq(user.id))
+            .select((
+                cp_dsl::profile_desc,
+                cp_dsl::content_desc,
+                cp_dsl::audience_desc,
+            ))
+            .first::<(String, String, String)>(conn)
+            .await
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        let embedding = encoder
+            .encode(
+                encoder
+                    .templates()
+                    .creator(&profile_desc, &content_desc, &audience_desc),
+            )
+            .await?;
+
+        diesel::update(cp_dsl::creatorprofile.filter(cp_dsl::user_id.eq(user.id)))
+            .set(cp_dsl::embedding.eq(Vector::from(embedding.clone())))
+            .execute(conn)
+            .await?;
+
+        Ok(Some(embedding))
+    }
+
+    /// Replaces only the profile picture, leaving the text and the embedding untouched. Returns
+    /// `None` if the user has no creator profile yet.
+    pub async fn update_pfp(
+        user: User,
+        pfp: PreparedImage,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        storage: Storage,
+    ) -> Result<Option<String>, Error> {
+        use schema::creatorprofile::dsl as cp_dsl;
+
+        let exists = diesel::select(diesel::dsl::exists(
+            cp_dsl::creatorprofile.filter(cp_dsl::user_id.eq(user.id)),
+        ))
+        .get_result::<bool>(conn)
+        .await?;
+        if !exists {
+            return Ok(None);
+        }
+
+        let pfp_path = storage
+            .store_public_image(Folder::ProfilePicture, user.id, user, Some(pfp), conn)
+            .await?
+            .expect("The image was provided so it should have been stored");
+
+        diesel::update(cp_dsl::creatorprofile)
+            .filter(cp_dsl::user_id.eq(user.id))
+            .set(cp_dsl::pfp_path.eq(&pfp_path))
+            .execute(conn)
+            .await?;
+
+        Ok(Some(pfp_path))
+    }
+}
+
+/// Deletes the profile picture of the user. The creator and company user profiles share the
+/// stored picture so it is cleared from both.
+pub async fn remove_profile_picture(
+    user: User,
+    storage: Storage,
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<(), Error> {
+    use schema::companyuserprofile::dsl as cup_dsl;
+    use schema::creatorprofile::dsl as cp_dsl;
+
+    storage
+        .delete_public_image(Folder::ProfilePicture, user.id, conn)
+        .await?;
+
+    diesel::update(cp_dsl::creatorprofile)
+        .filter(cp_dsl::user_id.eq(user.id))
+        .set(cp_dsl::pfp_path.eq(None::<String>))
+        .execute(conn)
+        .await?;
+    diesel::update(cup_dsl::companyuserprofile)
+        .filter(cup_dsl::user_id.eq(user.id))
+        .set(cup_dsl::pfp_path.eq(None::<String>))
+        .execute(conn)
+        .await?;
+
+    Ok(())
 }
 
 #[derive(Clone, Queryable, Selectable, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 #[diesel(table_name = schema::creatorprofile)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct CreatorProfileQuery {
@@ -265,10 +729,141 @@ pub struct CreatorProfileQuery {
     pub profile_desc: String,
     pub content_desc: String,
     pub audience_desc: String,
-    pub pfp_path: String,
+    pub pfp_path: Option<String>,
+    #[serde(skip)]
+    pub updated_at: PrimitiveDateTime,
+    /// Offers below this are flagged, or rejected if `min_payout_strict` is set
+    #[serde(serialize_with = "crate::utils::serialize_optional_cents")]
+    pub min_payout: Option<Cents>,
+    pub min_payout_strict: bool,
+    /// Normalized by [`normalize_tags`]
+    pub tags: Vec<String>,
+}
+
+impl StoredImages for CreatorProfileQuery {
+    fn resolve_urls(&mut self, config: &Config) {
+        fill_default_avatar(
+            config.default_avatar,
+            &mut self.pfp_path,
+            &self.given_name,
+            &self.family_name,
+        );
+        self.pfp_path.resolve_urls(config);
+    }
+}
+
+/// What companies can see of a creator, leaving out the payout they expect
+#[derive(QueryableByName, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatorPublicProfile {
+    #[diesel(sql_type = diesel::sql_types::Uuid)]
+    pub user_id: Uuid,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub given_name: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub family_name: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub pronouns: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub profile_desc: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub content_desc: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub audience_desc: String,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Text>)]
+    pub pfp_path: Option<String>,
+    #[diesel(sql_type = diesel::sql_types::Array<diesel::sql_types::Text>)]
+    pub tags: Vec<String>,
+    /// An estimate, see [`CreatorPlatformStats::estimated_reach`]
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub estimated_reach: i64,
+    /// Platforms the creator has linked an account of, e.g. `youtube` or `twitch`
+    #[diesel(sql_type = diesel::sql_types::Array<diesel::sql_types::Text>)]
+    pub platforms: Vec<String>,
+    /// Paths of the gallery images in display order
+    #[diesel(sql_type = diesel::sql_types::Array<diesel::sql_types::Text>)]
+    pub gallery: Vec<String>,
+}
+
+impl StoredImages for CreatorPublicProfile {
+    fn resolve_urls(&mut self, config: &Config) {
+        fill_default_avatar(
+            config.default_avatar,
+            &mut self.pfp_path,
+            &self.given_name,
+            &self.family_name,
+        );
+        self.pfp_path.resolve_urls(config);
+        self.gallery.resolve_urls(config);
+    }
+}
+
+impl CreatorPublicProfile {
+    /// The profiles of the creators in one query, in the order of `user_ids`. Users without a
+    /// creator profile or whose account is deleted are left out.
+    pub async fn list(
+        user_ids: &[Uuid],
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        Ok(diesel::sql_query(format!(
+            "SELECT cp.user_id, cp.given_name, cp.family_name, cp.pronouns, cp.profile_desc, \
+                cp.content_desc, cp.audience_desc, cp.pfp_path, cp.tags, \
+                (SELECT {ESTIMATED_REACH_SQL} FROM CreatorPlatformStats st \
+                    WHERE st.user_id = cp.user_id) AS estimated_reach, \
+                ARRAY(SELECT DISTINCT st.platform FROM CreatorPlatformStats st \
+                    WHERE st.user_id = cp.user_id ORDER BY st.platform) AS platforms, \
+                ARRAY(SELECT g.image_path FROM CreatorGallery g \
+                    WHERE g.user_id = cp.user_id ORDER BY g.position) AS gallery \
+            FROM CreatorProfile cp \
+            JOIN InnerUser u ON u.id = cp.user_id AND u.deleted_at IS NULL \
+            WHERE cp.user_id = ANY($1) \
+            ORDER BY array_position($1, cp.user_id)"
+        ))
+        .bind::<diesel::sql_types::Array<diesel::sql_types::Uuid>, _>(user_ids)
+        .load(conn)
+        .await?)
+    }
+}
+
+/// The lowest payout a creator wants to be offered
+pub struct MinPayout {
+    pub cents: i64,
+    /// Reject offers below the minimum instead of only warning the company
+    pub strict: bool,
 }
 
 impl CreatorProfileQuery {
+    /// Up to `limit` creators who were active since `since`, ordered by id and starting after
+    /// `after` so that batches can walk through every creator
+    pub async fn active_creators(
+        after: Option<Uuid>,
+        since: PrimitiveDateTime,
+        limit: i64,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<User>, Error> {
+        use schema::creatorprofile::dsl as cp_dsl;
+        use schema::inneruser::dsl as iu_dsl;
+
+        let mut query = cp_dsl::creatorprofile
+            .inner_join(iu_dsl::inneruser)
+            .filter(iu_dsl::last_active_at.ge(since))
+            .filter(iu_dsl::deleted_at.is_null())
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(cp_dsl::user_id.gt(after));
+        }
+
+        Ok(query
+            .order_by(cp_dsl::user_id.asc())
+            .limit(limit)
+            .select(cp_dsl::user_id)
+            .load::<Uuid>(conn)
+            .await?
+            .into_iter()
+            .map(|id| User { id })
+            .collect())
+    }
+
     pub async fn get(
         user: User,
         conn: &mut impl AsyncConnection<Backend = Pg>,
@@ -282,9 +877,68 @@ impl CreatorProfileQuery {
             .await
             .optional()?)
     }
+
+    /// The embedding of the profile of the user, if they have one
+    pub async fn embedding(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Vector>, Error> {
+        use schema::creatorprofile::dsl as cp_dsl;
+
+        Ok(cp_dsl::creatorprofile
+            .filter(cp_dsl::user_id.eq(user.id))
+            .select(cp_dsl::embedding)
+            .first(conn)
+            .await
+            .optional()?)
+    }
+
+    pub async fn min_payout(
+        user_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<MinPayout>, Error> {
+        use schema::creatorprofile::dsl as cp_dsl;
+
+        let min_payout = cp_dsl::creatorprofile
+            .filter(cp_dsl::user_id.eq(user_id))
+            .select((cp_dsl::min_payout, cp_dsl::min_payout_strict))
+            .first::<(Option<Cents>, bool)>(conn)
+            .await
+            .optional()?;
+
+        Ok(match min_payout {
+            Some((Some(cents), strict)) => Some(MinPayout {
+                cents: cents.0,
+                strict,
+            }),
+            _ => None,
+        })
+    }
+
+    /// Checks if a creator other than `except` already uses this display name. Both sides go
+    /// through the `normalize_name` SQL function, which ignores case and collapses whitespace.
+    pub async fn is_name_taken(
+        name: &str,
+        except: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<bool, Error> {
+        use schema::creatorprofile::dsl as cp_dsl;
+
+        let display_name =
+            normalize_name(cp_dsl::given_name.concat(" ").concat(cp_dsl::family_name));
+
+        Ok(diesel::select(diesel::dsl::exists(
+            cp_dsl::creatorprofile
+                .filter(cp_dsl::user_id.ne(except.id))
+                .filter(display_name.eq(normalize_name(name))),
+        ))
+        .get_result(conn)
+        .await?)
+    }
 }
 
 #[derive(Clone, Queryable, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 #[diesel(table_name = schema::creatorprofile)]
 #[diesel(table_name = schema::companyuserprofile)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -292,7 +946,19 @@ pub struct UserProfile {
     pub given_name: String,
     pub family_name: String,
     pub pronouns: String,
-    pub pfp_path: String,
+    pub pfp_path: Option<String>,
+}
+
+impl StoredImages for UserProfile {
+    fn resolve_urls(&mut self, config: &Config) {
+        fill_default_avatar(
+            config.default_avatar,
+            &mut self.pfp_path,
+            &self.given_name,
+            &self.family_name,
+        );
+        self.pfp_path.resolve_urls(config);
+    }
 }
 
 impl UserProfile {
@@ -333,11 +999,18 @@ impl UserProfile {
 }
 
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct UserInfo {
     pub profile: UserProfile,
     pub companies: Vec<Uuid>,
 }
 
+impl StoredImages for UserInfo {
+    fn resolve_urls(&mut self, config: &Config) {
+        self.profile.resolve_urls(config);
+    }
+}
+
 impl UserInfo {
     pub async fn for_user(
         user: User,
@@ -358,18 +1031,322 @@ impl UserInfo {
                 .await?,
         }))
     }
-}
 
-#[derive(Insertable, Queryable)]
-#[diesel(table_name = schema::twitchaccount)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
-pub struct TwitchAccount {
-    pub id: String,
-    pub access_token: String,
-    pub expires_at: PrimitiveDateTime,
-    pub refresh_token: String,
-    pub user_id: Uuid,
-}
+    /// Same as [`UserInfo::for_user`] for many users at once, in a single query. Users without
+    /// a profile are left out.
+    pub async fn for_users(
+        user_ids: &[Uuid],
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<HashMap<Uuid, Self>, Error> {
+        use diesel::{JoinOnDsl, NullableExpressionMethods};
+        use schema::companyuser::dsl as cu_dsl;
+        use schema::companyuserprofile::dsl as cup_dsl;
+        use schema::creatorprofile::dsl as cp_dsl;
+        use schema::inneruser::dsl as iu_dsl;
+
+        // One row per company of the user, or a single row if they are in none
+        let rows = iu_dsl::inneruser
+            .left_join(cp_dsl::creatorprofile.on(cp_dsl::user_id.eq(iu_dsl::id)))
+            .left_join(cup_dsl::companyuserprofile.on(cup_dsl::user_id.eq(iu_dsl::id)))
+            .left_join(cu_dsl::companyuser.on(cu_dsl::user_id.eq(iu_dsl::id)))
+            .filter(iu_dsl::id.eq_any(user_ids))
+            .filter(iu_dsl::deleted_at.is_null())
+            .select((
+                iu_dsl::id,
+                (
+                    cp_dsl::given_name,
+                    cp_dsl::family_name,
+                    cp_dsl::pronouns,
+                    cp_dsl::pfp_path,
+                )
+                    .nullable(),
+                (
+                    cup_dsl::given_name,
+                    cup_dsl::family_name,
+                    cup_dsl::pronouns,
+                    cup_dsl::pfp_path,
+                )
+                    .nullable(),
+                cu_dsl::company_id.nullable(),
+            ))
+            .load::<(Uuid, Option<UserProfile>, Option<UserProfile>, Option<Uuid>)>(conn)
+            .await?;
+
+        let mut infos = HashMap::<Uuid, UserInfo>::default();
+        for (user_id, creator_profile, company_user_profile, company_id) in rows {
+            let Some(profile) = creator_profile.or(company_user_profile) else {
+                continue;
+            };
+
+            let info = infos.entry(user_id).or_insert_with(|| UserInfo {
+                profile,
+                companies: Vec::default(),
+            });
+            info.companies.extend(company_id);
+        }
+
+        Ok(infos)
+    }
+}
+
+/// A file written by [`Storage`] on behalf of a user, used to enforce storage quotas
+#[derive(Clone, Insertable, Queryable, Selectable)]
+#[diesel(table_name = schema::storedfile)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct StoredFile {
+    pub folder: String,
+    pub object_id: Uuid,
+    pub user_id: Uuid,
+    pub path: String,
+    pub size_bytes: i64,
+}
+
+impl StoredFile {
+    pub async fn get(
+        folder: &str,
+        object_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Self>, Error> {
+        use schema::storedfile::dsl as sf_dsl;
+
+        Ok(sf_dsl::storedfile
+            .filter(sf_dsl::folder.eq(folder))
+            .filter(sf_dsl::object_id.eq(object_id))
+            .select(Self::as_select())
+            .first(conn)
+            .await
+            .optional()?)
+    }
+
+    /// Total bytes stored by the user across all folders
+    pub async fn usage(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<u64, Error> {
+        use schema::storedfile::dsl as sf_dsl;
+
+        let usage = sf_dsl::storedfile
+            .filter(sf_dsl::user_id.eq(user.id))
+            .select(diesel::dsl::sql::<diesel::sql_types::BigInt>(
+                "COALESCE(SUM(size_bytes), 0)::BIGINT",
+            ))
+            .get_result::<i64>(conn)
+            .await?;
+
+        Ok(usage as u64)
+    }
+
+    /// Serializes the uploads of the user until the transaction ends, so two uploads cannot
+    /// both pass the quota check before either is recorded
+    pub async fn lock_usage(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        diesel::sql_query(
+            "SELECT pg_advisory_xact_lock(hashtext('StoredFile'), hashtext($1::TEXT))",
+        )
+        .bind::<diesel::sql_types::Uuid, _>(user.id)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn insert_or_update(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use schema::storedfile::dsl as sf_dsl;
+
+        diesel::insert_into(sf_dsl::storedfile)
+            .values(self)
+            .on_conflict((sf_dsl::folder, sf_dsl::object_id))
+            .do_update()
+            .set((
+                sf_dsl::user_id.eq(excluded(sf_dsl::user_id)),
+                sf_dsl::path.eq(excluded(sf_dsl::path)),
+                sf_dsl::size_bytes.eq(excluded(sf_dsl::size_bytes)),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(
+        folder: &str,
+        object_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Self>, Error> {
+        use schema::storedfile::dsl as sf_dsl;
+
+        Ok(diesel::delete(sf_dsl::storedfile)
+            .filter(sf_dsl::folder.eq(folder))
+            .filter(sf_dsl::object_id.eq(object_id))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+            .optional()?)
+    }
+
+    /// Deletes the rows of the files the user stored in `folders`
+    pub async fn delete_for_user(
+        user: User,
+        folders: &[&str],
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        use schema::storedfile::dsl as sf_dsl;
+
+        Ok(diesel::delete(sf_dsl::storedfile)
+            .filter(sf_dsl::user_id.eq(user.id))
+            .filter(sf_dsl::folder.eq_any(folders))
+            .returning(Self::as_returning())
+            .get_results(conn)
+            .await?)
+    }
+}
+
+#[derive(Clone, Queryable, Selectable, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[diesel(table_name = schema::creatorgallery)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreatorGalleryImage {
+    pub id: Uuid,
+    pub image_path: String,
+}
+
+impl StoredImages for CreatorGalleryImage {
+    fn resolve_urls(&mut self, config: &Config) {
+        self.image_path.resolve_urls(config);
+    }
+}
+
+impl CreatorGalleryImage {
+    /// Locks the gallery of the user until the transaction ends. The row of the user is locked
+    /// since an empty gallery has no rows to lock.
+    pub async fn lock(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use schema::inneruser::dsl as iu_dsl;
+
+        iu_dsl::inneruser
+            .filter(iu_dsl::id.eq(user.id))
+            .select(iu_dsl::id)
+            .for_update()
+            .first::<Uuid>(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gallery of the user in display order
+    pub async fn list(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        use schema::creatorgallery::dsl as cg_dsl;
+
+        Ok(cg_dsl::creatorgallery
+            .filter(cg_dsl::user_id.eq(user.id))
+            .order_by(cg_dsl::position.asc())
+            .select(Self::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    /// Appends the image to the end of the gallery of the user
+    pub async fn insert(
+        user: User,
+        id: Uuid,
+        image_path: &str,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Self, Error> {
+        use schema::creatorgallery::dsl as cg_dsl;
+
+        let last_position = cg_dsl::creatorgallery
+            .filter(cg_dsl::user_id.eq(user.id))
+            .select(diesel::dsl::max(cg_dsl::position))
+            .first::<Option<i32>>(conn)
+            .await?;
+
+        Ok(diesel::insert_into(cg_dsl::creatorgallery)
+            .values((
+                cg_dsl::id.eq(id),
+                cg_dsl::user_id.eq(user.id),
+                cg_dsl::position.eq(last_position.map_or(0, |position| position + 1)),
+                cg_dsl::image_path.eq(image_path),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await?)
+    }
+
+    /// Gives every image of the user the position of its id in `order`. `order` must contain
+    /// exactly the ids of the gallery of the user.
+    pub async fn reorder(
+        user: User,
+        order: &[Uuid],
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use schema::creatorgallery::dsl as cg_dsl;
+
+        let mut current = cg_dsl::creatorgallery
+            .filter(cg_dsl::user_id.eq(user.id))
+            .select(cg_dsl::id)
+            .load::<Uuid>(conn)
+            .await?;
+        current.sort();
+
+        let mut requested = order.to_vec();
+        requested.sort();
+
+        if current != requested {
+            return Err(Error::Custom {
+                status_code: StatusCode::BAD_REQUEST,
+                error: "The order must contain every image of the gallery exactly once".into(),
+            });
+        }
+
+        for (position, id) in order.iter().enumerate() {
+            diesel::update(cg_dsl::creatorgallery)
+                .filter(cg_dsl::id.eq(id))
+                .set(cg_dsl::position.eq(position as i32))
+                .execute(conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns `false` if the user has no gallery image with this id
+    pub async fn delete(
+        user: User,
+        id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<bool, Error> {
+        use schema::creatorgallery::dsl as cg_dsl;
+
+        let deleted = diesel::delete(cg_dsl::creatorgallery)
+            .filter(cg_dsl::id.eq(id))
+            .filter(cg_dsl::user_id.eq(user.id))
+            .execute(conn)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+}
+
+#[derive(Insertable, Queryable, Selectable)]
+#[diesel(table_name = schema::twitchaccount)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct TwitchAccount {
+    pub id: String,
+    pub access_token: String,
+    pub expires_at: PrimitiveDateTime,
+    pub refresh_token: String,
+    pub user_id: Uuid,
+}
 
 impl TwitchAccount {
     pub async fn list(
@@ -380,25 +1357,42 @@ impl TwitchAccount {
 
         let accounts = ta_dsl::twitchaccount
             .filter(ta_dsl::user_id.eq(user.id))
+            .select(Self::as_select())
             .load(conn)
             .await?;
 
         Ok(accounts)
     }
 
+    /// Accounts of the user that do not have to be linked again
+    pub async fn list_usable(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        use schema::twitchaccount::dsl as ta_dsl;
+
+        Ok(ta_dsl::twitchaccount
+            .filter(ta_dsl::user_id.eq(user.id))
+            .filter(ta_dsl::reauth_required.eq(false))
+            .select(Self::as_select())
+            .load(conn)
+            .await?)
+    }
+
     pub fn meta(&self) -> TwitchAccountMeta {
         TwitchAccountMeta {
             id: self.id.clone(),
         }
     }
 
+    /// Returns the account and whether it was linked for the first time rather than updated
     pub async fn insert_or_update(
         self,
         conn: &mut impl AsyncConnection<Backend = Pg>,
-    ) -> Result<Self, Error> {
+    ) -> Result<(Self, bool), Error> {
         use schema::twitchaccount::dsl as ta_dsl;
 
-        diesel::insert_into(ta_dsl::twitchaccount)
+        let inserted = diesel::insert_into(ta_dsl::twitchaccount)
             .values(&self)
             .on_conflict(ta_dsl::id)
             .do_update()
@@ -407,11 +1401,14 @@ impl TwitchAccount {
                 ta_dsl::expires_at.eq(excluded(ta_dsl::expires_at)),
                 ta_dsl::refresh_token.eq(excluded(ta_dsl::refresh_token)),
                 ta_dsl::user_id.eq(excluded(ta_dsl::user_id)),
+                ta_dsl::refreshed_at.eq(diesel::dsl::now),
+                ta_dsl::reauth_required.eq(false),
             ))
-            .execute(conn)
+            .returning(upsert_inserted())
+            .get_result::<bool>(conn)
             .await?;
 
-        Ok(self)
+        Ok((self, inserted))
     }
 }
 
@@ -422,14 +1419,18 @@ pub struct TwitchAccountMeta {
 }
 
 impl AuthenticationHeader for TwitchAccount {
-    const EXTRA_HEADERS: Self::ExtraHeader = [(
-        "Client-Id",
-        HeaderValue::from_static(TwitchSession::CLIENT_ID),
-    )];
+    fn extra_headers(config: &Config) -> Self::ExtraHeader {
+        [(
+            "Client-Id",
+            HeaderValue::from_static(config.twitch_oauth.client_id),
+        )]
+    }
 
     type ExtraHeader = [(&'static str, HeaderValue); 1];
     type Session = TwitchSession;
 
+    const PROVIDER: Provider = Provider::Twitch;
+
     fn access_token(&self) -> &str {
         &self.access_token
     }
@@ -452,9 +1453,28 @@ impl AuthenticationHeader for TwitchAccount {
         self.refresh_token = session.refresh_token();
         // session.id does not change so we don't need to update it
     }
+
+    fn account_id(&self) -> &str {
+        &self.id
+    }
+
+    async fn mark_reauth_required(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use schema::twitchaccount::dsl as ta_dsl;
+
+        diesel::update(ta_dsl::twitchaccount)
+            .filter(ta_dsl::id.eq(&self.id))
+            .set(ta_dsl::reauth_required.eq(true))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
 }
 
-#[derive(Clone, Insertable, Queryable, AsChangeset)]
+#[derive(Clone, Insertable, Queryable, Selectable, AsChangeset)]
 #[diesel(table_name = schema::googleaccount)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct GoogleAccount {
@@ -475,6 +1495,7 @@ impl GoogleAccount {
 
         let user = ga_dsl::googleaccount
             .filter(ga_dsl::sub.eq(sub))
+            .select(Self::as_select())
             .first(conn)
             .await
             .optional()?;
@@ -490,12 +1511,28 @@ impl GoogleAccount {
 
         let accounts = ga_dsl::googleaccount
             .filter(ga_dsl::user_id.eq(user.id))
+            .select(Self::as_select())
             .load(conn)
             .await?;
 
         Ok(accounts)
     }
 
+    /// Accounts of the user that do not have to be linked again
+    pub async fn list_usable(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        use schema::googleaccount::dsl as ga_dsl;
+
+        Ok(ga_dsl::googleaccount
+            .filter(ga_dsl::user_id.eq(user.id))
+            .filter(ga_dsl::reauth_required.eq(false))
+            .select(Self::as_select())
+            .load(conn)
+            .await?)
+    }
+
     pub fn meta(&self) -> GoogleAccountMeta {
         GoogleAccountMeta {
             sub: self.sub.clone(),
@@ -503,13 +1540,14 @@ impl GoogleAccount {
         }
     }
 
+    /// Returns the account and whether it was linked for the first time rather than updated
     pub async fn insert_or_update(
         self,
         conn: &mut impl AsyncConnection<Backend = Pg>,
-    ) -> Result<Self, Error> {
+    ) -> Result<(Self, bool), Error> {
         use schema::googleaccount::dsl as ga_dsl;
 
-        diesel::insert_into(ga_dsl::googleaccount)
+        let inserted = diesel::insert_into(ga_dsl::googleaccount)
             .values(&self)
             .on_conflict(ga_dsl::sub)
             .do_update()
@@ -519,11 +1557,177 @@ impl GoogleAccount {
                 ga_dsl::expires_at.eq(excluded(ga_dsl::expires_at)),
                 ga_dsl::refresh_token.eq(excluded(ga_dsl::refresh_token)),
                 ga_dsl::user_id.eq(excluded(ga_dsl::user_id)),
+                ga_dsl::refreshed_at.eq(diesel::dsl::now),
+                ga_dsl::reauth_required.eq(false),
+            ))
+            .returning(upsert_inserted())
+            .get_result::<bool>(conn)
+            .await?;
+
+        Ok((self, inserted))
+    }
+}
+
+/// Returned by an upsert, true if the row was inserted. Postgres only sets `xmax` on the row when
+/// the conflict was resolved by updating it.
+fn upsert_inserted() -> diesel::expression::SqlLiteral<diesel::sql_types::Bool> {
+    diesel::dsl::sql("xmax = 0")
+}
+
+/// Aggregate over the `CreatorPlatformStats` rows aliased `st`, see
+/// [`CreatorPlatformStats::estimated_reach`]
+pub(crate) const ESTIMATED_REACH_SQL: &str =
+    "COALESCE(SUM(COALESCE(st.followers, st.subscribers)), 0)::BIGINT";
+
+/// Last known audience of a youtube channel or twitch account
+pub struct CreatorPlatformStats<'s> {
+    pub platform: &'static str,
+    pub platform_id: &'s str,
+    pub followers: Option<i64>,
+    pub subscribers: i64,
+}
+
+impl<'s> CreatorPlatformStats<'s> {
+    pub async fn upsert(
+        &self,
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use schema::creatorplatformstats::dsl as cps_dsl;
+
+        diesel::insert_into(cps_dsl::creatorplatformstats)
+            .values((
+                cps_dsl::platform.eq(self.platform),
+                cps_dsl::platform_id.eq(self.platform_id),
+                cps_dsl::user_id.eq(user.id),
+                cps_dsl::followers.eq(self.followers),
+                cps_dsl::subscribers.eq(self.subscribers),
+            ))
+            .on_conflict((cps_dsl::platform, cps_dsl::platform_id))
+            .do_update()
+            .set((
+                cps_dsl::user_id.eq(excluded(cps_dsl::user_id)),
+                cps_dsl::followers.eq(excluded(cps_dsl::followers)),
+                cps_dsl::subscribers.eq(excluded(cps_dsl::subscribers)),
+                cps_dsl::refreshed_at.eq(diesel::dsl::now),
             ))
             .execute(conn)
             .await?;
 
-        Ok(self)
+        Ok(())
+    }
+
+    /// An estimate of how many people the creator reaches, from the stats stored by the last
+    /// refresh. It is the followers of every linked account, or the subscribers where there
+    /// are no followers, added up. Someone following the creator on several platforms is
+    /// counted once for each, so this is an upper bound rather than a count of people.
+    pub async fn estimated_reach(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<i64, Error> {
+        #[derive(QueryableByName)]
+        struct Reach {
+            #[diesel(sql_type = diesel::sql_types::BigInt)]
+            reach: i64,
+        }
+
+        let reach = diesel::sql_query(format!(
+            "SELECT {ESTIMATED_REACH_SQL} AS reach FROM CreatorPlatformStats st \
+            WHERE st.user_id = $1"
+        ))
+        .bind::<diesel::sql_types::Uuid, _>(user.id)
+        .get_result::<Reach>(conn)
+        .await?;
+
+        Ok(reach.reach)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Provider {
+    Google,
+    Twitch,
+}
+
+/// Link health of an account from any provider, worked out without calling the provider
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkedAccount {
+    pub provider: Provider,
+    pub account_id: String,
+    pub email: Option<String>,
+    /// Whether the access token has not expired yet. An expired token is renewed with the
+    /// refresh token on the next use.
+    pub token_valid: bool,
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub expires_at: PrimitiveDateTime,
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub last_refreshed_at: PrimitiveDateTime,
+    /// The provider rejected the refresh token so the account has to be linked again
+    pub reauth_required: bool,
+}
+
+impl LinkedAccount {
+    pub async fn list(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        use schema::googleaccount::dsl as ga_dsl;
+        use schema::twitchaccount::dsl as ta_dsl;
+
+        let now = OffsetDateTime::now_utc();
+        let now = PrimitiveDateTime::new(now.date(), now.time());
+
+        let google_accounts = ga_dsl::googleaccount
+            .filter(ga_dsl::user_id.eq(user.id))
+            .order_by(ga_dsl::sub.asc())
+            .select((
+                ga_dsl::sub,
+                ga_dsl::email,
+                ga_dsl::expires_at,
+                ga_dsl::refreshed_at,
+                ga_dsl::reauth_required,
+            ))
+            .load::<(String, String, PrimitiveDateTime, PrimitiveDateTime, bool)>(conn)
+            .await?;
+        let twitch_accounts = ta_dsl::twitchaccount
+            .filter(ta_dsl::user_id.eq(user.id))
+            .order_by(ta_dsl::id.asc())
+            .select((
+                ta_dsl::id,
+                ta_dsl::expires_at,
+                ta_dsl::refreshed_at,
+                ta_dsl::reauth_required,
+            ))
+            .load::<(String, PrimitiveDateTime, PrimitiveDateTime, bool)>(conn)
+            .await?;
+
+        let mut accounts = Vec::with_capacity(google_accounts.len() + twitch_accounts.len());
+        for (sub, email, expires_at, last_refreshed_at, reauth_required) in google_accounts {
+            accounts.push(LinkedAccount {
+                provider: Provider::Google,
+                account_id: sub,
+                email: Some(email),
+                token_valid: !reauth_required && expires_at > now,
+                expires_at,
+                last_refreshed_at,
+                reauth_required,
+            });
+        }
+        for (id, expires_at, last_refreshed_at, reauth_required) in twitch_accounts {
+            accounts.push(LinkedAccount {
+                provider: Provider::Twitch,
+                account_id: id,
+                email: None,
+                token_valid: !reauth_required && expires_at > now,
+                expires_at,
+                last_refreshed_at,
+                reauth_required,
+            });
+        }
+
+        Ok(accounts)
     }
 }
 
@@ -535,11 +1739,15 @@ pub struct GoogleAccountMeta {
 }
 
 impl AuthenticationHeader for GoogleAccount {
-    const EXTRA_HEADERS: Self::ExtraHeader = [];
+    fn extra_headers(_config: &Config) -> Self::ExtraHeader {
+        []
+    }
 
     type ExtraHeader = [(&'static str, HeaderValue); 0];
     type Session = GoogleSession;
 
+    const PROVIDER: Provider = Provider::Google;
+
     fn access_token(&self) -> &str {
         &self.access_token
     }
@@ -563,4 +1771,136 @@ impl AuthenticationHeader for GoogleAccount {
         self.email = session.email();
         // session.sub does not change so we don't need to update it
     }
+
+    fn account_id(&self) -> &str {
+        &self.sub
+    }
+
+    async fn mark_reauth_required(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use schema::googleaccount::dsl as ga_dsl;
+
+        diesel::update(ga_dsl::googleaccount)
+            .filter(ga_dsl::sub.eq(&self.sub))
+            .set(ga_dsl::reauth_required.eq(true))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn delete_for_user_can_keep_the_current_session() {
+        let mut conn = testing::conn().await;
+        let user = testing::create_user(&mut conn).await;
+        let other = testing::create_user(&mut conn).await;
+        let current = testing::create_session(user, &mut conn).await;
+        let old = testing::create_session(user, &mut conn).await;
+        let others = testing::create_session(other, &mut conn).await;
+
+        let ended = UserSession::delete_for_user(user, Some(&current.token), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(ended, vec![old.token.to_string()]);
+
+        let ended = UserSession::delete_for_user(user, None, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(ended, vec![current.token.to_string()]);
+
+        // Sessions of other users are left alone
+        let ended = UserSession::delete_for_user(other, None, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(ended, vec![others.token.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn user_infos_of_many_users_match_the_single_lookup() {
+        use schema::companyuserprofile::dsl as cup_dsl;
+
+        let mut conn = testing::conn().await;
+        let member = testing::create_user(&mut conn).await;
+        let without_profile = testing::create_user(&mut conn).await;
+        let first = testing::create_company("First Inc", member, &mut conn).await;
+        let second = testing::create_company("Second Inc", member, &mut conn).await;
+        diesel::insert_into(cup_dsl::companyuserprofile)
+            .values((
+                cup_dsl::user_id.eq(member.id),
+                cup_dsl::given_name.eq("Given"),
+                cup_dsl::family_name.eq("Family"),
+                cup_dsl::pronouns.eq(""),
+            ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let mut infos = UserInfo::for_users(&[member.id, without_profile.id], &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(infos.len(), 1);
+
+        let mut info = infos.remove(&member.id).unwrap();
+        info.companies.sort();
+        let mut expected = vec![first, second];
+        expected.sort();
+        assert_eq!(info.profile.given_name, "Given");
+        assert_eq!(info.companies, expected);
+
+        let single = UserInfo::for_user(member, &mut conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(single.profile.given_name, info.profile.given_name);
+    }
+
+    #[tokio::test]
+    async fn purging_keeps_users_with_contract_offers() {
+        use crate::models::{ChatContractOffer, ChatRoom, Message};
+        use schema::inneruser::dsl as dsl_iu;
+
+        let mut conn = testing::conn().await;
+        let owner = testing::create_user(&mut conn).await;
+        let with_offer = testing::create_user(&mut conn).await;
+        let without_offer = testing::create_user(&mut conn).await;
+        let company_id = testing::create_company("Purge Inc", owner, &mut conn).await;
+        for creator in [with_offer, without_offer] {
+            let (room_id, _) = ChatRoom::create(company_id, creator.id, owner.id, &mut conn)
+                .await
+                .unwrap();
+            let (message_id, _) = Message::insert(room_id, owner.id, "offer", &mut conn)
+                .await
+                .unwrap();
+            if creator.id == with_offer.id {
+                ChatContractOffer::create(message_id, 10_000, "USD", &mut conn)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let long_ago = User::deletion_cutoff() - time::Duration::days(1);
+        diesel::update(dsl_iu::inneruser)
+            .filter(dsl_iu::id.eq_any([with_offer.id, without_offer.id]))
+            .set(dsl_iu::deleted_at.eq(long_ago))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        User::purge_deleted(&mut conn).await.unwrap();
+
+        let remaining = dsl_iu::inneruser
+            .filter(dsl_iu::id.eq_any([with_offer.id, without_offer.id]))
+            .select(dsl_iu::id)
+            .load::<Uuid>(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(remaining, vec![with_offer.id]);
+    }
 }
@@ -0,0 +1,128 @@
+use diesel::{
+    pg::Pg, BoolExpressionMethods, ExpressionMethods, JoinOnDsl, NullableExpressionMethods,
+    OptionalExtension, QueryDsl,
+};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use uuid::Uuid;
+
+use crate::Error;
+
+use super::{schema, User};
+
+/// Whether the user gets push notifications, events still reach their open pages either way
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationSettings {
+    pub push: bool,
+}
+
+impl NotificationSettings {
+    pub async fn get(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Self, Error> {
+        use schema::inneruser::dsl as dsl_iu;
+
+        let push = dsl_iu::inneruser
+            .filter(dsl_iu::id.eq(user.id))
+            .select(dsl_iu::push_notifications)
+            .first(conn)
+            .await?;
+
+        Ok(NotificationSettings { push })
+    }
+
+    pub async fn set(
+        &self,
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use schema::inneruser::dsl as dsl_iu;
+
+        diesel::update(dsl_iu::inneruser)
+            .filter(dsl_iu::id.eq(user.id))
+            .set(dsl_iu::push_notifications.eq(self.push))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The setting for an event of the company if given, falling back to the setting of the
+    /// user when they have not set one for the company or are not a member of it
+    pub async fn push_enabled(
+        user_id: Uuid,
+        company_id: Option<Uuid>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<bool, Error> {
+        use schema::companyuser::dsl as dsl_cu;
+        use schema::inneruser::dsl as dsl_iu;
+
+        let settings = dsl_iu::inneruser
+            .filter(dsl_iu::id.eq(user_id))
+            .left_join(
+                dsl_cu::companyuser.on(dsl_cu::user_id
+                    .eq(dsl_iu::id)
+                    .and(dsl_cu::company_id.nullable().eq(company_id))),
+            )
+            .select((
+                dsl_iu::push_notifications,
+                dsl_cu::push_notifications.nullable(),
+            ))
+            .first::<(bool, Option<Option<bool>>)>(conn)
+            .await
+            .optional()?;
+
+        Ok(match settings {
+            Some((global, company)) => company.flatten().unwrap_or(global),
+            None => false,
+        })
+    }
+}
+
+/// Overrides [`NotificationSettings`] for the events of one company
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompanyNotificationSettings {
+    /// `None` follows the setting of the user
+    pub push: Option<bool>,
+}
+
+impl CompanyNotificationSettings {
+    /// `None` if the user is not a member of the company
+    pub async fn get(
+        company_id: Uuid,
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Self>, Error> {
+        use schema::companyuser::dsl as dsl_cu;
+
+        Ok(dsl_cu::companyuser
+            .filter(dsl_cu::company_id.eq(company_id))
+            .filter(dsl_cu::user_id.eq(user.id))
+            .select(dsl_cu::push_notifications)
+            .first::<Option<bool>>(conn)
+            .await
+            .optional()?
+            .map(|push| CompanyNotificationSettings { push }))
+    }
+
+    /// Returns `false` if the user is not a member of the company
+    pub async fn set(
+        &self,
+        company_id: Uuid,
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<bool, Error> {
+        use schema::companyuser::dsl as dsl_cu;
+
+        let updated = diesel::update(dsl_cu::companyuser)
+            .filter(dsl_cu::company_id.eq(company_id))
+            .filter(dsl_cu::user_id.eq(user.id))
+            .set(dsl_cu::push_notifications.eq(self.push))
+            .execute(conn)
+            .await?;
+
+        Ok(updated > 0)
+    }
+}
@@ -1,5 +1,20 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    apikey (id) {
+        id -> Text,
+        secret -> Text,
+        name -> Text,
+        scopes -> Array<Text>,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use pgvector::sql_types::*;
@@ -9,6 +24,7 @@ diesel::table! {
         id -> Int8,
         message_id -> Int8,
         offered_payout -> Money,
+        currency -> Bpchar,
     }
 }
 
@@ -25,6 +41,19 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    chatdraft (room_id, user_id) {
+        room_id -> Uuid,
+        user_id -> Uuid,
+        content -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use pgvector::sql_types::*;
@@ -51,6 +80,19 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    chatreaction (message_id, user_id, emoji) {
+        message_id -> Int8,
+        user_id -> Uuid,
+        emoji -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use pgvector::sql_types::*;
@@ -60,6 +102,7 @@ diesel::table! {
         id -> Uuid,
         company_id -> Uuid,
         user_id -> Uuid,
+        opened_by -> Nullable<Uuid>,
     }
 }
 
@@ -74,7 +117,9 @@ diesel::table! {
         banner_desc -> Text,
         logo_url -> Text,
         embedding -> Vector,
+        logo_source_url -> Nullable<Text>,
         created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -87,6 +132,9 @@ diesel::table! {
         company_id -> Uuid,
         user_id -> Uuid,
         is_admin -> Bool,
+        is_owner -> Bool,
+        joined_at -> Timestamp,
+        push_notifications -> Nullable<Bool>,
     }
 }
 
@@ -100,6 +148,7 @@ diesel::table! {
         company_id -> Uuid,
         will_be_given_admin -> Bool,
         from_user_id -> Uuid,
+        invited_at -> Timestamp,
     }
 }
 
@@ -113,7 +162,37 @@ diesel::table! {
         given_name -> Text,
         family_name -> Text,
         pronouns -> Text,
-        pfp_path -> Text,
+        pfp_path -> Nullable<Text>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    creatorgallery (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        position -> Int4,
+        image_path -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    creatorplatformstats (platform, platform_id) {
+        platform -> Text,
+        platform_id -> Text,
+        user_id -> Uuid,
+        followers -> Nullable<Int8>,
+        subscribers -> Int8,
+        refreshed_at -> Timestamp,
     }
 }
 
@@ -130,8 +209,12 @@ diesel::table! {
         profile_desc -> Text,
         content_desc -> Text,
         audience_desc -> Text,
-        pfp_path -> Text,
+        pfp_path -> Nullable<Text>,
         embedding -> Vector,
+        updated_at -> Timestamp,
+        min_payout -> Nullable<Money>,
+        min_payout_strict -> Bool,
+        tags -> Array<Text>,
     }
 }
 
@@ -147,6 +230,22 @@ diesel::table! {
         expires_at -> Timestamp,
         refresh_token -> Text,
         user_id -> Uuid,
+        refreshed_at -> Timestamp,
+        reauth_required -> Bool,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    idempotencykey (user_id, endpoint, key) {
+        user_id -> Uuid,
+        key -> Text,
+        response -> Nullable<Text>,
+        created_at -> Timestamp,
+        endpoint -> Text,
     }
 }
 
@@ -158,6 +257,10 @@ diesel::table! {
     inneruser (id) {
         id -> Uuid,
         created_at -> Timestamp,
+        last_active_at -> Timestamp,
+        is_admin -> Bool,
+        deleted_at -> Nullable<Timestamp>,
+        push_notifications -> Bool,
     }
 }
 
@@ -184,6 +287,45 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    signedrequest (signature) {
+        signature -> Bytea,
+        key_id -> Text,
+        recorded_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    storedfile (folder, object_id) {
+        folder -> Text,
+        object_id -> Uuid,
+        user_id -> Uuid,
+        path -> Text,
+        size_bytes -> Int8,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    suggestedmatch (creator_id, company_id) {
+        creator_id -> Uuid,
+        company_id -> Uuid,
+        score -> Float4,
+        computed_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use pgvector::sql_types::*;
@@ -195,17 +337,54 @@ diesel::table! {
         expires_at -> Timestamp,
         refresh_token -> Text,
         user_id -> Uuid,
+        refreshed_at -> Timestamp,
+        reauth_required -> Bool,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    webhook (id) {
+        id -> Uuid,
+        company_id -> Uuid,
+        url -> Text,
+        secret -> Text,
+        events -> Array<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use pgvector::sql_types::*;
+    use super::super::sql_types::*;
+
+    webhookdeadletter (id) {
+        id -> Int8,
+        webhook_id -> Uuid,
+        event -> Text,
+        payload -> Text,
+        attempts -> Int4,
+        last_error -> Text,
+        failed_at -> Timestamp,
     }
 }
 
 diesel::joinable!(chatcontractoffer -> chatmessage (message_id));
 diesel::joinable!(chatcontractofferupdate -> chatcontractoffer (offer_id));
 diesel::joinable!(chatcontractofferupdate -> chatmessage (message_id));
+diesel::joinable!(chatdraft -> chatroom (room_id));
+diesel::joinable!(chatdraft -> inneruser (user_id));
 diesel::joinable!(chatlastseen -> chatmessage (last_message_seen_id));
 diesel::joinable!(chatlastseen -> chatroom (room_id));
 diesel::joinable!(chatlastseen -> inneruser (user_id));
 diesel::joinable!(chatmessage -> chatroom (room_id));
 diesel::joinable!(chatmessage -> inneruser (from_user_id));
+diesel::joinable!(chatreaction -> chatmessage (message_id));
+diesel::joinable!(chatreaction -> inneruser (user_id));
 diesel::joinable!(chatroom -> company (company_id));
 diesel::joinable!(chatroom -> inneruser (user_id));
 diesel::joinable!(companyuser -> company (company_id));
@@ -213,26 +392,46 @@ diesel::joinable!(companyuser -> inneruser (user_id));
 diesel::joinable!(companyuserinvitation -> company (company_id));
 diesel::joinable!(companyuserinvitation -> inneruser (from_user_id));
 diesel::joinable!(companyuserprofile -> inneruser (user_id));
+diesel::joinable!(creatorgallery -> inneruser (user_id));
+diesel::joinable!(creatorplatformstats -> inneruser (user_id));
 diesel::joinable!(creatorprofile -> inneruser (user_id));
 diesel::joinable!(googleaccount -> inneruser (user_id));
+diesel::joinable!(idempotencykey -> inneruser (user_id));
 diesel::joinable!(innerusersession -> inneruser (user_id));
 diesel::joinable!(sessionfcmtoken -> innerusersession (session_token));
+diesel::joinable!(signedrequest -> apikey (key_id));
+diesel::joinable!(storedfile -> inneruser (user_id));
+diesel::joinable!(suggestedmatch -> company (company_id));
+diesel::joinable!(suggestedmatch -> creatorprofile (creator_id));
 diesel::joinable!(twitchaccount -> inneruser (user_id));
+diesel::joinable!(webhook -> company (company_id));
+diesel::joinable!(webhookdeadletter -> webhook (webhook_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    apikey,
     chatcontractoffer,
     chatcontractofferupdate,
+    chatdraft,
     chatlastseen,
     chatmessage,
+    chatreaction,
     chatroom,
     company,
     companyuser,
     companyuserinvitation,
     companyuserprofile,
+    creatorgallery,
+    creatorplatformstats,
     creatorprofile,
     googleaccount,
+    idempotencykey,
     inneruser,
     innerusersession,
     sessionfcmtoken,
+    signedrequest,
+    storedfile,
+    suggestedmatch,
     twitchaccount,
+    webhook,
+    webhookdeadletter,
 );
@@ -0,0 +1,48 @@
+use diesel::{pg::Pg, ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+
+use crate::Error;
+
+use super::schema;
+
+/// Top-line counts of the platform
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformStats {
+    pub users: i64,
+    pub creators: i64,
+    pub companies: i64,
+    pub active_sessions: i64,
+    pub messages_last_day: i64,
+}
+
+impl PlatformStats {
+    pub async fn count(conn: &mut impl AsyncConnection<Backend = Pg>) -> Result<Self, Error> {
+        use schema::{
+            chatmessage::dsl as cm_dsl, company::dsl as c_dsl, creatorprofile::dsl as cp_dsl,
+            inneruser::dsl as iu_dsl, innerusersession::dsl as ius_dsl,
+        };
+
+        let now = OffsetDateTime::now_utc();
+        let day_ago = now - Duration::days(1);
+
+        Ok(PlatformStats {
+            users: iu_dsl::inneruser.count().get_result(conn).await?,
+            creators: cp_dsl::creatorprofile.count().get_result(conn).await?,
+            companies: c_dsl::company.count().get_result(conn).await?,
+            active_sessions: ius_dsl::innerusersession
+                .filter(ius_dsl::expires_at.gt(PrimitiveDateTime::new(now.date(), now.time())))
+                .count()
+                .get_result(conn)
+                .await?,
+            messages_last_day: cm_dsl::chatmessage
+                .filter(
+                    cm_dsl::created_at.gt(PrimitiveDateTime::new(day_ago.date(), day_ago.time())),
+                )
+                .count()
+                .get_result(conn)
+                .await?,
+        })
+    }
+}
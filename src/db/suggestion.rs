@@ -0,0 +1,212 @@
+use diesel::{pg::Pg, sql_types, QueryableByName};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+use crate::{
+    state::Config,
+    storage::{fill_default_avatar, StoredImages},
+    Error,
+};
+
+use super::User;
+
+/// How many matches are kept for each creator and for each company
+const MATCHES_PER_PARTY: i64 = 20;
+
+/// A company suggested to a creator
+#[derive(QueryableByName, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompanySuggestion {
+    #[diesel(sql_type = sql_types::Uuid)]
+    pub company_id: Uuid,
+    #[diesel(sql_type = sql_types::Text)]
+    pub full_name: String,
+    #[diesel(sql_type = sql_types::Text)]
+    pub banner_desc: String,
+    #[diesel(sql_type = sql_types::Text)]
+    pub logo_url: String,
+    /// Cosine similarity of the embeddings, higher is a better match
+    #[diesel(sql_type = sql_types::Float)]
+    pub score: f32,
+    #[diesel(sql_type = sql_types::Timestamp)]
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub computed_at: PrimitiveDateTime,
+}
+
+impl StoredImages for CompanySuggestion {
+    fn resolve_urls(&mut self, config: &Config) {
+        self.logo_url.resolve_urls(config);
+    }
+}
+
+/// A creator suggested to one of the companies of a user
+#[derive(QueryableByName, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatorSuggestion {
+    #[diesel(sql_type = sql_types::Uuid)]
+    pub company_id: Uuid,
+    #[diesel(sql_type = sql_types::Uuid)]
+    pub creator_id: Uuid,
+    #[diesel(sql_type = sql_types::Text)]
+    pub given_name: String,
+    #[diesel(sql_type = sql_types::Text)]
+    pub family_name: String,
+    #[diesel(sql_type = sql_types::Nullable<sql_types::Text>)]
+    pub pfp_path: Option<String>,
+    /// Cosine similarity of the embeddings, higher is a better match
+    #[diesel(sql_type = sql_types::Float)]
+    pub score: f32,
+    /// An estimate, see [`super::CreatorPlatformStats::estimated_reach`]
+    #[diesel(sql_type = sql_types::BigInt)]
+    pub estimated_reach: i64,
+    #[diesel(sql_type = sql_types::Array<sql_types::Text>)]
+    pub tags: Vec<String>,
+    #[diesel(sql_type = sql_types::Timestamp)]
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub computed_at: PrimitiveDateTime,
+}
+
+impl StoredImages for CreatorSuggestion {
+    fn resolve_urls(&mut self, config: &Config) {
+        fill_default_avatar(
+            config.default_avatar,
+            &mut self.pfp_path,
+            &self.given_name,
+            &self.family_name,
+        );
+        self.pfp_path.resolve_urls(config);
+    }
+}
+
+/// How the suggested creators of each company are ordered
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CreatorSort {
+    /// Best matches first
+    #[default]
+    Score,
+    /// Largest estimated reach first
+    Reach,
+}
+
+impl CreatorSort {
+    fn order_by(self) -> &'static str {
+        match self {
+            CreatorSort::Score => "s.score DESC",
+            CreatorSort::Reach => "reach.estimated_reach DESC, s.score DESC",
+        }
+    }
+}
+
+/// Recomputes the best matching companies of every creator and the best matching creators of
+/// every company. Pairs that already have a room or share a company are never suggested.
+pub async fn refresh_suggestions(
+    conn: &mut impl AsyncConnection<Backend = Pg>,
+) -> Result<(), Error> {
+    let now = OffsetDateTime::now_utc();
+    let now = PrimitiveDateTime::new(now.date(), now.time());
+
+    // Both directions are upserted into the same table, a pair can be picked by either side
+    for query in [
+        "INSERT INTO SuggestedMatch (creator_id, company_id, score, computed_at) \
+        SELECT cp.user_id, c.id, 1 - c.distance, $2 \
+        FROM CreatorProfile cp \
+        JOIN InnerUser u ON u.id = cp.user_id AND u.deleted_at IS NULL \
+        CROSS JOIN LATERAL (SELECT c.id, c.embedding <=> cp.embedding AS distance FROM Company c \
+            WHERE NOT EXISTS (SELECT 1 FROM ChatRoom r \
+                WHERE r.company_id = c.id AND r.user_id = cp.user_id) \
+            AND NOT EXISTS (SELECT 1 FROM CompanyUser cu \
+                WHERE cu.company_id = c.id AND cu.user_id = cp.user_id) \
+            ORDER BY distance LIMIT $1) c \
+        ON CONFLICT (creator_id, company_id) \
+        DO UPDATE SET score = excluded.score, computed_at = excluded.computed_at",
+        "INSERT INTO SuggestedMatch (creator_id, company_id, score, computed_at) \
+        SELECT cp.user_id, c.id, 1 - cp.distance, $2 \
+        FROM Company c \
+        CROSS JOIN LATERAL (SELECT cp.user_id, cp.embedding <=> c.embedding AS distance \
+            FROM CreatorProfile cp \
+            JOIN InnerUser u ON u.id = cp.user_id AND u.deleted_at IS NULL \
+            WHERE NOT EXISTS (SELECT 1 FROM ChatRoom r \
+                WHERE r.company_id = c.id AND r.user_id = cp.user_id) \
+            AND NOT EXISTS (SELECT 1 FROM CompanyUser cu \
+                WHERE cu.company_id = c.id AND cu.user_id = cp.user_id) \
+            ORDER BY distance LIMIT $1) cp \
+        ON CONFLICT (creator_id, company_id) \
+        DO UPDATE SET score = excluded.score, computed_at = excluded.computed_at",
+    ] {
+        diesel::sql_query(query)
+            .bind::<sql_types::BigInt, _>(MATCHES_PER_PARTY)
+            .bind::<sql_types::Timestamp, _>(now)
+            .execute(conn)
+            .await?;
+    }
+
+    // Pairs that were not picked again have dropped out of the top matches
+    diesel::sql_query("DELETE FROM SuggestedMatch WHERE computed_at < $1")
+        .bind::<sql_types::Timestamp, _>(now)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+impl CompanySuggestion {
+    /// Best matches first, leaving out companies contacted since the suggestions were computed
+    pub async fn list(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        Ok(diesel::sql_query(
+            "SELECT c.id AS company_id, c.full_name, c.banner_desc, c.logo_url, s.score, \
+                s.computed_at \
+            FROM SuggestedMatch s \
+            JOIN Company c ON c.id = s.company_id \
+            WHERE s.creator_id = $1 \
+            AND NOT EXISTS (SELECT 1 FROM ChatRoom r \
+                WHERE r.company_id = s.company_id AND r.user_id = s.creator_id) \
+            ORDER BY s.score DESC \
+            LIMIT $2",
+        )
+        .bind::<sql_types::Uuid, _>(user.id)
+        .bind::<sql_types::BigInt, _>(MATCHES_PER_PARTY)
+        .load(conn)
+        .await?)
+    }
+}
+
+impl CreatorSuggestion {
+    /// Suggested creators grouped by company of the user in the order of `sort`, leaving out
+    /// creators contacted since the suggestions were computed, those reaching less than
+    /// `min_reach` and those missing any of `tags`
+    pub async fn list(
+        user: User,
+        sort: CreatorSort,
+        min_reach: Option<i64>,
+        tags: &[String],
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        Ok(diesel::sql_query(format!(
+            "SELECT s.company_id, s.creator_id, cp.given_name, cp.family_name, cp.pfp_path, \
+                s.score, reach.estimated_reach, cp.tags, s.computed_at \
+            FROM SuggestedMatch s \
+            JOIN CompanyUser cu ON cu.company_id = s.company_id AND cu.user_id = $1 \
+            JOIN CreatorProfile cp ON cp.user_id = s.creator_id \
+            JOIN InnerUser u ON u.id = s.creator_id AND u.deleted_at IS NULL \
+            CROSS JOIN LATERAL (SELECT {} AS estimated_reach FROM CreatorPlatformStats st \
+                WHERE st.user_id = s.creator_id) reach \
+            WHERE NOT EXISTS (SELECT 1 FROM ChatRoom r \
+                WHERE r.company_id = s.company_id AND r.user_id = s.creator_id) \
+            AND ($2::BIGINT IS NULL OR reach.estimated_reach >= $2) \
+            AND cp.tags @> $3 \
+            ORDER BY s.company_id, {}",
+            super::ESTIMATED_REACH_SQL,
+            sort.order_by(),
+        ))
+        .bind::<sql_types::Uuid, _>(user.id)
+        .bind::<sql_types::Nullable<sql_types::BigInt>, _>(min_reach)
+        .bind::<sql_types::Array<sql_types::Text>, _>(tags)
+        .load(conn)
+        .await?)
+    }
+}
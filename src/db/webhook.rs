@@ -0,0 +1,227 @@
+use diesel::{
+    pg::Pg, ExpressionMethods, OptionalExtension, PgArrayExpressionMethods, QueryDsl, Queryable,
+    Selectable, SelectableHelper,
+};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use crate::Error;
+
+use super::schema;
+
+/// Events companies can subscribe their webhooks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum WebhookEvent {
+    /// A message was posted in a room of the company
+    #[serde(rename = "message.created")]
+    MessageCreated,
+    /// A creator accepted a contract offer of the company
+    #[serde(rename = "contract.accepted")]
+    ContractAccepted,
+}
+
+impl WebhookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::MessageCreated => "message.created",
+            WebhookEvent::ContractAccepted => "contract.accepted",
+        }
+    }
+}
+
+fn event_names(events: &[WebhookEvent]) -> Vec<&'static str> {
+    events.iter().map(|event| event.as_str()).collect()
+}
+
+/// A url notified of the events of a company, see [`crate::webhook`]
+#[derive(Clone, Queryable, Selectable, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[diesel(table_name = schema::webhook)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Webhook {
+    pub id: Uuid,
+    pub company_id: Uuid,
+    pub url: String,
+    /// Only returned once when the webhook is created
+    #[serde(skip)]
+    pub secret: String,
+    pub events: Vec<String>,
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub created_at: PrimitiveDateTime,
+}
+
+impl Webhook {
+    pub async fn list(
+        company_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        use schema::webhook::dsl as wh_dsl;
+
+        Ok(wh_dsl::webhook
+            .filter(wh_dsl::company_id.eq(company_id))
+            .order_by(wh_dsl::created_at.asc())
+            .select(Self::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    /// The webhooks of the company that are subscribed to the event
+    pub async fn subscribed(
+        company_id: Uuid,
+        event: WebhookEvent,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        use schema::webhook::dsl as wh_dsl;
+
+        Ok(wh_dsl::webhook
+            .filter(wh_dsl::company_id.eq(company_id))
+            .filter(wh_dsl::events.contains(vec![event.as_str()]))
+            .select(Self::as_select())
+            .load(conn)
+            .await?)
+    }
+
+    pub async fn count(
+        company_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<i64, Error> {
+        use schema::webhook::dsl as wh_dsl;
+
+        Ok(wh_dsl::webhook
+            .filter(wh_dsl::company_id.eq(company_id))
+            .count()
+            .get_result(conn)
+            .await?)
+    }
+
+    /// Creates the webhook with a new random secret
+    pub async fn create(
+        company_id: Uuid,
+        url: &str,
+        events: &[WebhookEvent],
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Self, Error> {
+        use rand::Rng;
+        use schema::webhook::dsl as wh_dsl;
+
+        let secret = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(64)
+            .map(char::from)
+            .collect::<String>();
+
+        Ok(diesel::insert_into(wh_dsl::webhook)
+            .values((
+                wh_dsl::id.eq(Uuid::new_v4()),
+                wh_dsl::company_id.eq(company_id),
+                wh_dsl::url.eq(url),
+                wh_dsl::secret.eq(secret),
+                wh_dsl::events.eq(event_names(events)),
+            ))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await?)
+    }
+
+    /// Replaces the url and the events, returns `None` if the company has no webhook of this id
+    pub async fn update(
+        company_id: Uuid,
+        id: Uuid,
+        url: &str,
+        events: &[WebhookEvent],
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Self>, Error> {
+        use schema::webhook::dsl as wh_dsl;
+
+        Ok(diesel::update(wh_dsl::webhook)
+            .filter(wh_dsl::company_id.eq(company_id))
+            .filter(wh_dsl::id.eq(id))
+            .set((wh_dsl::url.eq(url), wh_dsl::events.eq(event_names(events))))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .await
+            .optional()?)
+    }
+
+    /// Returns `false` if the company has no webhook of this id
+    pub async fn delete(
+        company_id: Uuid,
+        id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<bool, Error> {
+        use schema::webhook::dsl as wh_dsl;
+
+        let deleted = diesel::delete(wh_dsl::webhook)
+            .filter(wh_dsl::company_id.eq(company_id))
+            .filter(wh_dsl::id.eq(id))
+            .execute(conn)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+}
+
+/// A delivery that still failed after every retry, kept so the company can see what it missed
+#[derive(Queryable, Selectable, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[diesel(table_name = schema::webhookdeadletter)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct WebhookDeadLetter {
+    pub id: i64,
+    pub webhook_id: Uuid,
+    pub event: String,
+    /// The JSON body that was sent
+    pub payload: String,
+    pub attempts: i32,
+    pub last_error: String,
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub failed_at: PrimitiveDateTime,
+}
+
+impl WebhookDeadLetter {
+    /// How many are listed at once, newest first
+    const LIST_LIMIT: i64 = 100;
+
+    pub async fn insert(
+        webhook_id: Uuid,
+        event: WebhookEvent,
+        payload: &str,
+        attempts: i32,
+        last_error: &str,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use schema::webhookdeadletter::dsl as dl_dsl;
+
+        diesel::insert_into(dl_dsl::webhookdeadletter)
+            .values((
+                dl_dsl::webhook_id.eq(webhook_id),
+                dl_dsl::event.eq(event.as_str()),
+                dl_dsl::payload.eq(payload),
+                dl_dsl::attempts.eq(attempts),
+                dl_dsl::last_error.eq(last_error),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The latest failed deliveries of every webhook of the company
+    pub async fn list(
+        company_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
+        use schema::webhook::dsl as wh_dsl;
+        use schema::webhookdeadletter::dsl as dl_dsl;
+
+        Ok(dl_dsl::webhookdeadletter
+            .inner_join(wh_dsl::webhook)
+            .filter(wh_dsl::company_id.eq(company_id))
+            .order_by(dl_dsl::failed_at.desc())
+            .limit(Self::LIST_LIMIT)
+            .select(Self::as_select())
+            .load(conn)
+            .await?)
+    }
+}
@@ -1,4 +1,4 @@
-mod youtube;
+pub mod youtube;
 
 use axum::{routing, Json, Router};
 use diesel::pg::Pg;
@@ -9,8 +9,11 @@ use time::PrimitiveDateTime;
 
 use crate::{
     db::{GoogleAccount, User},
-    state::DbConn,
-    utils::{oauth::OAuthAccountHelper, AuthenticationHeader},
+    state::{Config, DbConn, HttpClient, MsgEmitter},
+    utils::{
+        oauth::{OAuthAccountHelper, OAuthCredentials},
+        AuthenticationHeader,
+    },
     Error,
 };
 
@@ -55,12 +58,14 @@ impl GoogleSession {
 }
 
 impl OAuthAccountHelper for GoogleSession {
-    const CLIENT_ID: &'static str = "<GoogleID>";
-    const CLIENT_SECRET: &'static str = "<GoogleSecret>";
     const AUTH_URL: &'static str = "https://accounts.google.com/o/oauth2/v2/auth";
     const TOKEN_URL: &'static str = "https://oauth2.googleapis.com/token";
     const AUTH_TYPE: oauth2::AuthType = oauth2::AuthType::BasicAuth;
 
+    fn credentials(config: &Config) -> OAuthCredentials {
+        config.google_oauth
+    }
+
     type ExtraFields = IdToken;
     type Account = GoogleAccount;
     type Response = Vec<youtube::Channel>;
@@ -70,6 +75,8 @@ impl OAuthAccountHelper for GoogleSession {
         expires_at: PrimitiveDateTime,
         refresh_token: RefreshToken,
         extra_fields: &Self::ExtraFields,
+        _client: &reqwest::Client,
+        _credentials: OAuthCredentials,
     ) -> Result<Self, Error> {
         let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
         validation.insecure_disable_signature_validation();
@@ -96,7 +103,7 @@ impl OAuthAccountHelper for GoogleSession {
         &self,
         user: User,
         conn: &mut impl AsyncConnection<Backend = Pg>,
-    ) -> Result<Self::Account, Error> {
+    ) -> Result<(Self::Account, bool), Error> {
         GoogleAccount {
             sub: self.sub.clone(),
             email: self.email.clone(),
@@ -118,6 +125,7 @@ pub fn router() -> Router<crate::state::AppState> {
 }
 
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 struct ProfilePhoto {
     primary: bool,
     url: String,
@@ -127,6 +135,9 @@ impl ProfilePhoto {
     async fn list(
         user: User,
         DbConn { mut conn }: DbConn,
+        HttpClient { client }: HttpClient,
+        emitter: MsgEmitter,
+        config: Config,
     ) -> Result<Json<Vec<ProfilePhoto>>, Error> {
         #[derive(serde::Deserialize)]
         struct Response {
@@ -146,30 +157,28 @@ impl ProfilePhoto {
         }
 
         let mut photos = Vec::default();
-        let client = reqwest::Client::default();
 
         let accounts = GoogleAccount::list(user, &mut conn).await?;
 
         let mut account_headers = Vec::with_capacity(accounts.len());
         for mut account in accounts {
-            let headers = account.headers(&mut conn).await?;
+            let headers = account
+                .headers(client, &emitter, &config, &mut conn)
+                .await?;
             account_headers.push(headers);
         }
 
         let mut responses = futures::stream::iter(account_headers)
-            .map(|headers| {
-                let client = client.clone();
-                async move {
-                    let req = client
-                        .get("https://people.googleapis.com/v1/people/me?personFields=photos")
-                        .headers(headers)
-                        .build()?;
-                    let resp: Response = client.execute(req).await?.json().await?;
-
-                    Result::<_, Error>::Ok(resp)
-                }
+            .map(|headers| async move {
+                let req = client
+                    .get("https://people.googleapis.com/v1/people/me?personFields=photos")
+                    .headers(headers)
+                    .build()?;
+                let resp: Response = client.execute(req).await?.json().await?;
+
+                Result::<_, Error>::Ok(resp)
             })
-            .buffer_unordered(10);
+            .buffer_unordered(config.provider_concurrency);
 
         while let Some(response) = responses.next().await {
             for photo in response?.photos {
@@ -5,7 +5,7 @@ use futures::StreamExt;
 
 use crate::{
     db::{GoogleAccount, GoogleAccountMeta, User},
-    state::DbConn,
+    state::{Config, DbConn, HttpClient, MsgEmitter},
     utils::{AuthenticationHeader, GetDetail},
     Error,
 };
@@ -72,21 +72,28 @@ impl GetDetail for Vec<Channel> {
 }
 
 impl Channel {
-    pub async fn list(user: User, DbConn { mut conn }: DbConn) -> Result<Json<Vec<Self>>, Error> {
+    pub async fn list(
+        user: User,
+        DbConn { mut conn }: DbConn,
+        HttpClient { client }: HttpClient,
+        emitter: MsgEmitter,
+        config: Config,
+    ) -> Result<Json<Vec<Self>>, Error> {
         let accounts = GoogleAccount::list(user, &mut conn).await?;
         let mut channels = Vec::default();
 
         let mut acc_and_headers = Vec::with_capacity(accounts.len());
         for mut account in accounts {
-            let headers = account.headers(&mut conn).await?;
+            let headers = account
+                .headers(client, &emitter, &config, &mut conn)
+                .await?;
             acc_and_headers.push((account, headers));
         }
         let mut channels_iter = futures::stream::iter(acc_and_headers.into_iter())
-            .map(|(mut account, headers)| {
-                let client = reqwest::Client::default();
-                async move { Vec::<Self>::get(&mut account, &client, headers).await }
+            .map(|(mut account, headers)| async move {
+                Vec::<Self>::get(&mut account, client, headers).await
             })
-            .buffer_unordered(10);
+            .buffer_unordered(config.provider_concurrency);
 
         while let Some(channel) = channels_iter.next().await {
             channels.extend(channel?);
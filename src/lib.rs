@@ -2,45 +2,198 @@
 #![feature(lazy_cell)]
 #![feature(let_chains)]
 
+mod accounts;
+mod admin;
 mod chat;
 mod company;
 mod creator;
 mod db;
 mod google;
+mod me;
 pub mod models;
+mod presence;
 pub mod schema;
+mod service;
+mod session;
 mod state;
 mod storage;
+mod suggestions;
+#[cfg(test)]
+pub mod testing;
 mod twitch;
+mod user;
 mod utils;
+mod webhook;
 mod ws;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+};
 
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::{Html, IntoResponse},
-    routing, Router,
+    routing, Json, Router,
 };
 use diesel::{pg::Pg, Connection};
 use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use diesel_migrations::MigrationHarness;
 use time::Duration;
 use tokio::sync::mpsc;
-use tower_http::services::ServeDir;
+use tower_http::{
+    compression::{
+        predicate::{DefaultPredicate, SizeAbove},
+        Predicate,
+    },
+    services::ServeDir,
+};
 
 pub const MIGRATIONS: diesel_migrations::EmbeddedMigrations =
     diesel_migrations::embed_migrations!("migrations");
 
+/// Default names of the cookies, the names actually used are in [`state::CookieNames`]
 pub const SESSION_COOKIE_NAME: &str = "MERCANT-SESSION";
 pub const USER_ID_COOKIE_NAME: &str = "MERCANT-USER-ID";
 pub const SESSION_COOKIE_DURATION: Duration = Duration::days(90);
+pub const IDEMPOTENCY_KEY_DURATION: Duration = Duration::days(1);
+/// A request that has not recorded its response by then is taken to have died, e.g. because the
+/// client disconnected, and its Idempotency-Key can be reused
+pub const IDEMPOTENCY_KEY_PROCESSING_TIMEOUT: Duration = Duration::minutes(5);
+pub const LAST_ACTIVE_UPDATE_INTERVAL: Duration = Duration::minutes(5);
+/// How long a deleted user can be restored before they are removed for good
+pub const USER_DELETION_GRACE_PERIOD: Duration = Duration::days(30);
+/// Invites older than this are listed as expired until they are resent
+pub const INVITE_EXPIRY: Duration = Duration::days(14);
+/// Largest request body accepted, which bounds the size of uploaded images
+pub const MAX_UPLOAD_BYTES: usize = 2 * 1024 * 1024;
+/// Responses smaller than this are sent as is, compressing them saves next to nothing
+pub const MIN_COMPRESSED_BYTES: u16 = 1024;
+pub const WS_TICKET_DURATION: std::time::Duration = std::time::Duration::from_secs(30);
+/// How many messages can be queued for a websocket before events to it start getting dropped
+pub const WS_SEND_BUFFER: usize = 256;
+/// How long a method response can wait for room in the queue, or a message for the socket to
+/// accept it, before the connection is closed
+pub const WS_SEND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// How often the server pings a websocket. Browsers answer pings on their own so a healthy but
+/// quiet client still resets its heartbeat timeout.
+pub const WS_PING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+/// How long a websocket can go without receiving anything, not even a pong, before it is
+/// considered dead
+pub const WS_HEARTBEAT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(90);
+/// How often an open websocket checks that its session was not logged out or deleted
+pub const WS_AUTH_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_mins(1);
+
+pub const WS_CALL_RATE_LIMIT: utils::rate_limit::RateLimit = utils::rate_limit::RateLimit {
+    max: 120,
+    window: std::time::Duration::from_mins(1),
+};
+
+pub const NAME_AVAILABILITY_RATE_LIMIT: utils::rate_limit::RateLimit =
+    utils::rate_limit::RateLimit {
+        max: 30,
+        window: std::time::Duration::from_mins(1),
+    };
+
+pub const INVITE_RESEND_RATE_LIMIT: utils::rate_limit::RateLimit = utils::rate_limit::RateLimit {
+    max: 1,
+    window: std::time::Duration::from_mins(10),
+};
+
+/// How many rooms a user can open, a room that already exists does not count
+pub const ROOM_CREATION_RATE_LIMIT: utils::rate_limit::RateLimit = utils::rate_limit::RateLimit {
+    max: 10,
+    window: std::time::Duration::from_hours(1),
+};
+
+/// How many contract changes a user can post in a room. Kept apart from `MESSAGE_RATE_LIMIT` so
+/// a busy room never holds up a deal.
+pub const CONTRACT_CHANGE_RATE_LIMIT: utils::rate_limit::RateLimit = utils::rate_limit::RateLimit {
+    max: 5,
+    window: std::time::Duration::from_mins(1),
+};
+
+/// How many rooms without any messages a creator, or a company, can have before opening more
+pub const MAX_EMPTY_ROOMS: i64 = 5;
+
+/// How often the logo of a company can be fetched again from its source url
+pub const LOGO_REFRESH_RATE_LIMIT: utils::rate_limit::RateLimit = utils::rate_limit::RateLimit {
+    max: 5,
+    window: std::time::Duration::from_hours(1),
+};
+
+/// Consecutive failed sends after which the fcm client is rebuilt
+pub const FCM_FAILURES_BEFORE_REBUILD: u32 = 5;
+pub const FCM_REBUILD_MAX_BACKOFF: std::time::Duration = std::time::Duration::from_mins(5);
+
+pub const ADMIN_STATS_CACHE_DURATION: std::time::Duration = std::time::Duration::from_secs(30);
+/// How far the timestamp of a signed service request can be from now, either way
+pub const SIGNED_REQUEST_MAX_AGE: std::time::Duration = std::time::Duration::from_mins(5);
+
+/// Deliveries of a webhook event, the delay between them doubles after every failure
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+pub const WEBHOOK_RETRY_BACKOFF: std::time::Duration = std::time::Duration::from_secs(10);
+pub const MAX_WEBHOOKS_PER_COMPANY: i64 = 10;
+/// Creators whose profiles can be fetched in one request, enough for a page of search results
+pub const MAX_BULK_CREATOR_PROFILES: usize = 100;
 
 pub const MAINTENANCE_INTERVAL: std::time::Duration = std::time::Duration::from_days(1);
+pub const SUGGESTION_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_hours(6);
+
+pub const DEFAULT_LOG_FILTER: &str = "info";
+
+/// Re-checks [`chat::may_deliver`] right before a queued message is sent to its fcm token.
+/// Messages are dropped if the check cannot be made.
+async fn fcm_deliverable(
+    msg: &fcm::Message,
+    pool: &diesel_async::pooled_connection::deadpool::Pool<AsyncPgConnection>,
+) -> bool {
+    let fcm::Target::Token(token) = &msg.target else {
+        return true;
+    };
+
+    let deliverable = async {
+        let mut conn = pool.get().await?;
+        let Some(session_token) = models::SessionFcmToken::session_token(token, &mut conn).await?
+        else {
+            return Ok(false);
+        };
+
+        chat::may_deliver(&session_token, msg.data.as_ref(), &mut conn).await
+    };
+
+    match deliverable.await {
+        Ok(deliverable) => deliverable,
+        Err::<_, Error>(err) => {
+            tracing::error!("Failed to check if an fcm message can still be delivered: {err:?}");
+            false
+        }
+    }
+}
+
+/// Sets up logging with the filter from `RUST_LOG` and the format from `LOG_FORMAT`, which is
+/// either `pretty` (the default) or `json`
+fn init_tracing() {
+    let filter = dotenvy::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_FILTER.into());
+    let filter = tracing_subscriber::EnvFilter::try_new(&filter).unwrap_or_else(|err| {
+        eprintln!("Invalid RUST_LOG filter {filter:?}: {err}, using {DEFAULT_LOG_FILTER:?}");
+        tracing_subscriber::EnvFilter::new(DEFAULT_LOG_FILTER)
+    });
+
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match dotenvy::var("LOG_FORMAT").as_deref() {
+        Ok("json") => subscriber.json().init(),
+        Ok("pretty") | Err(_) => subscriber.init(),
+        Ok(format) => {
+            subscriber.init();
+            tracing::warn!("Unknown LOG_FORMAT {format:?}, using pretty logs");
+        }
+    }
+}
 
 pub async fn run() {
-    tracing_subscriber::fmt::init();
+    init_tracing();
 
     let db_url = &*dotenvy::var("DATABASE_URL")
         .expect("Failed to get DATABASE_URL")
@@ -50,6 +203,155 @@ pub async fn run() {
             .expect("Failed to get STORAGE_PATH")
             .leak(),
     );
+    let static_path = std::path::Path::new(
+        &*dotenvy::var("STATIC_PATH")
+            .unwrap_or_else(|_| "frontend/build".into())
+            .leak(),
+    );
+
+    let embedding_rate_limit = utils::rate_limit::RateLimit {
+        max: dotenvy::var("EMBEDDING_RATE_LIMIT")
+            .ok()
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(10),
+        window: std::time::Duration::from_mins(1),
+    };
+
+    let message_rate_limit = utils::rate_limit::RateLimit {
+        max: dotenvy::var("MESSAGE_RATE_LIMIT")
+            .ok()
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(10),
+        window: std::time::Duration::from_secs(10),
+    };
+
+    let storage_quota = storage::StorageQuota {
+        creator_bytes: dotenvy::var("STORAGE_QUOTA_CREATOR_BYTES")
+            .ok()
+            .and_then(|quota| quota.parse().ok())
+            .unwrap_or(50 * 1024 * 1024),
+        company_member_bytes: dotenvy::var("STORAGE_QUOTA_COMPANY_MEMBER_BYTES")
+            .ok()
+            .and_then(|quota| quota.parse().ok())
+            .unwrap_or(200 * 1024 * 1024),
+    };
+
+    let http_timeouts = state::HttpTimeouts {
+        connect: std::time::Duration::from_secs(
+            dotenvy::var("HTTP_CONNECT_TIMEOUT_SECS")
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or(5),
+        ),
+        total: std::time::Duration::from_secs(
+            dotenvy::var("HTTP_TIMEOUT_SECS")
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or(30),
+        ),
+    };
+
+    let remote_image_policy = storage::RemoteImagePolicy {
+        allow_private_ips: dotenvy::var("REMOTE_IMAGE_ALLOW_PRIVATE_IPS")
+            .is_ok_and(|allow| allow == "1" || allow == "true"),
+        allowed_hosts: dotenvy::var("REMOTE_IMAGE_ALLOWED_HOSTS")
+            .ok()
+            .map(|hosts| {
+                &*hosts
+                    .split(',')
+                    .map(|host| host.trim().to_ascii_lowercase())
+                    .filter(|host| !host.is_empty())
+                    .collect::<Vec<_>>()
+                    .leak()
+            }),
+    };
+
+    let public_base_url = dotenvy::var("PUBLIC_BASE_URL")
+        .ok()
+        .map(|base_url| base_url.trim().trim_end_matches('/').to_string())
+        .filter(|base_url| !base_url.is_empty())
+        .map(|base_url| &*base_url.leak());
+
+    // A generated avatar of the initials is used unless an image is configured
+    let default_avatar = match dotenvy::var("DEFAULT_AVATAR_URL") {
+        Ok(url) if !url.trim().is_empty() => {
+            storage::DefaultAvatar::Url(url.trim().to_string().leak())
+        }
+        _ => storage::DefaultAvatar::Initials,
+    };
+
+    let embedding_templates = db::EmbeddingTemplates {
+        creator: dotenvy::var("EMBEDDING_CREATOR_TEMPLATE")
+            .map(|template| &*template.leak())
+            .unwrap_or(db::EmbeddingTemplates::DEFAULT_CREATOR),
+        company: dotenvy::var("EMBEDDING_COMPANY_TEMPLATE")
+            .map(|template| &*template.leak())
+            .unwrap_or(db::EmbeddingTemplates::DEFAULT_COMPANY),
+    };
+    let missing_placeholders = embedding_templates.missing_placeholders();
+    assert!(
+        missing_placeholders.is_empty(),
+        "The embedding templates are missing the placeholders: {missing_placeholders:?}"
+    );
+
+    let stats_refresh = state::StatsRefresh {
+        batch_size: dotenvy::var("STATS_REFRESH_BATCH_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(50),
+        interval: std::time::Duration::from_secs(
+            dotenvy::var("STATS_REFRESH_INTERVAL_SECS")
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or(60 * 60),
+        ),
+    };
+
+    let provider_concurrency = dotenvy::var("PROVIDER_CONCURRENCY")
+        .ok()
+        .and_then(|concurrency| concurrency.parse().ok())
+        .filter(|concurrency| *concurrency > 0)
+        .unwrap_or(10);
+
+    let ws_idle_timeout = std::time::Duration::from_mins(
+        dotenvy::var("WS_IDLE_TIMEOUT_MINS")
+            .ok()
+            .and_then(|mins| mins.parse().ok())
+            .filter(|mins| *mins > 0)
+            .unwrap_or(30),
+    );
+
+    let cookie_prefix = match dotenvy::var("COOKIE_PREFIX").as_deref().map(str::trim) {
+        Ok("__Host-") => state::CookiePrefix::Host,
+        Ok("__Secure-") => state::CookiePrefix::Secure,
+        Ok("") | Err(_) => state::CookiePrefix::None,
+        Ok(prefix) => panic!("COOKIE_PREFIX must be __Host- or __Secure-, not {prefix}"),
+    };
+    let cookies = state::CookieNames::new(
+        cookie_prefix,
+        &dotenvy::var("SESSION_COOKIE_NAME").unwrap_or_else(|_| SESSION_COOKIE_NAME.into()),
+        &dotenvy::var("USER_ID_COOKIE_NAME").unwrap_or_else(|_| USER_ID_COOKIE_NAME.into()),
+    );
+
+    // Read here so a missing client secret stops the server before it accepts any logins
+    let google_oauth = utils::oauth::OAuthCredentials::from_env("GOOGLE");
+    let twitch_oauth = utils::oauth::OAuthCredentials::from_env("TWITCH");
+
+    // Analytics events are only logged unless a webhook is set up to receive them
+    let analytics_webhook = dotenvy::var("ANALYTICS_WEBHOOK_URL").ok().map(|url| {
+        let secret = dotenvy::var("ANALYTICS_WEBHOOK_SECRET")
+            .expect("ANALYTICS_WEBHOOK_SECRET must be set with ANALYTICS_WEBHOOK_URL");
+        webhook::AnalyticsWebhook {
+            url: url.leak(),
+            secret: secret.leak(),
+        }
+    });
+
+    let unique_company_names =
+        dotenvy::var("UNIQUE_COMPANY_NAMES").is_ok_and(|unique| unique == "1" || unique == "true");
+
+    // Presence is only shared between instances through redis
+    let redis_url: Option<&'static str> = dotenvy::var("REDIS_URL").ok().map(|url| &*url.leak());
 
     // Running migrations
     tokio::task::spawn_blocking(|| {
@@ -57,6 +359,7 @@ pub async fn run() {
             .expect("Failed to make connection to db to perform migrations");
         conn.run_pending_migrations(MIGRATIONS)
             .expect("Failed to perform migrations");
+        db::assert_column_dimensions(&mut conn);
     })
     .await
     .expect("Failed to execute the migration task");
@@ -64,6 +367,9 @@ pub async fn run() {
     tokio::spawn(async move {
         async fn maintain(conn: &mut impl AsyncConnection<Backend = Pg>) -> Result<(), Error> {
             db::UserSession::prune_expired(conn).await?;
+            db::IdempotencyKey::prune_expired(conn).await?;
+            db::SignedRequest::prune_expired(conn).await?;
+            db::User::purge_deleted(conn).await?;
 
             diesel::sql_query("REINDEX INDEX CONCURRENTLY creator_profile_embedding;")
                 .execute(conn)
@@ -103,14 +409,45 @@ pub async fn run() {
     let state = state::AppState::new(
         db_url,
         fcm_tx.clone(),
-        ws::WsFunctions::default().add_scoped("chat", chat::functions()),
-        state::Config { storage_path },
+        ws::functions().add_scoped("chat", chat::functions()),
+        state::Config {
+            storage_path,
+            static_path,
+            embedding_rate_limit,
+            message_rate_limit,
+            storage_quota,
+            remote_image_policy,
+            http_timeouts,
+            public_base_url,
+            default_avatar,
+            embedding_templates,
+            stats_refresh,
+            provider_concurrency,
+            redis_url,
+            ws_idle_timeout,
+            cookies,
+            google_oauth,
+            twitch_oauth,
+            analytics_webhook,
+            unique_company_names,
+        },
     )
     .await;
 
+    tokio::spawn(creator::stats::refresh_job(state));
+    tokio::spawn(chat::expire_typing_job(state));
+    tokio::spawn(suggestions::refresh_job(state));
+
     let pool = state.pool.clone();
+    let fcm_healthy = state.fcm_healthy();
     tokio::spawn(async move {
+        let mut consecutive_failures = 0;
+
         while let Some(msg) = fcm_rx.recv().await {
+            if !fcm_deliverable(&msg, pool).await {
+                continue;
+            }
+
             if let Err(err) = fcm_client.send(&msg).await {
                 match err {
                     fcm::Error::InvalidMessage(err) => match &msg.target {
@@ -155,8 +492,37 @@ pub async fn run() {
                             }
                         });
                     }
-                    _ => tracing::error!("Failed to send message over fcm: {err:?}"),
+                    _ => {
+                        tracing::error!("Failed to send message over fcm: {err:?}");
+
+                        // Persistent failures usually mean the client lost its credentials, so
+                        // it is rebuilt from scratch
+                        consecutive_failures += 1;
+                        if consecutive_failures >= FCM_FAILURES_BEFORE_REBUILD {
+                            fcm_healthy.store(false, Ordering::Relaxed);
+
+                            let mut backoff = std::time::Duration::from_secs(1);
+                            fcm_client = loop {
+                                match fcm::Client::new().await {
+                                    Ok(client) => break client,
+                                    Err(err) => {
+                                        tracing::error!(
+                                            "Failed to rebuild the fcm client, retrying in {backoff:?}: {err:?}"
+                                        );
+                                        tokio::time::sleep(backoff).await;
+                                        backoff = (backoff * 2).min(FCM_REBUILD_MAX_BACKOFF);
+                                    }
+                                }
+                            };
+
+                            tracing::info!("Rebuilt the fcm client");
+                            consecutive_failures = 0;
+                            fcm_healthy.store(true, Ordering::Relaxed);
+                        }
+                    }
                 }
+            } else {
+                consecutive_failures = 0;
             }
         }
     });
@@ -170,47 +536,95 @@ pub async fn run() {
     );
 
     let governor_limiter = governor_conf.limiter().clone();
+    let embedding_limiter = state.embedding_limiter();
+    let name_availability_limiter = state.name_availability_limiter();
+    let invite_resend_limiter = state.invite_resend_limiter();
+    let logo_refresh_limiter = state.logo_refresh_limiter();
+    let room_creation_limiter = state.room_creation_limiter();
+    let message_limiter = state.message_limiter();
+    let contract_change_limiter = state.contract_change_limiter();
+    let ws_call_limiter = state.ws_call_limiter();
     tokio::spawn(async move {
         const CLEANUP_INVERVAL: std::time::Duration = std::time::Duration::from_mins(1);
 
         loop {
             tokio::time::sleep(CLEANUP_INVERVAL).await;
             governor_limiter.retain_recent();
+            embedding_limiter.retain_recent();
+            name_availability_limiter.retain_recent();
+            invite_resend_limiter.retain_recent();
+            logo_refresh_limiter.retain_recent();
+            room_creation_limiter.retain_recent();
+            message_limiter.retain_recent();
+            contract_change_limiter.retain_recent();
+            ws_call_limiter.retain_recent();
+            state.prune_ws_tickets();
         }
     });
 
-    let app = Router::new()
-        .nest(
-            "/api/v1",
-            Router::new()
-                .nest("/creator", creator::router())
-                .nest("/company", company::router())
-                .nest("/google", google::router())
-                .nest("/twitch", twitch::router())
-                .nest("/storage", storage::router())
-                .layer(
-                    tower_http::compression::CompressionLayer::new()
-                        .gzip(true)
-                        .br(true)
-                        .deflate(true)
-                        .zstd(true)
-                        .quality(tower_http::CompressionLevel::Best),
-                )
-                .layer(tower_governor::GovernorLayer {
-                    config: governor_conf,
-                }),
-        )
-        .nest_service(
+    let app = Router::new().nest(
+        "/api/v1",
+        Router::new()
+            .nest("/accounts", accounts::router())
+            .nest("/admin", admin::router())
+            .nest("/creator", creator::router())
+            .nest("/company", company::router())
+            .nest("/google", google::router())
+            .nest("/me", me::router())
+            .nest("/twitch", twitch::router())
+            .nest("/user", user::router())
+            .nest("/storage", storage::router())
+            .nest("/suggestions", suggestions::router())
+            .nest("/service", service::router())
+            .nest("/session", session::router())
+            .nest("/ws", ws::router())
+            .route("/config", routing::get(client_config))
+            .layer(axum::middleware::from_fn_with_state(
+                state,
+                service::verify_signature,
+            ))
+            .layer(axum::extract::DefaultBodyLimit::max(MAX_UPLOAD_BYTES))
+            // Picked from `Accept-Encoding`. Images and the websocket upgrade are left alone by
+            // the default predicate, the best level costs too much cpu on every JSON response
+            .layer(
+                tower_http::compression::CompressionLayer::new()
+                    .gzip(true)
+                    .br(true)
+                    .deflate(true)
+                    .zstd(true)
+                    .quality(tower_http::CompressionLevel::Default)
+                    .compress_when(
+                        DefaultPredicate::new().and(SizeAbove::new(MIN_COMPRESSED_BYTES)),
+                    ),
+            )
+            .layer(tower_governor::GovernorLayer {
+                config: governor_conf,
+            }),
+    );
+
+    let app = if static_path.is_dir() {
+        app.nest_service(
             "/",
             utils::AddHtmlExtService(
-                ServeDir::new("frontend/build")
+                ServeDir::new(static_path)
                     .append_index_html_on_directories(true)
                     .precompressed_gzip()
                     .precompressed_br(),
             ),
         )
+    } else {
+        tracing::warn!(
+            "Static directory {static_path:?} does not exist, only the API will be served. \
+            Build the frontend or point STATIC_PATH at an existing build."
+        );
+
+        app.route("/", routing::get(frontend_missing))
+    };
+
+    let app = app
         .route("/test/:id", axum::routing::get(test))
         .route("/ws", routing::get(ws::connect))
+        .route("/readyz", routing::get(readyz))
         .with_state(state);
 
     // run our app with hyper, listening globally on port 3000
@@ -220,14 +634,133 @@ pub async fn run() {
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(shutdown_signal(state))
     .await
     .unwrap();
+
+    // Upgraded websockets are not covered by the graceful shutdown so they are waited on here
+    state.ws_sockets().close();
+    if tokio::time::timeout(WS_SEND_TIMEOUT, state.ws_sockets().wait())
+        .await
+        .is_err()
+    {
+        tracing::warn!("Gave up waiting for websockets to close");
+    }
+}
+
+/// Resolves on ctrl-c or SIGTERM and tells the open websockets that the server is going away
+async fn shutdown_signal(state: state::AppState) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+
+    tracing::info!("Shutting down");
+    state.shutdown().cancel();
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Readiness {
+    database: bool,
+    fcm: bool,
+    /// Only reported, the server is still ready without embeddings
+    embeddings: bool,
+}
+
+/// Responds with 503 while a dependency the server needs is unavailable
+async fn readyz(State(state): State<state::AppState>) -> (StatusCode, Json<Readiness>) {
+    let readiness = Readiness {
+        database: state.get_conn().await.is_ok(),
+        fcm: state.fcm_healthy().load(Ordering::Relaxed),
+        embeddings: state.embeddings_available(),
+    };
+
+    let status_code = if readiness.database && readiness.fcm {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status_code, Json(readiness))
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ClientConfig {
+    google_client_id: &'static str,
+    twitch_client_id: &'static str,
+    max_upload_bytes: usize,
+    /// Where stored images are served from when it is not this server
+    public_base_url: Option<&'static str>,
+    storage_quota: ClientStorageQuota,
+    /// The cookie the id of the logged in user can be read from
+    user_id_cookie_name: &'static str,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ClientStorageQuota {
+    creator_bytes: u64,
+    company_member_bytes: u64,
+}
+
+/// Configuration the frontend needs at runtime so one build can be served by any environment.
+/// Only public values belong here, never secrets.
+async fn client_config(State(state): State<state::AppState>) -> Json<ClientConfig> {
+    use utils::oauth::OAuthAccountHelper;
+
+    let config = state.config();
+
+    Json(ClientConfig {
+        google_client_id: config.google_oauth.client_id,
+        twitch_client_id: config.twitch_oauth.client_id,
+        max_upload_bytes: MAX_UPLOAD_BYTES,
+        public_base_url: config.public_base_url,
+        storage_quota: ClientStorageQuota {
+            creator_bytes: config.storage_quota.creator_bytes,
+            company_member_bytes: config.storage_quota.company_member_bytes,
+        },
+        user_id_cookie_name: config.cookies.user_id,
+    })
+}
+
+async fn frontend_missing() -> Html<&'static str> {
+    Html(
+        "<!DOCTYPE html>\
+        <html>\
+            <head><title>Halogin API</title></head>\
+            <body>\
+                <h1>Frontend is not built</h1>\
+                <p>The API is available under <code>/api/v1</code>.</p>\
+                <p>Build the frontend or set <code>STATIC_PATH</code> to serve the webpage.</p>\
+            </body>\
+        </html>",
+    )
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Request must be made from an authenticated session")]
-    Unauthorized,
+    Unauthenticated,
+    #[error("{reason}")]
+    Forbidden { reason: String },
     #[error("The requested RPC namespace does not exist")]
     RpcMissingNamespace,
     #[error("The requested RPC method does not exist in the given namespace")]
@@ -242,7 +775,11 @@ pub enum Error {
     #[error("Failed to query using the token from the DB: {0:?}")]
     QueryError(#[from] diesel::result::Error),
     #[error("Failed to make a request: {0:?}")]
-    ReqwestError(#[from] reqwest::Error),
+    ReqwestError(reqwest::Error),
+    #[error("An upstream service did not respond in time")]
+    UpstreamTimeout,
+    #[error("The provider revoked access to the account, it has to be linked again")]
+    ReauthRequired,
     #[error("Failed to parse url: {0:?}")]
     ParseError(#[from] url::ParseError),
     #[error("Failed to parse json: {0:?}")]
@@ -265,23 +802,58 @@ pub enum Error {
     QdrantError(anyhow::Error),
     #[error("Failed to convert header while trying to fetch a image: {0:?}")]
     HeaderCoversionError(axum::http::header::ToStrError),
+    #[error("Encountered an error in redis: {0:?}")]
+    RedisError(#[from] redis::RedisError),
+    #[error("Too many requests, retry after {retry_after:?}")]
+    TooManyRequests { retry_after: std::time::Duration },
+    #[error("Invalid fields: {0:?}")]
+    Validation(utils::validation::ValidationError),
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         match self {
-            Error::Unauthorized => {
+            Error::Unauthenticated => {
                 (StatusCode::UNAUTHORIZED, Html(format!("{self:?}"))).into_response()
             }
+            Error::Forbidden { reason } => (StatusCode::FORBIDDEN, Html(reason)).into_response(),
             Error::RpcMissingNamespace | Error::RpcMissingMethod | Error::SerdeJsonError(_) => {
                 (StatusCode::BAD_REQUEST, Html(format!("{self:?}"))).into_response()
             }
+            Error::TooManyRequests { retry_after } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(
+                    axum::http::header::RETRY_AFTER,
+                    (retry_after.as_secs_f64().ceil() as u64).to_string(),
+                )],
+                Html(format!("{self:?}")),
+            )
+                .into_response(),
+            Error::UpstreamTimeout => {
+                (StatusCode::GATEWAY_TIMEOUT, Html(format!("{self:?}"))).into_response()
+            }
+            Error::ReauthRequired => {
+                (StatusCode::FORBIDDEN, Html(self.to_string())).into_response()
+            }
             Error::Custom { status_code, error } => (status_code, Html(error)).into_response(),
+            Error::Validation(errors) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(errors)).into_response()
+            }
             _ => (StatusCode::INTERNAL_SERVER_ERROR, Html(format!("{self:?}"))).into_response(),
         }
     }
 }
 
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Error::UpstreamTimeout
+        } else {
+            Error::ReqwestError(err)
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum Platform {
     Youtube {
@@ -0,0 +1,61 @@
+use axum::{routing, Json, Router};
+
+use crate::{
+    db::{company, CreatorProfileQuery, LinkedAccount, User},
+    models,
+    state::{AppState, Config, DbConn},
+    storage::StoredImages,
+    utils::etag::{Conditional, ETag, IfNoneMatch},
+    Error,
+};
+
+/// Everything the home view needs, so the first load is a single round trip
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Dashboard {
+    companies: Vec<company::Company>,
+    /// `None` until the user fills in their creator profile
+    creator_profile: Option<CreatorProfileQuery>,
+    accounts: Vec<LinkedAccount>,
+    /// The first page of pending invites the user received
+    invites: Vec<company::CompanyInvitationDetailed>,
+    pending_invites: i64,
+    /// Rooms the user is in as a creator
+    rooms: Vec<models::ChatRoom>,
+    unread: models::UnreadCounts,
+}
+
+async fn get_dashboard(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    config: Config,
+    if_none_match: IfNoneMatch,
+) -> Result<Conditional<Json<Dashboard>>, Error> {
+    let mut dashboard = Dashboard {
+        companies: company::Company::list_for_user(user, &mut conn).await?,
+        creator_profile: CreatorProfileQuery::get(user, &mut conn).await?,
+        accounts: LinkedAccount::list(user, &mut conn).await?,
+        invites: company::CompanyInvitationDetailed::list(
+            user,
+            Some(company::InviteStatus::Pending),
+            None,
+            company::INVITE_PAGE_SIZE,
+            &mut conn,
+        )
+        .await?,
+        pending_invites: company::CompanyInvitationDetailed::pending_count(user, &mut conn).await?,
+        rooms: models::ChatRoom::list(user.id, &mut conn).await?,
+        unread: models::UnreadCounts::for_user(user.id, &mut conn).await?,
+    };
+    dashboard.companies.resolve_urls(&config);
+    dashboard.creator_profile.resolve_urls(&config);
+    dashboard.invites.resolve_urls(&config);
+    // Aggregated from many tables so the tag has to come from the body
+    let etag = ETag::from_json(&dashboard)?;
+
+    Ok(if_none_match.respond(etag, Json(dashboard)))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/dashboard", routing::get(get_dashboard))
+}
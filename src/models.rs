@@ -1,5 +1,6 @@
 use std::io::Write;
 
+use axum::http::StatusCode;
 use diesel::{
     data_types::Cents,
     deserialize::{self, FromSql, FromSqlRow},
@@ -69,6 +70,21 @@ pub struct SessionFcmToken {
 }
 
 impl SessionFcmToken {
+    /// The session the fcm token was registered by
+    pub async fn session_token(
+        token: &str,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<String>, Error> {
+        use crate::schema::sessionfcmtoken::dsl as dsl_uft;
+
+        Ok(dsl_uft::sessionfcmtoken
+            .filter(dsl_uft::token.eq(token))
+            .select(dsl_uft::session_token)
+            .first(conn)
+            .await
+            .optional()?)
+    }
+
     pub async fn delete(
         token: &str,
         conn: &mut impl AsyncConnection<Backend = Pg>,
@@ -85,6 +101,7 @@ impl SessionFcmToken {
 }
 
 #[derive(Clone, Insertable, Queryable, AsChangeset, Selectable, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 #[diesel(table_name = crate::schema::chatroom)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct ChatRoom {
@@ -102,6 +119,7 @@ impl ChatRoom {
 
         let room = dsl_cr::chatroom
             .filter(dsl_cr::id.eq(room_id))
+            .select(Self::as_select())
             .first(conn)
             .await
             .optional()?;
@@ -109,25 +127,88 @@ impl ChatRoom {
         Ok(room)
     }
 
+    /// The room between the company and the creator, if there is one
+    pub async fn find(
+        company_id: Uuid,
+        user_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Uuid>, Error> {
+        use crate::schema::chatroom::dsl as dsl_cr;
+
+        Ok(dsl_cr::chatroom
+            .filter(dsl_cr::company_id.eq(company_id))
+            .filter(dsl_cr::user_id.eq(user_id))
+            .select(dsl_cr::id)
+            .first(conn)
+            .await
+            .optional()?)
+    }
+
+    /// Rooms without a single message opened by the creator, or by the company when it is set.
+    /// Rooms opened by the other side, or before the opener was recorded, are not counted.
+    pub async fn count_empty(
+        user_id: Option<Uuid>,
+        company_id: Option<Uuid>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<i64, Error> {
+        use diesel::sql_types;
+
+        #[derive(QueryableByName)]
+        struct Count {
+            #[diesel(sql_type = sql_types::BigInt)]
+            count: i64,
+        }
+
+        let count = diesel::sql_query(
+            "SELECT COUNT(*) AS count FROM ChatRoom r \
+            WHERE ($1::UUID IS NULL OR (r.user_id = $1 AND r.opened_by = r.user_id)) \
+            AND ($2::UUID IS NULL OR (r.company_id = $2 AND r.opened_by <> r.user_id)) \
+            AND NOT EXISTS (SELECT 1 FROM ChatMessage m WHERE m.room_id = r.id)",
+        )
+        .bind::<sql_types::Nullable<sql_types::Uuid>, _>(user_id)
+        .bind::<sql_types::Nullable<sql_types::Uuid>, _>(company_id)
+        .get_result::<Count>(conn)
+        .await?;
+
+        Ok(count.count)
+    }
+
+    /// Creates the room of the company and user unless it already exists, recording who opened
+    /// it. Returns the id of the room and whether it was created.
     pub async fn create(
         company_id: Uuid,
         user_id: Uuid,
+        opened_by: Uuid,
         conn: &mut impl AsyncConnection<Backend = Pg>,
-    ) -> Result<Uuid, Error> {
+    ) -> Result<(Uuid, bool), Error> {
         use crate::schema::chatroom::dsl as dsl_cr;
 
         let room_id = Uuid::new_v4();
 
-        diesel::insert_into(dsl_cr::chatroom)
+        let inserted = diesel::insert_into(dsl_cr::chatroom)
             .values((
                 dsl_cr::id.eq(room_id),
                 dsl_cr::company_id.eq(company_id),
                 dsl_cr::user_id.eq(user_id),
+                dsl_cr::opened_by.eq(opened_by),
             ))
+            .on_conflict((dsl_cr::company_id, dsl_cr::user_id))
+            .do_nothing()
             .execute(conn)
             .await?;
+        if inserted > 0 {
+            return Ok((room_id, true));
+        }
+
+        // The room was created before, possibly by a request racing this one
+        let existing = dsl_cr::chatroom
+            .filter(dsl_cr::company_id.eq(company_id))
+            .filter(dsl_cr::user_id.eq(user_id))
+            .select(dsl_cr::id)
+            .first(conn)
+            .await?;
 
-        Ok(room_id)
+        Ok((existing, false))
     }
 
     pub async fn list(
@@ -144,42 +225,288 @@ impl ChatRoom {
 
         Ok(rooms)
     }
+
+    /// Ids of the companies the creator has a room with
+    pub async fn company_ids_for_creator(
+        user_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Uuid>, Error> {
+        use crate::schema::chatroom::dsl as dsl_cr;
+
+        let company_ids = dsl_cr::chatroom
+            .filter(dsl_cr::user_id.eq(user_id))
+            .select(dsl_cr::company_id)
+            .distinct()
+            .load::<Uuid>(conn)
+            .await?;
+
+        Ok(company_ids)
+    }
+
+    /// Rooms of the company as seen by `user_id`, most recently active first
+    pub async fn list_for_company(
+        company_id: Uuid,
+        user_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<RoomSummary>, Error> {
+        use diesel::sql_types;
+
+        Ok(diesel::sql_query(
+            "SELECT r.id AS room_id, r.user_id, m.id AS last_message_id, \
+                m.from_user_id AS last_message_from, LEFT(m.content, $3) AS last_message_preview, \
+                m.created_at AS last_message_at, \
+                (SELECT COUNT(*) FROM ChatMessage u WHERE u.room_id = r.id \
+                    AND u.from_user_id <> $2 \
+                    AND u.id > COALESCE(s.last_message_seen_id, 0)) AS unread_count \
+            FROM ChatRoom r \
+            LEFT JOIN LATERAL (SELECT id, from_user_id, content, created_at FROM ChatMessage \
+                WHERE room_id = r.id ORDER BY id DESC LIMIT 1) m ON TRUE \
+            LEFT JOIN ChatLastSeen s ON s.room_id = r.id AND s.user_id = $2 \
+            WHERE r.company_id = $1 \
+            ORDER BY m.id DESC NULLS LAST, r.id",
+        )
+        .bind::<sql_types::Uuid, _>(company_id)
+        .bind::<sql_types::Uuid, _>(user_id)
+        .bind::<sql_types::Integer, _>(RoomSummary::PREVIEW_LEN)
+        .load(conn)
+        .await?)
+    }
+}
+
+/// Messages from others after the last one the user has seen, in every room they are in
+#[derive(Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnreadCounts {
+    /// Only rooms with unread messages are included. Ordered so the serialized form is stable.
+    pub rooms: std::collections::BTreeMap<Uuid, i64>,
+    pub total: i64,
+}
+
+impl UnreadCounts {
+    /// Covers the rooms of the user as a creator and the rooms of every company they are a
+    /// member of, in one grouped query
+    pub async fn for_user(
+        user_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Self, Error> {
+        use diesel::sql_types;
+
+        #[derive(QueryableByName)]
+        struct RoomUnread {
+            #[diesel(sql_type = sql_types::Uuid)]
+            room_id: Uuid,
+            #[diesel(sql_type = sql_types::BigInt)]
+            unread_count: i64,
+        }
+
+        let rooms = diesel::sql_query(
+            "SELECT r.id AS room_id, COUNT(*) AS unread_count FROM ChatRoom r \
+            LEFT JOIN ChatLastSeen s ON s.room_id = r.id AND s.user_id = $1 \
+            JOIN ChatMessage m ON m.room_id = r.id AND m.from_user_id <> $1 \
+                AND m.id > COALESCE(s.last_message_seen_id, 0) \
+            WHERE r.user_id = $1 \
+                OR r.company_id IN (SELECT company_id FROM CompanyUser WHERE user_id = $1) \
+            GROUP BY r.id",
+        )
+        .bind::<sql_types::Uuid, _>(user_id)
+        .load::<RoomUnread>(conn)
+        .await?;
+
+        let mut counts = UnreadCounts::default();
+        for room in rooms {
+            counts.total += room.unread_count;
+            counts.rooms.insert(room.room_id, room.unread_count);
+        }
+
+        Ok(counts)
+    }
+}
+
+#[derive(QueryableByName, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomSummary {
+    #[diesel(sql_type = diesel::sql_types::Uuid)]
+    pub room_id: Uuid,
+    /// The creator the company is talking to
+    #[diesel(sql_type = diesel::sql_types::Uuid)]
+    pub user_id: Uuid,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::BigInt>)]
+    pub last_message_id: Option<i64>,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Uuid>)]
+    pub last_message_from: Option<Uuid>,
+    /// The first [`RoomSummary::PREVIEW_LEN`] characters of the last message
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Text>)]
+    pub last_message_preview: Option<String>,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Timestamp>)]
+    #[serde(serialize_with = "crate::utils::serialize_optional_timestamp")]
+    pub last_message_at: Option<PrimitiveDateTime>,
+    /// Messages from others after the last one the user has seen
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub unread_count: i64,
+}
+
+impl RoomSummary {
+    const PREVIEW_LEN: i32 = 100;
+}
+
+/// How active a creator has been in their room with a company
+#[derive(QueryableByName, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatorActivity {
+    /// When the creator last posted in the room
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Timestamp>)]
+    #[serde(serialize_with = "crate::utils::serialize_optional_timestamp")]
+    pub last_message_at: Option<PrimitiveDateTime>,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub message_count: i64,
+    /// Messages of the creator that answered someone from the company
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub reply_count: i64,
+    /// Average time between a message from the company and the creator answering it
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Double>)]
+    pub average_reply_secs: Option<f64>,
+}
+
+impl CreatorActivity {
+    pub async fn for_company(
+        company_id: Uuid,
+        creator_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Self, Error> {
+        use diesel::sql_types;
+
+        // A reply is a message of the creator right after one that is not theirs
+        Ok(diesel::sql_query(
+            "SELECT MAX(m.created_at) FILTER (WHERE m.from_user_id = $2) AS last_message_at, \
+                COUNT(*) FILTER (WHERE m.from_user_id = $2) AS message_count, \
+                COUNT(*) FILTER (WHERE m.from_user_id = $2 AND m.prev_from <> $2) \
+                    AS reply_count, \
+                AVG(EXTRACT(EPOCH FROM m.created_at - m.prev_at)) \
+                    FILTER (WHERE m.from_user_id = $2 AND m.prev_from <> $2)::FLOAT8 \
+                    AS average_reply_secs \
+            FROM (SELECT m.from_user_id, m.created_at, \
+                    LAG(m.from_user_id) OVER w AS prev_from, LAG(m.created_at) OVER w AS prev_at \
+                FROM ChatMessage m \
+                JOIN ChatRoom r ON r.id = m.room_id \
+                WHERE r.company_id = $1 AND r.user_id = $2 \
+                WINDOW w AS (PARTITION BY m.room_id ORDER BY m.id)) m",
+        )
+        .bind::<sql_types::Uuid, _>(company_id)
+        .bind::<sql_types::Uuid, _>(creator_id)
+        .get_result(conn)
+        .await?)
+    }
 }
 
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Message {
+    /// Monotonically increasing, this is the only field that should be used to order and page
+    /// through messages
     pub id: i64,
     pub from_user: Uuid,
     pub content: String,
+    /// Only meant for display, it is not guaranteed to be ordered the same way as `id`
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
     pub created_at: PrimitiveDateTime,
     pub extra: Option<MessageExtra>,
+    pub reactions: Vec<ReactionCount>,
+}
+
+#[derive(Clone, Selectable, Queryable)]
+#[diesel(table_name = crate::schema::chatmessage)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct DbMessage {
+    id: i64,
+    from_user_id: Uuid,
+    content: String,
+    created_at: PrimitiveDateTime,
 }
 
 impl Message {
+    /// Lists up to `limit` messages of the room with an id lower than `before`, newest first.
+    ///
+    /// The id of the last returned message is the cursor for the next page.
     pub async fn list(
         room_id: Uuid,
+        before: Option<i64>,
+        limit: i64,
         conn: &mut impl AsyncConnection<Backend = Pg>,
     ) -> Result<Vec<Self>, Error> {
-        #[derive(Clone, Selectable, Queryable)]
-        #[diesel(table_name = crate::schema::chatmessage)]
-        #[diesel(check_for_backend(diesel::pg::Pg))]
-        struct DbMessage {
-            id: i64,
-            from_user_id: Uuid,
-            content: String,
-            created_at: PrimitiveDateTime,
+        use crate::schema::chatmessage::dsl as dsl_cm;
+
+        let mut query = dsl_cm::chatmessage
+            .filter(dsl_cm::room_id.eq(room_id))
+            .into_boxed();
+        if let Some(before) = before {
+            query = query.filter(dsl_cm::id.lt(before));
         }
 
+        let db_messages = query
+            .order_by(dsl_cm::id.desc())
+            .limit(limit)
+            .select(DbMessage::as_select())
+            .load::<DbMessage>(conn)
+            .await?;
+
+        Self::assemble(db_messages, conn).await
+    }
+
+    /// Lists up to `limit` messages of the room with an id higher than `after`, newest first
+    pub async fn list_after(
+        room_id: Uuid,
+        after: i64,
+        limit: i64,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
         use crate::schema::chatmessage::dsl as dsl_cm;
 
-        let db_messages = dsl_cm::chatmessage
+        let mut db_messages = dsl_cm::chatmessage
             .filter(dsl_cm::room_id.eq(room_id))
+            .filter(dsl_cm::id.gt(after))
             .order_by(dsl_cm::id.asc())
+            .limit(limit)
             .select(DbMessage::as_select())
             .load::<DbMessage>(conn)
             .await?;
+        db_messages.reverse();
+
+        Self::assemble(db_messages, conn).await
+    }
+
+    /// A single message, it is up to the caller to check that the user can see its room
+    pub async fn get(
+        message_id: i64,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Self>, Error> {
+        use crate::schema::chatmessage::dsl as dsl_cm;
+
+        let db_message = dsl_cm::chatmessage
+            .filter(dsl_cm::id.eq(message_id))
+            .select(DbMessage::as_select())
+            .first::<DbMessage>(conn)
+            .await
+            .optional()?;
+
+        match db_message {
+            Some(db_message) => Ok(Self::assemble(vec![db_message], conn).await?.pop()),
+            None => Ok(None),
+        }
+    }
+
+    /// Resolves the `extra` and reactions of the messages, keeping their order
+    async fn assemble(
+        db_messages: Vec<DbMessage>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<Self>, Error> {
         let mut messages = Vec::with_capacity(db_messages.len());
 
+        let message_ids = db_messages
+            .iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>();
+        let mut reactions = ChatReaction::counts(&message_ids, conn).await?;
+
         use crate::schema::chatcontractoffer::dsl as dsl_cco;
         use crate::schema::chatcontractofferupdate::dsl as dsl_ccou;
 
@@ -188,15 +515,16 @@ impl Message {
 
             let contract_offer = dsl_cco::chatcontractoffer
                 .filter(dsl_cco::message_id.eq(db_message.id))
-                .select((dsl_cco::id, dsl_cco::offered_payout))
-                .first::<(i64, Cents)>(conn)
+                .select((dsl_cco::id, dsl_cco::offered_payout, dsl_cco::currency))
+                .first::<(i64, Cents, String)>(conn)
                 .await
                 .optional()?;
 
-            if let Some((offer_id, payout)) = contract_offer {
+            if let Some((offer_id, payout, currency)) = contract_offer {
                 extra = Some(MessageExtra::ContractOfferCreated {
                     offer_id,
                     payout: payout.0,
+                    currency,
                 });
             } else {
                 let contract_update = dsl_ccou::chatcontractofferupdate
@@ -220,18 +548,222 @@ impl Message {
                 content: db_message.content,
                 created_at: db_message.created_at,
                 extra,
+                reactions: reactions.remove(&db_message.id).unwrap_or_default(),
             });
         }
 
         Ok(messages)
     }
+
+    /// The room the message was posted in
+    pub async fn room_id(
+        message_id: i64,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Uuid>, Error> {
+        use crate::schema::chatmessage::dsl as dsl_cm;
+
+        Ok(dsl_cm::chatmessage
+            .filter(dsl_cm::id.eq(message_id))
+            .select(dsl_cm::room_id)
+            .first(conn)
+            .await
+            .optional()?)
+    }
+
+    /// Inserts the message and returns its id and when it was created
+    pub async fn insert(
+        room_id: Uuid,
+        from_user_id: Uuid,
+        content: &str,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(i64, PrimitiveDateTime), Error> {
+        use crate::schema::chatmessage::dsl as dsl_cm;
+
+        Ok(diesel::insert_into(dsl_cm::chatmessage)
+            .values((
+                dsl_cm::room_id.eq(room_id),
+                dsl_cm::from_user_id.eq(from_user_id),
+                dsl_cm::content.eq(content),
+            ))
+            .returning((dsl_cm::id, dsl_cm::created_at))
+            .get_result(conn)
+            .await?)
+    }
+}
+
+pub struct ChatContractOffer;
+
+impl ChatContractOffer {
+    /// Creates an offer attached to the message and returns its id
+    pub async fn create(
+        message_id: i64,
+        payout: i64,
+        currency: &str,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<i64, Error> {
+        use crate::schema::chatcontractoffer::dsl as dsl_cco;
+
+        Ok(diesel::insert_into(dsl_cco::chatcontractoffer)
+            .values((
+                dsl_cco::message_id.eq(message_id),
+                dsl_cco::offered_payout.eq(Cents(payout)),
+                dsl_cco::currency.eq(currency),
+            ))
+            .returning(dsl_cco::id)
+            .get_result(conn)
+            .await?)
+    }
+
+    /// Records a status change of the offer. Changing to the status the offer already has is
+    /// rejected here, other invalid transitions by the `contract_offer_update_checker` trigger.
+    pub async fn update(
+        message_id: i64,
+        offer_id: i64,
+        new_status: ContractOfferStatus,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use crate::schema::chatcontractoffer::dsl as dsl_cco;
+        use crate::schema::chatcontractofferupdate::dsl as dsl_ccou;
+
+        // Changes to the same offer wait on each other so they each see the latest status
+        dsl_cco::chatcontractoffer
+            .filter(dsl_cco::id.eq(offer_id))
+            .select(dsl_cco::id)
+            .for_update()
+            .first::<i64>(conn)
+            .await?;
+
+        let status = dsl_ccou::chatcontractofferupdate
+            .filter(dsl_ccou::offer_id.eq(offer_id))
+            .order(dsl_ccou::id.desc())
+            .select(dsl_ccou::update_kind)
+            .first::<ContractOfferStatus>(conn)
+            .await
+            .optional()?;
+        if status == Some(new_status) {
+            return Err(Error::Custom {
+                status_code: StatusCode::CONFLICT,
+                error: "The contract offer already has this status".into(),
+            });
+        }
+
+        diesel::insert_into(dsl_ccou::chatcontractofferupdate)
+            .values((
+                dsl_ccou::message_id.eq(message_id),
+                dsl_ccou::offer_id.eq(offer_id),
+                dsl_ccou::update_kind.eq(new_status),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Every change made to the offer, oldest first, starting with its creation
+    pub async fn history(
+        offer_id: i64,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<ContractHistoryEntry>, Error> {
+        use crate::schema::chatcontractoffer::dsl as dsl_cco;
+        use crate::schema::chatcontractofferupdate::dsl as dsl_ccou;
+        use crate::schema::chatmessage::dsl as dsl_cm;
+
+        let Some((message_id, by_user, at, payout, currency)) = dsl_cco::chatcontractoffer
+            .filter(dsl_cco::id.eq(offer_id))
+            .inner_join(dsl_cm::chatmessage)
+            .select((
+                dsl_cm::id,
+                dsl_cm::from_user_id,
+                dsl_cm::created_at,
+                dsl_cco::offered_payout,
+                dsl_cco::currency,
+            ))
+            .first::<(i64, Uuid, PrimitiveDateTime, Cents, String)>(conn)
+            .await
+            .optional()?
+        else {
+            return Ok(Vec::new());
+        };
+
+        let updates = dsl_ccou::chatcontractofferupdate
+            .filter(dsl_ccou::offer_id.eq(offer_id))
+            .inner_join(dsl_cm::chatmessage)
+            .order_by(dsl_cm::id.asc())
+            .select((
+                dsl_cm::id,
+                dsl_cm::from_user_id,
+                dsl_cm::created_at,
+                dsl_ccou::update_kind,
+            ))
+            .load::<(i64, Uuid, PrimitiveDateTime, ContractOfferStatus)>(conn)
+            .await?;
+
+        let mut history = Vec::with_capacity(updates.len() + 1);
+        history.push(ContractHistoryEntry {
+            message_id,
+            by_user,
+            at,
+            change: MessageExtra::ContractOfferCreated {
+                offer_id,
+                payout: payout.0,
+                currency,
+            },
+        });
+        history.extend(
+            updates.into_iter().map(
+                |(message_id, by_user, at, new_status)| ContractHistoryEntry {
+                    message_id,
+                    by_user,
+                    at,
+                    change: MessageExtra::ContractOfferStatusChange {
+                        offer_id,
+                        new_status,
+                    },
+                },
+            ),
+        );
+
+        Ok(history)
+    }
+
+    /// The room the offer was made in
+    pub async fn room_id(
+        offer_id: i64,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Uuid>, Error> {
+        use crate::schema::chatcontractoffer::dsl as dsl_cco;
+        use crate::schema::chatmessage::dsl as dsl_cm;
+
+        Ok(dsl_cco::chatcontractoffer
+            .filter(dsl_cco::id.eq(offer_id))
+            .inner_join(dsl_cm::chatmessage)
+            .select(dsl_cm::room_id)
+            .first(conn)
+            .await
+            .optional()?)
+    }
+}
+
+/// A change made to a contract offer and the message that made it
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContractHistoryEntry {
+    pub message_id: i64,
+    pub by_user: Uuid,
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub at: PrimitiveDateTime,
+    pub change: MessageExtra,
 }
 
 #[derive(serde::Serialize)]
+#[serde(rename_all_fields = "camelCase")]
 pub enum MessageExtra {
     ContractOfferCreated {
         offer_id: i64,
+        /// In the minor unit of `currency`
         payout: i64,
+        /// ISO 4217 code
+        currency: String,
     },
     ContractOfferStatusChange {
         offer_id: i64,
@@ -262,4 +794,393 @@ impl ChatLastSeen {
 
         Ok(last_seens)
     }
+
+    /// Marks every room of the company seen by the user up to `up_to`, or their last message
+    /// without it. Rooms already seen that far are left alone and not returned.
+    pub async fn mark_company_seen(
+        company_id: Uuid,
+        user_id: Uuid,
+        up_to: Option<i64>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Vec<SeenRoom>, Error> {
+        use diesel::sql_types;
+
+        Ok(diesel::sql_query(
+            "WITH seen AS (\
+                INSERT INTO ChatLastSeen (room_id, user_id, last_message_seen_id) \
+                SELECT r.id, $2, MAX(m.id) FROM ChatRoom r \
+                JOIN ChatMessage m ON m.room_id = r.id \
+                WHERE r.company_id = $1 AND ($3::BIGINT IS NULL OR m.id <= $3) \
+                GROUP BY r.id \
+                ON CONFLICT (room_id, user_id) \
+                DO UPDATE SET last_message_seen_id = excluded.last_message_seen_id \
+                WHERE ChatLastSeen.last_message_seen_id < excluded.last_message_seen_id \
+                RETURNING room_id, last_message_seen_id) \
+            SELECT seen.room_id, r.user_id AS creator_id, seen.last_message_seen_id \
+            FROM seen JOIN ChatRoom r ON r.id = seen.room_id",
+        )
+        .bind::<sql_types::Uuid, _>(company_id)
+        .bind::<sql_types::Uuid, _>(user_id)
+        .bind::<sql_types::Nullable<sql_types::BigInt>, _>(up_to)
+        .load(conn)
+        .await?)
+    }
+}
+
+/// A room whose last seen message changed
+#[derive(QueryableByName)]
+pub struct SeenRoom {
+    #[diesel(sql_type = diesel::sql_types::Uuid)]
+    pub room_id: Uuid,
+    #[diesel(sql_type = diesel::sql_types::Uuid)]
+    pub creator_id: Uuid,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub last_message_seen_id: i64,
+}
+
+#[derive(Debug, Clone, Selectable, Queryable, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+#[diesel(table_name = crate::schema::chatdraft)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ChatDraft {
+    pub content: String,
+    #[serde(serialize_with = "crate::utils::serialize_timestamp")]
+    pub updated_at: PrimitiveDateTime,
+}
+
+impl ChatDraft {
+    pub async fn get(
+        room_id: Uuid,
+        user_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Option<Self>, Error> {
+        use crate::schema::chatdraft::dsl as dsl_cd;
+
+        Ok(dsl_cd::chatdraft
+            .filter(dsl_cd::room_id.eq(room_id))
+            .filter(dsl_cd::user_id.eq(user_id))
+            .select(ChatDraft::as_select())
+            .first(conn)
+            .await
+            .optional()?)
+    }
+
+    pub async fn save(
+        room_id: Uuid,
+        user_id: Uuid,
+        content: &str,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<Self, Error> {
+        use crate::schema::chatdraft::dsl as dsl_cd;
+
+        Ok(diesel::insert_into(dsl_cd::chatdraft)
+            .values((
+                dsl_cd::room_id.eq(room_id),
+                dsl_cd::user_id.eq(user_id),
+                dsl_cd::content.eq(content),
+            ))
+            .on_conflict((dsl_cd::room_id, dsl_cd::user_id))
+            .do_update()
+            .set((
+                dsl_cd::content.eq(content),
+                dsl_cd::updated_at.eq(diesel::dsl::now),
+            ))
+            .returning(ChatDraft::as_returning())
+            .get_result(conn)
+            .await?)
+    }
+
+    pub async fn clear(
+        room_id: Uuid,
+        user_id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use crate::schema::chatdraft::dsl as dsl_cd;
+
+        diesel::delete(dsl_cd::chatdraft)
+            .filter(dsl_cd::room_id.eq(room_id))
+            .filter(dsl_cd::user_id.eq(user_id))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+}
+
+pub struct ChatReaction;
+
+impl ChatReaction {
+    /// Returns false if the user had already reacted with the emoji
+    pub async fn add(
+        message_id: i64,
+        user_id: Uuid,
+        emoji: &str,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<bool, Error> {
+        use crate::schema::chatreaction::dsl as dsl_cr;
+
+        let inserted = diesel::insert_into(dsl_cr::chatreaction)
+            .values((
+                dsl_cr::message_id.eq(message_id),
+                dsl_cr::user_id.eq(user_id),
+                dsl_cr::emoji.eq(emoji),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        Ok(inserted > 0)
+    }
+
+    /// Returns false if the user had not reacted with the emoji
+    pub async fn remove(
+        message_id: i64,
+        user_id: Uuid,
+        emoji: &str,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<bool, Error> {
+        use crate::schema::chatreaction::dsl as dsl_cr;
+
+        let deleted = diesel::delete(dsl_cr::chatreaction)
+            .filter(dsl_cr::message_id.eq(message_id))
+            .filter(dsl_cr::user_id.eq(user_id))
+            .filter(dsl_cr::emoji.eq(emoji))
+            .execute(conn)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    /// How many users reacted with each emoji, by message
+    pub async fn counts(
+        message_ids: &[i64],
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<std::collections::HashMap<i64, Vec<ReactionCount>>, Error> {
+        use crate::schema::chatreaction::dsl as dsl_cr;
+
+        let rows = dsl_cr::chatreaction
+            .filter(dsl_cr::message_id.eq_any(message_ids))
+            .group_by((dsl_cr::message_id, dsl_cr::emoji))
+            .select((dsl_cr::message_id, dsl_cr::emoji, diesel::dsl::count_star()))
+            .order_by((dsl_cr::message_id, dsl_cr::emoji))
+            .load::<(i64, String, i64)>(conn)
+            .await?;
+
+        let mut counts = std::collections::HashMap::<_, Vec<_>>::new();
+        for (message_id, emoji, count) in rows {
+            counts
+                .entry(message_id)
+                .or_default()
+                .push(ReactionCount { emoji, count });
+        }
+
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn unread_counts_follow_messages_and_seen_markers() {
+        let mut conn = testing::conn().await;
+        let creator = testing::create_user(&mut conn).await;
+        let owner = testing::create_user(&mut conn).await;
+        let company_id = testing::create_company("Unread Inc", owner, &mut conn).await;
+        let (room_id, _) = ChatRoom::create(company_id, creator.id, creator.id, &mut conn)
+            .await
+            .unwrap();
+
+        let mut from_company = Vec::new();
+        for content in ["one", "two", "three"] {
+            let (id, _) = Message::insert(room_id, owner.id, content, &mut conn)
+                .await
+                .unwrap();
+            from_company.push(id);
+        }
+        Message::insert(room_id, creator.id, "reply", &mut conn)
+            .await
+            .unwrap();
+
+        // Messages of the user themselves are never unread
+        let creator_counts = UnreadCounts::for_user(creator.id, &mut conn).await.unwrap();
+        assert_eq!(creator_counts.rooms.get(&room_id), Some(&3));
+        assert_eq!(creator_counts.total, 3);
+        let owner_counts = UnreadCounts::for_user(owner.id, &mut conn).await.unwrap();
+        assert_eq!(owner_counts.rooms.get(&room_id), Some(&1));
+        assert_eq!(owner_counts.total, 1);
+
+        {
+            use crate::schema::chatlastseen::dsl as dsl_cls;
+
+            diesel::insert_into(dsl_cls::chatlastseen)
+                .values((
+                    dsl_cls::room_id.eq(room_id),
+                    dsl_cls::user_id.eq(creator.id),
+                    dsl_cls::last_message_seen_id.eq(from_company[1]),
+                ))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+        let creator_counts = UnreadCounts::for_user(creator.id, &mut conn).await.unwrap();
+        assert_eq!(creator_counts.rooms.get(&room_id), Some(&1));
+        assert_eq!(creator_counts.total, 1);
+
+        // Rooms without unread messages are left out
+        ChatLastSeen::mark_company_seen(company_id, owner.id, None, &mut conn)
+            .await
+            .unwrap();
+        let owner_counts = UnreadCounts::for_user(owner.id, &mut conn).await.unwrap();
+        assert!(owner_counts.rooms.is_empty());
+        assert_eq!(owner_counts.total, 0);
+    }
+
+    #[tokio::test]
+    async fn messages_are_paged_newest_first_by_id() {
+        let mut conn = testing::conn().await;
+        let creator = testing::create_user(&mut conn).await;
+        let owner = testing::create_user(&mut conn).await;
+        let company_id = testing::create_company("Paging Inc", owner, &mut conn).await;
+        let (room_id, _) = ChatRoom::create(company_id, creator.id, creator.id, &mut conn)
+            .await
+            .unwrap();
+        let (other_room_id, _) = ChatRoom::create(company_id, owner.id, creator.id, &mut conn)
+            .await
+            .unwrap();
+
+        let mut ids = Vec::new();
+        for content in ["one", "two", "three", "four", "five"] {
+            let (id, _) = Message::insert(room_id, creator.id, content, &mut conn)
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        Message::insert(other_room_id, creator.id, "elsewhere", &mut conn)
+            .await
+            .unwrap();
+
+        // Messages arriving between page fetches are newer than the cursor, so they neither
+        // shift the following pages nor show up in them
+        let mut pages = Vec::new();
+        let mut before = None;
+        loop {
+            let page = Message::list(room_id, before, 2, &mut conn).await.unwrap();
+            let Some(last) = page.last() else {
+                break;
+            };
+            before = Some(last.id);
+            pages.push(page.iter().map(|message| message.id).collect::<Vec<_>>());
+
+            Message::insert(room_id, owner.id, "meanwhile", &mut conn)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            pages,
+            vec![vec![ids[4], ids[3]], vec![ids[2], ids[1]], vec![ids[0]]]
+        );
+        let mut seen = pages.concat();
+        seen.sort_unstable();
+        seen.dedup();
+        assert_eq!(seen, ids);
+    }
+
+    #[tokio::test]
+    async fn creating_a_room_twice_returns_the_existing_one() {
+        let mut conn = testing::conn().await;
+        let creator = testing::create_user(&mut conn).await;
+        let owner = testing::create_user(&mut conn).await;
+        let company_id = testing::create_company("Rooms Inc", owner, &mut conn).await;
+
+        let (room_id, created) = ChatRoom::create(company_id, creator.id, owner.id, &mut conn)
+            .await
+            .unwrap();
+        assert!(created);
+
+        let (again_id, created) = ChatRoom::create(company_id, creator.id, creator.id, &mut conn)
+            .await
+            .unwrap();
+        assert!(!created);
+        assert_eq!(again_id, room_id);
+    }
+
+    #[tokio::test]
+    async fn racing_room_creations_return_the_same_room() {
+        use diesel_async::scoped_futures::ScopedFutureExt;
+
+        // Both connections have to see the company and users, so they are committed and
+        // removed again at the end
+        let mut first = testing::committed_conn().await;
+        let mut second = testing::committed_conn().await;
+        let creator = testing::create_user(&mut first).await;
+        let owner = testing::create_user(&mut first).await;
+        let company_id = testing::create_company("Racing Inc", owner, &mut first).await;
+
+        // The first creation holds its transaction open so the second one has to wait on the
+        // conflicting insert
+        let (first_room, second_room) = tokio::join!(
+            first.transaction::<_, Error, _>(|conn| async move {
+                let room = ChatRoom::create(company_id, creator.id, creator.id, conn).await?;
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                Ok(room)
+            }
+            .scope_boxed()),
+            async {
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                ChatRoom::create(company_id, creator.id, owner.id, &mut second).await
+            },
+        );
+
+        {
+            use crate::schema::{company::dsl as dsl_c, inneruser::dsl as dsl_iu};
+
+            diesel::delete(dsl_c::company.find(company_id))
+                .execute(&mut first)
+                .await
+                .unwrap();
+            diesel::delete(dsl_iu::inneruser.filter(dsl_iu::id.eq_any([creator.id, owner.id])))
+                .execute(&mut first)
+                .await
+                .unwrap();
+        }
+
+        let (first_room, second_room) = (first_room.unwrap(), second_room.unwrap());
+        // Exactly one of them created the room
+        assert_ne!(first_room.1, second_room.1);
+        assert_eq!(first_room.0, second_room.0);
+    }
+
+    #[tokio::test]
+    async fn session_lookups_are_indexed() {
+        #[derive(QueryableByName)]
+        struct Index {
+            #[diesel(sql_type = diesel::sql_types::Text)]
+            indexdef: String,
+        }
+
+        let mut conn = testing::conn().await;
+
+        for (name, column) in [
+            ("inner_user_session_user", "(user_id)"),
+            ("session_fcm_token_session", "(session_token)"),
+        ] {
+            let index = diesel::sql_query("SELECT indexdef FROM pg_indexes WHERE indexname = $1")
+                .bind::<diesel::sql_types::Text, _>(name)
+                .get_result::<Index>(&mut conn)
+                .await
+                .unwrap();
+            assert!(index.indexdef.ends_with(column), "{}", index.indexdef);
+        }
+    }
 }
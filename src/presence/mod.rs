@@ -0,0 +1,224 @@
+//! Which sessions have websocket pages open, shared between every instance of the server.
+//!
+//! The pages themselves stay in the memory of the instance they are connected to, only the
+//! count of them is kept in the store. Events for pages go through an [`EventBus`] so they
+//! reach the instance the page is connected to. Websocket tickets are kept in the store too, so
+//! a ticket can be redeemed on any instance.
+
+mod redis;
+
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    state::{deliver_to_local_pages, Sessions},
+    Error,
+};
+
+pub use self::redis::{RedisBus, RedisPresence};
+
+/// Identifies a session without revealing its token, used wherever sessions are shared between
+/// instances
+pub fn session_id(session_token: &str) -> String {
+    format!("{:x}", Sha256::digest(session_token.as_bytes()))
+}
+
+#[async_trait]
+pub trait PresenceStore: Send + Sync {
+    /// Records that a page of the session was opened on this instance
+    async fn page_opened(&self, session_token: &str) -> Result<(), Error>;
+
+    /// Undoes [`PresenceStore::page_opened`]
+    async fn page_closed(&self, session_token: &str) -> Result<(), Error>;
+
+    /// Pages of the session that are open across all instances
+    async fn open_pages(&self, session_token: &str) -> Result<u64, Error>;
+
+    /// Records that a page of the session on this instance moved from viewing one room to
+    /// another, `None` being no room
+    async fn viewing_changed(
+        &self,
+        session_token: &str,
+        from: Option<Uuid>,
+        to: Option<Uuid>,
+    ) -> Result<(), Error>;
+
+    /// If any page of the session, across all instances, is viewing the room
+    async fn is_viewing(&self, session_token: &str, room_id: Uuid) -> Result<bool, Error>;
+
+    /// Stores a websocket ticket of the session that can be taken until `ttl` passes
+    async fn store_ws_ticket(
+        &self,
+        ticket: &str,
+        session_token: &str,
+        ttl: Duration,
+    ) -> Result<(), Error>;
+
+    /// Removes the ticket and returns the session token it was stored for if it has not
+    /// expired
+    async fn take_ws_ticket(&self, ticket: &str) -> Result<Option<String>, Error>;
+
+    /// Drops expired tickets, for stores that do not expire them by themselves
+    fn prune_ws_tickets(&self) {}
+}
+
+/// Presence of a single instance, lost on restart
+#[derive(Default)]
+pub struct MemoryPresence {
+    pages: DashMap<String, u64>,
+    viewing: DashMap<(String, Uuid), u64>,
+    /// Session token and expiry of every ticket
+    ws_tickets: DashMap<String, (String, Instant)>,
+}
+
+#[async_trait]
+impl PresenceStore for MemoryPresence {
+    async fn page_opened(&self, session_token: &str) -> Result<(), Error> {
+        *self.pages.entry(session_token.into()).or_default() += 1;
+        Ok(())
+    }
+
+    async fn page_closed(&self, session_token: &str) -> Result<(), Error> {
+        self.pages.remove_if_mut(session_token, |_, pages| {
+            *pages = pages.saturating_sub(1);
+            *pages == 0
+        });
+        Ok(())
+    }
+
+    async fn open_pages(&self, session_token: &str) -> Result<u64, Error> {
+        Ok(self.pages.get(session_token).map_or(0, |pages| *pages))
+    }
+
+    async fn viewing_changed(
+        &self,
+        session_token: &str,
+        from: Option<Uuid>,
+        to: Option<Uuid>,
+    ) -> Result<(), Error> {
+        if let Some(from) = from {
+            self.viewing
+                .remove_if_mut(&(session_token.into(), from), |_, pages| {
+                    *pages = pages.saturating_sub(1);
+                    *pages == 0
+                });
+        }
+        if let Some(to) = to {
+            *self.viewing.entry((session_token.into(), to)).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    async fn is_viewing(&self, session_token: &str, room_id: Uuid) -> Result<bool, Error> {
+        Ok(self.viewing.contains_key(&(session_token.into(), room_id)))
+    }
+
+    async fn store_ws_ticket(
+        &self,
+        ticket: &str,
+        session_token: &str,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        self.ws_tickets
+            .insert(ticket.into(), (session_token.into(), Instant::now() + ttl));
+        Ok(())
+    }
+
+    async fn take_ws_ticket(&self, ticket: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .ws_tickets
+            .remove(ticket)
+            .filter(|(_, (_, expires_at))| *expires_at >= Instant::now())
+            .map(|(_, (session_token, _))| session_token))
+    }
+
+    fn prune_ws_tickets(&self) {
+        let now = Instant::now();
+        self.ws_tickets
+            .retain(|_, (_, expires_at)| *expires_at >= now);
+    }
+}
+
+/// An event for every page of the sessions, on whichever instance they are open
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct PageEvent {
+    /// [`session_id`] of the sessions
+    pub session_ids: Vec<String>,
+    pub data: serde_json::Value,
+}
+
+#[async_trait]
+pub trait EventBus: Send + Sync {
+    async fn publish(&self, event: PageEvent) -> Result<(), Error>;
+}
+
+/// Bus of a single instance, every page is local
+pub struct MemoryBus {
+    sessions: &'static Sessions,
+}
+
+#[async_trait]
+impl EventBus for MemoryBus {
+    async fn publish(&self, event: PageEvent) -> Result<(), Error> {
+        deliver_to_local_pages(self.sessions, &event).await;
+        Ok(())
+    }
+}
+
+/// Builds the store and bus for the configured backend, Redis if a url is set and memory
+/// otherwise
+pub async fn connect(
+    redis_url: Option<&str>,
+    sessions: &'static Sessions,
+) -> (&'static dyn PresenceStore, &'static dyn EventBus) {
+    match redis_url {
+        Some(url) => {
+            let presence: &'static RedisPresence = Box::leak(Box::new(
+                RedisPresence::connect(url)
+                    .await
+                    .expect("Failed to connect to redis"),
+            ));
+            tokio::spawn(presence.heartbeat());
+
+            let bus = RedisBus::connect(url, sessions)
+                .await
+                .expect("Failed to connect to redis");
+
+            (presence, Box::leak(Box::new(bus)))
+        }
+        None => (
+            Box::leak(Box::<MemoryPresence>::default()),
+            Box::leak(Box::new(MemoryBus { sessions })),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn ws_tickets_are_taken_once_and_expire() {
+        let presence = MemoryPresence::default();
+        let ttl = Duration::from_secs(30);
+
+        presence
+            .store_ws_ticket("ticket", "token", ttl)
+            .await
+            .unwrap();
+        let taken = presence.take_ws_ticket("ticket").await.unwrap();
+        assert_eq!(taken.as_deref(), Some("token"));
+        assert_eq!(presence.take_ws_ticket("ticket").await.unwrap(), None);
+
+        presence
+            .store_ws_ticket("expired", "token", Duration::ZERO)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(presence.take_ws_ticket("expired").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,255 @@
+use std::time::Duration;
+
+use axum::async_trait;
+use dashmap::DashMap;
+use futures::StreamExt;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use uuid::Uuid;
+
+use crate::{
+    state::{deliver_to_local_pages, Sessions},
+    Error,
+};
+
+use super::{session_id, EventBus, PageEvent, PresenceStore};
+
+/// Presence shared through Redis. Every session is a hash of instance id to the pages it has
+/// open there, and every room viewed by the session is a hash of the same shape. Sessions are
+/// keyed by [`session_id`] so the tokens never reach Redis, apart from websocket tickets which
+/// hold the token of their session until they are taken or expire. Instances keep a key alive
+/// while they run so the pages of an instance that died without closing them stop being counted
+/// once the key expires, the hashes expire the same way once no instance refreshes them.
+pub struct RedisPresence {
+    conn: ConnectionManager,
+    instance_id: Uuid,
+    /// Hashes this instance has a count in, refreshed by the heartbeat
+    held: DashMap<String, u64>,
+}
+
+impl RedisPresence {
+    const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+    const INSTANCE_TTL_SECS: u64 = 30;
+    const HASH_TTL_SECS: i64 = 30;
+
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let client = redis::Client::open(url)?;
+
+        Ok(RedisPresence {
+            conn: ConnectionManager::new(client).await?,
+            instance_id: Uuid::new_v4(),
+            held: DashMap::new(),
+        })
+    }
+
+    fn session_key(session_token: &str) -> String {
+        format!("presence:session:{}", session_id(session_token))
+    }
+
+    fn viewing_key(session_token: &str, room_id: Uuid) -> String {
+        format!("presence:viewing:{}:{room_id}", session_id(session_token))
+    }
+
+    fn ws_ticket_key(ticket: &str) -> String {
+        format!("ws-ticket:{}", session_id(ticket))
+    }
+
+    fn instance_key(instance_id: &str) -> String {
+        format!("presence:instance:{instance_id}")
+    }
+
+    /// Keeps this instance marked as alive, runs forever
+    pub async fn heartbeat(&'static self) {
+        let key = Self::instance_key(&self.instance_id.to_string());
+
+        loop {
+            let mut conn = self.conn.clone();
+            if let Err(err) = conn
+                .set_ex::<_, _, ()>(&key, 1, Self::INSTANCE_TTL_SECS)
+                .await
+            {
+                tracing::error!("Failed to refresh the presence of this instance: {err:?}");
+            }
+
+            let mut pipe = redis::pipe();
+            for key in self.held.iter() {
+                pipe.expire(key.key(), Self::HASH_TTL_SECS).ignore();
+            }
+            if let Err(err) = pipe.query_async::<_, ()>(&mut conn).await {
+                tracing::error!("Failed to refresh the presence of the sessions: {err:?}");
+            }
+
+            tokio::time::sleep(Self::HEARTBEAT_INTERVAL).await;
+        }
+    }
+
+    /// Increments the count of this instance in the hash
+    async fn increment(&self, key: String) -> Result<(), Error> {
+        let mut conn = self.conn.clone();
+        redis::pipe()
+            .hincr(&key, self.instance_id.to_string(), 1)
+            .ignore()
+            .expire(&key, Self::HASH_TTL_SECS)
+            .ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        *self.held.entry(key).or_default() += 1;
+
+        Ok(())
+    }
+
+    /// Decrements the count of this instance in the hash, dropping it once it reaches zero
+    async fn decrement(&self, key: String) -> Result<(), Error> {
+        let instance_id = self.instance_id.to_string();
+
+        let mut conn = self.conn.clone();
+        let (count, ()): (i64, ()) = redis::pipe()
+            .hincr(&key, &instance_id, -1)
+            .expire(&key, Self::HASH_TTL_SECS)
+            .query_async(&mut conn)
+            .await?;
+        if count <= 0 {
+            conn.hdel::<_, _, ()>(&key, &instance_id).await?;
+        }
+
+        self.held.remove_if_mut(&key, |_, held| {
+            *held = held.saturating_sub(1);
+            *held == 0
+        });
+
+        Ok(())
+    }
+
+    /// Sums the counts of the hash over the instances that are still alive
+    async fn count(&self, key: String) -> Result<u64, Error> {
+        let mut conn = self.conn.clone();
+        let counts: Vec<(String, i64)> = conn.hgetall(key).await?;
+
+        let mut total = 0;
+        for (instance_id, count) in counts {
+            if conn
+                .exists::<_, bool>(Self::instance_key(&instance_id))
+                .await?
+            {
+                total += count.max(0) as u64;
+            }
+        }
+
+        Ok(total)
+    }
+}
+
+#[async_trait]
+impl PresenceStore for RedisPresence {
+    async fn page_opened(&self, session_token: &str) -> Result<(), Error> {
+        self.increment(Self::session_key(session_token)).await
+    }
+
+    async fn page_closed(&self, session_token: &str) -> Result<(), Error> {
+        self.decrement(Self::session_key(session_token)).await
+    }
+
+    async fn open_pages(&self, session_token: &str) -> Result<u64, Error> {
+        self.count(Self::session_key(session_token)).await
+    }
+
+    async fn viewing_changed(
+        &self,
+        session_token: &str,
+        from: Option<Uuid>,
+        to: Option<Uuid>,
+    ) -> Result<(), Error> {
+        if let Some(from) = from {
+            self.decrement(Self::viewing_key(session_token, from))
+                .await?;
+        }
+        if let Some(to) = to {
+            self.increment(Self::viewing_key(session_token, to)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn is_viewing(&self, session_token: &str, room_id: Uuid) -> Result<bool, Error> {
+        Ok(self
+            .count(Self::viewing_key(session_token, room_id))
+            .await?
+            > 0)
+    }
+
+    async fn store_ws_ticket(
+        &self,
+        ticket: &str,
+        session_token: &str,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let mut conn = self.conn.clone();
+        conn.set_ex::<_, _, ()>(Self::ws_ticket_key(ticket), session_token, ttl.as_secs())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn take_ws_ticket(&self, ticket: &str) -> Result<Option<String>, Error> {
+        let mut conn = self.conn.clone();
+        Ok(conn.get_del(Self::ws_ticket_key(ticket)).await?)
+    }
+}
+
+/// Bus shared through Redis pub/sub. Every instance, including the one publishing, delivers
+/// the events to the pages connected to it. Events published while an instance is
+/// resubscribing are not delivered to it.
+pub struct RedisBus {
+    conn: ConnectionManager,
+}
+
+impl RedisBus {
+    const CHANNEL: &'static str = "page-events";
+    const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+    pub async fn connect(url: &str, sessions: &'static Sessions) -> Result<Self, Error> {
+        let client = redis::Client::open(url)?;
+        tokio::spawn(Self::subscribe(client.clone(), sessions));
+
+        Ok(RedisBus {
+            conn: ConnectionManager::new(client).await?,
+        })
+    }
+
+    async fn subscribe(client: redis::Client, sessions: &'static Sessions) {
+        loop {
+            if let Err(err) = Self::listen(&client, sessions).await {
+                tracing::error!("Lost the subscription to page events: {err:?}");
+            }
+
+            tokio::time::sleep(Self::RESUBSCRIBE_DELAY).await;
+        }
+    }
+
+    async fn listen(client: &redis::Client, sessions: &'static Sessions) -> Result<(), Error> {
+        let mut pubsub = client.get_async_pubsub().await?;
+        pubsub.subscribe(Self::CHANNEL).await?;
+
+        let mut messages = pubsub.on_message();
+        while let Some(msg) = messages.next().await {
+            let payload: String = msg.get_payload()?;
+            match serde_json::from_str::<PageEvent>(&payload) {
+                Ok(event) => deliver_to_local_pages(sessions, &event).await,
+                Err(err) => tracing::warn!("Ignoring a malformed page event: {err:?}"),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventBus for RedisBus {
+    async fn publish(&self, event: PageEvent) -> Result<(), Error> {
+        let mut conn = self.conn.clone();
+        conn.publish::<_, _, ()>(Self::CHANNEL, serde_json::to_string(&event)?)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -11,6 +11,7 @@ diesel::table! {
         id -> Int8,
         message_id -> Int8,
         offered_payout -> Money,
+        currency -> Bpchar,
     }
 }
 
@@ -26,6 +27,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    chatdraft (room_id, user_id) {
+        room_id -> Uuid,
+        user_id -> Uuid,
+        content -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     chatlastseen (room_id, user_id) {
         room_id -> Uuid,
@@ -44,11 +54,21 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    chatreaction (message_id, user_id, emoji) {
+        message_id -> Int8,
+        user_id -> Uuid,
+        emoji -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     chatroom (id) {
         id -> Uuid,
         company_id -> Uuid,
         user_id -> Uuid,
+        opened_by -> Nullable<Uuid>,
     }
 }
 
@@ -126,11 +146,15 @@ diesel::table! {
 diesel::joinable!(chatcontractoffer -> chatmessage (message_id));
 diesel::joinable!(chatcontractofferupdate -> chatcontractoffer (offer_id));
 diesel::joinable!(chatcontractofferupdate -> chatmessage (message_id));
+diesel::joinable!(chatdraft -> chatroom (room_id));
+diesel::joinable!(chatdraft -> inneruser (user_id));
 diesel::joinable!(chatlastseen -> chatmessage (last_message_seen_id));
 diesel::joinable!(chatlastseen -> chatroom (room_id));
 diesel::joinable!(chatlastseen -> inneruser (user_id));
 diesel::joinable!(chatmessage -> chatroom (room_id));
 diesel::joinable!(chatmessage -> inneruser (from_user_id));
+diesel::joinable!(chatreaction -> chatmessage (message_id));
+diesel::joinable!(chatreaction -> inneruser (user_id));
 diesel::joinable!(chatroom -> company (company_id));
 diesel::joinable!(chatroom -> inneruser (user_id));
 diesel::joinable!(companyuser -> company (company_id));
@@ -144,8 +168,10 @@ diesel::joinable!(twitchaccount -> inneruser (user_id));
 diesel::allow_tables_to_appear_in_same_query!(
     chatcontractoffer,
     chatcontractofferupdate,
+    chatdraft,
     chatlastseen,
     chatmessage,
+    chatreaction,
     chatroom,
     company,
     companyuser,
@@ -0,0 +1,152 @@
+//! Requests from other services, authenticated with a signature instead of a session.
+//!
+//! A signed request carries three headers:
+//! - `X-Api-Key-Id`, the id of the [`ApiKey`]
+//! - `X-Timestamp`, when it was signed in seconds since the unix epoch
+//! - `X-Signature`, the hex encoded HMAC-SHA256 keyed with the secret of the key of
+//!   `{method}\n{path and query}\n{timestamp}\n{body}`, the path including `/api/v1`
+//!
+//! Requests signed more than [`crate::SIGNED_REQUEST_MAX_AGE`] away from now are rejected so a
+//! captured request cannot be replayed later. Within that window the signatures of accepted
+//! requests are recorded as [`SignedRequest`]s and a signature is only accepted once. Identical
+//! requests signed within the same second therefore only go through the first time.
+
+use axum::{
+    body::Body,
+    extract::{OriginalUri, Request, State},
+    http::{HeaderName, StatusCode},
+    middleware::Next,
+    response::Response,
+    routing, Json, Router,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use time::OffsetDateTime;
+
+use crate::{
+    db::{ApiKey, PlatformStats, ServicePrincipal, SignedRequest},
+    state::{AppState, DbConn},
+    Error,
+};
+
+const API_KEY_ID_HEADER: HeaderName = HeaderName::from_static("x-api-key-id");
+const TIMESTAMP_HEADER: HeaderName = HeaderName::from_static("x-timestamp");
+const SIGNATURE_HEADER: HeaderName = HeaderName::from_static("x-signature");
+
+/// Signed requests are rejected like requests without a session, the reason is only logged
+fn unauthenticated(reason: &str) -> Error {
+    tracing::debug!("Rejected a signed request: {reason}");
+    Error::Unauthenticated
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Checks the signature of requests made with an api key and makes their
+/// [`ServicePrincipal`] available to the handlers. Requests without a key are passed through
+/// untouched.
+pub async fn verify_signature(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    if !request.headers().contains_key(API_KEY_ID_HEADER) {
+        return Ok(next.run(request).await);
+    }
+
+    let header = |name: &HeaderName| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .ok_or_else(|| unauthenticated(&format!("Missing or invalid {name} header")))
+    };
+    let key_id = header(&API_KEY_ID_HEADER)?;
+    let timestamp = header(&TIMESTAMP_HEADER)?;
+    let signature = decode_hex(&header(&SIGNATURE_HEADER)?)
+        .ok_or_else(|| unauthenticated("The signature is not hex encoded"))?;
+
+    let signed_at = timestamp
+        .parse::<i64>()
+        .map_err(|_| unauthenticated("The timestamp is not a number of seconds"))?;
+    let age = OffsetDateTime::now_utc()
+        .unix_timestamp()
+        .abs_diff(signed_at);
+    if age > crate::SIGNED_REQUEST_MAX_AGE.as_secs() {
+        return Err(unauthenticated("The request was signed too long ago"));
+    }
+
+    let key = {
+        let mut conn = state.get_conn().await?;
+        ApiKey::active(&key_id, &mut conn)
+            .await?
+            .ok_or_else(|| unauthenticated("Unknown or revoked api key"))?
+    };
+
+    let (mut parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, crate::MAX_UPLOAD_BYTES)
+        .await
+        .map_err(|_| Error::Custom {
+            status_code: StatusCode::PAYLOAD_TOO_LARGE,
+            error: "The request body is too large".into(),
+        })?;
+
+    // The uri of the request is stripped of the prefix the router is nested under
+    let uri = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(&parts.uri, |uri| &uri.0);
+    let path = uri
+        .path_and_query()
+        .map_or(uri.path(), |path_and_query| path_and_query.as_str());
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(parts.method.as_str().as_bytes());
+    mac.update(b"\n");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(timestamp.as_bytes());
+    mac.update(b"\n");
+    mac.update(&body);
+    mac.verify_slice(&signature)
+        .map_err(|_| unauthenticated("Invalid signature"))?;
+
+    {
+        let mut conn = state.get_conn().await?;
+        if !SignedRequest::record(&key.id, &signature, &mut conn).await? {
+            return Err(unauthenticated("The request was already made"));
+        }
+    }
+
+    parts.extensions.insert(ServicePrincipal {
+        key_id: key.id,
+        name: key.name,
+        scopes: key.scopes,
+    });
+
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+/// The same counts admins see, for analytics
+async fn stats(
+    principal: ServicePrincipal,
+    DbConn { mut conn }: DbConn,
+) -> Result<Json<PlatformStats>, Error> {
+    principal.require_scope("stats:read")?;
+
+    Ok(Json(crate::admin::cached_stats(&mut conn).await?))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/stats", routing::get(stats))
+}
@@ -0,0 +1,44 @@
+use axum::{extract::State, routing, Json, Router};
+
+use crate::{
+    db::{User, UserSession},
+    state::{AppState, DbConn, Session},
+    Error,
+};
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RevokeAllParam {
+    /// Keeps the session making the request signed in
+    #[serde(default)]
+    keep_current: bool,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Revoked {
+    revoked: usize,
+}
+
+/// Signs the user out everywhere, e.g. when they suspect someone else has one of their sessions
+async fn revoke_all(
+    user: User,
+    session: Session,
+    DbConn { mut conn }: DbConn,
+    State(state): State<AppState>,
+    // A POST without a body revokes every session
+    param: Option<Json<RevokeAllParam>>,
+) -> Result<Json<Revoked>, Error> {
+    let keep_current = param.is_some_and(|Json(param)| param.keep_current);
+    let keep = keep_current.then(|| session.token());
+    let revoked = UserSession::delete_for_user(user, keep, &mut conn).await?;
+    state.drop_sessions(&revoked);
+
+    Ok(Json(Revoked {
+        revoked: revoked.len(),
+    }))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/revoke-all", routing::post(revoke_all))
+}
@@ -1,33 +1,64 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{atomic::AtomicBool, Arc},
+    time::Instant,
+};
 
 use axum::{
     async_trait,
     extract::FromRequestParts,
-    http::{header::COOKIE, request::Parts},
+    http::{
+        header::{AUTHORIZATION, COOKIE},
+        request::Parts,
+        HeaderMap,
+    },
 };
 use dashmap::DashMap;
-use diesel::{pg::Pg, ExpressionMethods, JoinOnDsl, QueryDsl};
+use diesel::{pg::Pg, ExpressionMethods, JoinOnDsl, NullableExpressionMethods, QueryDsl};
 use diesel_async::{
     pooled_connection::deadpool::{Object, Pool},
+    scoped_futures::ScopedBoxFuture,
     AsyncConnection, AsyncPgConnection, RunQueryDsl,
 };
 use tokio::sync::{mpsc, RwLock};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use uuid::Uuid;
 
 use crate::{
-    db::{Encoder, User},
+    db::{EmbeddingTemplates, Encoder, NotificationSettings, User},
+    presence::{session_id, EventBus, PageEvent, PresenceStore},
+    storage::{DefaultAvatar, RemoteImagePolicy, StorageQuota},
+    utils::{
+        oauth::OAuthCredentials,
+        rate_limit::{RateLimit, RateLimiter, UserRateLimiter},
+    },
     ws::{WsError, WsFuncParam, WsFunctions, WsResponse},
-    Error, SESSION_COOKIE_NAME,
+    Error,
 };
 
 #[derive(Clone, Copy)]
 pub struct AppState {
     pub(super) pool: &'static Pool<AsyncPgConnection>,
-    sessions: &'static DashMap<String, Arc<RwLock<SessionState>>>,
+    sessions: &'static Sessions,
+    presence: &'static dyn PresenceStore,
+    bus: &'static dyn EventBus,
     ws_funcs: &'static WsFunctions,
+    ws_call_limiter: &'static UserRateLimiter,
+    ws_sockets: &'static TaskTracker,
+    typing: &'static DashMap<(Uuid, Uuid), Instant>,
+    shutdown: &'static CancellationToken,
     fcm_tx: &'static mpsc::UnboundedSender<fcm::Message>,
+    http_client: &'static reqwest::Client,
     config: Config,
     encoder: Encoder,
+    name_availability_limiter: &'static UserRateLimiter,
+    invite_resend_limiter: &'static RateLimiter<(Uuid, String)>,
+    logo_refresh_limiter: &'static RateLimiter<Uuid>,
+    room_creation_limiter: &'static UserRateLimiter,
+    message_limiter: &'static RateLimiter<(Uuid, Uuid)>,
+    contract_change_limiter: &'static RateLimiter<(Uuid, Uuid)>,
+    fcm_healthy: &'static AtomicBool,
 }
 
 impl AppState {
@@ -37,6 +68,12 @@ impl AppState {
         ws_funcs: WsFunctions,
         config: Config,
     ) -> Self {
+        let embedding_limiter =
+            Box::leak(Box::new(UserRateLimiter::new(config.embedding_rate_limit)));
+
+        let sessions: &'static Sessions = Box::leak(Box::default());
+        let (presence, bus) = crate::presence::connect(config.redis_url, sessions).await;
+
         Self {
             pool: {
                 let config =
@@ -48,11 +85,47 @@ impl AppState {
 
                 Box::leak(Box::new(pool))
             },
-            sessions: Box::leak(Box::default()),
+            sessions,
+            presence,
+            bus,
             ws_funcs: Box::leak(Box::new(ws_funcs)),
+            ws_call_limiter: Box::leak(Box::new(UserRateLimiter::new(crate::WS_CALL_RATE_LIMIT))),
+            ws_sockets: Box::leak(Box::default()),
+            typing: Box::leak(Box::default()),
+            shutdown: Box::leak(Box::default()),
             fcm_tx: Box::leak(Box::new(fcm_tx)),
+            http_client: Box::leak(Box::new(
+                config
+                    .http_timeouts
+                    .client_builder()
+                    .pool_idle_timeout(std::time::Duration::from_secs(90))
+                    .build()
+                    .expect("Failed to build the http client"),
+            )),
             config,
-            encoder: Encoder::new().await,
+            encoder: Encoder::new(
+                embedding_limiter,
+                config.http_timeouts,
+                config.embedding_templates,
+            )
+            .await,
+            name_availability_limiter: Box::leak(Box::new(UserRateLimiter::new(
+                crate::NAME_AVAILABILITY_RATE_LIMIT,
+            ))),
+            invite_resend_limiter: Box::leak(Box::new(RateLimiter::new(
+                crate::INVITE_RESEND_RATE_LIMIT,
+            ))),
+            logo_refresh_limiter: Box::leak(Box::new(RateLimiter::new(
+                crate::LOGO_REFRESH_RATE_LIMIT,
+            ))),
+            room_creation_limiter: Box::leak(Box::new(UserRateLimiter::new(
+                crate::ROOM_CREATION_RATE_LIMIT,
+            ))),
+            message_limiter: Box::leak(Box::new(RateLimiter::new(config.message_rate_limit))),
+            contract_change_limiter: Box::leak(Box::new(RateLimiter::new(
+                crate::CONTRACT_CHANGE_RATE_LIMIT,
+            ))),
+            fcm_healthy: Box::leak(Box::new(AtomicBool::new(true))),
         }
     }
 
@@ -64,15 +137,150 @@ impl AppState {
         self.ws_funcs
     }
 
+    pub fn ws_call_limiter(&self) -> &'static UserRateLimiter {
+        self.ws_call_limiter
+    }
+
+    /// Every open websocket holds a token from this tracker so shutdown can wait for them to
+    /// send their close frames
+    pub fn ws_sockets(&self) -> &'static TaskTracker {
+        self.ws_sockets
+    }
+
+    pub fn typing(&self) -> Typing {
+        Typing(self.typing)
+    }
+
+    /// Cancelled once the server starts shutting down
+    pub fn shutdown(&self) -> &'static CancellationToken {
+        self.shutdown
+    }
+
     pub fn config(&self) -> Config {
         self.config
     }
+
+    /// Cleared while the fcm client keeps failing and is being rebuilt
+    pub fn fcm_healthy(&self) -> &'static AtomicBool {
+        self.fcm_healthy
+    }
+
+    pub fn http_client(&self) -> &'static reqwest::Client {
+        self.http_client
+    }
+
+    pub fn msg_emitter(&self) -> MsgEmitter {
+        MsgEmitter {
+            fcm_tx: self.fcm_tx,
+            presence: self.presence,
+            bus: self.bus,
+        }
+    }
+
+    pub fn embedding_limiter(&self) -> &'static UserRateLimiter {
+        self.encoder.limiter()
+    }
+
+    pub fn embeddings_available(&self) -> bool {
+        self.encoder.is_available()
+    }
+
+    pub fn name_availability_limiter(&self) -> &'static UserRateLimiter {
+        self.name_availability_limiter
+    }
+
+    /// Limits resends per company and invited email
+    pub fn invite_resend_limiter(&self) -> &'static RateLimiter<(Uuid, String)> {
+        self.invite_resend_limiter
+    }
+
+    /// Keyed by company id
+    pub fn logo_refresh_limiter(&self) -> &'static RateLimiter<Uuid> {
+        self.logo_refresh_limiter
+    }
+
+    pub fn room_creation_limiter(&self) -> &'static UserRateLimiter {
+        self.room_creation_limiter
+    }
+
+    /// Keyed by room id and user id
+    pub fn message_limiter(&self) -> &'static RateLimiter<(Uuid, Uuid)> {
+        self.message_limiter
+    }
+
+    /// Keyed by room id and user id
+    pub fn contract_change_limiter(&self) -> &'static RateLimiter<(Uuid, Uuid)> {
+        self.contract_change_limiter
+    }
+
+    /// Issues a one time ticket that can be used instead of the session cookie to open a
+    /// websocket
+    pub async fn issue_ws_ticket(&self, session: &Session) -> Result<String, Error> {
+        use rand::Rng;
+
+        let ticket: String = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(64)
+            .map(char::from)
+            .collect();
+
+        self.presence
+            .store_ws_ticket(&ticket, &session.session_token, crate::WS_TICKET_DURATION)
+            .await?;
+
+        Ok(ticket)
+    }
+
+    /// Consumes the ticket, on whichever instance it was issued, and returns the session it was
+    /// issued for if it has not expired
+    pub async fn redeem_ws_ticket(&self, ticket: &str) -> Result<Option<Session>, Error> {
+        let Some(session_token) = self.presence.take_ws_ticket(ticket).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(Session {
+            state: self
+                .sessions
+                .entry(session_id(&session_token))
+                .or_default()
+                .clone(),
+            session_token,
+            presence: self.presence,
+        }))
+    }
+
+    /// Forgets the state of the sessions on this instance. Their pages here and on other
+    /// instances are closed by the next auth check of the websocket.
+    pub fn drop_sessions(&self, session_tokens: &[String]) {
+        for token in session_tokens {
+            self.sessions.remove(&session_id(token));
+        }
+    }
+
+    pub fn prune_ws_tickets(&self) {
+        self.presence.prune_ws_tickets();
+    }
 }
 
 pub struct DbConn {
     pub conn: Object<AsyncPgConnection>,
 }
 
+impl DbConn {
+    /// Runs `f` inside a transaction that is committed if it returns `Ok` and rolled back
+    /// otherwise
+    pub async fn transaction<'a, R, F>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: for<'r> FnOnce(&'r mut AsyncPgConnection) -> ScopedBoxFuture<'a, 'r, Result<R, Error>>
+            + Send
+            + 'a,
+        R: Send + 'a,
+    {
+        let conn: &mut AsyncPgConnection = &mut self.conn;
+        conn.transaction(f).await
+    }
+}
+
 #[async_trait]
 impl FromRequestParts<AppState> for DbConn {
     type Rejection = Error;
@@ -86,6 +294,28 @@ impl FromRequestParts<AppState> for DbConn {
     }
 }
 
+impl WsFuncParam for AppState {
+    async fn make<'m>(
+        _data: &'m serde_json::Value,
+        _session: &'m SessionWithPage,
+        _user: User,
+        state: &'m AppState,
+    ) -> Result<Self, WsError> {
+        Ok(*state)
+    }
+}
+
+impl WsFuncParam for Config {
+    async fn make<'m>(
+        _data: &'m serde_json::Value,
+        _session: &'m SessionWithPage,
+        _user: User,
+        state: &'m AppState,
+    ) -> Result<Self, WsError> {
+        Ok(state.config)
+    }
+}
+
 impl WsFuncParam for DbConn {
     async fn make<'m>(
         _data: &'m serde_json::Value,
@@ -98,30 +328,125 @@ impl WsFuncParam for DbConn {
     }
 }
 
+/// Client shared by every request to an external api so connections and TLS sessions are
+/// reused
+pub struct HttpClient {
+    pub client: &'static reqwest::Client,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for HttpClient {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(HttpClient {
+            client: state.http_client,
+        })
+    }
+}
+
 pub struct MsgEmitter {
     fcm_tx: &'static mpsc::UnboundedSender<fcm::Message>,
+    presence: &'static dyn PresenceStore,
+    bus: &'static dyn EventBus,
 }
 
 impl MsgEmitter {
+    /// Sends the event to every session of the user. Sessions with a page open on any instance
+    /// get it there. It is pushed over fcm, if the user has push notifications on, to the rest
+    /// and to the sessions that have no page with the room of the event in focus.
     pub async fn send(
         &self,
         user_id: Uuid,
         msg_data: Option<serde_json::Value>,
         msg_notif: Option<fcm::Notification>,
         conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        self.send_scoped(user_id, None, msg_data, msg_notif, conn)
+            .await
+    }
+
+    /// Like [`MsgEmitter::send`] for an event of the company, pushed according to the setting
+    /// of the user for that company
+    pub async fn send_for_company(
+        &self,
+        company_id: Uuid,
+        user_id: Uuid,
+        msg_data: Option<serde_json::Value>,
+        msg_notif: Option<fcm::Notification>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        self.send_scoped(user_id, Some(company_id), msg_data, msg_notif, conn)
+            .await
+    }
+
+    async fn send_scoped(
+        &self,
+        user_id: Uuid,
+        company_id: Option<Uuid>,
+        msg_data: Option<serde_json::Value>,
+        msg_notif: Option<fcm::Notification>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
     ) -> Result<(), Error> {
         use crate::schema::innerusersession::dsl as dsl_ius;
         use crate::schema::sessionfcmtoken::dsl as dsl_sft;
 
-        let room_user_tokens = dsl_ius::innerusersession
+        let sessions = dsl_ius::innerusersession
             .filter(dsl_ius::user_id.eq(user_id))
-            .inner_join(dsl_sft::sessionfcmtoken.on(dsl_sft::session_token.eq(dsl_ius::token)))
-            .select(dsl_sft::token)
-            .distinct()
-            .load::<String>(conn)
+            .left_join(dsl_sft::sessionfcmtoken.on(dsl_sft::session_token.eq(dsl_ius::token)))
+            .select((dsl_ius::token, dsl_sft::token.nullable()))
+            .load::<(String, Option<String>)>(conn)
             .await?;
 
-        for token in room_user_tokens {
+        // A session has a row for every fcm token it registered
+        let mut session_fcm_tokens = HashMap::<String, Vec<String>>::new();
+        for (session_token, fcm_token) in sessions {
+            session_fcm_tokens
+                .entry(session_token)
+                .or_default()
+                .extend(fcm_token);
+        }
+
+        let room_id = crate::chat::event_room_id(msg_data.as_ref());
+        let mut live_sessions = Vec::new();
+        let mut fcm_tokens = Vec::new();
+        for (session_token, tokens) in session_fcm_tokens {
+            let live = self.presence.open_pages(&session_token).await? > 0;
+            // Events of a room are still pushed to a live session unless one of its pages has
+            // that room in focus
+            let push = match (live, room_id) {
+                (false, _) => true,
+                (true, Some(room_id)) => !self.presence.is_viewing(&session_token, room_id).await?,
+                (true, None) => false,
+            };
+
+            if push {
+                fcm_tokens.extend(tokens);
+            }
+            if live {
+                live_sessions.push(session_id(&session_token));
+            }
+        }
+
+        if !fcm_tokens.is_empty()
+            && !NotificationSettings::push_enabled(user_id, company_id, conn).await?
+        {
+            fcm_tokens.clear();
+        }
+
+        if !live_sessions.is_empty() {
+            self.bus
+                .publish(PageEvent {
+                    session_ids: live_sessions,
+                    data: page_event_data(&msg_data, &msg_notif),
+                })
+                .await?;
+        }
+
+        for token in fcm_tokens {
             if self
                 .fcm_tx
                 .send(fcm::Message {
@@ -141,6 +466,41 @@ impl MsgEmitter {
 
         Ok(())
     }
+
+    /// Sends the event only to the sessions with a page open on any instance. Meant for events
+    /// that would be stale by the time a notification is seen.
+    pub async fn send_ephemeral(
+        &self,
+        user_id: Uuid,
+        msg_data: serde_json::Value,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        use crate::schema::innerusersession::dsl as dsl_ius;
+
+        let session_tokens = dsl_ius::innerusersession
+            .filter(dsl_ius::user_id.eq(user_id))
+            .select(dsl_ius::token)
+            .load::<String>(conn)
+            .await?;
+
+        let mut live_sessions = Vec::new();
+        for session_token in session_tokens {
+            if self.presence.open_pages(&session_token).await? > 0 {
+                live_sessions.push(session_id(&session_token));
+            }
+        }
+
+        if !live_sessions.is_empty() {
+            self.bus
+                .publish(PageEvent {
+                    session_ids: live_sessions,
+                    data: page_event_data(&Some(msg_data), &None),
+                })
+                .await?;
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -151,9 +511,7 @@ impl FromRequestParts<AppState> for MsgEmitter {
         _parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        Ok(MsgEmitter {
-            fcm_tx: state.fcm_tx,
-        })
+        Ok(state.msg_emitter())
     }
 }
 
@@ -164,15 +522,57 @@ impl WsFuncParam for MsgEmitter {
         _user: User,
         state: &'m AppState,
     ) -> Result<Self, WsError> {
-        Ok(MsgEmitter {
-            fcm_tx: state.fcm_tx,
-        })
+        Ok(state.msg_emitter())
+    }
+}
+
+/// When each user last said they were typing in a room, keyed by (room id, user id). Only the
+/// typers connected to this instance are tracked here.
+#[derive(Clone, Copy)]
+pub struct Typing(&'static DashMap<(Uuid, Uuid), Instant>);
+
+impl Typing {
+    /// Returns true if the user was not typing in the room already
+    pub fn refresh(&self, room_id: Uuid, user_id: Uuid) -> bool {
+        self.0.insert((room_id, user_id), Instant::now()).is_none()
+    }
+
+    /// Returns true if the user was typing in the room
+    pub fn stop(&self, room_id: Uuid, user_id: Uuid) -> bool {
+        self.0.remove(&(room_id, user_id)).is_some()
+    }
+
+    /// Removes and returns the typers that have not refreshed within `expiry`
+    pub fn take_expired(&self, expiry: std::time::Duration) -> Vec<(Uuid, Uuid)> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.0.retain(|key, last_typed| {
+            let alive = now.duration_since(*last_typed) < expiry;
+            if !alive {
+                expired.push(*key);
+            }
+            alive
+        });
+
+        expired
+    }
+}
+
+impl WsFuncParam for Typing {
+    async fn make<'m>(
+        _data: &'m serde_json::Value,
+        _session: &'m SessionWithPage,
+        _user: User,
+        state: &'m AppState,
+    ) -> Result<Self, WsError> {
+        Ok(state.typing())
     }
 }
 
 pub struct OpenPageState {
-    ws_tx: mpsc::UnboundedSender<WsResponse>,
-    currently_viewing: bool,
+    ws_tx: mpsc::Sender<WsResponse>,
+    /// The chat room in focus on the page
+    currently_viewing: Option<Uuid>,
 }
 
 slotmap::new_key_type! { struct PageKey;  }
@@ -182,77 +582,65 @@ pub struct SessionState {
     pages: slotmap::DenseSlotMap<PageKey, OpenPageState>,
 }
 
+/// State of the sessions with pages connected to this instance, by [`session_id`]
+pub type Sessions = DashMap<String, Arc<RwLock<SessionState>>>;
+
+fn page_event_data(
+    data: &Option<serde_json::Value>,
+    notification: &Option<fcm::Notification>,
+) -> serde_json::Value {
+    serde_json::json!({
+        "data": data,
+        "notification": notification,
+    })
+}
+
+/// Sends the event to the pages of its sessions that are connected to this instance
+pub async fn deliver_to_local_pages(sessions: &Sessions, event: &PageEvent) {
+    for session_id in &event.session_ids {
+        let Some(state) = sessions.get(session_id).map(|state| state.clone()) else {
+            continue;
+        };
+
+        for (_, page) in &state.read().await.pages {
+            let msg = WsResponse::Event {
+                event: "NewMessage".into(),
+                data: event.data.clone(),
+            };
+
+            crate::ws::send_event(&page.ws_tx, msg);
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Session {
     session_token: String,
     state: Arc<RwLock<SessionState>>,
-    fcm_tx: &'static mpsc::UnboundedSender<fcm::Message>,
+    presence: &'static dyn PresenceStore,
 }
 
 impl Session {
-    pub async fn add_page(&self, ws_tx: mpsc::UnboundedSender<WsResponse>) -> SessionWithPage {
+    pub fn token(&self) -> &str {
+        &self.session_token
+    }
+
+    pub async fn add_page(&self, ws_tx: mpsc::Sender<WsResponse>) -> SessionWithPage {
         let mut state = self.state.write().await;
         let page_key = state.pages.insert(OpenPageState {
             ws_tx,
-            currently_viewing: false,
+            currently_viewing: None,
         });
 
+        if let Err(err) = self.presence.page_opened(&self.session_token).await {
+            tracing::error!("Failed to record an opened page: {err:?}");
+        }
+
         SessionWithPage {
             session: self.clone(),
             page_key,
         }
     }
-
-    pub async fn notify(
-        &self,
-        data: Option<serde_json::Value>,
-        notification: Option<fcm::Notification>,
-        webpush: Option<fcm::WebpushConfig>,
-        conn: &mut impl AsyncConnection<Backend = Pg>,
-    ) -> Result<(), Error> {
-        let state = self.state.read().await;
-        if state.pages.is_empty() {
-            use crate::schema::sessionfcmtoken::dsl as dsl_sft;
-
-            let fcm_token = dsl_sft::sessionfcmtoken
-                .filter(dsl_sft::session_token.eq(&self.session_token))
-                .select(dsl_sft::token)
-                .first::<String>(conn)
-                .await?;
-
-            if self
-                .fcm_tx
-                .send(fcm::Message {
-                    data,
-                    notification,
-                    target: fcm::Target::Token(fcm_token),
-                    android: None,
-                    webpush,
-                    apns: None,
-                    fcm_options: None,
-                })
-                .is_err()
-            {
-                tracing::error!("Failed to send fcm message to the fcm client thread");
-            }
-        } else {
-            for (_, page) in &state.pages {
-                let msg = WsResponse::Event {
-                    event: "NewMessage".into(),
-                    data: serde_json::json!({
-                        "data": data,
-                        "notification": notification,
-                    }),
-                };
-
-                if page.ws_tx.send(msg).is_err() {
-                    tracing::error!("Failed to notify and send message to page");
-                }
-            }
-        }
-
-        Ok(())
-    }
 }
 
 #[async_trait]
@@ -263,26 +651,15 @@ impl FromRequestParts<AppState> for Session {
         parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        if let Some(cookies) = parts.headers.get(COOKIE) {
-            let parts = cookies.as_bytes().split(|c| *c == b';');
-            for part in parts {
-                if let Ok(part) = std::str::from_utf8(part) {
-                    let part = part.trim();
-
-                    if let Some((name, value)) = part.split_once('=') {
-                        if name == SESSION_COOKIE_NAME {
-                            return Ok(Session {
-                                session_token: value.into(),
-                                state: state.sessions.entry(value.into()).or_default().clone(),
-                                fcm_tx: state.fcm_tx,
-                            });
-                        }
-                    }
-                }
-            }
+        if let Some(token) = state.config.cookies.session_token(&parts.headers) {
+            return Ok(Session {
+                session_token: token.into(),
+                state: state.sessions.entry(session_id(token)).or_default().clone(),
+                presence: state.presence,
+            });
         }
 
-        Err(Error::Unauthorized)
+        Err(Error::Unauthenticated)
     }
 }
 
@@ -304,12 +681,48 @@ pub struct SessionWithPage {
 }
 
 impl SessionWithPage {
+    /// Closes the page, closing it again does nothing
     pub async fn close(&self) {
-        self.session.state.write().await.pages.remove(self.page_key);
+        let Some(removed) = self.session.state.write().await.pages.remove(self.page_key) else {
+            return;
+        };
+
+        let presence = self.session.presence;
+        let session_token = &self.session.session_token;
+        if let Err(err) = presence.page_closed(session_token).await {
+            tracing::error!("Failed to record a closed page: {err:?}");
+        }
+        if removed.currently_viewing.is_some()
+            && let Err(err) = presence
+                .viewing_changed(session_token, removed.currently_viewing, None)
+                .await
+        {
+            tracing::error!("Failed to record a page no longer viewing a room: {err:?}");
+        }
+    }
+
+    /// Changes the chat room in focus on the page
+    pub async fn set_viewing(&self, room_id: Option<Uuid>) -> Result<(), Error> {
+        let previous = {
+            let mut state = self.session.state.write().await;
+            let Some(page) = state.pages.get_mut(self.page_key) else {
+                return Ok(());
+            };
+            std::mem::replace(&mut page.currently_viewing, room_id)
+        };
+
+        if previous != room_id {
+            self.session
+                .presence
+                .viewing_changed(&self.session.session_token, previous, room_id)
+                .await?;
+        }
+
+        Ok(())
     }
 }
 
-pub struct AllSessions(pub &'static DashMap<String, Arc<RwLock<SessionState>>>);
+pub struct AllSessions(pub &'static Sessions);
 
 #[async_trait]
 impl FromRequestParts<AppState> for AllSessions {
@@ -349,6 +762,135 @@ impl<'f> FromRequestParts<AppState> for &'f WsFunctions {
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
     pub storage_path: &'static Path,
+    pub static_path: &'static Path,
+    /// How many embeddings a single user can cause within the window
+    pub embedding_rate_limit: RateLimit,
+    /// How many messages a user can post in a single room within the window, contract changes
+    /// are not counted
+    pub message_rate_limit: RateLimit,
+    pub storage_quota: StorageQuota,
+    pub remote_image_policy: RemoteImagePolicy,
+    pub http_timeouts: HttpTimeouts,
+    /// Prefixed to the paths of stored images, e.g. the url of a CDN in front of `/static`
+    pub public_base_url: Option<&'static str>,
+    /// Returned in place of missing profile pictures and logos
+    pub default_avatar: DefaultAvatar,
+    pub embedding_templates: EmbeddingTemplates,
+    pub stats_refresh: StatsRefresh,
+    /// How many accounts of a user are queried at once when listing from a provider
+    pub provider_concurrency: usize,
+    /// Shares presence between instances when set, otherwise it is kept in memory
+    pub redis_url: Option<&'static str>,
+    /// How long a websocket stays open without the client sending anything but pongs, so
+    /// abandoned tabs do not hold on to a connection
+    pub ws_idle_timeout: std::time::Duration,
+    pub cookies: CookieNames,
+    pub google_oauth: OAuthCredentials,
+    pub twitch_oauth: OAuthCredentials,
+    pub analytics_webhook: Option<crate::webhook::AnalyticsWebhook>,
+    /// Rejects company names another company already has, ignoring case and surrounding spaces.
+    /// Off by default as some real companies share a name.
+    pub unique_company_names: bool,
+}
+
+/// A prefix that makes browsers enforce how a cookie was set, see
+/// <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Set-Cookie#cookie_prefixes>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookiePrefix {
+    None,
+    /// `__Secure-`, the cookie has to be set over https with `Secure`
+    Secure,
+    /// `__Host-`, like [`CookiePrefix::Secure`] and also on `/` without a `Domain`
+    Host,
+}
+
+impl CookiePrefix {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CookiePrefix::None => "",
+            CookiePrefix::Secure => "__Secure-",
+            CookiePrefix::Host => "__Host-",
+        }
+    }
+}
+
+/// The names of every cookie the server sets, prefixed with the configured [`CookiePrefix`]
+#[derive(Debug, Clone, Copy)]
+pub struct CookieNames {
+    pub prefix: CookiePrefix,
+    /// `HttpOnly`, the server resolves the user from the session it holds
+    pub session: &'static str,
+    /// Readable by the frontend so it knows who is logged in, and whether anyone is, without a
+    /// request. It is never trusted by the server.
+    pub user_id: &'static str,
+}
+
+impl CookieNames {
+    pub fn new(prefix: CookiePrefix, session: &str, user_id: &str) -> Self {
+        Self {
+            prefix,
+            session: format!("{}{session}", prefix.as_str()).leak(),
+            user_id: format!("{}{user_id}", prefix.as_str()).leak(),
+        }
+    }
+
+    /// Browsers drop prefixed cookies that are not `Secure`
+    pub fn require_secure(&self) -> bool {
+        self.prefix != CookiePrefix::None
+    }
+
+    /// The value of the session cookie sent with the request, or else the token of an
+    /// `Authorization: Bearer <session-token>` header.
+    ///
+    /// The header is meant for native clients that do not keep cookies. Browsers never attach it
+    /// on their own, so unlike the cookie it cannot be ridden by a cross-site request.
+    pub fn session_token<'h>(&self, headers: &'h HeaderMap) -> Option<&'h str> {
+        headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|cookies| cookies.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find_map(|(name, value)| (name == self.session).then_some(value))
+            .or_else(|| bearer_token(headers))
+    }
+}
+
+/// The token of an `Authorization: Bearer` header, the scheme is case insensitive
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let (scheme, token) = headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .trim()
+        .split_once(' ')?;
+    let token = token.trim();
+
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// How the linked account stats of active creators are refreshed in the background
+#[derive(Debug, Clone, Copy)]
+pub struct StatsRefresh {
+    /// Creators refreshed per run
+    pub batch_size: i64,
+    /// Time between runs
+    pub interval: std::time::Duration,
+}
+
+/// Timeouts of every request made to an external service
+#[derive(Debug, Clone, Copy)]
+pub struct HttpTimeouts {
+    pub connect: std::time::Duration,
+    pub total: std::time::Duration,
+}
+
+impl HttpTimeouts {
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        reqwest::Client::builder()
+            .connect_timeout(self.connect)
+            .timeout(self.total)
+    }
 }
 
 #[async_trait]
@@ -384,3 +926,51 @@ impl WsFuncParam for Encoder {
         Ok(state.encoder)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(axum::http::HeaderName, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn session_token_prefers_the_cookie() {
+        let cookies = CookieNames::new(CookiePrefix::Host, "session", "user_id");
+
+        let headers = headers(&[
+            (COOKIE, "__Host-user_id=someone; __Host-session=from-cookie"),
+            (AUTHORIZATION, "Bearer from-header"),
+        ]);
+        assert_eq!(cookies.session_token(&headers), Some("from-cookie"));
+    }
+
+    #[test]
+    fn session_token_falls_back_to_the_bearer_token() {
+        let cookies = CookieNames::new(CookiePrefix::Host, "session", "user_id");
+
+        // The unprefixed cookie is not the session cookie
+        let headers = headers(&[
+            (COOKIE, "session=wrong-name"),
+            (AUTHORIZATION, "bearer  from-header "),
+        ]);
+        assert_eq!(cookies.session_token(&headers), Some("from-header"));
+
+        assert_eq!(cookies.session_token(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn bearer_token_needs_the_scheme_and_a_token() {
+        let token = |value| bearer_token(&headers(&[(AUTHORIZATION, value)])).map(str::to_owned);
+
+        assert_eq!(token("Bearer abc").as_deref(), Some("abc"));
+        assert_eq!(token("BEARER abc").as_deref(), Some("abc"));
+        assert_eq!(token("Basic abc"), None);
+        assert_eq!(token("Bearer "), None);
+        assert_eq!(token("abc"), None);
+    }
+}
@@ -0,0 +1,117 @@
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+
+use crate::state::Config;
+
+/// What is shown in place of a missing profile picture or logo
+#[derive(Debug, Clone, Copy)]
+pub enum DefaultAvatar {
+    /// The same image for everyone, a url or a path of the frontend
+    Url(&'static str),
+    /// An svg of the initials of the name on a background picked from them
+    Initials,
+}
+
+/// Name of the generated avatar shown when there is no name to take initials from
+const NO_INITIALS: &str = "default";
+
+/// Backgrounds of the generated avatars, the initials are drawn in white over them
+const BACKGROUNDS: [&str; 8] = [
+    "#e57373", "#f06292", "#ba68c8", "#7986cb", "#4fc3f7", "#4db6ac", "#81c784", "#ffb74d",
+];
+
+fn initials(given_name: &str, family_name: &str) -> String {
+    [given_name, family_name]
+        .into_iter()
+        .filter_map(|name| name.chars().find(|c| c.is_alphanumeric()))
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+/// The path returned in place of a missing picture, relative to the storage routes like the
+/// paths of stored images unless a url is configured
+pub fn default_avatar_path(avatar: DefaultAvatar, given_name: &str, family_name: &str) -> String {
+    match avatar {
+        DefaultAvatar::Url(url) => url.into(),
+        DefaultAvatar::Initials => {
+            let initials = initials(given_name, family_name);
+            let name = if initials.is_empty() {
+                NO_INITIALS.into()
+            } else {
+                url::form_urlencoded::byte_serialize(initials.as_bytes()).collect::<String>()
+            };
+
+            format!("avatar/{name}.svg")
+        }
+    }
+}
+
+/// Fills in the default avatar when there is no picture
+pub fn fill_default_avatar(
+    avatar: DefaultAvatar,
+    pfp_path: &mut Option<String>,
+    given_name: &str,
+    family_name: &str,
+) {
+    if pfp_path.is_none() {
+        *pfp_path = Some(default_avatar_path(avatar, given_name, family_name));
+    }
+}
+
+/// Where requests for a stored picture that does not exist are sent
+pub(super) fn missing_picture_redirect(avatar: DefaultAvatar) -> Redirect {
+    match avatar {
+        DefaultAvatar::Url(url) => Redirect::temporary(url),
+        DefaultAvatar::Initials => {
+            Redirect::temporary(&format!("/api/v1/storage/avatar/{NO_INITIALS}.svg"))
+        }
+    }
+}
+
+/// Serves the generated avatar of the initials. The same initials always get the same
+/// background so the avatars can be cached for a long time.
+pub(super) async fn get_avatar(config: Config, Path(name): Path<String>) -> Response {
+    if let DefaultAvatar::Url(url) = config.default_avatar {
+        return Redirect::temporary(url).into_response();
+    }
+
+    let Some(initials) = name.strip_suffix(".svg") else {
+        return (StatusCode::NOT_FOUND, format!("Invalid avatar: {name}")).into_response();
+    };
+    let initials = if initials == NO_INITIALS {
+        ""
+    } else {
+        initials
+    };
+    // Only what `initials` could have made is drawn, so nothing has to be escaped in the svg.
+    // Uppercasing can turn a single letter into two
+    if initials.chars().count() > 4 || !initials.chars().all(char::is_alphanumeric) {
+        return (StatusCode::NOT_FOUND, format!("Invalid avatar: {name}")).into_response();
+    }
+
+    let hash = initials.chars().fold(0usize, |hash, c| {
+        hash.wrapping_mul(31).wrapping_add(c as usize)
+    });
+    let background = BACKGROUNDS[hash % BACKGROUNDS.len()];
+
+    let svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"128\" height=\"128\" \
+        viewBox=\"0 0 128 128\">\
+        <rect width=\"128\" height=\"128\" fill=\"{background}\"/>\
+        <text x=\"50%\" y=\"50%\" dy=\".35em\" text-anchor=\"middle\" fill=\"#ffffff\" \
+        font-family=\"sans-serif\" font-size=\"52\">{initials}</text>\
+        </svg>"
+    );
+
+    (
+        [
+            (header::CONTENT_TYPE, "image/svg+xml"),
+            (header::CACHE_CONTROL, "public, max-age=604800"),
+        ],
+        svg,
+    )
+        .into_response()
+}
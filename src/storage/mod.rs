@@ -1,3 +1,8 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
 use axum::{
     body::Body,
     extract::{FromRequestParts, Path},
@@ -5,27 +10,188 @@ use axum::{
     response::IntoResponse,
     routing, Router,
 };
+use diesel::pg::Pg;
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
 use image::{DynamicImage, ImageFormat};
 use tokio::fs;
 use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
 use crate::{
+    db::{company::CompanyMembership, StoredFile, User},
     state::{AppState, Config},
     Error,
 };
 
+mod avatar;
+
+pub use avatar::{default_avatar_path, fill_default_avatar, DefaultAvatar};
+
+/// An image ready to be written by [`Storage::store_public_image`]
+pub struct PreparedImage {
+    bytes: Vec<u8>,
+    format: ImageFormat,
+    /// The url the image was fetched from, `None` if it was uploaded
+    pub source_url: Option<String>,
+}
+
 pub enum Folder {
     ProfilePicture,
     Logo,
+    Gallery,
+}
+
+impl Folder {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Folder::ProfilePicture => "pfp",
+            Folder::Logo => "logo",
+            Folder::Gallery => "gallery",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pfp" => Some(Folder::ProfilePicture),
+            "logo" => Some(Folder::Logo),
+            "gallery" => Some(Folder::Gallery),
+            _ => None,
+        }
+    }
 }
 
 impl AsRef<std::path::Path> for Folder {
     fn as_ref(&self) -> &std::path::Path {
-        match self {
-            Folder::ProfilePicture => std::path::Path::new("pfp"),
-            Folder::Logo => std::path::Path::new("logo"),
+        std::path::Path::new(self.name())
+    }
+}
+
+/// Turns the path of a stored image into the url clients should load it from. Paths stay
+/// relative when there is no [`Config::public_base_url`] and urls of remote images are left as
+/// they are.
+pub fn public_url(config: &Config, path: &str) -> String {
+    match config.public_base_url {
+        Some(base_url) if path.starts_with("static/") => format!("{base_url}/{path}"),
+        _ => path.into(),
+    }
+}
+
+/// Responses holding paths of stored images. The paths are loaded as they are stored and turned
+/// into urls with [`StoredImages::resolve_urls`] right before the response is sent.
+pub trait StoredImages {
+    fn resolve_urls(&mut self, config: &Config);
+}
+
+impl StoredImages for String {
+    fn resolve_urls(&mut self, config: &Config) {
+        *self = public_url(config, self);
+    }
+}
+
+impl<T: StoredImages> StoredImages for Option<T> {
+    fn resolve_urls(&mut self, config: &Config) {
+        if let Some(inner) = self {
+            inner.resolve_urls(config);
+        }
+    }
+}
+
+impl<T: StoredImages> StoredImages for Vec<T> {
+    fn resolve_urls(&mut self, config: &Config) {
+        for inner in self {
+            inner.resolve_urls(config);
+        }
+    }
+}
+
+/// How many bytes a user can store across all folders
+#[derive(Debug, Clone, Copy)]
+pub struct StorageQuota {
+    pub creator_bytes: u64,
+    pub company_member_bytes: u64,
+}
+
+/// Which hosts remote images can be fetched from
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteImagePolicy {
+    /// Allows fetching from loopback, private and link local addresses. Only meant for local
+    /// development.
+    pub allow_private_ips: bool,
+    /// If set only these hosts can be fetched from
+    pub allowed_hosts: Option<&'static [String]>,
+}
+
+impl RemoteImagePolicy {
+    fn is_public_ip(ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => {
+                let [a, b, ..] = ip.octets();
+                !(ip.is_private()
+                    || ip.is_loopback()
+                    || ip.is_link_local()
+                    || ip.is_unspecified()
+                    || ip.is_broadcast()
+                    || ip.is_documentation()
+                    || ip.is_multicast()
+                    // Carrier grade NAT, 100.64.0.0/10
+                    || (a == 100 && (b & 0b1100_0000) == 64))
+            }
+            IpAddr::V6(ip) => {
+                if let Some(ip) = ip.to_ipv4_mapped() {
+                    return Self::is_public_ip(IpAddr::V4(ip));
+                }
+
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local, fc00::/7
+                    || (first & 0xfe00) == 0xfc00
+                    // Link local, fe80::/10
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        }
+    }
+
+    /// The same policy for other user supplied urls, like webhooks, which are not limited to
+    /// the hosts images can be fetched from
+    pub(crate) fn any_host(self) -> Self {
+        Self {
+            allowed_hosts: None,
+            ..self
+        }
+    }
+
+    /// Resolves the host of the url and returns the address to connect to if the policy allows
+    /// it. Every address the host resolves to has to be allowed so that a host cannot smuggle
+    /// an internal address in next to a public one.
+    pub(crate) async fn resolve(&self, url: &url::Url) -> Result<SocketAddr, String> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| "The url has no host".to_string())?;
+
+        if let Some(allowed_hosts) = self.allowed_hosts
+            && !allowed_hosts
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(host))
+        {
+            return Err(format!("Fetching from {host} is not allowed"));
+        }
+
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addrs = tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+            .await
+            .map_err(|err| format!("Failed to resolve {host}: {err}"))?
+            .collect::<Vec<_>>();
+
+        if !self.allow_private_ips && addrs.iter().any(|addr| !Self::is_public_ip(addr.ip())) {
+            return Err(format!("{host} resolves to a non public address"));
         }
+
+        addrs
+            .into_iter()
+            .next()
+            .ok_or_else(|| format!("{host} did not resolve to any address"))
     }
 }
 
@@ -38,80 +204,316 @@ impl Storage {
     const THUMBNAIL_IMG_WIDTH: u32 = 400;
     const THUMBNAIL_IMG_HEIGHT: u32 = 400;
 
+    const REMOTE_IMG_MAX_BYTES: usize = 10 * 1024 * 1024;
+    const REMOTE_IMG_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(config: Config) -> Self {
+        Storage { config }
+    }
+
+    /// Downloads an image from a user supplied https url. The download is capped in time and
+    /// size and the bytes have to decode as the format the server claims.
+    async fn fetch_remote_image(&self, url: &str) -> Result<(DynamicImage, ImageFormat), Error> {
+        let bad_request = |error: String| Error::Custom {
+            status_code: StatusCode::BAD_REQUEST,
+            error,
+        };
+
+        let parsed =
+            url::Url::parse(url).map_err(|err| bad_request(format!("Invalid image url: {err}")))?;
+        if parsed.scheme() != "https" {
+            return Err(bad_request(
+                "Remote images must be fetched over https".into(),
+            ));
+        }
+
+        let addr = self
+            .config
+            .remote_image_policy
+            .resolve(&parsed)
+            .await
+            .map_err(bad_request)?;
+
+        // The client connects to the address that was checked instead of resolving the host
+        // again, and does not follow redirects that could point somewhere else
+        let client = reqwest::Client::builder()
+            .resolve(parsed.host_str().unwrap_or_default(), addr)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+
+        let mut response = client
+            .get(parsed)
+            .timeout(Self::REMOTE_IMG_TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| bad_request(format!("Failed to fetch the image: {err}")))?;
+        if response.status().is_redirection() {
+            return Err(bad_request(
+                "Image urls that redirect are not supported".into(),
+            ));
+        }
+
+        let mime_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .ok_or_else(|| {
+                bad_request("Could not figure out image content type from the url request.".into())
+            })?
+            .to_str()
+            .map_err(Error::HeaderCoversionError)?
+            .to_string();
+        let format = ImageFormat::from_mime_type(&mime_type).ok_or_else(|| {
+            bad_request(format!(
+                "Could not figure out image format from mime type: {mime_type}"
+            ))
+        })?;
+
+        if response
+            .content_length()
+            .is_some_and(|len| len > Self::REMOTE_IMG_MAX_BYTES as u64)
+        {
+            return Err(bad_request(format!(
+                "The image is larger than {} bytes",
+                Self::REMOTE_IMG_MAX_BYTES
+            )));
+        }
+
+        let mut img_bytes = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| bad_request(format!("Failed to fetch the image: {err}")))?
+        {
+            if img_bytes.len() + chunk.len() > Self::REMOTE_IMG_MAX_BYTES {
+                return Err(bad_request(format!(
+                    "The image is larger than {} bytes",
+                    Self::REMOTE_IMG_MAX_BYTES
+                )));
+            }
+            img_bytes.extend_from_slice(&chunk);
+        }
+
+        if image::guess_format(&img_bytes).ok() != Some(format) {
+            return Err(bad_request(format!(
+                "The fetched bytes are not a valid {mime_type} image"
+            )));
+        }
+
+        let image = image::load_from_memory_with_format(&img_bytes, format)
+            .map_err(|err| bad_request(format!("Failed to decode the image: {err}")))?;
+
+        Ok((image, format))
+    }
+
+    async fn quota_for(
+        &self,
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<u64, Error> {
+        let quota = self.config.storage_quota;
+        if CompanyMembership::list_for_user(user, conn)
+            .await?
+            .is_empty()
+        {
+            Ok(quota.creator_bytes)
+        } else {
+            Ok(quota.company_member_bytes)
+        }
+    }
+
+    /// Fetches the image from `remote_url` unless one was uploaded, and encodes its thumbnail.
+    /// Done apart from [`Storage::store_public_image`] so the slow part does not hold a
+    /// transaction open.
+    pub async fn prepare_public_image(
+        &self,
+        remote_url: Option<&str>,
+        image: Option<(DynamicImage, ImageFormat)>,
+    ) -> Result<Option<PreparedImage>, Error> {
+        let (image, format, source_url) = match (remote_url, image) {
+            (None, None) => {
+                return Ok(None);
+            }
+            (Some(url), None) if !url.is_empty() => {
+                let (image, format) = self.fetch_remote_image(url).await?;
+                (image, format, Some(url.to_string()))
+            }
+            (_, Some((image, format))) => (image, format, None),
+            (Some(_), None) => return Ok(None),
+        };
+
+        let thumbnail = image.thumbnail(Self::THUMBNAIL_IMG_WIDTH, Self::THUMBNAIL_IMG_HEIGHT);
+
+        let bytes = tokio::task::spawn_blocking(move || {
+            let mut bytes = std::io::Cursor::new(Vec::new());
+            thumbnail.write_to(&mut bytes, format)?;
+            Result::<_, image::ImageError>::Ok(bytes.into_inner())
+        })
+        .await??;
+
+        Ok(Some(PreparedImage {
+            bytes,
+            format,
+            source_url,
+        }))
+    }
+
+    /// Stores the image as `id` inside `folder`, charging its size against the quota of `owner`
     pub async fn store_public_image(
         &self,
         folder: Folder,
         id: Uuid,
-        remote_url: Option<&str>,
-        image: Option<(DynamicImage, ImageFormat)>,
+        owner: User,
+        image: Option<PreparedImage>,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
     ) -> Result<Option<String>, Error> {
+        let Some(PreparedImage { bytes, format, .. }) = image else {
+            return Ok(None);
+        };
+
         let uuid = id.to_string();
         let sub_folder_id = uuid.chars().next().expect("User Id has not chars");
 
         let mut path = self.config.storage_path.to_path_buf();
-        path.push(folder);
+        path.push(&folder);
         path.push(sub_folder_id.to_ascii_lowercase().to_string());
 
         fs::create_dir_all(&path).await?;
 
-        let (image, format) = match (remote_url, image) {
-            (None, None) => {
-                return Ok(None);
-            }
-            (Some(url), None) if !url.is_empty() => {
-                let response = reqwest::get(url).await?;
-                let mime_type = response
-                    .headers()
-                    .get(header::CONTENT_TYPE)
-                    .ok_or(Error::Custom {
-                        status_code: StatusCode::BAD_REQUEST,
-                        error: "Could not figure out image content type from the url request."
-                            .into(),
-                    })?
-                    .to_str()
-                    .map_err(Error::HeaderCoversionError)?
-                    .to_string();
-                let img_bytes = response.bytes().await?;
-                let format = ImageFormat::from_mime_type(&mime_type).ok_or(Error::Custom {
-                    status_code: StatusCode::BAD_REQUEST,
-                    error: format!("Could not figure out image format from mime type: {mime_type}"),
-                })?;
-
-                let image = image::load_from_memory_with_format(&img_bytes, format)?;
-
-                (image, format)
-            }
-            (_, Some((image, format))) => (image, format),
-            (Some(_), None) => return Ok(None),
-        };
+        path.push(format!("{uuid}.{}", format.extensions_str()[0]));
 
-        let thumbnail = image.thumbnail(Self::THUMBNAIL_IMG_WIDTH, Self::THUMBNAIL_IMG_HEIGHT);
+        let folder_name = folder.name();
+        // Checks the quota and records the file in one transaction, see `StoredFile::lock_usage`
+        conn.transaction(|conn| {
+            async move {
+                StoredFile::lock_usage(owner, conn).await?;
 
-        path.push(format!("{uuid}.{}", format.extensions_str()[0]));
+                let previous = StoredFile::get(folder_name, id, conn).await?;
+                let replaced_bytes = previous
+                    .as_ref()
+                    .filter(|previous| previous.user_id == owner.id)
+                    .map(|previous| previous.size_bytes as u64)
+                    .unwrap_or(0);
+                let usage = StoredFile::usage(owner, conn)
+                    .await?
+                    .saturating_sub(replaced_bytes);
+                let quota = self.quota_for(owner, conn).await?;
+                if usage + bytes.len() as u64 > quota {
+                    return Err(Error::Custom {
+                        status_code: StatusCode::PAYLOAD_TOO_LARGE,
+                        error: format!(
+                            "Storage quota exceeded: {usage} of {quota} bytes are used and the file needs {} bytes",
+                            bytes.len()
+                        ),
+                    });
+                }
 
-        {
-            let path = path.clone();
-            tokio::task::spawn_blocking(move || thumbnail.save_with_format(&path, format))
-                .await??;
-        }
+                fs::write(&path, &bytes).await?;
+
+                let path_str = path.to_string_lossy().into_owned();
+                if let Some(previous) = previous
+                    && previous.path != path_str
+                {
+                    Self::remove_file(&previous.path).await?;
+                }
+
+                StoredFile {
+                    folder: folder_name.into(),
+                    object_id: id,
+                    user_id: owner.id,
+                    path: path_str,
+                    size_bytes: bytes.len() as i64,
+                }
+                .insert_or_update(conn)
+                .await
+            }
+            .scope_boxed()
+        })
+        .await?;
 
         Ok(Some(format!(
-            "static/pfp/{uuid}.{}",
+            "static/{folder_name}/{uuid}.{}",
             format.extensions_str()[0]
         )))
     }
 
-    async fn get_public_pfp(Path(name): Path<String>, config: Config) -> impl IntoResponse {
+    /// Deletes the image stored as `id` inside `folder` and frees up its quota
+    pub async fn delete_public_image(
+        &self,
+        folder: Folder,
+        id: Uuid,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        if let Some(stored) = StoredFile::delete(folder.name(), id, conn).await? {
+            Self::remove_file(&stored.path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the profile picture and gallery of the user. Company logos stay since the company
+    /// outlives the user who uploaded them.
+    pub async fn purge_user(
+        user: User,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        let folders = [Folder::ProfilePicture.name(), Folder::Gallery.name()];
+        for stored in StoredFile::delete_for_user(user, &folders, conn).await? {
+            Self::remove_file(&stored.path).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn remove_file(path: &str) -> Result<(), Error> {
+        match fs::remove_file(path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Splits a `{uuid}.{ext}` name as written by [`Storage::store_public_image`]
+    fn parse_file_name(name: &str) -> Option<(Uuid, &str)> {
+        let (id, ext) = name.split_once('.')?;
+        let id = Uuid::try_parse(id)
+            .ok()
+            .filter(|parsed| parsed.to_string() == id)?;
+        ImageFormat::from_extension(ext)
+            .filter(|format| format.extensions_str().first() == Some(&ext))?;
+
+        Some((id, ext))
+    }
+
+    async fn get_public_file(
+        Path((folder, name)): Path<(String, String)>,
+        config: Config,
+    ) -> impl IntoResponse {
+        let Some(folder) = Folder::from_name(&folder) else {
+            return Err((StatusCode::NOT_FOUND, format!("Unknown folder: {folder}")));
+        };
+        let has_default = matches!(folder, Folder::ProfilePicture | Folder::Logo);
+
+        // Only names the storage could have written are accepted so the name can never
+        // escape the folder
+        let Some((id, ext)) = Self::parse_file_name(&name) else {
+            return Err((StatusCode::NOT_FOUND, format!("Invalid file name: {name}")));
+        };
+        let id = id.to_string();
+
         let mut path = config.storage_path.to_path_buf();
-        path.push("pfp");
+        path.push(folder);
 
-        let folder_id = name.chars().next().expect("User Id has not chars");
-        path.push(folder_id.to_ascii_lowercase().to_string());
+        let folder_id = id.chars().next().expect("User Id has not chars");
+        path.push(folder_id.to_string());
 
-        path.push(&name);
+        path.push(format!("{id}.{ext}"));
 
         let file = match tokio::fs::File::open(&path).await {
             Ok(file) => file,
+            // Pictures that were replaced or removed fall back to the default avatar
+            Err(err) if has_default && err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(avatar::missing_picture_redirect(config.default_avatar).into_response());
+            }
             Err(err) => return Err((StatusCode::NOT_FOUND, format!("File not found: {}", err))),
         };
         let content_type = match mime_guess::from_path(&path).first_raw() {
@@ -137,7 +539,7 @@ impl Storage {
             ),
         ];
 
-        Ok((headers, body))
+        Ok((headers, body).into_response())
     }
 }
 
@@ -149,12 +551,77 @@ impl FromRequestParts<AppState> for Storage {
         _parts: &mut Parts,
         state: &AppState,
     ) -> std::result::Result<Self, Self::Rejection> {
-        Ok(Storage {
-            config: state.config(),
-        })
+        Ok(Storage::new(state.config()))
     }
 }
 
 pub fn router() -> Router<crate::state::AppState> {
-    Router::new().route("/static/pfp/:name", routing::get(Storage::get_public_pfp))
+    Router::new()
+        .route(
+            "/static/:folder/:name",
+            routing::get(Storage::get_public_file),
+        )
+        .route("/avatar/:name", routing::get(avatar::get_avatar))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn public_url_is_relative_without_a_base_url() {
+        let config = testing::config();
+
+        assert_eq!(public_url(&config, "static/pfp/a.png"), "static/pfp/a.png");
+    }
+
+    #[test]
+    fn public_url_prefixes_stored_images_with_the_base_url() {
+        let config = Config {
+            public_base_url: Some("https://cdn.example.com"),
+            ..testing::config()
+        };
+
+        let mut paths = vec![
+            Some("static/pfp/a.png".to_string()),
+            Some("https://example.com/remote.png".to_string()),
+            None,
+        ];
+        paths.resolve_urls(&config);
+
+        assert_eq!(
+            paths,
+            vec![
+                Some("https://cdn.example.com/static/pfp/a.png".to_string()),
+                Some("https://example.com/remote.png".to_string()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn only_names_written_by_storage_are_parsed() {
+        let id = Uuid::new_v4();
+
+        assert_eq!(
+            Storage::parse_file_name(&format!("{id}.png")),
+            Some((id, "png"))
+        );
+        assert_eq!(
+            Storage::parse_file_name(&format!("{id}.jpg")),
+            Some((id, "jpg"))
+        );
+
+        for name in [
+            format!("{}.png", id.to_string().to_uppercase()),
+            format!("{}.png", id.simple()),
+            format!("{id}.jpeg"),
+            format!("{id}.png.html"),
+            format!("{id}"),
+            format!("../{id}.png"),
+        ] {
+            assert_eq!(Storage::parse_file_name(&name), None, "{name} was parsed");
+        }
+    }
 }
@@ -0,0 +1,73 @@
+use axum::{extract::Query, routing, Json, Router};
+
+use crate::{
+    db::{normalize_tags, CompanySuggestion, CreatorSort, CreatorSuggestion, User},
+    state::{AppState, Config, DbConn},
+    storage::StoredImages,
+    Error,
+};
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Suggestions {
+    /// Companies matching the creator profile of the user
+    companies: Vec<CompanySuggestion>,
+    /// Creators matching the companies the user is a member of
+    creators: Vec<CreatorSuggestion>,
+}
+
+#[derive(serde::Deserialize)]
+struct SuggestionsQuery {
+    /// Order of the suggested creators
+    #[serde(default)]
+    sort: CreatorSort,
+    /// Leaves out suggested creators with a smaller estimated reach
+    min_reach: Option<i64>,
+    /// Comma separated tags the suggested creators must all have
+    tags: Option<String>,
+}
+
+/// Matches precomputed by [`refresh_job`] so they can be shown without an embedding search
+async fn suggestions(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    config: Config,
+    Query(query): Query<SuggestionsQuery>,
+) -> Result<Json<Suggestions>, Error> {
+    let tags = query
+        .tags
+        .as_deref()
+        .map(normalize_tags)
+        .unwrap_or_default();
+
+    let mut suggestions = Suggestions {
+        companies: CompanySuggestion::list(user, &mut conn).await?,
+        creators: CreatorSuggestion::list(user, query.sort, query.min_reach, &tags, &mut conn)
+            .await?,
+    };
+    suggestions.companies.resolve_urls(&config);
+    suggestions.creators.resolve_urls(&config);
+
+    Ok(Json(suggestions))
+}
+
+/// Recomputes the suggestions of everyone once per [`crate::SUGGESTION_REFRESH_INTERVAL`],
+/// forever
+pub async fn refresh_job(state: AppState) {
+    loop {
+        match state.get_conn().await {
+            Ok(mut conn) => {
+                if let Err(err) = crate::db::refresh_suggestions(&mut conn).await {
+                    tracing::warn!("Failed to refresh the suggested matches: {err:?}");
+                }
+            }
+            Err(err) => tracing::warn!("Failed to refresh the suggested matches: {err:?}"),
+        }
+
+        tokio::time::sleep(crate::SUGGESTION_REFRESH_INTERVAL).await;
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/", routing::get(suggestions))
+}
@@ -0,0 +1,159 @@
+//! Helpers for tests that need a live database.
+//!
+//! Every connection handed out by [`conn`] is inside a test transaction that is never committed,
+//! so tests can write freely and run in parallel without cleaning up after themselves. The
+//! database is read from `TEST_DATABASE_URL` (falling back to `DATABASE_URL`) and has its
+//! migrations applied once per test binary.
+
+use std::{path::Path, time::Duration};
+
+use diesel::Connection;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use diesel_migrations::MigrationHarness;
+use pgvector::Vector;
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+use crate::{
+    db::{company, EmbeddingTemplates, User, UserSession, EMBEDDING_DIMENSION},
+    state::{Config, CookieNames, CookiePrefix, HttpTimeouts, StatsRefresh},
+    storage::{DefaultAvatar, RemoteImagePolicy, Storage, StorageQuota},
+    utils::{oauth::OAuthCredentials, rate_limit::RateLimit},
+};
+
+static MIGRATED: OnceCell<&'static str> = OnceCell::const_new();
+
+async fn db_url() -> &'static str {
+    MIGRATED
+        .get_or_init(|| async {
+            let db_url: &'static str = dotenvy::var("TEST_DATABASE_URL")
+                .or_else(|_| dotenvy::var("DATABASE_URL"))
+                .expect("Failed to get TEST_DATABASE_URL or DATABASE_URL")
+                .leak();
+
+            tokio::task::spawn_blocking(move || {
+                let mut conn = diesel::PgConnection::establish(db_url)
+                    .expect("Failed to make connection to db to perform migrations");
+                conn.run_pending_migrations(crate::MIGRATIONS)
+                    .expect("Failed to perform migrations");
+            })
+            .await
+            .expect("Failed to execute the migration task");
+
+            db_url
+        })
+        .await
+}
+
+/// Returns a connection whose changes are rolled back once it is dropped
+pub async fn conn() -> AsyncPgConnection {
+    let mut conn = AsyncPgConnection::establish(db_url().await)
+        .await
+        .expect("Failed to connect to the test database");
+    conn.begin_test_transaction()
+        .await
+        .expect("Failed to begin the test transaction");
+
+    conn
+}
+
+/// Returns a connection whose changes are committed, for tests that need several connections to
+/// see each other's rows. The test has to delete whatever it created.
+pub async fn committed_conn() -> AsyncPgConnection {
+    AsyncPgConnection::establish(db_url().await)
+        .await
+        .expect("Failed to connect to the test database")
+}
+
+pub async fn create_user(conn: &mut AsyncPgConnection) -> User {
+    User::new(conn).await.expect("Failed to create a user")
+}
+
+pub async fn create_session(user: User, conn: &mut AsyncPgConnection) -> UserSession {
+    let now = OffsetDateTime::now_utc();
+    let expires_at =
+        PrimitiveDateTime::new(now.date(), now.time()) + crate::SESSION_COOKIE_DURATION;
+
+    UserSession::new_for_user(user, expires_at, conn)
+        .await
+        .expect("Failed to create a session")
+}
+
+/// Creates a company with a zeroed embedding and makes `owner` its owner
+pub async fn create_company(full_name: &str, owner: User, conn: &mut AsyncPgConnection) -> Uuid {
+    use crate::db::schema::company::dsl as c_dsl;
+    use diesel::ExpressionMethods;
+
+    let company_id = diesel::insert_into(c_dsl::company)
+        .values((
+            c_dsl::full_name.eq(full_name),
+            c_dsl::banner_desc.eq(""),
+            c_dsl::logo_url.eq(""),
+            c_dsl::embedding.eq(Vector::from(vec![0.0; EMBEDDING_DIMENSION])),
+        ))
+        .returning(c_dsl::id)
+        .get_result(conn)
+        .await
+        .expect("Failed to create a company");
+
+    company::add_owner(company_id, owner, conn)
+        .await
+        .expect("Failed to add the owner to the company");
+
+    company_id
+}
+
+/// A config with the defaults of `main`, files are stored in a temporary directory
+pub fn config() -> Config {
+    let rate_limit = RateLimit {
+        max: 100,
+        window: Duration::from_secs(60),
+    };
+    let oauth = OAuthCredentials {
+        client_id: "client-id",
+        client_secret: "client-secret",
+        secondary_client_secret: None,
+    };
+
+    Config {
+        storage_path: Box::leak(std::env::temp_dir().join("halogin-test").into_boxed_path()),
+        static_path: Path::new("frontend/build"),
+        embedding_rate_limit: rate_limit,
+        message_rate_limit: rate_limit,
+        storage_quota: StorageQuota {
+            creator_bytes: 100 * 1024 * 1024,
+            company_member_bytes: 100 * 1024 * 1024,
+        },
+        remote_image_policy: RemoteImagePolicy {
+            allow_private_ips: false,
+            allowed_hosts: None,
+        },
+        http_timeouts: HttpTimeouts {
+            connect: Duration::from_secs(5),
+            total: Duration::from_secs(30),
+        },
+        public_base_url: None,
+        default_avatar: DefaultAvatar::Initials,
+        embedding_templates: EmbeddingTemplates {
+            creator: EmbeddingTemplates::DEFAULT_CREATOR,
+            company: EmbeddingTemplates::DEFAULT_COMPANY,
+        },
+        stats_refresh: StatsRefresh {
+            batch_size: 10,
+            interval: Duration::from_secs(60),
+        },
+        provider_concurrency: 4,
+        redis_url: None,
+        ws_idle_timeout: Duration::from_secs(60),
+        cookies: CookieNames::new(CookiePrefix::None, "session", "user_id"),
+        google_oauth: oauth,
+        twitch_oauth: oauth,
+        analytics_webhook: None,
+        unique_company_names: false,
+    }
+}
+
+pub fn storage() -> Storage {
+    Storage::new(config())
+}
@@ -7,8 +7,11 @@ use time::PrimitiveDateTime;
 
 use crate::{
     db::{TwitchAccount, User},
-    state::DbConn,
-    utils::{oauth::OAuthAccountHelper, AuthenticationHeader, GetDetail},
+    state::{Config, DbConn, HttpClient, MsgEmitter},
+    utils::{
+        oauth::{OAuthAccountHelper, OAuthCredentials},
+        AuthenticationHeader, GetDetail,
+    },
     Error,
 };
 
@@ -35,12 +38,14 @@ impl TwitchSession {
 }
 
 impl OAuthAccountHelper for TwitchSession {
-    const CLIENT_ID: &'static str = "<TwitchID>";
-    const CLIENT_SECRET: &'static str = "<TwitchSecret>";
     const AUTH_URL: &'static str = "https://id.twitch.tv/oauth2/authorize";
     const TOKEN_URL: &'static str = "https://id.twitch.tv/oauth2/token";
     const AUTH_TYPE: oauth2::AuthType = oauth2::AuthType::RequestBody;
 
+    fn credentials(config: &Config) -> OAuthCredentials {
+        config.twitch_oauth
+    }
+
     type ExtraFields = oauth2::EmptyExtraTokenFields;
     type Account = TwitchAccount;
     type Response = Account;
@@ -50,9 +55,9 @@ impl OAuthAccountHelper for TwitchSession {
         expires_at: PrimitiveDateTime,
         refresh_token: RefreshToken,
         _extra_fields: &Self::ExtraFields,
+        client: &reqwest::Client,
+        credentials: OAuthCredentials,
     ) -> Result<Self, Error> {
-        let client = reqwest::Client::new();
-
         #[derive(serde::Deserialize)]
         struct Resp {
             data: Vec<Data>,
@@ -64,7 +69,7 @@ impl OAuthAccountHelper for TwitchSession {
         let req = client
             .get("https://api.twitch.tv/helix/users")
             .bearer_auth(access_token.secret())
-            .header("Client-Id", Self::CLIENT_ID)
+            .header("Client-Id", credentials.client_id)
             .build()?;
         let mut resp: Resp = client.execute(req).await?.json().await?;
 
@@ -84,7 +89,7 @@ impl OAuthAccountHelper for TwitchSession {
         &self,
         user: User,
         conn: &mut impl AsyncConnection<Backend = Pg>,
-    ) -> Result<Self::Account, Error> {
+    ) -> Result<(Self::Account, bool), Error> {
         TwitchAccount {
             id: self.id.clone(),
             access_token: self.access_token.secret().clone(),
@@ -104,12 +109,26 @@ pub fn router() -> Router<crate::state::AppState> {
 }
 
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Account {
-    id: usize,
+    /// Kept as the string Twitch sends, it is only documented to be an opaque id
+    pub id: String,
+    pub display_name: String,
+    pub profile_image_url: String,
+    pub follower_count: usize,
+    pub subscriber_count: usize,
+}
+
+#[derive(serde::Deserialize)]
+struct UserResp {
+    data: Vec<UserData>,
+}
+
+#[derive(serde::Deserialize)]
+struct UserData {
+    id: String,
     display_name: String,
     profile_image_url: String,
-    follower_count: usize,
-    subscriber_count: usize,
 }
 
 impl GetDetail for Account {
@@ -120,17 +139,6 @@ impl GetDetail for Account {
         client: &'g reqwest::Client,
         headers: HeaderMap,
     ) -> Result<Self, Error> {
-        #[derive(serde::Deserialize)]
-        struct UserResp {
-            data: Vec<UserData>,
-        }
-        #[derive(serde::Deserialize)]
-        struct UserData {
-            #[serde(deserialize_with = "crate::utils::deserialize_usize_from_string")]
-            id: usize,
-            display_name: String,
-            profile_image_url: String,
-        }
         #[derive(serde::Deserialize)]
         struct SubsriberResp {
             total: usize,
@@ -188,21 +196,29 @@ impl GetDetail for Account {
 }
 
 impl Account {
-    async fn list(user: User, DbConn { mut conn }: DbConn) -> Result<Json<Vec<Account>>, Error> {
+    async fn list(
+        user: User,
+        DbConn { mut conn }: DbConn,
+        HttpClient { client }: HttpClient,
+        emitter: MsgEmitter,
+        config: Config,
+    ) -> Result<Json<Vec<Account>>, Error> {
         let accounts = TwitchAccount::list(user, &mut conn).await?;
 
         let mut acc_and_headers = Vec::with_capacity(accounts.len());
         for mut account in accounts {
-            let headers = account.headers(&mut conn).await?;
+            let headers = account
+                .headers(client, &emitter, &config, &mut conn)
+                .await?;
             acc_and_headers.push((account, headers));
         }
         let mut accounts = Vec::default();
-        let mut accounts_iter = futures::stream::iter(acc_and_headers.into_iter())
-            .map(|(mut account, headers)| {
-                let client = reqwest::Client::default();
-                async move { Self::get(&mut account, &client, headers).await }
-            })
-            .buffer_unordered(10);
+        let mut accounts_iter =
+            futures::stream::iter(acc_and_headers.into_iter())
+                .map(|(mut account, headers)| async move {
+                    Self::get(&mut account, client, headers).await
+                })
+                .buffer_unordered(config.provider_concurrency);
 
         while let Some(account) = accounts_iter.next().await {
             accounts.push(account?);
@@ -211,3 +227,22 @@ impl Account {
         Ok(Json(accounts))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_data_keeps_large_ids() {
+        // Past both usize and u64
+        let resp: UserResp = serde_json::from_str(
+            r#"{"data":[{"id":"184467440737095516160","display_name":"Someone","profile_image_url":"https://example.com/pfp.png"}]}"#,
+        )
+        .unwrap();
+        let user = &resp.data[0];
+
+        assert_eq!(user.id, "184467440737095516160");
+        assert_eq!(user.display_name, "Someone");
+        assert_eq!(user.profile_image_url, "https://example.com/pfp.png");
+    }
+}
@@ -0,0 +1,41 @@
+use axum::{routing, Json, Router};
+
+use crate::{
+    db::{NotificationSettings, User},
+    state::{AppState, DbConn},
+    Error,
+};
+
+/// Deletes the account of the user. It is kept for [`crate::USER_DELETION_GRACE_PERIOD`] so it
+/// can still be restored by an admin.
+async fn delete_user(user: User, DbConn { mut conn }: DbConn) -> Result<(), Error> {
+    user.soft_delete(&mut conn).await
+}
+
+/// Applies to every event unless overridden for a company, see
+/// [`crate::db::CompanyNotificationSettings`]
+async fn get_notification_settings(
+    user: User,
+    DbConn { mut conn }: DbConn,
+) -> Result<Json<NotificationSettings>, Error> {
+    Ok(Json(NotificationSettings::get(user, &mut conn).await?))
+}
+
+async fn set_notification_settings(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    Json(settings): Json<NotificationSettings>,
+) -> Result<Json<NotificationSettings>, Error> {
+    settings.set(user, &mut conn).await?;
+
+    Ok(Json(settings))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", routing::delete(delete_user))
+        .route(
+            "/notifications",
+            routing::get(get_notification_settings).put(set_notification_settings),
+        )
+}
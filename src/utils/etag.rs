@@ -0,0 +1,100 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{
+        header::{ETAG, IF_NONE_MATCH},
+        request::Parts,
+        HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use crate::{state::AppState, Error};
+
+/// A strong entity tag sent back in the `ETag` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag(String);
+
+impl ETag {
+    /// Builds a tag from the `updated_at` column of the row identified by `id`
+    pub fn from_updated_at(id: Uuid, updated_at: PrimitiveDateTime) -> Self {
+        ETag(format!(
+            "\"{}-{}\"",
+            id.simple(),
+            updated_at.assume_utc().unix_timestamp_nanos()
+        ))
+    }
+
+    /// Builds a tag by hashing the serialized body, for responses that are aggregated
+    /// from multiple tables and have no single `updated_at`
+    pub fn from_json<T: serde::Serialize>(body: &T) -> Result<Self, Error> {
+        let bytes = serde_json::to_vec(body)?;
+        Ok(ETag(format!("\"{:016x}\"", fxhash::hash64(&bytes))))
+    }
+
+    fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.0).expect("ETag is always a valid header value")
+    }
+}
+
+/// The `If-None-Match` header of the request if it had one
+pub struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    pub fn matches(&self, etag: &ETag) -> bool {
+        let Some(header) = &self.0 else {
+            return false;
+        };
+
+        header.split(',').map(str::trim).any(|candidate| {
+            // Weak comparison is fine for GET requests
+            candidate == "*" || candidate.trim_start_matches("W/") == etag.0
+        })
+    }
+
+    pub fn respond<T>(&self, etag: ETag, body: T) -> Conditional<T> {
+        if self.matches(&etag) {
+            Conditional::NotModified { etag }
+        } else {
+            Conditional::Modified { etag, body }
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for IfNoneMatch {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(IfNoneMatch(
+            parts
+                .headers
+                .get(IF_NONE_MATCH)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+        ))
+    }
+}
+
+pub enum Conditional<T> {
+    NotModified { etag: ETag },
+    Modified { etag: ETag, body: T },
+}
+
+impl<T: IntoResponse> IntoResponse for Conditional<T> {
+    fn into_response(self) -> Response {
+        match self {
+            Conditional::NotModified { etag } => {
+                (StatusCode::NOT_MODIFIED, [(ETAG, etag.header_value())]).into_response()
+            }
+            Conditional::Modified { etag, body } => {
+                ([(ETAG, etag.header_value())], body).into_response()
+            }
+        }
+    }
+}
@@ -2,7 +2,7 @@ use axum::{extract::Multipart, http::StatusCode};
 use fxhash::FxHashMap;
 use image::{DynamicImage, ImageFormat};
 
-use crate::Error;
+use crate::{utils::validation::ValidationError, Error};
 
 pub struct ImageFileBuilder {
     pub fields: FxHashMap<String, String>,
@@ -10,18 +10,39 @@ pub struct ImageFileBuilder {
 }
 
 impl ImageFileBuilder {
-    pub async fn build(mut multipart: Multipart) -> Result<Self, Error> {
+    /// Reads the image from the `image_field` file field and every other field as text. Files
+    /// in other fields, text in the image field and more than one image are rejected.
+    pub async fn build(mut multipart: Multipart, image_field: &str) -> Result<Self, Error> {
         let mut builder = ImageFileBuilder {
             fields: FxHashMap::default(),
             image: None,
         };
 
         while let Some(field) = multipart.next_field().await? {
+            let name = field.name().unwrap_or_default().to_string();
+
             if let Some(file_name) = field.file_name() {
+                if name != image_field {
+                    return Err(Error::Custom {
+                        status_code: StatusCode::BAD_REQUEST,
+                        error: format!(
+                            "Unexpected file in field {name:?}, only {image_field:?} takes a file"
+                        ),
+                    });
+                }
+
+                // Browsers send an empty file when none was picked
                 if file_name == "" {
                     continue;
                 }
 
+                if builder.image.is_some() {
+                    return Err(Error::Custom {
+                        status_code: StatusCode::BAD_REQUEST,
+                        error: format!("Only one image can be sent in field {name:?}"),
+                    });
+                }
+
                 let (_name, ext) = file_name.split_once(".").ok_or(Error::Custom {
                     status_code: StatusCode::BAD_REQUEST,
                     error: format!("File name: {file_name} has no extension"),
@@ -35,21 +56,71 @@ impl ImageFileBuilder {
                 let image = image::load_from_memory_with_format(&img_bytes, format)?;
 
                 builder.image = Some((image, format));
-            } else if let Some(name) = field.name() {
-                builder.fields.insert(name.into(), field.text().await?);
+            } else if name == image_field {
+                return Err(Error::Custom {
+                    status_code: StatusCode::BAD_REQUEST,
+                    error: format!("Field {name:?} has to be a file"),
+                });
+            } else if !name.is_empty() {
+                builder.fields.insert(name, field.text().await?);
             }
         }
 
         Ok(builder)
     }
 
-    pub fn missing_fields(&self, fields: &[&'static str]) -> Vec<&'static str> {
-        let mut missing = Vec::default();
+    /// Starts a validation with an error for each of `fields` that was not sent
+    pub fn required_fields(&self, fields: &[&'static str]) -> ValidationError {
+        let mut errors = ValidationError::default();
         for needed in fields {
             if !self.fields.contains_key(*needed) {
-                missing.push(*needed);
+                errors.add(*needed, "This field is required");
+            }
+        }
+        errors
+    }
+
+    /// Adds an error for each of `fields` that is longer than `max` characters
+    pub fn check_max_chars(
+        &self,
+        fields: &[&'static str],
+        max: usize,
+        errors: &mut ValidationError,
+    ) {
+        for field in fields {
+            if self
+                .fields
+                .get(*field)
+                .is_some_and(|value| value.chars().count() > max)
+            {
+                errors.add(*field, format!("Cannot be longer than {max} characters"));
             }
         }
-        missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_max_chars_counts_characters() {
+        let builder = ImageFileBuilder {
+            fields: [
+                ("short".to_string(), "é".repeat(5)),
+                ("long".to_string(), "a".repeat(6)),
+            ]
+            .into_iter()
+            .collect(),
+            image: None,
+        };
+
+        let mut errors = ValidationError::default();
+        builder.check_max_chars(&["short", "long", "missing"], 5, &mut errors);
+
+        // Multi-byte characters count once, missing fields are left to `required_fields`
+        let errors = serde_json::to_value(&errors).unwrap();
+        let fields = errors["fields"].as_object().unwrap();
+        assert_eq!(fields.keys().collect::<Vec<_>>(), ["long"]);
     }
 }
@@ -1,44 +1,95 @@
 use std::convert::Infallible;
 use std::task::{Context, Poll};
 
-use crate::{db::User, Error};
+use crate::{
+    db::{Provider, User},
+    state::{Config, MsgEmitter},
+    Error,
+};
 use axum::body::Bytes;
 use axum::http::{HeaderMap, HeaderValue, Request, Response};
-use diesel::pg::Pg;
+use diesel::{data_types::Cents, pg::Pg};
 use diesel_async::AsyncConnection;
 use time::{Duration, OffsetDateTime, PrimitiveDateTime};
 use tower::Service;
 use tower_http::services::ServeDir;
 
+pub mod currency;
+pub mod etag;
 pub mod formdata;
 pub mod oauth;
+pub mod rate_limit;
+pub mod validation;
 
 use oauth::OAuthAccountHelper;
 
 const BUFFER_TIME: Duration = Duration::seconds(1);
 
 pub trait AuthenticationHeader {
-    const EXTRA_HEADERS: Self::ExtraHeader;
+    fn extra_headers(config: &Config) -> Self::ExtraHeader;
 
     type ExtraHeader: IntoIterator<Item = (&'static str, HeaderValue)>;
     type Session: OAuthAccountHelper;
 
+    const PROVIDER: Provider;
+
     fn access_token(&self) -> &str;
     fn expires_at(&self) -> PrimitiveDateTime;
     fn refresh_token(&self) -> String;
     fn user(&self) -> User;
     fn update(&mut self, session: Self::Session);
+    fn account_id(&self) -> &str;
+
+    /// Flags the account until it is linked again or a refresh succeeds
+    fn mark_reauth_required(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> impl futures::Future<Output = Result<(), Error>>;
 
+    /// Headers to authenticate with the provider, refreshing the access token first if it is
+    /// about to expire. If the provider rejects the refresh the account is flagged and the user
+    /// is sent an `account.reauth_required` event.
     fn headers(
         &mut self,
+        client: &reqwest::Client,
+        emitter: &MsgEmitter,
+        config: &Config,
         conn: &mut impl AsyncConnection<Backend = Pg>,
     ) -> impl futures::Future<Output = Result<reqwest::header::HeaderMap, Error>> {
         async move {
             let now = OffsetDateTime::now_utc();
             if (PrimitiveDateTime::new(now.date(), now.time()) + BUFFER_TIME) > self.expires_at() {
-                let session =
-                    Self::Session::renew(oauth2::RefreshToken::new(self.refresh_token())).await?;
+                let session = match Self::Session::renew(
+                    oauth2::RefreshToken::new(self.refresh_token()),
+                    client,
+                    config,
+                )
+                .await
+                {
+                    Ok(session) => session,
+                    Err(Error::ReauthRequired) => {
+                        self.mark_reauth_required(conn).await?;
+                        emitter
+                            .send(
+                                self.user().id,
+                                Some(serde_json::json!({
+                                    "kind": "account.reauth_required",
+                                    "data": {
+                                        "provider": Self::PROVIDER,
+                                        "account_id": self.account_id(),
+                                    },
+                                })),
+                                None,
+                                conn,
+                            )
+                            .await?;
+
+                        return Err(Error::ReauthRequired);
+                    }
+                    Err(err) => return Err(err),
+                };
 
+                // Also clears the reauth flag
                 session.insert_or_update_for_user(self.user(), conn).await?;
 
                 self.update(session);
@@ -50,7 +101,7 @@ pub trait AuthenticationHeader {
                 reqwest::header::HeaderValue::from_str(&format!("Bearer {}", self.access_token()))
                     .expect("Failed to make the bearer token header value"),
             );
-            for (key, val) in Self::EXTRA_HEADERS {
+            for (key, val) in Self::extra_headers(config) {
                 map.insert(key, val.clone());
             }
 
@@ -59,6 +110,38 @@ pub trait AuthenticationHeader {
     }
 }
 
+/// Serializes a timestamp, which are all stored in UTC, as RFC 3339 like
+/// `2024-05-15T05:42:10.5Z`
+pub fn serialize_timestamp<S: serde::Serializer>(
+    timestamp: &PrimitiveDateTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let formatted = timestamp
+        .assume_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .map_err(serde::ser::Error::custom)?;
+
+    serializer.serialize_str(&formatted)
+}
+
+pub fn serialize_optional_timestamp<S: serde::Serializer>(
+    timestamp: &Option<PrimitiveDateTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match timestamp {
+        Some(timestamp) => serialize_timestamp(timestamp, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serializes an amount of money as its number of cents
+pub fn serialize_optional_cents<S: serde::Serializer>(
+    cents: &Option<Cents>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(&cents.map(|cents| cents.0), serializer)
+}
+
 pub trait GetDetail: Sized {
     type Account;
 
@@ -123,3 +206,37 @@ where
         StringOrUsize::Number(i) => Ok(i),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Serialize)]
+    struct Timestamps {
+        #[serde(serialize_with = "serialize_timestamp")]
+        at: PrimitiveDateTime,
+        #[serde(serialize_with = "serialize_optional_timestamp")]
+        maybe_at: Option<PrimitiveDateTime>,
+    }
+
+    #[test]
+    fn timestamps_serialize_as_utc_rfc3339() {
+        let at = PrimitiveDateTime::new(
+            time::Date::from_calendar_date(2024, time::Month::May, 15).unwrap(),
+            time::Time::from_hms_milli(5, 42, 10, 500).unwrap(),
+        );
+
+        let json = serde_json::to_value(Timestamps { at, maybe_at: None }).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "at": "2024-05-15T05:42:10.5Z", "maybe_at": null })
+        );
+
+        let json = serde_json::to_value(Timestamps {
+            at,
+            maybe_at: Some(at),
+        })
+        .unwrap();
+        assert_eq!(json["maybe_at"], json["at"]);
+    }
+}
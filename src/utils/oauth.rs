@@ -1,5 +1,6 @@
 use axum::{
-    http::{header::SET_COOKIE, HeaderName, StatusCode},
+    extract::State,
+    http::{header::SET_COOKIE, HeaderName, HeaderValue, StatusCode},
     Json,
 };
 use axum_extra::{either::Either, extract::cookie::Cookie};
@@ -7,22 +8,141 @@ use diesel::pg::Pg;
 use diesel_async::AsyncConnection;
 use oauth2::{
     basic::{
-        BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
-        BasicTokenType,
+        BasicErrorResponse, BasicErrorResponseType, BasicRevocationErrorResponse,
+        BasicTokenIntrospectionResponse, BasicTokenType,
     },
     AccessToken, AuthType, AuthUrl, Client, ClientId, ClientSecret, ExtraTokenFields, RedirectUrl,
-    RefreshToken, StandardRevocableToken, TokenResponse, TokenType, TokenUrl,
+    RefreshToken, RequestTokenError, StandardRevocableToken, TokenResponse, TokenType, TokenUrl,
 };
 use time::{OffsetDateTime, PrimitiveDateTime};
 
 use crate::{
-    db::{User, UserSession},
-    state::DbConn,
+    db::{LinkedAccount, User, UserSession},
+    state::{AppState, Config, DbConn, HttpClient, MsgEmitter},
     Error,
 };
 
 use super::{AuthenticationHeader, GetDetail};
 
+/// The session and user id cookies set on login. They only outlive the browser session if
+/// `keep_logged_in` is set.
+fn login_cookies(
+    config: &Config,
+    user: User,
+    session: UserSession,
+    keep_logged_in: bool,
+) -> [Cookie<'static>; 2] {
+    let expires_at = session.expires_at;
+    let mut session_cookie = Cookie::new(config.cookies.session, session.token);
+    let mut user_id_cookie = Cookie::new(config.cookies.user_id, user.id.to_string());
+
+    // Only the session cookie authenticates, the user id cookie is left readable for the
+    // frontend, see `CookieNames::user_id`
+    session_cookie.set_secure(true);
+    session_cookie.set_http_only(true);
+    session_cookie.set_path("/");
+    user_id_cookie.set_path("/");
+    if config.cookies.require_secure() {
+        user_id_cookie.set_secure(true);
+    }
+    if keep_logged_in {
+        let expire_time = OffsetDateTime::new_utc(expires_at.date(), expires_at.time());
+        session_cookie.set_expires(expire_time);
+        user_id_cookie.set_expires(expire_time);
+    }
+
+    [session_cookie, user_id_cookie]
+}
+
+/// Checks if the error was caused by a request to the provider timing out
+fn is_timeout(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if err
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|err| err.is_timeout())
+        {
+            return true;
+        }
+        source = err.source();
+    }
+
+    false
+}
+
+/// The provider rejected the client id or secret rather than the code or token
+fn is_invalid_client<RE: std::error::Error + 'static>(
+    err: &RequestTokenError<RE, BasicErrorResponse>,
+) -> bool {
+    matches!(
+        err,
+        RequestTokenError::ServerResponse(resp)
+            if *resp.error() == BasicErrorResponseType::InvalidClient
+    )
+}
+
+/// The app registered with a provider, read from the environment at startup so it is never
+/// compiled in.
+///
+/// To rotate the secret without downtime the new one is made the primary and the old one the
+/// secondary until the provider stops accepting it. The secondary is only tried when the
+/// provider rejects the primary.
+#[derive(Clone, Copy)]
+pub struct OAuthCredentials {
+    pub client_id: &'static str,
+    pub client_secret: &'static str,
+    pub secondary_client_secret: Option<&'static str>,
+}
+
+/// Keeps the secrets out of logs
+impl std::fmt::Debug for OAuthCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OAuthCredentials")
+            .field("client_id", &self.client_id)
+            .field(
+                "has_secondary_client_secret",
+                &self.secondary_client_secret.is_some(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl OAuthCredentials {
+    /// Reads `{provider}_CLIENT_ID`, `{provider}_CLIENT_SECRET` and the optional
+    /// `{provider}_CLIENT_SECRET_SECONDARY`, panicking if one that is required is missing or the
+    /// client id cannot be sent as a header
+    pub fn from_env(provider: &str) -> Self {
+        let var = |name: String| {
+            dotenvy::var(&name)
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .map(|value| &*value.leak())
+        };
+        let required =
+            |name: String| var(name.clone()).unwrap_or_else(|| panic!("{name} must be set"));
+
+        let client_id = required(format!("{provider}_CLIENT_ID"));
+        if HeaderValue::from_str(client_id).is_err() {
+            panic!("{provider}_CLIENT_ID must be a valid header value");
+        }
+
+        Self {
+            client_id,
+            client_secret: required(format!("{provider}_CLIENT_SECRET")),
+            secondary_client_secret: var(format!("{provider}_CLIENT_SECRET_SECONDARY")),
+        }
+    }
+
+    /// The primary secret first
+    fn secrets(&self) -> Vec<&'static str> {
+        [Some(self.client_secret), self.secondary_client_secret]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
 #[derive(serde::Deserialize)]
 pub struct LoginParams {
     redirect_origin: String,
@@ -79,8 +199,6 @@ where
 }
 
 pub trait OAuthAccountHelper: Sized {
-    const CLIENT_ID: &'static str;
-    const CLIENT_SECRET: &'static str;
     const AUTH_URL: &'static str;
     const TOKEN_URL: &'static str;
     const AUTH_TYPE: AuthType;
@@ -89,43 +207,85 @@ pub trait OAuthAccountHelper: Sized {
     type Account: AuthenticationHeader;
     type Response: serde::Serialize + GetDetail<Account = Self::Account>;
 
+    /// The credentials of the provider in the config
+    fn credentials(config: &Config) -> OAuthCredentials;
+
     fn new(
         access_token: AccessToken,
         expires_at: PrimitiveDateTime,
         refresh_token: RefreshToken,
         extra_fields: &Self::ExtraFields,
+        client: &reqwest::Client,
+        credentials: OAuthCredentials,
     ) -> impl futures::Future<Output = Result<Self, Error>> + Send + Sync;
 
+    /// Returns the account and whether it was linked for the first time rather than updated
     async fn insert_or_update_for_user(
         &self,
         user: User,
         conn: &mut impl AsyncConnection<Backend = Pg>,
-    ) -> Result<Self::Account, Error>;
-
-    async fn from_code(redirect_url: String, code: String) -> Result<Self, Error> {
-        let client = Client::<
-            BasicErrorResponse,
-            MinimalTokenResponse<Self::ExtraFields, BasicTokenType>,
-            BasicTokenIntrospectionResponse,
-            StandardRevocableToken,
-            BasicRevocationErrorResponse,
-        >::new(ClientId::new(Self::CLIENT_ID.into()))
-        .set_auth_type(Self::AUTH_TYPE)
-        .set_client_secret(ClientSecret::new(Self::CLIENT_SECRET.into()))
-        .set_auth_uri(AuthUrl::new(Self::AUTH_URL.into())?)
-        .set_token_uri(TokenUrl::new(Self::TOKEN_URL.into())?)
-        .set_redirect_uri(RedirectUrl::new(redirect_url).map_err(|err| Error::Custom {
+    ) -> Result<(Self::Account, bool), Error>;
+
+    async fn from_code(
+        redirect_url: String,
+        code: String,
+        client: &reqwest::Client,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let credentials = Self::credentials(config);
+        let redirect_url = RedirectUrl::new(redirect_url).map_err(|err| Error::Custom {
             status_code: StatusCode::BAD_REQUEST,
             error: format!("Failed to parse redirect url: {err:?}"),
-        })?);
-
-        let auth = client
-            .exchange_code(oauth2::AuthorizationCode::new(code))
-            .request_async(&reqwest::Client::default())
-            .await
-            .map_err(|err| Error::Custom {
-                status_code: StatusCode::BAD_REQUEST,
-                error: format!("Could not get the tokens from the provided code: {err:?}"),
+        })?;
+
+        // A code is only used up once the client is authenticated so it can be exchanged again
+        // with the secondary secret
+        let secrets = credentials.secrets();
+        let mut auth = None;
+        for (i, secret) in secrets.iter().enumerate() {
+            let oauth_client = Client::<
+                BasicErrorResponse,
+                MinimalTokenResponse<Self::ExtraFields, BasicTokenType>,
+                BasicTokenIntrospectionResponse,
+                StandardRevocableToken,
+                BasicRevocationErrorResponse,
+            >::new(ClientId::new(credentials.client_id.into()))
+            .set_auth_type(Self::AUTH_TYPE)
+            .set_client_secret(ClientSecret::new(secret.to_string()))
+            .set_auth_uri(AuthUrl::new(Self::AUTH_URL.into())?)
+            .set_token_uri(TokenUrl::new(Self::TOKEN_URL.into())?)
+            .set_redirect_uri(redirect_url.clone());
+
+            let result = oauth_client
+                .exchange_code(oauth2::AuthorizationCode::new(code.clone()))
+                .request_async(client)
+                .await;
+            if let Err(err) = &result
+                && is_invalid_client(err)
+                && i + 1 < secrets.len()
+            {
+                tracing::warn!(
+                    "{} rejected the primary client secret, trying the secondary one",
+                    Self::TOKEN_URL
+                );
+                continue;
+            }
+
+            auth = Some(result);
+            break;
+        }
+
+        let auth = auth
+            .expect("There is always a primary secret")
+            .map_err(|err| {
+                if is_timeout(&err) {
+                    return Error::UpstreamTimeout;
+                }
+
+                Error::Custom {
+                    status_code: StatusCode::BAD_REQUEST,
+                    error: format!("Could not get the tokens from the provided code: {err:?}"),
+                }
             })?;
 
         assert_eq!(*auth.token_type(), BasicTokenType::Bearer);
@@ -147,30 +307,89 @@ pub trait OAuthAccountHelper: Sized {
             PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
             refresh_token,
             &auth.extra_fields,
+            client,
+            credentials,
         )
         .await
     }
 
-    async fn renew(refresh_token: RefreshToken) -> Result<Self, Error> {
-        let client = Client::<
-            BasicErrorResponse,
-            MinimalTokenResponse<Self::ExtraFields, BasicTokenType>,
-            BasicTokenIntrospectionResponse,
-            StandardRevocableToken,
-            BasicRevocationErrorResponse,
-        >::new(ClientId::new(Self::CLIENT_ID.into()))
-        .set_auth_type(Self::AUTH_TYPE)
-        .set_client_secret(ClientSecret::new(Self::CLIENT_SECRET.into()))
-        .set_auth_uri(AuthUrl::new(Self::AUTH_URL.into())?)
-        .set_token_uri(TokenUrl::new(Self::TOKEN_URL.into())?);
-
-        let resp = client
-            .exchange_refresh_token(&refresh_token)
-            .request_async(&reqwest::Client::default())
-            .await
-            .map_err(|err| Error::Custom {
-                status_code: StatusCode::INTERNAL_SERVER_ERROR,
-                error: format!("Failed to exchange refresh token for a new access token: {err:?}"),
+    async fn renew(
+        refresh_token: RefreshToken,
+        client: &reqwest::Client,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        let credentials = Self::credentials(config);
+
+        let secrets = credentials.secrets();
+        let mut resp = None;
+        for (i, secret) in secrets.iter().enumerate() {
+            let oauth_client = Client::<
+                BasicErrorResponse,
+                MinimalTokenResponse<Self::ExtraFields, BasicTokenType>,
+                BasicTokenIntrospectionResponse,
+                StandardRevocableToken,
+                BasicRevocationErrorResponse,
+            >::new(ClientId::new(credentials.client_id.into()))
+            .set_auth_type(Self::AUTH_TYPE)
+            .set_client_secret(ClientSecret::new(secret.to_string()))
+            .set_auth_uri(AuthUrl::new(Self::AUTH_URL.into())?)
+            .set_token_uri(TokenUrl::new(Self::TOKEN_URL.into())?);
+
+            let result = oauth_client
+                .exchange_refresh_token(&refresh_token)
+                .request_async(client)
+                .await;
+            if let Err(err) = &result
+                && is_invalid_client(err)
+                && i + 1 < secrets.len()
+            {
+                tracing::warn!(
+                    "{} rejected the primary client secret, trying the secondary one",
+                    Self::TOKEN_URL
+                );
+                continue;
+            }
+
+            resp = Some(result);
+            break;
+        }
+
+        let resp = resp
+            .expect("There is always a primary secret")
+            .map_err(|err| {
+                if is_timeout(&err) {
+                    return Error::UpstreamTimeout;
+                }
+                // A rejected secret is our misconfiguration, the accounts are still fine
+                if is_invalid_client(&err) {
+                    return Error::Custom {
+                        status_code: StatusCode::INTERNAL_SERVER_ERROR,
+                        error: format!("{} rejected the client secret", Self::TOKEN_URL),
+                    };
+                }
+                if let RequestTokenError::ServerResponse(resp) = &err {
+                    // The provider rejected the refresh token, usually because the user revoked
+                    // access
+                    if *resp.error() == BasicErrorResponseType::InvalidGrant {
+                        return Error::ReauthRequired;
+                    }
+
+                    // Anything else is a problem with the provider, the account may still be fine
+                    return Error::Custom {
+                        status_code: StatusCode::BAD_GATEWAY,
+                        error: format!(
+                            "{} refused to renew the access token: {resp:?}",
+                            Self::TOKEN_URL
+                        ),
+                    };
+                }
+
+                Error::Custom {
+                    status_code: StatusCode::INTERNAL_SERVER_ERROR,
+                    error: format!(
+                        "Failed to exchange refresh token for a new access token: {err:?}"
+                    ),
+                }
             })?;
 
         assert_eq!(*resp.token_type(), BasicTokenType::Bearer);
@@ -189,60 +408,130 @@ pub trait OAuthAccountHelper: Sized {
             PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
             refresh_token,
             &resp.extra_fields,
+            client,
         )
         .await
     }
 
+    /// Records that an account was linked, not called when only its tokens were updated
+    async fn track_link(
+        state: AppState,
+        acct: &Self::Account,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+    ) -> Result<(), Error> {
+        let provider = <Self::Account as AuthenticationHeader>::PROVIDER;
+        // The account was just inserted so it is the first if it is the only one
+        let provider_accounts = LinkedAccount::list(acct.user(), conn)
+            .await?
+            .into_iter()
+            .filter(|linked| linked.provider == provider)
+            .count();
+
+        crate::webhook::track(
+            state,
+            "account.linked",
+            serde_json::json!({
+                "userId": acct.user().id,
+                "provider": provider,
+                "accountId": acct.account_id(),
+                "firstLink": provider_accounts == 1,
+            }),
+        );
+
+        Ok(())
+    }
+
     async fn login(
         user: Option<User>,
         DbConn { mut conn }: DbConn,
+        HttpClient { client }: HttpClient,
+        emitter: MsgEmitter,
+        config: Config,
+        State(state): State<AppState>,
         Json(login_params): Json<LoginParams>,
     ) -> Result<
         Either<Json<Self::Response>, ([(HeaderName, String); 2], Json<Self::Response>)>,
         Error,
     > {
-        let session = Self::from_code(login_params.redirect_origin, login_params.code).await?;
+        let session = Self::from_code(
+            login_params.redirect_origin,
+            login_params.code,
+            client,
+            &config,
+        )
+        .await?;
 
         let resp = if let Some(user) = user {
-            let mut acct = session.insert_or_update_for_user(user, &mut conn).await?;
+            let (mut acct, inserted) = session.insert_or_update_for_user(user, &mut conn).await?;
+            if inserted {
+                Self::track_link(state, &acct, &mut conn).await?;
+            }
 
-            let headers = acct.headers(&mut conn).await?;
-            Either::E1(Json(
-                Self::Response::get(&mut acct, &reqwest::Client::new(), headers).await?,
-            ))
+            let headers = acct.headers(client, &emitter, &config, &mut conn).await?;
+            Either::E1(Json(Self::Response::get(&mut acct, client, headers).await?))
         } else {
             let now = OffsetDateTime::now_utc();
             let expires_at =
                 PrimitiveDateTime::new(now.date(), now.time()) + crate::SESSION_COOKIE_DURATION;
 
             let user = User::new(&mut conn).await?;
-            let mut acct = session.insert_or_update_for_user(user, &mut conn).await?;
+            let (mut acct, inserted) = session.insert_or_update_for_user(user, &mut conn).await?;
+            if inserted {
+                Self::track_link(state, &acct, &mut conn).await?;
+            }
 
             let session = UserSession::new_for_user(user, expires_at, &mut conn).await?;
+            let [session_cookie, user_id_cookie] =
+                login_cookies(&config, user, session, login_params.keep_logged_in);
 
-            let mut session_cookie = Cookie::new(crate::SESSION_COOKIE_NAME, session.token);
-            let mut user_id_cookie = Cookie::new(crate::USER_ID_COOKIE_NAME, user.id.to_string());
-
-            session_cookie.set_secure(true);
-            session_cookie.set_http_only(true);
-            session_cookie.set_path("/");
-            user_id_cookie.set_path("/");
-            if login_params.keep_logged_in {
-                let expire_time = OffsetDateTime::new_utc(expires_at.date(), expires_at.time());
-                session_cookie.set_expires(expire_time);
-                user_id_cookie.set_expires(expire_time);
-            }
-
-            let headers = acct.headers(&mut conn).await?;
+            let headers = acct.headers(client, &emitter, &config, &mut conn).await?;
             Either::E2((
                 [
                     (SET_COOKIE, session_cookie.encoded().to_string()),
                     (SET_COOKIE, user_id_cookie.encoded().to_string()),
                 ],
-                Json(Self::Response::get(&mut acct, &reqwest::Client::new(), headers).await?),
+                Json(Self::Response::get(&mut acct, client, headers).await?),
             ))
         };
 
         Ok(resp)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::{CookieNames, CookiePrefix},
+        testing,
+    };
+
+    #[tokio::test]
+    async fn only_the_session_cookie_is_http_only() {
+        let mut conn = testing::conn().await;
+        let user = testing::create_user(&mut conn).await;
+        let session = testing::create_session(user, &mut conn).await;
+        let config = testing::config();
+
+        let [session_cookie, user_id_cookie] = login_cookies(&config, user, session.clone(), false);
+        assert_eq!(session_cookie.value(), session.token);
+        assert_eq!(session_cookie.http_only(), Some(true));
+        assert_eq!(session_cookie.secure(), Some(true));
+        assert_eq!(session_cookie.expires(), None);
+
+        // Readable by the frontend, and only secure when the prefix needs it
+        assert_eq!(user_id_cookie.value(), user.id.to_string());
+        assert_ne!(user_id_cookie.http_only(), Some(true));
+        assert_ne!(user_id_cookie.secure(), Some(true));
+
+        let config = Config {
+            cookies: CookieNames::new(CookiePrefix::Host, "session", "user_id"),
+            ..config
+        };
+        let [session_cookie, user_id_cookie] = login_cookies(&config, user, session, true);
+        assert_eq!(session_cookie.name(), "__Host-session");
+        assert_eq!(user_id_cookie.secure(), Some(true));
+        assert!(session_cookie.expires().is_some());
+        assert!(user_id_cookie.expires().is_some());
+    }
+}
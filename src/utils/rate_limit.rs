@@ -0,0 +1,63 @@
+use std::{
+    collections::VecDeque,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub max: usize,
+    pub window: Duration,
+}
+
+/// Sliding window rate limiter
+pub struct RateLimiter<K> {
+    limit: RateLimit,
+    hits: DashMap<K, VecDeque<Instant>>,
+}
+
+pub type UserRateLimiter = RateLimiter<Uuid>;
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            hits: DashMap::default(),
+        }
+    }
+
+    /// Records a hit for the key, or returns how long it has to wait if it is over the limit
+    pub fn check(&self, key: K) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut hits = self.hits.entry(key).or_default();
+
+        while hits
+            .front()
+            .is_some_and(|hit| now.duration_since(*hit) >= self.limit.window)
+        {
+            hits.pop_front();
+        }
+
+        match hits.front() {
+            Some(oldest) if hits.len() >= self.limit.max => {
+                Err(self.limit.window - now.duration_since(*oldest))
+            }
+            _ => {
+                hits.push_back(now);
+                Ok(())
+            }
+        }
+    }
+
+    /// Forgets keys that have not been limited within the window
+    pub fn retain_recent(&self) {
+        let now = Instant::now();
+        self.hits.retain(|_, hits| {
+            hits.back()
+                .is_some_and(|hit| now.duration_since(*hit) < self.limit.window)
+        });
+    }
+}
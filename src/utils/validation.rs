@@ -0,0 +1,29 @@
+use std::collections::BTreeMap;
+
+use crate::Error;
+
+/// What is wrong with each invalid field of a form, keyed by the field name, so every bad field
+/// can be pointed out at once. Responded to with `422 Unprocessable Entity`.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ValidationError {
+    fields: BTreeMap<String, String>,
+}
+
+impl ValidationError {
+    /// Keeps the first message if the field already has one
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.fields
+            .entry(field.into())
+            .or_insert_with(|| message.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+impl From<ValidationError> for Error {
+    fn from(err: ValidationError) -> Self {
+        Error::Validation(err)
+    }
+}
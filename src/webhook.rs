@@ -0,0 +1,387 @@
+//! Signed notifications of company events sent to urls the company registered.
+//!
+//! Every delivery is a JSON POST of `{ "event", "companyId", "data" }` with the headers:
+//! - `X-Webhook-Event`, the event it is about
+//! - `X-Webhook-Timestamp`, when it was signed in seconds since the unix epoch
+//! - `X-Webhook-Signature`, the hex encoded HMAC-SHA256 keyed with the secret of the webhook of
+//!   `{timestamp}\n{body}`
+//!
+//! Failed deliveries are retried [`crate::WEBHOOK_MAX_ATTEMPTS`] times with a growing delay and
+//! then kept as a [`WebhookDeadLetter`].
+//!
+//! Product analytics events are sent the same way to the [`AnalyticsWebhook`] if one is
+//! configured, without dead letters.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing, Json, Router,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    db::{company, User, Webhook, WebhookDeadLetter, WebhookEvent},
+    state::{AppState, DbConn},
+    Error,
+};
+
+fn bad_request(error: String) -> Error {
+    Error::Custom {
+        status_code: StatusCode::BAD_REQUEST,
+        error,
+    }
+}
+
+async fn require_admin(company_id: Uuid, user: User, conn: &mut DbConn) -> Result<(), Error> {
+    if !company::is_admin(company_id, user, &mut conn.conn)
+        .await?
+        .unwrap_or(false)
+    {
+        return Err(Error::Forbidden {
+            reason: "You are not an admin of this company".into(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Parses the url and resolves its host to the address that is connected to. Only https urls
+/// of public addresses are allowed so webhooks cannot be used to reach internal services.
+async fn resolve_url(
+    state: AppState,
+    url: &str,
+) -> Result<(url::Url, std::net::SocketAddr), String> {
+    let parsed = url::Url::parse(url).map_err(|err| format!("Invalid webhook url: {err}"))?;
+    if parsed.scheme() != "https" {
+        return Err("Webhooks must be sent over https".into());
+    }
+
+    let addr = state
+        .config()
+        .remote_image_policy
+        .any_host()
+        .resolve(&parsed)
+        .await?;
+
+    Ok((parsed, addr))
+}
+
+/// Makes a single attempt at delivering the payload
+async fn send(
+    state: AppState,
+    url: &str,
+    secret: &str,
+    event: &str,
+    payload: &str,
+) -> Result<(), String> {
+    // The host is resolved again for every attempt as it could have changed since the webhook
+    // was registered
+    let (url, addr) = resolve_url(state, url).await?;
+
+    let client = state
+        .config()
+        .http_timeouts
+        .client_builder()
+        .resolve(url.host_str().unwrap_or_default(), addr)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|err| format!("Failed to build the client: {err}"))?;
+
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp().to_string();
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b"\n");
+    mac.update(payload.as_bytes());
+    let signature = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("x-webhook-event", event)
+        .header("x-webhook-timestamp", timestamp)
+        .header("x-webhook-signature", signature)
+        .body(payload.to_string())
+        .send()
+        .await
+        .map_err(|err| format!("Failed to send: {err}"))?;
+
+    // Redirects are not followed so they count as failures too
+    if !response.status().is_success() {
+        return Err(format!("Responded with {}", response.status()));
+    }
+
+    Ok(())
+}
+
+/// Sends the payload until it is accepted or every attempt failed, returning the last error
+async fn send_with_retries(
+    state: AppState,
+    url: &str,
+    secret: &str,
+    event: &str,
+    payload: &str,
+) -> Result<(), String> {
+    let mut backoff = crate::WEBHOOK_RETRY_BACKOFF;
+    let mut last_error = String::new();
+
+    for attempt in 1..=crate::WEBHOOK_MAX_ATTEMPTS {
+        match send(state, url, secret, event, payload).await {
+            Ok(()) => return Ok(()),
+            Err(err) => last_error = err,
+        }
+
+        if attempt < crate::WEBHOOK_MAX_ATTEMPTS {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Delivers the payload with retries, recording it as a dead letter if every attempt failed
+async fn deliver(state: AppState, webhook: Webhook, event: WebhookEvent, payload: String) {
+    let Err(last_error) = send_with_retries(
+        state,
+        &webhook.url,
+        &webhook.secret,
+        event.as_str(),
+        &payload,
+    )
+    .await
+    else {
+        return;
+    };
+
+    tracing::warn!(
+        "Giving up on delivering {} to webhook {}: {last_error}",
+        event.as_str(),
+        webhook.id
+    );
+
+    let recorded = match state.get_conn().await {
+        Ok(mut conn) => {
+            WebhookDeadLetter::insert(
+                webhook.id,
+                event,
+                &payload,
+                crate::WEBHOOK_MAX_ATTEMPTS as i32,
+                &last_error,
+                &mut conn,
+            )
+            .await
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = recorded {
+        tracing::error!("Failed to record a dead webhook delivery: {err:?}");
+    }
+}
+
+/// Notifies the webhooks of the company subscribed to the event in the background
+pub fn dispatch(state: AppState, company_id: Uuid, event: WebhookEvent, data: serde_json::Value) {
+    tokio::spawn(async move {
+        let webhooks = match state.get_conn().await {
+            Ok(mut conn) => Webhook::subscribed(company_id, event, &mut conn).await,
+            Err(err) => Err(err),
+        };
+        let webhooks = match webhooks {
+            Ok(webhooks) => webhooks,
+            Err(err) => {
+                tracing::error!("Failed to load the webhooks of {company_id}: {err:?}");
+                return;
+            }
+        };
+        if webhooks.is_empty() {
+            return;
+        }
+
+        let payload = serde_json::json!({
+            "event": event,
+            "companyId": company_id,
+            "data": data,
+        })
+        .to_string();
+
+        for webhook in webhooks {
+            tokio::spawn(deliver(state, webhook, event, payload.clone()));
+        }
+    });
+}
+
+/// Where product analytics events are sent, set through `ANALYTICS_WEBHOOK_URL` and
+/// `ANALYTICS_WEBHOOK_SECRET`
+#[derive(Clone, Copy)]
+pub struct AnalyticsWebhook {
+    pub url: &'static str,
+    pub secret: &'static str,
+}
+
+/// Keeps the secret out of logs
+impl std::fmt::Debug for AnalyticsWebhook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AnalyticsWebhook")
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Records a product analytics event. It is always logged under the `analytics` target and
+/// also sent to the [`AnalyticsWebhook`] in the background when one is configured.
+pub fn track(state: AppState, event: &'static str, data: serde_json::Value) {
+    tracing::info!(target: "analytics", event, %data, "Analytics event");
+
+    let Some(webhook) = state.config().analytics_webhook else {
+        return;
+    };
+
+    let payload = serde_json::json!({
+        "event": event,
+        "data": data,
+    })
+    .to_string();
+    tokio::spawn(async move {
+        if let Err(err) =
+            send_with_retries(state, webhook.url, webhook.secret, event, &payload).await
+        {
+            tracing::warn!("Giving up on sending the analytics event {event}: {err}");
+        }
+    });
+}
+
+#[derive(serde::Deserialize)]
+struct WebhookParam {
+    url: String,
+    events: Vec<WebhookEvent>,
+}
+
+impl WebhookParam {
+    async fn validate(&self, state: AppState) -> Result<(), Error> {
+        if self.events.is_empty() {
+            return Err(bad_request(
+                "A webhook has to be subscribed to at least one event".into(),
+            ));
+        }
+
+        resolve_url(state, &self.url).await.map_err(bad_request)?;
+
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    /// Payloads are signed with it, it is not shown again
+    secret: String,
+}
+
+async fn list_webhooks(
+    user: User,
+    mut db: DbConn,
+    Path(company_id): Path<Uuid>,
+) -> Result<Json<Vec<Webhook>>, Error> {
+    require_admin(company_id, user, &mut db).await?;
+
+    Ok(Json(Webhook::list(company_id, &mut db.conn).await?))
+}
+
+async fn create_webhook(
+    user: User,
+    mut db: DbConn,
+    State(state): State<AppState>,
+    Path(company_id): Path<Uuid>,
+    Json(param): Json<WebhookParam>,
+) -> Result<Json<CreatedWebhook>, Error> {
+    require_admin(company_id, user, &mut db).await?;
+    param.validate(state).await?;
+
+    if Webhook::count(company_id, &mut db.conn).await? >= crate::MAX_WEBHOOKS_PER_COMPANY {
+        return Err(bad_request(format!(
+            "A company cannot have more than {} webhooks",
+            crate::MAX_WEBHOOKS_PER_COMPANY
+        )));
+    }
+
+    let webhook = Webhook::create(company_id, &param.url, &param.events, &mut db.conn).await?;
+    let secret = webhook.secret.clone();
+
+    Ok(Json(CreatedWebhook { webhook, secret }))
+}
+
+async fn update_webhook(
+    user: User,
+    mut db: DbConn,
+    State(state): State<AppState>,
+    Path((company_id, webhook_id)): Path<(Uuid, Uuid)>,
+    Json(param): Json<WebhookParam>,
+) -> Result<Json<Webhook>, Error> {
+    require_admin(company_id, user, &mut db).await?;
+    param.validate(state).await?;
+
+    Webhook::update(
+        company_id,
+        webhook_id,
+        &param.url,
+        &param.events,
+        &mut db.conn,
+    )
+    .await?
+    .map(Json)
+    .ok_or_else(|| Error::Custom {
+        status_code: StatusCode::NOT_FOUND,
+        error: "Webhook of this id was not found".into(),
+    })
+}
+
+async fn delete_webhook(
+    user: User,
+    mut db: DbConn,
+    Path((company_id, webhook_id)): Path<(Uuid, Uuid)>,
+) -> Result<(), Error> {
+    require_admin(company_id, user, &mut db).await?;
+
+    if !Webhook::delete(company_id, webhook_id, &mut db.conn).await? {
+        return Err(Error::Custom {
+            status_code: StatusCode::NOT_FOUND,
+            error: "Webhook of this id was not found".into(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Deliveries that failed after every retry
+async fn dead_letters(
+    user: User,
+    mut db: DbConn,
+    Path(company_id): Path<Uuid>,
+) -> Result<Json<Vec<WebhookDeadLetter>>, Error> {
+    require_admin(company_id, user, &mut db).await?;
+
+    Ok(Json(
+        WebhookDeadLetter::list(company_id, &mut db.conn).await?,
+    ))
+}
+
+/// Nested under the company they belong to
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", routing::get(list_webhooks).post(create_webhook))
+        .route("/dead-letter", routing::get(dead_letters))
+        .route(
+            "/:webhook-id",
+            routing::put(update_webhook).delete(delete_webhook),
+        )
+}
@@ -1,19 +1,54 @@
-use std::pin::Pin;
+use std::{
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use axum::{
-    extract::{ws::WebSocket, State, WebSocketUpgrade},
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
     response::Response,
-    Json,
+    routing, Json, Router,
 };
-use futures::{Future, SinkExt, StreamExt};
+use futures::{stream::SplitSink, Future, SinkExt, StreamExt};
 use fxhash::FxHashMap;
-use tokio::sync::mpsc;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+use uuid::Uuid;
 
 use crate::{
-    db::User,
-    state::{AppState, Session, SessionWithPage},
+    db::{company::CompanyMembership, User, UserProfile, UserSession},
+    state::{AppState, Config, DbConn, Session, SessionWithPage},
+    storage::StoredImages,
+    Error, WS_AUTH_CHECK_INTERVAL, WS_HEARTBEAT_TIMEOUT, WS_PING_INTERVAL, WS_SEND_BUFFER,
+    WS_SEND_TIMEOUT,
 };
 
+/// Events dropped because the socket they were meant for was not keeping up, the running total
+/// is logged with every drop
+static DROPPED_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Queues an event for a socket. Events are not critical so they are dropped instead of waiting
+/// when the socket is not draining its queue.
+pub fn send_event(ws_tx: &mpsc::Sender<WsResponse>, event: WsResponse) {
+    match ws_tx.try_send(event) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => {
+            let dropped = DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::warn!(
+                dropped,
+                "Dropped an event for a websocket that is not keeping up"
+            );
+        }
+        Err(TrySendError::Closed(_)) => {
+            tracing::error!("Failed to send an event to a closed websocket");
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct WsFunctions(FxHashMap<String, Box<dyn WsFuncErased>>);
 
@@ -191,14 +226,87 @@ impl WsFuncParam for User {
     }
 }
 
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WhoAmI {
+    user_id: Uuid,
+    profile: Option<UserProfile>,
+    companies: Vec<CompanyMembership>,
+}
+
+/// Returns the user the server resolved the socket's session to
+async fn whoami(
+    user: User,
+    DbConn { mut conn }: DbConn,
+    config: Config,
+) -> Result<Json<WhoAmI>, WsError> {
+    let mut profile = UserProfile::for_user(user, &mut conn).await?;
+    profile.resolve_urls(&config);
+
+    Ok(Json(WhoAmI {
+        user_id: user.id,
+        profile,
+        companies: CompanyMembership::list_for_user(user, &mut conn).await?,
+    }))
+}
+
+pub fn functions() -> WsFunctions {
+    WsFunctions::default().add(whoami)
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WsTicket {
+    ticket: String,
+}
+
+async fn issue_ticket(
+    session: Session,
+    _user: User,
+    State(state): State<AppState>,
+) -> Result<Json<WsTicket>, Error> {
+    Ok(Json(WsTicket {
+        ticket: state.issue_ws_ticket(&session).await?,
+    }))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/ticket", routing::post(issue_ticket))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConnectParams {
+    ticket: Option<String>,
+}
+
+/// Opens a websocket authenticated either by the session cookie or by a ticket from
+/// [`issue_ticket`] for clients that cannot send cookies on the handshake
 pub async fn connect(
     ws: WebSocketUpgrade,
-    session: Session,
-    user: User,
+    Query(params): Query<ConnectParams>,
+    session: Option<Session>,
+    user: Option<User>,
+    DbConn { mut conn }: DbConn,
     State(state): State<AppState>,
-) -> Response {
-    println!("here");
-    ws.on_upgrade(move |ws| handle_socket(ws, session, user, state))
+) -> Result<Response, Error> {
+    let (session, user) = match (params.ticket, session, user) {
+        (Some(ticket), _, _) => {
+            let session = state
+                .redeem_ws_ticket(&ticket)
+                .await?
+                .ok_or(Error::Unauthenticated)?;
+            let user = UserSession::get_user_by_token(session.token(), &mut conn)
+                .await?
+                .ok_or(Error::Unauthenticated)?;
+            user.touch(&mut conn).await?;
+
+            (session, user)
+        }
+        (None, Some(session), Some(user)) => (session, user),
+        _ => return Err(Error::Unauthenticated),
+    };
+
+    Ok(ws.on_upgrade(move |ws| handle_socket(ws, session, user, state)))
 }
 
 #[derive(serde::Deserialize)]
@@ -230,18 +338,103 @@ pub enum WsResponse {
     },
 }
 
+/// Why the server closed a websocket. It is sent in the close frame so clients can tell whether
+/// to reconnect, authenticate again or back off.
+#[derive(Debug, Clone, Copy)]
+pub enum CloseReason {
+    /// The session was logged out or its user was deleted
+    AuthLost,
+    /// Nothing was received for [`WS_HEARTBEAT_TIMEOUT`], not even a pong
+    HeartbeatTimeout,
+    /// The client sent nothing but pongs for [`crate::state::Config::ws_idle_timeout`]
+    IdleTimeout,
+    /// The user called methods faster than [`crate::WS_CALL_RATE_LIMIT`] allows
+    RateLimited,
+    ShuttingDown,
+}
+
+impl CloseReason {
+    fn frame(self) -> CloseFrame<'static> {
+        let (code, reason) = match self {
+            CloseReason::AuthLost => (4001, "Authentication lost"),
+            CloseReason::HeartbeatTimeout => (4000, "Heartbeat timeout"),
+            CloseReason::IdleTimeout => (4008, "Idle timeout"),
+            CloseReason::RateLimited => (4029, "Rate limited"),
+            CloseReason::ShuttingDown => (close_code::AWAY, "Server shutting down"),
+        };
+
+        CloseFrame {
+            code,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Returns false if the socket did not accept the message in time
+async fn send_message(ws_tx: &mut SplitSink<WebSocket, Message>, msg: Message) -> bool {
+    match tokio::time::timeout(WS_SEND_TIMEOUT, ws_tx.send(msg)).await {
+        Ok(Ok(())) => true,
+        Ok(Err(err)) => {
+            tracing::error!("Failed to respond due to error: {err:?}");
+            false
+        }
+        Err(_) => {
+            tracing::warn!("Closing a websocket that stopped accepting messages");
+            false
+        }
+    }
+}
+
+/// Only a session that is confirmed to be gone counts as lost, a failure to reach the database
+/// keeps the socket open
+async fn session_lost(session: &Session, state: &AppState) -> bool {
+    let Ok(mut conn) = state.get_conn().await else {
+        return false;
+    };
+
+    matches!(
+        UserSession::get_user_by_token(session.token(), &mut conn).await,
+        Ok(None)
+    )
+}
+
 async fn handle_socket(ws: WebSocket, session: Session, user: User, state: AppState) {
     let funcs = state.ws_funcs();
+    let _socket_token = state.ws_sockets().token();
 
     let (mut ws_tx, mut ws_rx) = ws.split();
-    let (proxy_tx, mut proxy_rx) = mpsc::unbounded_channel::<WsResponse>();
+    let (proxy_tx, mut proxy_rx) = mpsc::channel::<WsResponse>(WS_SEND_BUFFER);
+    let (close_tx, mut close_rx) = oneshot::channel::<CloseReason>();
 
     tokio::spawn(async move {
-        while let Some(msg) = proxy_rx.recv().await {
+        let mut ping = tokio::time::interval(WS_PING_INTERVAL);
+        ping.reset();
+
+        loop {
+            let msg = tokio::select! {
+                msg = proxy_rx.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                // The sender is dropped without a reason when the client went away on its own
+                reason = &mut close_rx => {
+                    if let Ok(reason) = reason {
+                        send_message(&mut ws_tx, Message::Close(Some(reason.frame()))).await;
+                    }
+                    break;
+                }
+                _ = ping.tick() => {
+                    if !send_message(&mut ws_tx, Message::Ping(Vec::new())).await {
+                        break;
+                    }
+                    continue;
+                }
+            };
+
             match serde_json::to_string(&msg) {
                 Ok(msg) => {
-                    if let Err(err) = ws_tx.send(axum::extract::ws::Message::Text(msg)).await {
-                        tracing::error!("Failed to respond due to error: {err:?}");
+                    if !send_message(&mut ws_tx, Message::Text(msg)).await {
+                        break;
                     }
                 }
                 Err(err) => {
@@ -254,18 +447,51 @@ async fn handle_socket(ws: WebSocket, session: Session, user: User, state: AppSt
     let ws_tx = proxy_tx.clone();
     let page = session.add_page(ws_tx).await;
 
-    while let Some(msg) = ws_rx.next().await {
+    let ws_idle_timeout = state.config().ws_idle_timeout;
+    let heartbeat = tokio::time::sleep(WS_HEARTBEAT_TIMEOUT);
+    tokio::pin!(heartbeat);
+    let idle = tokio::time::sleep(ws_idle_timeout);
+    tokio::pin!(idle);
+    let mut auth_check = tokio::time::interval(WS_AUTH_CHECK_INTERVAL);
+    auth_check.reset();
+
+    let close_reason = loop {
+        let msg = tokio::select! {
+            msg = ws_rx.next() => match msg {
+                Some(msg) => msg,
+                None => break None,
+            },
+            () = &mut heartbeat => break Some(CloseReason::HeartbeatTimeout),
+            () = &mut idle => break Some(CloseReason::IdleTimeout),
+            _ = auth_check.tick() => {
+                if session_lost(&session, &state).await {
+                    break Some(CloseReason::AuthLost);
+                }
+                continue;
+            }
+            () = state.shutdown().cancelled() => break Some(CloseReason::ShuttingDown),
+        };
+        let now = tokio::time::Instant::now();
+        heartbeat.as_mut().reset(now + WS_HEARTBEAT_TIMEOUT);
+        // Pongs are sent by the browser on its own so they do not show that the tab is in use
+        if !matches!(msg, Ok(Message::Pong(_))) {
+            idle.as_mut().reset(now + ws_idle_timeout);
+        }
+
         match msg {
             Ok(msg) => match msg {
-                axum::extract::ws::Message::Text(msg) => {
+                Message::Text(msg) => {
+                    if state.ws_call_limiter().check(user.id).is_err() {
+                        break Some(CloseReason::RateLimited);
+                    }
+
                     let call: FuncCallMessage = match serde_json::from_str(&msg) {
                         Ok(call) => call,
                         Err(err) => {
-                            if proxy_tx
-                                .send(WsResponse::RawError { error: err.into() })
-                                .is_err()
-                            {
-                                tracing::error!("Failed to send a message over ws");
+                            let resp = WsResponse::RawError { error: err.into() };
+                            if proxy_tx.send_timeout(resp, WS_SEND_TIMEOUT).await.is_err() {
+                                tracing::warn!("Closing a websocket that is not keeping up");
+                                break None;
                             }
                             continue;
                         }
@@ -284,22 +510,29 @@ async fn handle_socket(ws: WebSocket, session: Session, user: User, state: AppSt
                             error: err,
                             nonce: call.nonce,
                         });
-                    if proxy_tx.send(resp).is_err() {
-                        tracing::error!("Failed to send a message over ws");
+                    // Responses are never dropped, a client that cannot take them is disconnected
+                    if proxy_tx.send_timeout(resp, WS_SEND_TIMEOUT).await.is_err() {
+                        tracing::warn!("Closing a websocket that is not keeping up");
+                        break None;
                     }
                 }
-                axum::extract::ws::Message::Close(_) => page.close().await,
+                Message::Close(_) => page.close().await,
                 _ => continue,
             },
             Err(err) => {
-                if proxy_tx
-                    .send(WsResponse::RawError { error: err.into() })
-                    .is_err()
-                {
-                    tracing::error!("Failed to send a message over ws");
+                let resp = WsResponse::RawError { error: err.into() };
+                if proxy_tx.send_timeout(resp, WS_SEND_TIMEOUT).await.is_err() {
+                    tracing::warn!("Closing a websocket that is not keeping up");
+                    break None;
                 }
             }
         }
+    };
+
+    if let Some(reason) = close_reason {
+        tracing::debug!(?reason, "Closing a websocket");
+        // The writer only stops once the close frame is sent
+        let _ = close_tx.send(reason);
     }
 
     // Do a second close here just in case there was no close message